@@ -121,6 +121,7 @@ pub enum Code {
     IndexNotFound,
     InvalidIndexUid,
     InvalidMinWordLengthForTypo,
+    InvalidSettings,
 
     // invalid state error
     InvalidState,
@@ -133,6 +134,7 @@ pub enum Code {
 
     Filter,
     Sort,
+    Facet,
 
     BadParameter,
     BadRequest,
@@ -150,6 +152,7 @@ pub enum Code {
     PayloadTooLarge,
     RetrieveDocument,
     SearchDocuments,
+    SearchTimeout,
     UnsupportedMediaType,
 
     DumpAlreadyInProgress,
@@ -213,6 +216,8 @@ impl Code {
             Filter => ErrCode::invalid("invalid_filter", StatusCode::BAD_REQUEST),
             // error related to sorts
             Sort => ErrCode::invalid("invalid_sort", StatusCode::BAD_REQUEST),
+            // error related to facets
+            Facet => ErrCode::invalid("invalid_facet", StatusCode::BAD_REQUEST),
 
             BadParameter => ErrCode::invalid("bad_parameter", StatusCode::BAD_REQUEST),
             BadRequest => ErrCode::invalid("bad_request", StatusCode::BAD_REQUEST),
@@ -237,6 +242,7 @@ impl Code {
                 ErrCode::internal("unretrievable_document", StatusCode::BAD_REQUEST)
             }
             SearchDocuments => ErrCode::internal("search_error", StatusCode::BAD_REQUEST),
+            SearchTimeout => ErrCode::invalid("search_timeout", StatusCode::REQUEST_TIMEOUT),
             UnsupportedMediaType => {
                 ErrCode::invalid("unsupported_media_type", StatusCode::UNSUPPORTED_MEDIA_TYPE)
             }
@@ -275,6 +281,7 @@ impl Code {
             InvalidMinWordLengthForTypo => {
                 ErrCode::invalid("invalid_min_word_length_for_typo", StatusCode::BAD_REQUEST)
             }
+            InvalidSettings => ErrCode::invalid("invalid_settings", StatusCode::BAD_REQUEST),
         }
     }
 
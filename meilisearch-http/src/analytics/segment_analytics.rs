@@ -8,7 +8,7 @@ use actix_web::http::header::USER_AGENT;
 use actix_web::HttpRequest;
 use http::header::CONTENT_TYPE;
 use meilisearch_auth::SearchRules;
-use meilisearch_lib::index::{SearchQuery, SearchResult};
+use meilisearch_lib::index::{SearchQuery, SearchResult, DEFAULT_SEARCH_LIMIT};
 use meilisearch_lib::index_controller::Stats;
 use meilisearch_lib::MeiliSearch;
 use once_cell::sync::Lazy;
@@ -402,7 +402,7 @@ impl SearchAggregator {
             ret.max_terms_number = q.split_whitespace().count();
         }
 
-        ret.max_limit = query.limit;
+        ret.max_limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
         ret.max_offset = query.offset.unwrap_or_default();
 
         ret
@@ -64,6 +64,7 @@ pub fn setup_meilisearch(opt: &Opt) -> anyhow::Result<MeiliSearch> {
     meilisearch.build(
         opt.db_path.clone(),
         opt.indexer_options.clone(),
+        opt.search_options.clone(),
         opt.scheduler_options.clone(),
     )
 }
@@ -5,7 +5,7 @@ use std::sync::Arc;
 
 use byte_unit::Byte;
 use clap::Parser;
-use meilisearch_lib::options::{IndexerOpts, SchedulerConfig};
+use meilisearch_lib::options::{IndexerOpts, SchedulerConfig, SearchOpts};
 use rustls::{
     server::{
         AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
@@ -149,6 +149,10 @@ pub struct Opt {
     #[clap(flatten)]
     pub indexer_options: IndexerOpts,
 
+    #[serde(flatten)]
+    #[clap(flatten)]
+    pub search_options: SearchOpts,
+
     #[serde(flatten)]
     #[clap(flatten)]
     pub scheduler_options: SchedulerConfig,
@@ -2,12 +2,14 @@ use actix_web::{web, HttpRequest, HttpResponse};
 use log::debug;
 use meilisearch_auth::IndexSearchRules;
 use meilisearch_error::ResponseError;
+use meilisearch_lib::index::updates::TypoSettings;
 use meilisearch_lib::index::{
-    default_crop_length, default_crop_marker, default_highlight_post_tag,
-    default_highlight_pre_tag, SearchQuery, DEFAULT_SEARCH_LIMIT,
+    AttributeToCrop, AttributeToHighlight, CropStrategy, CropUnit, ExternalDocumentId, FacetSortBy,
+    FieldOrder, HitContent, MatchingStrategy, ResultFormat, SearchQuery, SearchResult,
 };
 use meilisearch_lib::MeiliSearch;
-use serde::Deserialize;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 
 use crate::analytics::{Analytics, SearchAggregator};
@@ -30,20 +32,78 @@ pub struct SearchQueryGet {
     limit: Option<usize>,
     attributes_to_retrieve: Option<String>,
     attributes_to_crop: Option<String>,
-    #[serde(default = "default_crop_length")]
-    crop_length: usize,
+    crop_length: Option<usize>,
     attributes_to_highlight: Option<String>,
     filter: Option<String>,
     sort: Option<String>,
-    #[serde(default = "Default::default")]
-    matches: bool,
+    matches: Option<bool>,
     facets_distribution: Option<String>,
-    #[serde(default = "default_highlight_pre_tag")]
-    highlight_pre_tag: String,
-    #[serde(default = "default_highlight_post_tag")]
-    highlight_post_tag: String,
-    #[serde(default = "default_crop_marker")]
-    crop_marker: String,
+    highlight_pre_tag: Option<String>,
+    highlight_post_tag: Option<String>,
+    crop_marker: Option<String>,
+    crop_marker_before: Option<String>,
+    crop_marker_after: Option<String>,
+    prefer_exact_matches: Option<bool>,
+    show_crop_info: Option<bool>,
+    min_prefix_length: Option<usize>,
+    show_result_hash: Option<bool>,
+    exhaustive_facets_count: Option<bool>,
+    page: Option<usize>,
+    hits_per_page: Option<usize>,
+    attributes_to_search_on: Option<String>,
+    matching_strategy: Option<MatchingStrategy>,
+    matches_on_formatted: Option<bool>,
+    matches_char_offsets: Option<bool>,
+    matches_by_pointer: Option<bool>,
+    show_matched_terms: Option<bool>,
+    strict_attributes: Option<bool>,
+    distinct: Option<String>,
+    show_processing_times: Option<bool>,
+    strict_limits: Option<bool>,
+    min_geo_distance: Option<bool>,
+    geo_distance_decimals: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_geo_point")]
+    geo_distance_from: Option<[f64; 2]>,
+    show_facet_stats: Option<bool>,
+    max_values_per_facet: Option<usize>,
+    facet_sort: Option<FacetSortBy>,
+    crop_strategy: Option<CropStrategy>,
+    crop_unit: Option<CropUnit>,
+    crop_on_sentences: Option<bool>,
+    highlight_numbers: Option<bool>,
+    exclude_document_ids: Option<String>,
+    raw_attributes: Option<String>,
+    show_filter_fields: Option<bool>,
+    disjunctive_facets: Option<bool>,
+    show_suggestion: Option<bool>,
+    timeout_ms: Option<u64>,
+    hit_content: Option<HitContent>,
+    show_ranking_rules: Option<bool>,
+    show_typos: Option<bool>,
+    show_matched_words_count: Option<bool>,
+    show_normalized_query: Option<bool>,
+    formatted_segments: Option<bool>,
+    crop_snippets: Option<usize>,
+    show_truncation: Option<bool>,
+    sort_ties_by_id: Option<bool>,
+    show_size_bytes: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_typo_tolerance")]
+    typo_tolerance: Option<TypoSettings>,
+    exclude_attributes_by_default: Option<bool>,
+    show_field_coverage: Option<bool>,
+    highlight_case_sensitive: Option<bool>,
+    highlight_diacritic_sensitive: Option<bool>,
+    min_match_length: Option<usize>,
+    flatten_documents: Option<bool>,
+    field_order: Option<FieldOrder>,
+    format: Option<ResultFormat>,
+    max_highlights_per_field: Option<usize>,
+    cursor: Option<String>,
+    ranking_rules: Option<String>,
+    escape_html: Option<bool>,
+    show_candidates_count: Option<bool>,
+    show_ranking_score: Option<bool>,
+    ranking_score_threshold: Option<f64>,
 }
 
 impl From<SearchQueryGet> for SearchQuery {
@@ -52,18 +112,42 @@ impl From<SearchQueryGet> for SearchQuery {
             .attributes_to_retrieve
             .map(|attrs| attrs.split(',').map(String::from).collect());
 
-        let attributes_to_crop = other
-            .attributes_to_crop
-            .map(|attrs| attrs.split(',').map(String::from).collect());
+        let attributes_to_crop = other.attributes_to_crop.map(|attrs| {
+            attrs
+                .split(',')
+                .map(|attr| AttributeToCrop::Name(attr.to_string()))
+                .collect()
+        });
 
-        let attributes_to_highlight = other
-            .attributes_to_highlight
-            .map(|attrs| attrs.split(',').map(String::from).collect());
+        let attributes_to_highlight = other.attributes_to_highlight.map(|attrs| {
+            attrs
+                .split(',')
+                .map(|attr| AttributeToHighlight::Name(attr.to_string()))
+                .collect()
+        });
 
         let facets_distribution = other
             .facets_distribution
             .map(|attrs| attrs.split(',').map(String::from).collect());
 
+        let attributes_to_search_on = other
+            .attributes_to_search_on
+            .map(|attrs| attrs.split(',').map(String::from).collect());
+
+        let exclude_document_ids = other.exclude_document_ids.map(|ids| {
+            ids.split(',')
+                .map(|id| ExternalDocumentId::String(id.to_string()))
+                .collect()
+        });
+
+        let raw_attributes = other
+            .raw_attributes
+            .map(|attrs| attrs.split(',').map(String::from).collect());
+
+        let ranking_rules = other
+            .ranking_rules
+            .map(|rules| rules.split(',').map(String::from).collect());
+
         let filter = match other.filter {
             Some(f) => match serde_json::from_str(&f) {
                 Ok(v) => Some(v),
@@ -77,7 +161,7 @@ impl From<SearchQueryGet> for SearchQuery {
         Self {
             q: other.q,
             offset: other.offset,
-            limit: other.limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+            limit: other.limit,
             attributes_to_retrieve,
             attributes_to_crop,
             crop_length: other.crop_length,
@@ -89,10 +173,103 @@ impl From<SearchQueryGet> for SearchQuery {
             highlight_pre_tag: other.highlight_pre_tag,
             highlight_post_tag: other.highlight_post_tag,
             crop_marker: other.crop_marker,
+            crop_marker_before: other.crop_marker_before,
+            crop_marker_after: other.crop_marker_after,
+            prefer_exact_matches: other.prefer_exact_matches,
+            show_crop_info: other.show_crop_info,
+            min_prefix_length: other.min_prefix_length,
+            show_result_hash: other.show_result_hash,
+            exhaustive_facets_count: other.exhaustive_facets_count,
+            page: other.page,
+            hits_per_page: other.hits_per_page,
+            attributes_to_search_on,
+            matching_strategy: other.matching_strategy,
+            matches_on_formatted: other.matches_on_formatted,
+            matches_char_offsets: other.matches_char_offsets,
+            matches_by_pointer: other.matches_by_pointer,
+            show_matched_terms: other.show_matched_terms,
+            strict_attributes: other.strict_attributes,
+            distinct: other.distinct.map(Some),
+            show_processing_times: other.show_processing_times,
+            strict_limits: other.strict_limits,
+            min_geo_distance: other.min_geo_distance,
+            geo_distance_decimals: other.geo_distance_decimals,
+            geo_distance_from: other.geo_distance_from,
+            show_facet_stats: other.show_facet_stats,
+            max_values_per_facet: other.max_values_per_facet,
+            facet_sort: other.facet_sort,
+            crop_strategy: other.crop_strategy,
+            crop_unit: other.crop_unit,
+            crop_on_sentences: other.crop_on_sentences,
+            highlight_numbers: other.highlight_numbers,
+            exclude_document_ids,
+            raw_attributes,
+            show_filter_fields: other.show_filter_fields,
+            disjunctive_facets: other.disjunctive_facets,
+            show_suggestion: other.show_suggestion,
+            timeout_ms: other.timeout_ms,
+            hit_content: other.hit_content,
+            show_ranking_rules: other.show_ranking_rules,
+            show_typos: other.show_typos,
+            show_matched_words_count: other.show_matched_words_count,
+            show_normalized_query: other.show_normalized_query,
+            formatted_segments: other.formatted_segments,
+            crop_snippets: other.crop_snippets,
+            show_truncation: other.show_truncation,
+            sort_ties_by_id: other.sort_ties_by_id,
+            show_size_bytes: other.show_size_bytes,
+            typo_tolerance: other.typo_tolerance,
+            exclude_attributes_by_default: other.exclude_attributes_by_default,
+            show_field_coverage: other.show_field_coverage,
+            highlight_case_sensitive: other.highlight_case_sensitive,
+            highlight_diacritic_sensitive: other.highlight_diacritic_sensitive,
+            min_match_length: other.min_match_length,
+            flatten_documents: other.flatten_documents,
+            field_order: other.field_order,
+            format: other.format,
+            max_highlights_per_field: other.max_highlights_per_field,
+            cursor: other.cursor,
+            ranking_rules,
+            escape_html: other.escape_html,
+            show_candidates_count: other.show_candidates_count,
+            show_ranking_score: other.show_ranking_score,
+            ranking_score_threshold: other.ranking_score_threshold,
         }
     }
 }
 
+/// Deserialize a `lat,lng` query parameter into a geo point.
+fn deserialize_geo_point<'de, D>(deserializer: D) -> Result<Option<[f64; 2]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let point = match Option::<String>::deserialize(deserializer)? {
+        Some(point) => point,
+        None => return Ok(None),
+    };
+    let coordinates: Result<Vec<f64>, _> = point.split(',').map(|c| c.trim().parse()).collect();
+    match coordinates.as_deref() {
+        Ok([lat, lng]) if lat.is_finite() && lng.is_finite() => Ok(Some([*lat, *lng])),
+        _ => Err(D::Error::custom(format!(
+            "invalid geo point `{}`, expected `lat,lng`",
+            point
+        ))),
+    }
+}
+
+/// Deserialize a `typoTolerance` query parameter holding a JSON object.
+fn deserialize_typo_tolerance<'de, D>(deserializer: D) -> Result<Option<TypoSettings>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(settings) => serde_json::from_str(&settings).map(Some).map_err(|e| {
+            D::Error::custom(format!("invalid `typoTolerance` `{}`: {}", settings, e))
+        }),
+        None => Ok(None),
+    }
+}
+
 /// Incorporate search rules in search query
 fn add_search_rules(query: &mut SearchQuery, rules: IndexSearchRules) {
     query.filter = match (query.filter.take(), rules.filter) {
@@ -113,6 +290,19 @@ fn add_search_rules(query: &mut SearchQuery, rules: IndexSearchRules) {
     }
 }
 
+/// Serializes the search result in the requested `format`.
+fn search_response(
+    search_result: SearchResult,
+    format: ResultFormat,
+) -> Result<HttpResponse, ResponseError> {
+    match format {
+        ResultFormat::Json => Ok(HttpResponse::Ok().json(search_result)),
+        ResultFormat::GeoJson => Ok(HttpResponse::Ok()
+            .content_type("application/geo+json")
+            .json(search_result.to_geojson()?)),
+    }
+}
+
 // TODO: TAMO: split on :asc, and :desc, instead of doing some weird things
 
 /// Transform the sort query parameter into something that matches the post expected format.
@@ -159,6 +349,7 @@ pub async fn search_with_url_query(
         add_search_rules(&mut query, search_rules);
     }
 
+    let format = query.format.unwrap_or_default();
     let mut aggregate = SearchAggregator::from_query(&query, &req);
 
     let search_result = meilisearch.search(index_uid, query).await;
@@ -174,7 +365,7 @@ pub async fn search_with_url_query(
     assert!(!search_result.exhaustive_nb_hits);
 
     debug!("returns: {:?}", search_result);
-    Ok(HttpResponse::Ok().json(search_result))
+    search_response(search_result, format)
 }
 
 pub async fn search_with_post(
@@ -197,6 +388,7 @@ pub async fn search_with_post(
         add_search_rules(&mut query, search_rules);
     }
 
+    let format = query.format.unwrap_or_default();
     let mut aggregate = SearchAggregator::from_query(&query, &req);
 
     let search_result = meilisearch.search(index_uid, query).await;
@@ -212,7 +404,7 @@ pub async fn search_with_post(
     assert!(!search_result.exhaustive_nb_hits);
 
     debug!("returns: {:?}", search_result);
-    Ok(HttpResponse::Ok().json(search_result))
+    search_response(search_result, format)
 }
 
 #[cfg(test)]
@@ -252,4 +444,18 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_deserialize_geo_distance_from() {
+        let query = web::Query::<SearchQueryGet>::from_query("geoDistanceFrom=48.85,%202.35");
+        assert_eq!(query.unwrap().geo_distance_from, Some([48.85, 2.35]));
+
+        let query = web::Query::<SearchQueryGet>::from_query("q=hello");
+        assert_eq!(query.unwrap().geo_distance_from, None);
+
+        for point in ["48.85", "48.85,2.35,3", "north,2.35"] {
+            let query = format!("geoDistanceFrom={}", point);
+            assert!(web::Query::<SearchQueryGet>::from_query(&query).is_err());
+        }
+    }
 }
@@ -210,6 +210,15 @@ make_setting_route!(
     "distinctAttribute"
 );
 
+make_setting_route!(
+    "/default-search-query",
+    meilisearch_lib::index::DefaultSearchQuery,
+    default_search_query,
+    "defaultSearchQuery"
+);
+
+make_setting_route!("/max-total-hits", usize, max_total_hits, "maxTotalHits");
+
 make_setting_route!(
     "/ranking-rules",
     Vec<String>,
@@ -254,7 +263,9 @@ generate_configure!(
     stop_words,
     synonyms,
     ranking_rules,
-    typo
+    typo,
+    default_search_query,
+    max_total_hits
 );
 
 pub async fn update_all(
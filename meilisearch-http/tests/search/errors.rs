@@ -481,3 +481,468 @@ async fn sort_unset_ranking_rule() {
         )
         .await;
 }
+
+#[actix_rt::test]
+async fn search_mixed_pagination() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let expected_response = json!({
+        "message": "The `page` and `hitsPerPage` parameters cannot be used together with the `offset` and `limit` parameters.",
+        "code": "bad_request",
+        "type": "invalid_request",
+        "link": "https://docs.meilisearch.com/errors#bad_request"
+    });
+
+    index
+        .search(json!({"page": 1, "offset": 2}), |response, code| {
+            assert_eq!(code, 400, "{}", response);
+            assert_eq!(response, expected_response);
+        })
+        .await;
+
+    index
+        .search(json!({"hitsPerPage": 2, "limit": 2}), |response, code| {
+            assert_eq!(code, 400, "{}", response);
+            assert_eq!(response, expected_response);
+        })
+        .await;
+
+    index
+        .search(json!({"page": 0}), |response, code| {
+            assert_eq!(code, 400, "{}", response);
+            assert_eq!(response["code"], "bad_request");
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_invalid_matching_strategy() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .search(json!({"matchingStrategy": "first"}), |response, code| {
+            assert_eq!(code, 400, "{}", response);
+            assert_eq!(response["code"], "bad_request");
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_invalid_geo_bounding_box() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "filterableAttributes": ["_geo", "genre"] }))
+        .await;
+    index.wait_task(0).await;
+
+    for filter in [
+        "_geoBoundingBox([50.7, 3.1])",
+        "_geoBoundingBox([50.5, 3.1], [50.7, 2.9])",
+        "_geoBoundingBox([91, 3.1], [50.5, 2.9])",
+        "genre = action AND _geoBoundingBox([50.7, 3.1], [50.5, 2.9])",
+    ] {
+        index
+            .search(json!({ "filter": filter }), |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "invalid_filter");
+            })
+            .await;
+    }
+}
+
+#[actix_rt::test]
+async fn search_invalid_facet_sort() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .search(json!({"facetSort": "random"}), |response, code| {
+            assert_eq!(code, 400, "{}", response);
+            assert_eq!(response["code"], "bad_request");
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_invalid_crop_unit() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .search(json!({"cropUnit": "byte"}), |response, code| {
+            assert_eq!(code, 400, "{}", response);
+            assert_eq!(response["code"], "bad_request");
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_strict_attributes() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    // unknown attributes are ignored by default
+    index
+        .search(
+            json!({ "attributesToRetrieve": ["tilte"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"][0], json!({}));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "attributesToRetrieve": ["tilte"], "strictAttributes": true }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "bad_request");
+                assert_eq!(
+                    response["message"],
+                    "Attribute `tilte` of `attributesToRetrieve` does not exist or is not displayed."
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "attributesToRetrieve": ["title"], "attributesToCrop": ["overview"], "strictAttributes": true }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(
+                    response["message"],
+                    "Attribute `overview` of `attributesToCrop` does not exist or is not displayed."
+                );
+            },
+        )
+        .await;
+
+    let (response, code) = index
+        .search_post(json!({ "attributesToRetrieve": ["*", "-secret"], "strictAttributes": true }))
+        .await;
+    assert_eq!(code, 400, "{}", response);
+    assert_eq!(
+        response["message"],
+        "Attribute `secret` of `attributesToRetrieve` does not exist or is not displayed."
+    );
+}
+
+#[actix_rt::test]
+async fn search_strict_limits() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    // the offset is clamped by default
+    index
+        .search(json!({ "offset": 1001 }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["hits"], json!([]));
+            assert_eq!(response["offset"], 1001);
+        })
+        .await;
+
+    index
+        .search(
+            json!({ "offset": 1001, "strictLimits": true }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "bad_request");
+                assert_eq!(
+                    response["message"],
+                    "The `offset` (1001) and `limit` (20) go past the 1000 documents that a search can return."
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_timeout() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents: Vec<_> = (0..5000)
+        .map(|id| json!({ "id": id, "title": format!("the hobbit {}", id) }))
+        .collect();
+    index.add_documents(json!(documents), None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "limit": 1000, "timeoutMs": 0 }),
+            |response, code| {
+                assert_eq!(code, 408, "{}", response);
+                assert_eq!(response["code"], "search_timeout");
+                assert!(response["message"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("The search exceeded its timeout of 0ms and was aborted after "));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "limit": 1000, "timeoutMs": 60000 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 1000);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_invalid_facets_distribution() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "filterableAttributes": ["genre", "year"] }))
+        .await;
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "genre": "fantasy", "year": 1937 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["genre"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["facetsDistribution"]["genre"],
+                    json!({ "fantasy": 1 })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["genre", "title", "author"] }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "invalid_facet");
+                assert_eq!(
+                    response["message"],
+                    "Attributes `title, author` of `facetsDistribution` are not filterable. Available filterable attributes are: `genre, year`."
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "facetsDistribution": ["*"] }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(
+                response["facetsDistribution"]
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .collect::<Vec<_>>(),
+                ["genre", "year"]
+            );
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_typo_tolerance_override_of_the_minimum_word_sizes() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "The Hobbit" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "hobit",
+            "typoTolerance": { "minWordLengthForTypo": { "oneTypo": 8 } },
+        }))
+        .await;
+    assert_eq!(code, 400, "{}", response);
+    assert_eq!(response["code"], "bad_request");
+    assert_eq!(
+        response["message"],
+        "The `minWordLengthForTypo` typo tolerance setting of the index cannot be overridden by the `typoTolerance` parameter, only `enabled` can."
+    );
+}
+
+#[actix_rt::test]
+async fn search_geo_distance_filter_without_base() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "filterableAttributes": ["_geo"] }))
+        .await;
+    index.add_documents(json!([{ "id": 1 }]), None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "filter": "_geoDistance < 1000" }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "invalid_filter");
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_geo_distance_filter_without_filterable_geo() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index.add_documents(json!([{ "id": 1 }]), None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "filter": "_geoDistance < 1000", "geoDistanceFrom": [45.4, 9.2] }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "invalid_filter");
+                assert_eq!(
+                    response["message"],
+                    "Filtering on `_geoDistance` requires the `_geo` attribute to be filterable."
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_invalid_cursor() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index.add_documents(json!([{ "id": 1 }]), None).await;
+    index.wait_task(0).await;
+
+    for query in [
+        json!({ "cursor": "not a cursor" }),
+        json!({ "cursor": "", "offset": 10 }),
+        json!({ "cursor": "", "page": 2 }),
+    ] {
+        index
+            .search(query, |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "bad_request");
+            })
+            .await;
+    }
+}
+
+#[actix_rt::test]
+async fn search_invalid_attribute_regex() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index.add_documents(json!([{ "id": 1 }]), None).await;
+    index.wait_task(0).await;
+
+    let oversized = format!("re:{}", "(a|b|c){1000}".repeat(10));
+    for query in [
+        json!({ "attributesToRetrieve": ["re:price_("] }),
+        json!({ "attributesToHighlight": [oversized] }),
+    ] {
+        let (response, code) = index.search_post(query).await;
+        assert_eq!(code, 400, "{}", response);
+        assert_eq!(response["code"], "bad_request");
+    }
+}
+
+#[actix_rt::test]
+async fn search_invalid_ranking_rules() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index.add_documents(json!([{ "id": 1 }]), None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(json!({ "rankingRules": ["wrods"] }), |response, code| {
+            assert_eq!(code, 400, "{}", response);
+            assert_eq!(response["code"], "invalid_ranking_rule");
+        })
+        .await;
+
+    index
+        .search(
+            json!({ "rankingRules": ["words"], "cursor": "" }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "bad_request");
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_facets_distribution_pattern_without_match() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "filterableAttributes": ["price"] }))
+        .await;
+    index
+        .add_documents(json!([{ "id": 1, "price": 10 }]), None)
+        .await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["attributes.*"] }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(response["code"], "invalid_facet");
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_invalid_ranking_score_threshold() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    for threshold in [-0.1, 1.1] {
+        index
+            .search(
+                json!({ "rankingScoreThreshold": threshold }),
+                |response, code| {
+                    assert_eq!(code, 400, "{}", response);
+                    assert_eq!(response["code"], "bad_request");
+                },
+            )
+            .await;
+    }
+}
@@ -3,6 +3,7 @@
 
 mod errors;
 
+use crate::common::server::default_settings;
 use crate::common::Server;
 use once_cell::sync::Lazy;
 use serde_json::{json, Value};
@@ -605,3 +606,3974 @@ async fn search_is_hard_limited() {
         )
         .await;
 }
+
+#[actix_rt::test]
+async fn search_prefer_exact_matches() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "rankingRules": ["words", "typo"] }))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "Hobbit" },
+        { "id": 2, "title": "Hob" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index
+        .search_post(json!({ "q": "hob", "attributesToHighlight": ["title"] }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0]["id"], json!(1));
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "hob",
+            "attributesToHighlight": ["title"],
+            "preferExactMatches": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0]["id"], json!(2));
+    assert_eq!(response["hits"][0]["_formatted"]["title"], "<em>Hob</em>");
+    assert_eq!(
+        response["hits"][1]["_formatted"]["title"],
+        "<em>Hob</em>bit"
+    );
+}
+
+#[actix_rt::test]
+async fn search_prefer_exact_matches_across_pages() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "rankingRules": ["words", "typo"] }))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "Hobbit" },
+        { "id": 2, "title": "Hobbit" },
+        { "id": 3, "title": "Hobbit" },
+        { "id": 4, "title": "Hob" },
+        { "id": 5, "title": "Hob" },
+        { "id": 6, "title": "Hob" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    // the exact matches past the first page are ranked first, and the pages follow each other
+    // without duplicates nor gaps.
+    let mut ids = Vec::new();
+    for offset in [0, 3] {
+        let (response, code) = index
+            .search_post(json!({
+                "q": "hob",
+                "preferExactMatches": true,
+                "offset": offset,
+                "limit": 3,
+            }))
+            .await;
+        assert_eq!(code, 200, "{}", response);
+        ids.extend(
+            response["hits"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|hit| hit["id"].as_u64().unwrap()),
+        );
+    }
+    assert_eq!(ids, [4, 5, 6, 1, 2, 3]);
+}
+
+#[actix_rt::test]
+async fn search_show_crop_info() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "Harry Potter",
+            "overview": "Harry Potter is an orphan who lives with his aunt and uncle until he learns he is a wizard",
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "harry",
+            "attributesToCrop": ["title", "overview"],
+            "cropLength": 5,
+            "showCropInfo": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(
+        response["hits"][0]["_croppedAttributes"],
+        json!(["overview"])
+    );
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "harry",
+            "attributesToCrop": ["title", "overview"],
+            "cropLength": 5,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response["hits"][0].get("_croppedAttributes").is_none());
+}
+
+#[actix_rt::test]
+async fn search_min_prefix_length() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(json!({ "q": "g" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+        })
+        .await;
+
+    index
+        .search(
+            json!({ "q": "g", "minPrefixLength": 2 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 0);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "gla", "minPrefixLength": 2 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_result_hash() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index.search_post(json!({ "showResultHash": true })).await;
+    assert_eq!(code, 200, "{}", response);
+    let hash = response["resultHash"].clone();
+    assert!(hash.is_string(), "{}", response);
+
+    let (response, code) = index.search_post(json!({ "showResultHash": true })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["resultHash"], hash);
+
+    let (response, code) = index.search_post(json!({})).await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response.get("resultHash").is_none(), "{}", response);
+
+    index
+        .update_documents(json!([{ "id": "287947", "title": "Shazam!!" }]), None)
+        .await;
+    index.wait_task(1).await;
+
+    let (response, code) = index.search_post(json!({ "showResultHash": true })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response["resultHash"].is_string(), "{}", response);
+    assert_ne!(response["resultHash"], hash);
+}
+
+#[actix_rt::test]
+async fn search_exhaustive_facets_count() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({"filterableAttributes": ["tag", "parity"]}))
+        .await;
+
+    let documents: Vec<_> = (0..1100)
+        .map(|id| json!({ "id": id, "tag": format!("tag-{}", id), "parity": id % 2 }))
+        .collect();
+    index.add_documents(json!(documents), None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["parity"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["exhaustiveFacetsCount"], json!(true));
+                assert_eq!(
+                    response["facetsDistribution"]["parity"],
+                    json!({ "0": 550, "1": 550 })
+                );
+            },
+        )
+        .await;
+
+    let (response, code) = index
+        .search_post(json!({ "facetsDistribution": ["tag", "parity"] }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["exhaustiveFacetsCount"], json!(false));
+    let tags = response["facetsDistribution"]["tag"].as_object().unwrap();
+    assert_eq!(tags.len(), 1000);
+
+    let (response, code) = index
+        .search_post(json!({
+            "facetsDistribution": ["tag", "parity"],
+            "exhaustiveFacetsCount": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["exhaustiveFacetsCount"], json!(true));
+    let tags = response["facetsDistribution"]["tag"].as_object().unwrap();
+    assert_eq!(tags.len(), 1100);
+    assert!(tags.values().all(|count| count == &json!(1)));
+    assert_eq!(
+        response["facetsDistribution"]["parity"],
+        json!({ "0": 550, "1": 550 })
+    );
+}
+
+#[actix_rt::test]
+async fn search_with_page_and_hits_per_page() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(json!({"page": 2, "hitsPerPage": 2}), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["hits"].as_array().unwrap().len(), 2);
+            assert_eq!(response["offset"], json!(2));
+            assert_eq!(response["limit"], json!(2));
+            assert_eq!(response["totalHits"], json!(5));
+            assert_eq!(response["totalPages"], json!(3));
+        })
+        .await;
+
+    index
+        .search(json!({"page": 3, "hitsPerPage": 2}), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+        })
+        .await;
+
+    index
+        .search(json!({"offset": 1, "limit": 2}), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["hits"].as_array().unwrap().len(), 2);
+            assert!(response.get("totalHits").is_none(), "{}", response);
+            assert!(response.get("totalPages").is_none(), "{}", response);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_attributes_to_search_on() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "body": "A dragon sleeps under the mountain" },
+        { "id": 2, "title": "Dragon tales", "body": "Stories for children" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "mountain", "attributesToSearchOn": ["title"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 0);
+                assert_eq!(response["nbHits"], json!(0));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "dragon", "attributesToSearchOn": ["title"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hits = response["hits"].as_array().unwrap();
+                assert_eq!(hits.len(), 1);
+                assert_eq!(hits[0]["id"], json!(2));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "mountain", "attributesToSearchOn": ["*"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "dragon", "attributesToSearchOn": ["unknown"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 0);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_attributes_to_search_on_restricts_the_counts() {
+    let temp = tempfile::tempdir().unwrap();
+    let mut options = default_settings(temp.path());
+    options.search_options.max_total_hits = Some(3);
+    let server = Server::new_with_options(options).await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "filterableAttributes": ["genre"] }))
+        .await;
+    let documents = json!([
+        { "id": 1, "title": "A dragon", "body": "", "genre": "fantasy" },
+        { "id": 2, "title": "Kings", "body": "A dragon", "genre": "fantasy" },
+        { "id": 3, "title": "Queens", "body": "A dragon", "genre": "history" },
+        { "id": 4, "title": "Knights", "body": "A dragon", "genre": "history" },
+        { "id": 5, "title": "Dragon tales", "body": "", "genre": "tales" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    // the documents matching outside of the title only are neither counted nor returned, even
+    // past the max total hits.
+    index
+        .search(
+            json!({
+                "q": "dragon",
+                "attributesToSearchOn": ["title"],
+                "facetsDistribution": ["genre"],
+                "showCandidatesCount": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let ids: Vec<_> = response["hits"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|hit| hit["id"].as_u64().unwrap())
+                    .collect();
+                assert_eq!(ids, [1, 5]);
+                assert_eq!(response["nbHits"], json!(2));
+                assert_eq!(
+                    response["facetsDistribution"]["genre"],
+                    json!({ "fantasy": 1, "tales": 1 })
+                );
+                assert_eq!(response["candidatesCount"]["afterFilter"], json!(2));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "dragon", "attributesToSearchOn": ["body"], "limit": 1, "offset": 1 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+                assert_eq!(response["nbHits"], json!(3));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_matching_strategy() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let query = "train your dragon hidden qwxzpl";
+
+    index
+        .search(json!({ "q": query }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            let hits = response["hits"].as_array().unwrap();
+            assert_eq!(hits.len(), 1);
+            assert_eq!(hits[0]["id"], json!("166428"));
+        })
+        .await;
+
+    index
+        .search(
+            json!({ "q": query, "matchingStrategy": "last" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": query, "matchingStrategy": "all" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 0);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_crop_objects() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "dragon",
+            "attributesToCrop": [{ "attribute": "title", "cropLength": 2 }],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let title = response["hits"][0]["_formatted"]["title"].as_str().unwrap();
+    assert_ne!(title, "How to Train Your Dragon: The Hidden World");
+    assert!(title.contains("…"), "{}", title);
+
+    // the crop length of the object wins over the one given with the colon syntax
+    let (response, code) = index
+        .search_post(json!({
+            "q": "dragon",
+            "attributesToCrop": [{ "attribute": "title", "cropLength": 100 }, "title:2"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(
+        response["hits"][0]["_formatted"]["title"],
+        json!("How to Train Your Dragon: The Hidden World")
+    );
+}
+
+#[actix_rt::test]
+async fn search_with_highlight_tags_per_attribute() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "body": "A hobbit leaves the shire" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "hobbit",
+            "attributesToHighlight": [
+                { "attribute": "title", "highlightPreTag": "<strong>", "highlightPostTag": "</strong>" },
+                { "attribute": "body", "highlightPreTag": "<mark>", "highlightPostTag": "</mark>" },
+            ],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let formatted = &response["hits"][0]["_formatted"];
+    assert_eq!(formatted["title"], json!("The <strong>Hobbit</strong>"));
+    assert_eq!(
+        formatted["body"],
+        json!("A <mark>hobbit</mark> leaves the shire")
+    );
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "hobbit",
+            "attributesToHighlight": ["*", { "attribute": "title", "highlightPreTag": "<strong>" }],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let formatted = &response["hits"][0]["_formatted"];
+    assert_eq!(formatted["title"], json!("The <strong>Hobbit</em>"));
+    assert_eq!(
+        formatted["body"],
+        json!("A <em>hobbit</em> leaves the shire")
+    );
+}
+
+#[actix_rt::test]
+async fn search_matches_on_formatted() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "overview": "The story follows Bilbo Baggins who is reluctantly sent on a long journey to the Lonely Mountain, where he ends up stealing from a dragon",
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "dragon",
+            "matches": true,
+            "matchesOnFormatted": true,
+            "attributesToCrop": ["overview"],
+            "cropLength": 4,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let hit = &response["hits"][0];
+    let overview = hit["_formatted"]["overview"].as_str().unwrap();
+    let start = hit["_matchesInfo"]["overview"][0]["start"]
+        .as_u64()
+        .unwrap() as usize;
+    let length = hit["_matchesInfo"]["overview"][0]["length"]
+        .as_u64()
+        .unwrap() as usize;
+    assert_eq!(&overview[start..start + length], "dragon");
+
+    // without the option the offsets are relative to the original value
+    let (response, code) = index
+        .search_post(json!({
+            "q": "dragon",
+            "matches": true,
+            "attributesToCrop": ["overview"],
+            "cropLength": 4,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let hit = &response["hits"][0];
+    let overview = hit["overview"].as_str().unwrap();
+    let start = hit["_matchesInfo"]["overview"][0]["start"]
+        .as_u64()
+        .unwrap() as usize;
+    assert_eq!(&overview[start..start + 6], "dragon");
+}
+
+#[actix_rt::test]
+async fn search_matches_char_offsets() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "Une belle étoile, l'étoile du berger" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "berger", "matches": true, "matchesCharOffsets": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"]["title"],
+                    json!([{ "start": 30, "length": 6 }])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "berger", "matches": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"]["title"],
+                    json!([{ "start": 32, "length": 6 }])
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_geo_bounding_box() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "genre": "action", "_geo": { "lat": 50.629973371633746, "lng": 3.0569447399419567 } },
+        { "id": 2, "genre": "drama", "_geo": { "lat": 50.6924, "lng": 3.1763 } },
+        { "id": 3, "genre": "action", "_geo": { "lat": 48.8566, "lng": 2.3522 } },
+        { "id": 4, "genre": "drama", "_geo": { "lat": -17.7134, "lng": 178.065 } },
+        { "id": 5, "genre": "action", "_geo": { "lat": -16.5782, "lng": -179.4144 } },
+    ]);
+    index
+        .update_settings(json!({ "filterableAttributes": ["_geo", "genre"] }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let ids = |response: &Value| {
+        let mut ids: Vec<_> = response["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["id"].as_u64().unwrap())
+            .collect();
+        ids.sort_unstable();
+        ids
+    };
+
+    index
+        .search(
+            json!({ "filter": "_geoBoundingBox([50.7, 3.1], [50.5, 2.9])" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), vec![1]);
+            },
+        )
+        .await;
+
+    // a box crossing the antimeridian
+    index
+        .search(
+            json!({ "filter": "_geoBoundingBox([-10, -175], [-20, 175])" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), vec![4, 5]);
+            },
+        )
+        .await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "filter": ["genre = drama", "_geoBoundingBox([-10, -175], [-20, 175])"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![4]);
+
+    let (response, code) = index
+        .search_post(json!({
+            "filter": [["genre = drama", "_geoBoundingBox([50.7, 3.1], [50.5, 2.9])"]],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![1, 2, 4]);
+}
+
+#[actix_rt::test]
+async fn search_with_geo_radius_in_filter_array() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "genre": "action", "_geo": { "lat": 45.764, "lng": 4.8357 } },
+        { "id": 2, "genre": "drama", "_geo": { "lat": 48.8003, "lng": 2.3005 } },
+        { "id": 3, "genre": "comedy", "_geo": { "lat": 48.8566, "lng": 2.3522 } },
+        { "id": 4, "genre": "drama", "_geo": { "lat": 43.2965, "lng": 5.3698 } },
+    ]);
+    index
+        .update_settings(json!({ "filterableAttributes": ["_geo", "genre"] }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let ids = |response: &Value| {
+        let mut ids: Vec<_> = response["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["id"].as_u64().unwrap())
+            .collect();
+        ids.sort_unstable();
+        ids
+    };
+
+    index
+        .search(
+            json!({ "filter": "_geoRadius(48.8, 2.3, 1000)" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), vec![2]);
+            },
+        )
+        .await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "filter": [["genre = action", "_geoRadius(48.8,2.3,1000)"]],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![1, 2]);
+
+    let (response, code) = index
+        .search_post(json!({
+            "filter": ["genre = drama", "_geoRadius(48.8,2.3,1000)"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![2]);
+
+    let (response, code) = index
+        .search_post(json!({
+            "filter": ["genre != comedy", ["genre = action", "_geoRadius(48.8,2.3,10000)"]],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![1, 2]);
+}
+
+#[actix_rt::test]
+async fn search_min_geo_distance() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "city": "Lille", "_geo": { "lat": 50.629973371633746, "lng": 3.0569447399419567 } },
+    ]);
+    index
+        .update_settings(json!({ "sortableAttributes": ["_geo"] }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let sort = json!([
+        "_geoPoint(48.8566, 2.3522):asc",
+        "_geoPoint(50.629973371633746, 3.0569447399419567):asc",
+    ]);
+
+    let (response, code) = index.search_post(json!({ "sort": sort })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response["hits"][0]["_geoDistance"].as_u64().unwrap() > 0);
+
+    let (response, code) = index
+        .search_post(json!({ "sort": sort, "minGeoDistance": true }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0]["_geoDistance"], json!(0));
+}
+
+#[actix_rt::test]
+async fn search_geo_distance_from() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "price": 20, "_geo": { "lat": 50.629973371633746, "lng": 3.0569447399419567 } },
+        { "id": 2, "price": 10, "_geo": { "lat": 48.8566, "lng": 2.3522 } },
+        { "id": 3, "price": 30 },
+    ]);
+    index
+        .update_settings(json!({ "sortableAttributes": ["_geo", "price"] }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    // without a geo sort
+    let (response, code) = index
+        .search_post(json!({
+            "sort": ["price:asc"],
+            "geoDistanceFrom": [48.8566, 2.3522],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0]["id"], json!(2));
+    assert_eq!(response["hits"][0]["_geoDistance"], json!(0));
+    assert!(response["hits"][1]["_geoDistance"].as_u64().unwrap() > 0);
+    assert!(response["hits"][2].get("_geoDistance").is_none());
+
+    let (response, code) = index
+        .search_get(json!({ "sort": "price:asc", "geoDistanceFrom": "48.8566,2.3522" }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0]["_geoDistance"], json!(0));
+
+    // the explicit point takes precedence over the geo sort
+    let (response, code) = index
+        .search_post(json!({
+            "sort": ["_geoPoint(50.629973371633746, 3.0569447399419567):asc"],
+            "geoDistanceFrom": [48.8566, 2.3522],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0]["id"], json!(1));
+    assert!(response["hits"][0]["_geoDistance"].as_u64().unwrap() > 0);
+    assert_eq!(response["hits"][1]["_geoDistance"], json!(0));
+
+    // the sort-driven distance is still computed without the parameter
+    let (response, code) = index
+        .search_post(json!({
+            "sort": ["_geoPoint(50.629973371633746, 3.0569447399419567):asc"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0]["_geoDistance"], json!(0));
+}
+
+#[actix_rt::test]
+async fn search_facet_stats() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "red shirt", "color": "red", "price": 25.5 },
+        { "id": 2, "title": "blue shirt", "color": "blue", "price": 12 },
+        { "id": 3, "title": "red hat", "color": "red", "price": [8, 40] },
+        { "id": 4, "title": "green hat", "color": "green", "price": 100 },
+    ]);
+    index
+        .update_settings(json!({ "filterableAttributes": ["color", "price"] }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "shirt",
+            "facetsDistribution": ["color", "price"],
+            "showFacetStats": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(
+        response["facetStats"],
+        json!({ "price": { "min": 12.0, "max": 25.5 } })
+    );
+
+    let (response, code) = index
+        .search_post(json!({
+            "filter": "color = red",
+            "facetsDistribution": ["*"],
+            "showFacetStats": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(
+        response["facetStats"],
+        json!({ "price": { "min": 8.0, "max": 40.0 } })
+    );
+
+    let (response, code) = index
+        .search_post(json!({ "facetsDistribution": ["price"] }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response.get("facetStats").is_none());
+}
+
+#[actix_rt::test]
+async fn search_max_values_per_facet() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents: Vec<_> = (0..50)
+        .map(|id| json!({ "id": id, "author": format!("author {:02}", id), "genre": "fantasy" }))
+        .collect();
+    index
+        .update_settings(json!({ "filterableAttributes": ["author", "genre"] }))
+        .await;
+    index.add_documents(json!(documents), None).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "facetsDistribution": ["author", "genre"],
+            "maxValuesPerFacet": 10,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let authors = response["facetsDistribution"]["author"]
+        .as_object()
+        .unwrap();
+    assert_eq!(authors.len(), 10);
+    assert!(authors.contains_key("author 00"));
+    assert_eq!(
+        response["facetsDistribution"]["genre"],
+        json!({ "fantasy": 50 })
+    );
+    assert_eq!(response["exhaustiveFacetsCount"], json!(false));
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["genre"], "maxValuesPerFacet": 10 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["exhaustiveFacetsCount"], json!(true));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_facet_sort() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "color": "blue" },
+        { "id": 2, "color": "red" },
+        { "id": 3, "color": "red" },
+        { "id": 4, "color": "green" },
+        { "id": 5, "color": "red" },
+        { "id": 6, "color": "green" },
+    ]);
+    index
+        .update_settings(json!({ "filterableAttributes": ["color"] }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let order = |response: &Value| -> Vec<String> {
+        response["facetsDistribution"]["color"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    };
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["color"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(order(&response), ["blue", "green", "red"]);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["color"], "facetSort": "alpha" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(order(&response), ["blue", "green", "red"]);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["color"], "facetSort": "count" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(order(&response), ["red", "green", "blue"]);
+                assert_eq!(
+                    response["facetsDistribution"]["color"],
+                    json!({ "red": 3, "green": 2, "blue": 1 })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "facetsDistribution": ["color"],
+                "facetSort": "count",
+                "maxValuesPerFacet": 1,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(order(&response), ["red"]);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_limit_zero() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "Lille shirt", "color": "red", "_geo": { "lat": 50.6299, "lng": 3.0569 } },
+        { "id": 2, "title": "Paris shirt", "color": "blue", "_geo": { "lat": 48.8566, "lng": 2.3522 } },
+        { "id": 3, "title": "Lille hat", "color": "red", "_geo": { "lat": 50.6361, "lng": 3.0634 } },
+    ]);
+    index
+        .update_settings(json!({ "filterableAttributes": ["_geo", "color"] }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "q": "shirt", "limit": 0, "facetsDistribution": ["color"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"], json!([]));
+                assert_eq!(response["nbHits"], json!(2));
+                assert_eq!(response["limit"], json!(0));
+                assert_eq!(
+                    response["facetsDistribution"]["color"],
+                    json!({ "blue": 1, "red": 1 })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "shirt", "limit": 0, "preferExactMatches": true, "offset": 1 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"], json!([]));
+                assert_eq!(response["nbHits"], json!(2));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "limit": 0, "filter": "_geoBoundingBox([50.7, 3.1], [50.5, 2.9])" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"], json!([]));
+                assert_eq!(response["nbHits"], json!(2));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_crop_strategy() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "overview": "a hobbit walks along the river for many long days until night when the old dwarf and the hobbit meet",
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit dwarf",
+                "attributesToCrop": ["overview"],
+                "cropLength": 5,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["overview"],
+                    json!("a hobbit walks along the…")
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit dwarf",
+                "attributesToCrop": ["overview"],
+                "cropLength": 5,
+                "cropStrategy": "bestWindow",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["overview"],
+                    json!("…old dwarf and the hobbit…")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_crop_on_sentences() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "overview": "Harry Potter is a young boy. He lives with his aunt and uncle in a small house.",
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "uncle",
+                "attributesToCrop": ["overview"],
+                "cropLength": 6,
+                "cropOnSentences": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["overview"],
+                    json!("…He lives with his aunt and uncle in a small house.")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_crop_unit() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "overview": "a hobbit walks along the river for many long days until night when the old dwarf and the hobbit meet",
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "dwarf",
+                "attributesToCrop": ["overview"],
+                "cropLength": 4,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["overview"],
+                    json!("…the old dwarf and…")
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "dwarf",
+                "attributesToCrop": ["overview"],
+                "cropLength": 20,
+                "cropUnit": "char",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["overview"],
+                    json!("…when the old dwarf and…")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_matches_by_pointer() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "The Hobbit",
+            "authors": [
+                { "name": "Christopher Tolkien" },
+                { "name": "Tolkien" },
+            ],
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "tolkien",
+                "matches": true,
+                "matchesByPointer": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"],
+                    json!({
+                        "/authors/0/name": [{ "start": 12, "length": 7 }],
+                        "/authors/1/name": [{ "start": 0, "length": 7 }],
+                    })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "tolkien",
+                "matches": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"],
+                    json!({
+                        "authors": [{ "start": 12, "length": 7 }, { "start": 0, "length": 7 }],
+                    })
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_matched_terms() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "The Hobbit meets a Dwarf",
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit dwarf",
+                "matches": true,
+                "showMatchedTerms": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"]["title"],
+                    json!([
+                        { "start": 4, "length": 6, "matchedTerm": "hobbit" },
+                        { "start": 19, "length": 5, "matchedTerm": "dwarf" },
+                    ])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit dwarf",
+                "matches": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"]["title"],
+                    json!([
+                        { "start": 4, "length": 6 },
+                        { "start": 19, "length": 5 },
+                    ])
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_excluded_attributes_to_retrieve() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "The Hobbit",
+            "author": "Tolkien",
+            "internalNotes": "out of stock",
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "attributesToRetrieve": ["-internalNotes"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0],
+                    json!({ "id": 1, "title": "The Hobbit", "author": "Tolkien" })
+                );
+            },
+        )
+        .await;
+
+    let (response, code) = index
+        .search_post(json!({ "attributesToRetrieve": ["*", "-internalNotes", "-author"] }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(
+        response["hits"][0],
+        json!({ "id": 1, "title": "The Hobbit" })
+    );
+
+    let (response, code) = index
+        .search_post(json!({ "attributesToRetrieve": ["title", "author", "-author"] }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"][0], json!({ "title": "The Hobbit" }));
+}
+
+#[actix_rt::test]
+async fn search_keeps_the_order_of_attributes_to_retrieve() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "The Hobbit",
+            "author": "Tolkien",
+            "year": 1937,
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "attributesToRetrieve": ["year", "title", "id"],
+            "attributesToHighlight": ["title"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let hit = &response["hits"][0];
+    let keys: Vec<_> = hit.as_object().unwrap().keys().collect();
+    assert_eq!(keys, ["year", "title", "id", "_formatted"]);
+    let keys: Vec<_> = hit["_formatted"].as_object().unwrap().keys().collect();
+    assert_eq!(keys, ["year", "title", "id"]);
+}
+
+#[actix_rt::test]
+async fn search_with_query_distinct() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "name": "shirt", "color": "blue", "size": "M" },
+        { "id": 2, "name": "shirt", "color": "Blue", "size": "L" },
+        { "id": 3, "name": "shirt", "color": "red", "size": "M" },
+        { "id": 4, "name": "shirt", "size": "S" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let ids = |response: &Value| -> Vec<u64> {
+        response["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["id"].as_u64().unwrap())
+            .collect()
+    };
+
+    index
+        .search(json!({ "q": "shirt" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(ids(&response), [1, 2, 3, 4]);
+        })
+        .await;
+
+    // the values are normalized and the documents without value are kept
+    index
+        .search(
+            json!({ "q": "shirt", "distinct": "color" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), [1, 3, 4]);
+                assert_eq!(response["nbHits"], 3);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "shirt", "distinct": "size" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), [1, 2, 4]);
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "q": "shirt", "distinct": "" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(ids(&response), [1, 2, 3, 4]);
+        })
+        .await;
+
+    // the distinct attribute of the index can be repeated in the query
+    index
+        .update_settings(json!({ "distinctAttribute": "color" }))
+        .await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "q": "shirt", "distinct": "color" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), [1, 3, 4]);
+            },
+        )
+        .await;
+
+    // the distinct attribute of the query replaces the one of the index
+    index
+        .search(
+            json!({ "q": "shirt", "distinct": "size" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), [1, 2, 4]);
+                assert_eq!(response["nbHits"], 3);
+            },
+        )
+        .await;
+
+    // the distinct attribute of the index can be disabled
+    index
+        .search(json!({ "q": "shirt", "distinct": "" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(ids(&response), [1, 2, 3, 4]);
+            assert_eq!(response["nbHits"], 4);
+        })
+        .await;
+
+    let (response, code) = index
+        .search_post(json!({ "q": "shirt", "distinct": null }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), [1, 2, 3, 4]);
+}
+
+#[actix_rt::test]
+async fn search_show_processing_times() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "filterableAttributes": ["title"] }))
+        .await;
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "glass",
+            "attributesToHighlight": ["title"],
+            "facetsDistribution": ["title"],
+            "showProcessingTimes": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+
+    let times = response["processingTimes"].as_object().unwrap();
+    let phases: Vec<_> = times.keys().map(String::as_str).collect();
+    assert_eq!(phases, ["search", "documents", "formatting", "facets"]);
+    let sum: f64 = times.values().map(|time| time.as_f64().unwrap()).sum();
+    assert!(times.values().all(|time| time.as_f64().unwrap() >= 0.0));
+    // the total is truncated to the millisecond
+    let total = response["processingTimeMs"].as_f64().unwrap();
+    assert!(sum <= total + 1.0, "{} > {}", sum, total);
+
+    let (response, code) = index.search_post(json!({ "q": "glass" })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response.get("processingTimes").is_none());
+}
+
+#[actix_rt::test]
+async fn search_reports_processing_time_in_microseconds() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(json!({ "q": "glass" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            let us = response["processingTimeUs"].as_u64().unwrap();
+            let ms = response["processingTimeMs"].as_u64().unwrap();
+            assert!(us > 0);
+            assert_eq!(us / 1000, ms);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_highlights_phrases_together() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "Harry Potter and the Half-Blood Prince" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "\"half blood\"", "attributesToHighlight": ["title"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    json!("Harry Potter and the <em>Half-Blood</em> Prince")
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "half blood", "attributesToHighlight": ["title"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    json!("Harry Potter and the <em>Half</em>-<em>Blood</em> Prince")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_one_sided_crop_markers() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "Harry Potter and the Half-Blood Prince" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "half",
+                "attributesToCrop": ["title"],
+                "cropLength": 2,
+                "cropMarkerBefore": "‹…",
+                "cropMarkerAfter": "…›",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    json!("‹…the Half…›")
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "half",
+                "attributesToCrop": ["title"],
+                "cropLength": 2,
+                "cropMarker": "[…]",
+                "cropMarkerBefore": "",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    json!("the Half[…]")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_crop_without_trailing_marker_at_the_end_of_the_field() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "author": "Written by J. K. Rowling... " },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "rowling", "attributesToCrop": ["author"], "cropLength": 2 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["author"],
+                    json!("…K. Rowling")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_without_highlighting_numbers() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "publication_year": 1937 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "1937", "attributesToHighlight": ["*"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["publication_year"],
+                    json!("<em>1937</em>")
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "1937", "attributesToHighlight": ["*"], "highlightNumbers": false }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["publication_year"],
+                    json!(1937)
+                );
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    json!("The Hobbit")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_returns_estimated_total_hits() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = DOCUMENTS.clone();
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(json!({ "q": "" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["estimatedTotalHits"], response["nbHits"]);
+            assert_eq!(response["estimatedTotalHits"], json!(5));
+            assert!(response.get("totalHits").is_none());
+        })
+        .await;
+
+    index
+        .search(json!({ "q": "", "page": 1 }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["totalHits"], json!(5));
+            assert!(response.get("estimatedTotalHits").is_none());
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_cache_is_invalidated_by_writes() {
+    let temp = tempfile::tempdir().unwrap();
+    let mut options = default_settings(temp.path());
+    options.search_options.search_cache_size = 10;
+    let server = Server::new_with_options(options).await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "The Hobbit" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    for _ in 0..2 {
+        index
+            .search(json!({ "q": "hobbit" }), |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["nbHits"], json!(1));
+            })
+            .await;
+    }
+
+    let documents = json!([{ "id": 2, "title": "The Hobbit, an unexpected journey" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["nbHits"], json!(2));
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_excluding_document_ids() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({"filterableAttributes": ["genre"]}))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "genre": "fantasy" },
+        { "id": 2, "title": "The Fellowship of the Ring", "genre": "fantasy" },
+        { "id": 3, "title": "Dune", "genre": "science fiction" },
+        { "id": 4, "title": "Hyperion", "genre": "science fiction" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "excludeDocumentIds": [1, "3", 42],
+            "facetsDistribution": ["genre"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let ids: Vec<_> = response["hits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["id"].clone())
+        .collect();
+    assert_eq!(ids, vec![json!(2), json!(4)]);
+    assert_eq!(response["nbHits"], json!(2));
+    assert_eq!(
+        response["facetsDistribution"]["genre"],
+        json!({ "fantasy": 1, "science fiction": 1 })
+    );
+
+    index
+        .search(
+            json!({ "excludeDocumentIds": ["2"], "limit": 1, "offset": 1 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"][0]["id"], json!(3));
+                assert_eq!(response["nbHits"], json!(3));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_raw_attributes() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "The Hobbit",
+            "blob": { "data": "hobbit hobbit", "size": 1937 },
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToHighlight": ["*"],
+                "attributesToRetrieve": ["title"],
+                "rawAttributes": ["blob"],
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hit = &response["hits"][0];
+                let blob = json!({ "data": "hobbit hobbit", "size": 1937 });
+                assert_eq!(hit["blob"], blob);
+                assert_eq!(hit["_formatted"]["blob"], blob);
+                assert_eq!(hit["_formatted"]["title"], json!("The <em>Hobbit</em>"));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_filter_fields() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({"filterableAttributes": ["genre", "rating", "year"]}))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "Alien", "genre": "horror", "rating": 4, "year": 1979 },
+        { "id": 2, "title": "Dune", "genre": "science fiction", "rating": 3, "year": 2021 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "filter": [["genre = horror", "rating > 3"], "NOT year < 1950"],
+            "showFilterFields": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["filterFields"], json!(["genre", "rating", "year"]));
+
+    index
+        .search(
+            json!({ "filter": "genre = horror OR (rating > 3 AND year < 1950)" }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert!(response.get("filterFields").is_none(), "{}", response);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_negation_in_array_filter() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({"filterableAttributes": ["genre", "rating"]}))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "Alien", "genre": "horror", "rating": 4 },
+        { "id": 2, "title": "Dune", "genre": "science fiction", "rating": 3 },
+        { "id": 3, "title": "Scream", "genre": "horror", "rating": 2 },
+        { "id": 4, "title": "Amélie", "genre": "comedy", "rating": 5 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    // (NOT genre = horror OR rating > 3) AND NOT genre = comedy
+    let (response, code) = index
+        .search_post(json!({
+            "filter": [[{ "not": "genre = horror" }, "rating > 3"], { "not": "genre = comedy" }],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let mut ids: Vec<_> = response["hits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["id"].as_u64().unwrap())
+        .collect();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![1, 2]);
+}
+
+#[actix_rt::test]
+async fn search_with_disjunctive_facets() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({"filterableAttributes": ["genre", "year"]}))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "Alien", "genre": "horror", "year": 1979 },
+        { "id": 2, "title": "Dune", "genre": "science fiction", "year": 2021 },
+        { "id": 3, "title": "Scream", "genre": "horror", "year": 1996 },
+        { "id": 4, "title": "Arrival", "genre": "science fiction", "year": 2016 },
+        { "id": 5, "title": "Amélie", "genre": "comedy", "year": 2001 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let query = json!({
+        "filter": "genre = horror AND year > 1990",
+        "facetsDistribution": ["genre", "year"],
+    });
+
+    let (response, code) = index.search_post(query.clone()).await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["nbHits"], json!(1));
+    assert_eq!(
+        response["facetsDistribution"]["genre"],
+        json!({ "horror": 1 })
+    );
+    assert_eq!(response["facetsDistribution"]["year"], json!({ "1996": 1 }));
+
+    let mut disjunctive = query;
+    disjunctive["disjunctiveFacets"] = json!(true);
+    let (response, code) = index.search_post(disjunctive).await;
+    assert_eq!(code, 200, "{}", response);
+    // the hits are still filtered by all the clauses.
+    assert_eq!(response["nbHits"], json!(1));
+    // the genres are counted among the documents released after 1990.
+    assert_eq!(
+        response["facetsDistribution"]["genre"],
+        json!({ "comedy": 1, "horror": 1, "science fiction": 2 })
+    );
+    // the years are counted among the horror movies.
+    assert_eq!(
+        response["facetsDistribution"]["year"],
+        json!({ "1979": 1, "1996": 1 })
+    );
+}
+
+#[actix_rt::test]
+async fn search_with_suggestion() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit" },
+        { "id": 2, "title": "The Lord of the Rings" },
+        { "id": 3, "title": "The Silmarillion" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({"q": "the hobbbbit ", "showSuggestion": true}),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["nbHits"], json!(0));
+                assert_eq!(response["suggestion"], json!("the hobbit "));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({"q": "the hobbit", "showSuggestion": true}),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["nbHits"], json!(1));
+                assert!(response.get("suggestion").is_none(), "{}", response);
+            },
+        )
+        .await;
+
+    // the suggestion is only computed on demand.
+    index
+        .search(json!({"q": "the hobbbbit "}), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response.get("suggestion").is_none(), "{}", response);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_highlights_synonyms() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "synonyms": { "car": ["automobile"] } }))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "The history of the automobile" },
+        { "id": 2, "title": "A car for everyone" },
+        { "id": 3, "title": "The history of the bicycle" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "q": "car", "attributesToHighlight": ["title"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let mut formatted: Vec<_> = response["hits"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|hit| hit["_formatted"]["title"].as_str().unwrap().to_string())
+                    .collect();
+                formatted.sort();
+                assert_eq!(
+                    formatted,
+                    [
+                        "A <em>car</em> for everyone",
+                        "The history of the <em>automobile</em>"
+                    ]
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_hit_content() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "overview": "a hobbit goes on a journey" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToHighlight": ["title"],
+                "attributesToRetrieve": ["title"],
+                "hitContent": "formatted",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"],
+                    json!([{ "_formatted": { "title": "The <em>Hobbit</em>" } }])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToHighlight": ["title"],
+                "attributesToRetrieve": ["title"],
+                "hitContent": "document",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"], json!([{ "title": "The Hobbit" }]));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToHighlight": ["title"],
+                "attributesToRetrieve": ["title"],
+                "hitContent": "both",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"],
+                    json!([{
+                        "title": "The Hobbit",
+                        "_formatted": { "title": "The <em>Hobbit</em>" },
+                    }])
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_highlight_and_crop_with_pointers() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "overview": "a hobbit goes on a long journey with dwarves" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "hobbit",
+            "attributesToHighlight": ["/title"],
+            "attributesToCrop": ["/overview"],
+            "cropLength": 2,
+            "strictAttributes": true,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let formatted = &response["hits"][0]["_formatted"];
+    assert_eq!(formatted["title"], "The <em>Hobbit</em>");
+    assert_ne!(
+        formatted["overview"],
+        "a hobbit goes on a long journey with dwarves"
+    );
+    assert!(formatted["overview"].as_str().unwrap().contains("hobbit"));
+}
+
+#[actix_rt::test]
+async fn search_with_max_total_hits() {
+    let temp = tempfile::tempdir().unwrap();
+    let mut options = default_settings(temp.path());
+    options.search_options.max_total_hits = Some(10);
+    let server = Server::new_with_options(options).await;
+    let index = server.index("test");
+
+    let documents: Vec<_> = (0..30)
+        .map(|id| json!({ "id": id, "title": "the hobbit" }))
+        .collect();
+    index.add_documents(json!(documents), None).await;
+    index.wait_task(0).await;
+
+    // the number of hits is capped
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["nbHits"], json!(10));
+            assert_eq!(response["hits"].as_array().unwrap().len(), 10);
+        })
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "page": 1, "hitsPerPage": 4 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["totalHits"], json!(10));
+                assert_eq!(response["totalPages"], json!(3));
+            },
+        )
+        .await;
+
+    // the deep offsets are clamped
+    index
+        .search(
+            json!({ "q": "hobbit", "offset": 8, "limit": 5 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 2);
+            },
+        )
+        .await;
+
+    // or rejected with strict limits
+    index
+        .search(
+            json!({ "q": "hobbit", "offset": 8, "limit": 5, "strictLimits": true }),
+            |response, code| {
+                assert_eq!(code, 400, "{}", response);
+                assert_eq!(
+                    response["message"],
+                    "The `offset` (8) and `limit` (5) go past the 10 documents that a search can return."
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_max_total_hits_setting() {
+    let temp = tempfile::tempdir().unwrap();
+    let mut options = default_settings(temp.path());
+    options.search_options.max_total_hits = Some(10);
+    let server = Server::new_with_options(options).await;
+    let index = server.index("test");
+
+    let documents: Vec<_> = (0..30)
+        .map(|id| json!({ "id": id, "title": "the hobbit" }))
+        .collect();
+    index.add_documents(json!(documents), None).await;
+    index.update_settings(json!({ "maxTotalHits": 25 })).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index.settings().await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["maxTotalHits"], json!(25));
+
+    // the setting of the index overrides the one of the instance
+    index
+        .search(json!({ "q": "hobbit", "limit": 30 }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["nbHits"], json!(25));
+            assert_eq!(response["hits"].as_array().unwrap().len(), 25);
+        })
+        .await;
+
+    // the one of the instance applies again once the setting is reset
+    index.update_settings(json!({ "maxTotalHits": null })).await;
+    index.wait_task(2).await;
+
+    index
+        .search(json!({ "q": "hobbit", "limit": 30 }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["nbHits"], json!(10));
+            assert_eq!(response["hits"].as_array().unwrap().len(), 10);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_ranking_rules() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "sortableAttributes": ["rank"] }))
+        .await;
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "rank": 2 },
+        { "id": 2, "title": "The Hobbit, an unexpected journey", "rank": 1 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "sort": ["rank:asc"], "showRankingRules": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["rankingRules"],
+                    json!([
+                        "words",
+                        "typo",
+                        "proximity",
+                        "attribute",
+                        "sort(rank:asc)",
+                        "exactness"
+                    ])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "showRankingRules": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["rankingRules"],
+                    json!(["words", "typo", "proximity", "attribute", "exactness"])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response.get("rankingRules").is_none(), "{}", response);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_typos() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit" },
+        { "id": 2, "title": "The Hobit" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "showTypos": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hits = response["hits"].as_array().unwrap();
+                assert_eq!(hits.len(), 2);
+                assert_eq!(hits[0]["id"], json!(1));
+                assert_eq!(hits[0]["_typos"], json!(0));
+                assert_eq!(hits[1]["id"], json!(2));
+                assert_eq!(hits[1]["_typos"], json!(1));
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response["hits"][0].get("_typos").is_none());
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_formatted_segments() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "The Hobbit" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToHighlight": ["title"],
+                "formattedSegments": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    json!([
+                        { "text": "The ", "highlighted": false },
+                        { "text": "Hobbit", "highlighted": true },
+                    ])
+                );
+                assert_eq!(response["hits"][0]["title"], "The Hobbit");
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_crop_snippets() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "overview": "In a hole in the ground there lived a hobbit named Bilbo. Many years later he went to the mountain where the dragon Smaug was sleeping on the gold.",
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit dragon",
+                "attributesToCrop": ["overview"],
+                "cropLength": 4,
+                "cropSnippets": 2,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["overview"],
+                    "…lived a hobbit named…where the dragon Smaug…"
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_does_not_highlight_stop_words() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index.update_settings(json!({ "stopWords": ["the"] })).await;
+    let documents = json!([{ "id": 1, "title": "The Hobbit" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "q": "the hobbit", "attributesToHighlight": ["title"], "matches": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hit = &response["hits"][0];
+                assert_eq!(hit["_formatted"]["title"], "The <em>Hobbit</em>");
+                assert_eq!(
+                    hit["_matchesInfo"]["title"],
+                    json!([{ "start": 4, "length": 6 }])
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_truncation() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "title": "The Hobbit",
+        "overview": "A hobbit goes on a long journey to the Lonely Mountain with thirteen dwarves",
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToCrop": ["title", "overview"],
+                "cropLength": 5,
+                "showTruncation": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let truncated = &response["hits"][0]["_formattedTruncated"];
+                assert_eq!(truncated["title"], json!(false));
+                assert_eq!(truncated["overview"], json!(true));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToCrop": ["overview"], "cropLength": 5 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert!(response["hits"][0].get("_formattedTruncated").is_none());
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_sort_ties_by_id() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let ids = [17, 3, 42, 8, 25, 1, 33, 12, 5, 29, 2, 40];
+    let documents: Vec<Value> = ids
+        .iter()
+        .map(|id| json!({ "id": id, "title": "tie" }))
+        .collect();
+    index.add_documents(json!(documents), None).await;
+    index.wait_task(0).await;
+
+    let mut sorted = ids;
+    sorted.sort_unstable();
+
+    let hit_ids = |response: &Value| -> Vec<u64> {
+        response["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["id"].as_u64().unwrap())
+            .collect()
+    };
+
+    for q in ["", "tie"] {
+        let (response, code) = index.search_post(json!({ "q": q, "limit": 20 })).await;
+        assert_eq!(code, 200, "{}", response);
+        assert_eq!(hit_ids(&response), sorted);
+
+        // the pages follow each other without overlapping.
+        for offset in [0, 4, 8] {
+            let (response, code) = index
+                .search_post(json!({ "q": q, "offset": offset, "limit": 4 }))
+                .await;
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(hit_ids(&response), sorted[offset..offset + 4]);
+        }
+
+        // the documents are returned in the order they were indexed in when the query opts out.
+        let (response, code) = index
+            .search_post(json!({ "q": q, "limit": 20, "sortTiesById": false }))
+            .await;
+        assert_eq!(code, 200, "{}", response);
+        assert_eq!(hit_ids(&response), ids);
+    }
+}
+
+#[actix_rt::test]
+async fn search_sort_ties_by_id_keeps_the_ranking() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 9, "title": "hobbit" },
+        { "id": 4, "title": "the hobbit" },
+        { "id": 7, "title": "hobbit" },
+        { "id": 1, "title": "the hobbit" },
+        { "id": 8, "title": "hobbit" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    // only the documents tying on every ranking rule are ordered by id.
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            let hits: Vec<_> = response["hits"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|hit| hit["id"].as_u64().unwrap())
+                .collect();
+            assert_eq!(hits, [7, 8, 9, 1, 4]);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_size_bytes() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "title": "Le Petit Prince",
+        "overview": "Un aviateur échoué dans le désert rencontre un petit garçon",
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "prince", "attributesToHighlight": ["title"], "showSizeBytes": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let mut document = response["hits"][0].as_object().unwrap().clone();
+                let size = document.remove("_sizeBytes").unwrap();
+                document.remove("_formatted");
+                assert_eq!(size, json!(serde_json::to_vec(&document).unwrap().len()));
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "q": "prince" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response["hits"][0].get("_sizeBytes").is_none());
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_typo_tolerance_override() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "The Hobbit" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let (response, code) = index.search_post(json!({ "q": "hobit" })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+
+    let (response, code) = index
+        .search_post(json!({ "q": "hobit", "typoTolerance": { "enabled": false } }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"], json!([]));
+
+    let (response, code) = index
+        .search_get(json!({ "q": "hobit", "typoTolerance": r#"{ "enabled": false }"# }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"], json!([]));
+
+    // the typos are still allowed by the index.
+    let (response, code) = index.settings().await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["typoTolerance"]["enabled"], json!(true));
+
+    let (response, code) = index.search_post(json!({ "q": "hobit" })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+}
+
+#[actix_rt::test]
+async fn search_exclude_attributes_by_default() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "The Hobbit", "secret": "dragon" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    // all the displayed attributes are retrieved by default.
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(
+                response["hits"],
+                json!([{ "id": 1, "title": "The Hobbit", "secret": "dragon" }])
+            );
+        })
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "excludeAttributesByDefault": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"], json!([{}]));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToRetrieve": ["-secret"], "excludeAttributesByDefault": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"], json!([{}]));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToRetrieve": ["title"], "excludeAttributesByDefault": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"], json!([{ "title": "The Hobbit" }]));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_field_coverage() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "author": "Tolkien", "year": 1937 },
+        { "id": 2, "title": "The Silmarillion", "author": "Tolkien", "year": null },
+        { "id": 3, "title": "Dune", "author": null },
+        { "id": 4, "title": "Hobbit Lore" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "showFieldCoverage": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["fieldCoverage"],
+                    json!({ "id": 2, "title": 2, "author": 1, "year": 1 })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "showFieldCoverage": true }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(
+                response["fieldCoverage"],
+                json!({ "id": 4, "title": 4, "author": 2, "year": 1 })
+            );
+        })
+        .await;
+
+    // Only the hits of the page are counted.
+    index
+        .search(
+            json!({ "showFieldCoverage": true, "limit": 2 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["fieldCoverage"],
+                    json!({ "id": 2, "title": 2, "author": 2, "year": 1 })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response.get("fieldCoverage").is_none());
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_diacritic_sensitive_highlight() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "Une étoile et une Etoile" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "etoile", "attributesToHighlight": ["title"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    "Une <em>étoile</em> et une <em>Etoile</em>"
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "etoile",
+                "attributesToHighlight": ["title"],
+                "highlightDiacriticSensitive": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    "Une étoile et une <em>Etoile</em>"
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "etoile",
+                "attributesToHighlight": ["title"],
+                "highlightCaseSensitive": true,
+                "highlightDiacriticSensitive": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    "Une étoile et une Etoile"
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_min_match_length() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{ "id": 1, "title": "The Hobbit and the Prancing Pony" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit p", "matches": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"]["title"],
+                    json!([
+                        { "start": 4, "length": 6 },
+                        { "start": 19, "length": 1 },
+                        { "start": 28, "length": 1 },
+                    ])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit p", "matches": true, "minMatchLength": 2 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_matchesInfo"]["title"],
+                    json!([{ "start": 4, "length": 6 }])
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_flatten_documents() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "title": "The Hobbit",
+        "author": { "name": "Tolkien", "born": 1892 },
+        "tags": ["fantasy", "classic"],
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "flattenDocuments": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0],
+                    json!({
+                        "id": 1,
+                        "title": "The Hobbit",
+                        "author.name": "Tolkien",
+                        "author.born": 1892,
+                        "tags[0]": "fantasy",
+                        "tags[1]": "classic",
+                    })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "flattenDocuments": true, "attributesToRetrieve": ["author"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let keys: Vec<_> = response["hits"][0].as_object().unwrap().keys().collect();
+                assert_eq!(keys, ["author.name", "author.born"]);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_field_order() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    // the field ids follow the order the fields are first seen in, not the order of each document.
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "author": "Tolkien" },
+        { "genre": "fantasy", "author": "Tolkien", "title": "The Silmarillion", "id": 2 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let keys = |response: &Value| -> Vec<String> {
+        response["hits"][0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    };
+
+    index
+        .search(json!({ "q": "silmarillion" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(keys(&response), ["id", "title", "author", "genre"]);
+        })
+        .await;
+
+    index
+        .search(
+            json!({ "q": "silmarillion", "attributesToRetrieve": ["genre", "id"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(keys(&response), ["genre", "id"]);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({
+                "q": "silmarillion",
+                "attributesToRetrieve": ["genre", "id"],
+                "fieldOrder": "fieldId",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(keys(&response), ["id", "genre"]);
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_warnings() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "displayedAttributes": ["id", "title"] }))
+        .await;
+    let documents = json!([{ "id": 1, "title": "The Hobbit", "secret": "dragon" }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToCrop": ["secret"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["warnings"],
+                    json!([
+                        "Attribute `secret` of `attributesToCrop` does not exist or is not displayed, it is ignored."
+                    ])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "cropLength": 5 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["warnings"],
+                    json!(["`cropLength` is ignored because `attributesToCrop` is not set."])
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "q": "hobbit" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response.get("warnings").is_none());
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_crop_in_tokens() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([{
+        "id": 1,
+        "title": "one, two; three - the hobbit: four, five; six - seven",
+    }]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToCrop": ["title"], "cropLength": 6 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let cropped = response["hits"][0]["_formatted"]["title"].as_str().unwrap();
+                assert!(
+                    cropped.contains("two") && cropped.contains("five"),
+                    "{}",
+                    cropped
+                );
+            },
+        )
+        .await;
+
+    // the separators count toward the crop length, fewer words are kept.
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToCrop": ["title"],
+                "cropLength": 6,
+                "cropUnit": "token",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let cropped = response["hits"][0]["_formatted"]["title"].as_str().unwrap();
+                assert!(cropped.contains("hobbit"), "{}", cropped);
+                assert!(
+                    !cropped.contains("two") && !cropped.contains("five"),
+                    "{}",
+                    cropped
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_geo_distance_filter() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "genre": "action", "_geo": { "lat": 45.764, "lng": 4.8357 } },
+        { "id": 2, "genre": "drama", "_geo": { "lat": 48.8003, "lng": 2.3005 } },
+        { "id": 3, "genre": "comedy", "_geo": { "lat": 48.8566, "lng": 2.3522 } },
+        { "id": 4, "genre": "drama", "_geo": { "lat": 43.2965, "lng": 5.3698 } },
+        { "id": 5, "genre": "drama" },
+    ]);
+    index
+        .update_settings(json!({
+            "filterableAttributes": ["_geo", "genre"],
+            "sortableAttributes": ["_geo"],
+        }))
+        .await;
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let ids = |response: &Value| {
+        response["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["id"].as_u64().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // Paris, then a suburb 7km away, Lyon at ~390km and Marseille at ~660km.
+    let (response, code) = index
+        .search_post(json!({
+            "sort": ["_geoPoint(48.8566, 2.3522):asc"],
+            "filter": "_geoDistance < 400000",
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![3, 2, 1]);
+    assert_eq!(response["nbHits"], json!(3));
+    for hit in response["hits"].as_array().unwrap() {
+        assert!(hit["_geoDistance"].as_u64().unwrap() < 400000);
+    }
+
+    // the bounds are applied before the pagination.
+    let (response, code) = index
+        .search_post(json!({
+            "sort": ["_geoPoint(48.8566, 2.3522):asc"],
+            "filter": ["_geoDistance >= 1000", "_geoDistance < 400000"],
+            "limit": 1,
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![2]);
+    assert_eq!(response["nbHits"], json!(2));
+
+    let (response, code) = index
+        .search_post(json!({
+            "sort": ["_geoPoint(48.8566, 2.3522):asc"],
+            "filter": ["genre = drama", "_geoDistance > 1000"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![2, 4]);
+    assert_eq!(response["nbHits"], json!(2));
+
+    // the explicit point is used when the search isn't sorted by distance.
+    let (response, code) = index
+        .search_post(json!({
+            "geoDistanceFrom": [43.2965, 5.3698],
+            "filter": "_geoDistance <= 0",
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(ids(&response), vec![4]);
+}
+
+#[actix_rt::test]
+async fn search_with_cursor() {
+    let temp = tempfile::tempdir().unwrap();
+    let mut options = default_settings(temp.path());
+    options.search_options.max_total_hits = Some(10);
+    let server = Server::new_with_options(options).await;
+    let index = server.index("test");
+
+    let documents: Vec<_> = (0..30)
+        .map(|id| json!({ "id": id, "rank": id % 4 }))
+        .collect();
+    index
+        .update_settings(json!({ "sortableAttributes": ["rank"] }))
+        .await;
+    index.add_documents(json!(documents), None).await;
+    index.wait_task(1).await;
+
+    // the pages go past the max total hits, the hits tying on their rank are ordered by id.
+    let mut ids = Vec::new();
+    let mut cursor = json!("");
+    loop {
+        let (response, code) = index
+            .search_post(json!({ "sort": ["rank:desc"], "limit": 7, "cursor": cursor }))
+            .await;
+        assert_eq!(code, 200, "{}", response);
+        assert_eq!(response["nbHits"], json!(30));
+        for hit in response["hits"].as_array().unwrap() {
+            ids.push((hit["rank"].as_u64().unwrap(), hit["id"].as_u64().unwrap()));
+        }
+        match response.get("nextCursor") {
+            Some(next) => cursor = next.clone(),
+            None => break,
+        }
+    }
+
+    let mut expected: Vec<_> = (0..30).map(|id| (id % 4, id)).collect();
+    expected.sort_by_key(|(rank, id)| (std::cmp::Reverse(*rank), *id));
+    assert_eq!(ids, expected);
+
+    // the documents added before the position of the cursor don't shift the next page, the ones
+    // added after it are in the next pages.
+    let (response, code) = index
+        .search_post(json!({ "sort": ["rank:desc"], "limit": 5, "cursor": "" }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let cursor = response["nextCursor"].clone();
+    index
+        .add_documents(
+            json!([{ "id": 30, "rank": 3 }, { "id": 31, "rank": 4 }]),
+            None,
+        )
+        .await;
+    index.wait_task(2).await;
+
+    let (response, code) = index
+        .search_post(json!({ "sort": ["rank:desc"], "limit": 5, "cursor": cursor }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let ids: Vec<_> = response["hits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![23, 27, 30, 2, 6]);
+
+    // a cursor can't be used with another sort.
+    let (response, code) = index
+        .search_post(json!({ "sort": ["rank:asc"], "cursor": cursor }))
+        .await;
+    assert_eq!(code, 400, "{}", response);
+}
+
+#[actix_rt::test]
+async fn search_with_cursor_and_q() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents: Vec<_> = (0..20)
+        .map(|id| match id % 3 {
+            0 => json!({ "id": id, "title": "the hobbit" }),
+            1 => json!({ "id": id, "title": "the hobit" }),
+            _ => json!({ "id": id, "title": "the dragon" }),
+        })
+        .collect();
+    index.add_documents(json!(documents), None).await;
+    index.wait_task(0).await;
+
+    // the hits are ordered by their ranking score, the exact matches before the typos, then by id.
+    let mut ids = Vec::new();
+    let mut cursor = json!("");
+    loop {
+        let (response, code) = index
+            .search_post(json!({ "q": "hobbit", "limit": 4, "cursor": cursor }))
+            .await;
+        assert_eq!(code, 200, "{}", response);
+        ids.extend(
+            response["hits"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|hit| hit["id"].as_u64().unwrap()),
+        );
+        match response.get("nextCursor") {
+            Some(next) => cursor = next.clone(),
+            None => break,
+        }
+    }
+
+    let expected: Vec<_> = (0..20)
+        .filter(|id| id % 3 == 0)
+        .chain((0..20).filter(|id| id % 3 == 1))
+        .collect();
+    assert_eq!(ids, expected);
+
+    // a cursor of a keyword search can't be used without `q`.
+    let (response, code) = index
+        .search_post(json!({ "q": "hobbit", "limit": 4, "cursor": "" }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let (response, code) = index
+        .search_post(json!({ "limit": 4, "cursor": response["nextCursor"] }))
+        .await;
+    assert_eq!(code, 400, "{}", response);
+}
+
+#[actix_rt::test]
+async fn search_geojson_format() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "city": "Lille", "_geo": { "lat": 50.6292, "lng": 3.0573 } },
+        { "id": 2, "city": "Paris", "_geo": { "lat": 48.8566, "lng": 2.3522 } },
+        { "id": 3, "city": "Nowhere" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(json!({ "format": "geoJson" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["type"], json!("FeatureCollection"));
+            assert!(response.get("hits").is_none());
+            // the other fields of the result are kept.
+            assert_eq!(response["nbHits"], json!(3));
+
+            let features = response["features"].as_array().unwrap();
+            assert_eq!(features.len(), 2);
+            assert_eq!(
+                features[0],
+                json!({
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [3.0573, 50.6292] },
+                    "properties": { "id": 1, "city": "Lille" },
+                })
+            );
+            assert_eq!(
+                features[1]["geometry"]["coordinates"],
+                json!([2.3522, 48.8566])
+            );
+        })
+        .await;
+
+    // without `_geo` in the hits there is no feature.
+    let (response, code) = index
+        .search_post(json!({ "format": "geoJson", "attributesToRetrieve": ["city"] }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["features"], json!([]));
+
+    let (response, code) = index.search_post(json!({ "format": "json" })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"].as_array().unwrap().len(), 3);
+}
+
+#[actix_rt::test]
+async fn search_max_highlights_per_field() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "hobbit", "overview": "hobbit, hobbit and hobbit meet a hobbit" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToHighlight": ["*"],
+                "maxHighlightsPerField": 2,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let formatted = &response["hits"][0]["_formatted"];
+                assert_eq!(
+                    formatted["overview"],
+                    json!("<em>hobbit</em>, <em>hobbit</em> and hobbit meet a hobbit")
+                );
+                // the cap applies to each field.
+                assert_eq!(formatted["title"], json!("<em>hobbit</em>"));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_matched_words_count() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "overview": "A hobbit, another hobbit and a dragon" },
+        { "id": 2, "title": "The Wizard", "overview": "A wizard" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit dragon ring",
+                "matchingStrategy": "last",
+                "showMatchedWordsCount": true,
+                "matches": true,
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hit = &response["hits"][0];
+                assert_eq!(hit["id"], json!(1));
+                assert_eq!(hit["_matchedWordsCount"], json!(2));
+                // the matches count the occurrences.
+                let matches: usize = hit["_matchesInfo"]
+                    .as_object()
+                    .unwrap()
+                    .values()
+                    .map(|matches| matches.as_array().unwrap().len())
+                    .sum();
+                assert_eq!(matches, 4);
+            },
+        )
+        .await;
+
+    let (response, code) = index.search_post(json!({ "q": "hobbit" })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response["hits"][0].get("_matchedWordsCount").is_none());
+}
+
+#[actix_rt::test]
+async fn search_attributes_selected_by_regex() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "Hobbit ring",
+            "price_US": 10,
+            "price_EU": 9,
+            "price_JP": 1100,
+            "description": "A hobbit and a ring"
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToRetrieve": ["id", "re:^price_"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0],
+                    json!({ "id": 1, "price_US": 10, "price_EU": 9, "price_JP": 1100 })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToRetrieve": ["*", "-re:^price_(US|EU)$"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hit = response["hits"][0].as_object().unwrap();
+                assert!(hit.contains_key("price_JP"));
+                assert!(!hit.contains_key("price_US"));
+                assert!(!hit.contains_key("price_EU"));
+            },
+        )
+        .await;
+
+    let (response, code) = index
+        .search_post(json!({
+            "q": "hobbit",
+            "attributesToRetrieve": ["id"],
+            "attributesToHighlight": ["re:^(title|description)$"],
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    let formatted = &response["hits"][0]["_formatted"];
+    assert_eq!(formatted["title"], json!("<em>Hobbit</em> ring"));
+    assert_eq!(
+        formatted["description"],
+        json!("A <em>hobbit</em> and a ring")
+    );
+}
+
+#[actix_rt::test]
+async fn search_show_normalized_query() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index.update_settings(json!({ "stopWords": ["the"] })).await;
+    index
+        .add_documents(json!([{ "id": 1, "title": "L'Étoile" }]), None)
+        .await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "q": "The Étoile", "showNormalizedQuery": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["normalizedQuery"], json!(["etoile"]));
+            },
+        )
+        .await;
+
+    let (response, code) = index.search_post(json!({ "q": "The Étoile" })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response.get("normalizedQuery").is_none());
+}
+
+#[actix_rt::test]
+async fn search_cropped_numbers_stay_numbers() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "publication_year": 1937, "price": 12.5 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToCrop": ["title", "publication_year", "price"]
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let formatted = &response["hits"][0]["_formatted"];
+                // the numbers only retrieved keep their string form.
+                assert_eq!(formatted["id"], json!("1"));
+                assert_eq!(formatted["publication_year"], json!(1937));
+                assert_eq!(formatted["price"], json!(12.5));
+                assert_eq!(formatted["title"], json!("The Hobbit"));
+            },
+        )
+        .await;
+
+    // the highlighted numbers are still formatted as strings.
+    index
+        .search(
+            json!({ "q": "1937", "attributesToCrop": ["*"], "attributesToHighlight": ["*"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let formatted = &response["hits"][0]["_formatted"];
+                assert_eq!(formatted["publication_year"], json!("<em>1937</em>"));
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_with_ranking_rules() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "rank": 3 },
+        { "id": 2, "title": "The Hobbit returns", "rank": 1 },
+        { "id": 3, "title": "A hobbit", "rank": 2 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    let ids = |response: &Value| -> Vec<Value> {
+        response["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["id"].clone())
+            .collect()
+    };
+
+    index
+        .search(
+            json!({ "q": "hobbit", "rankingRules": ["words", "rank:asc"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), vec![json!(2), json!(3), json!(1)]);
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "rankingRules": ["words", "rank:desc"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(ids(&response), vec![json!(1), json!(3), json!(2)]);
+            },
+        )
+        .await;
+
+    // the ranking rules of the index are unchanged.
+    let (settings, code) = index.settings().await;
+    assert_eq!(code, 200, "{}", settings);
+    assert_eq!(
+        settings["rankingRules"],
+        json!([
+            "words",
+            "typo",
+            "proximity",
+            "attribute",
+            "sort",
+            "exactness"
+        ])
+    );
+}
+
+#[actix_rt::test]
+async fn search_with_matches_only_hit_content() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "overview": "a hobbit goes on a journey" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToHighlight": ["title"],
+                "hitContent": "matches",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"],
+                    json!([{
+                        "id": 1,
+                        "_matchesInfo": {
+                            "title": [{ "start": 4, "length": 6 }],
+                            "overview": [{ "start": 2, "length": 6 }],
+                        },
+                    }])
+                );
+            },
+        )
+        .await;
+
+    // the matches are computed on the attributes to retrieve only, the id is always returned.
+    index
+        .search(
+            json!({
+                "q": "hobbit",
+                "attributesToRetrieve": ["title"],
+                "hitContent": "matches",
+            }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"],
+                    json!([{
+                        "id": 1,
+                        "_matchesInfo": { "title": [{ "start": 4, "length": 6 }] },
+                    }])
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_keeps_the_order_of_displayed_attributes() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "displayedAttributes": ["year", "title", "id"] }))
+        .await;
+    let documents = json!([
+        {
+            "id": 1,
+            "title": "The Hobbit",
+            "author": "Tolkien",
+            "year": 1937,
+        },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    for query in [
+        json!({ "q": "hobbit" }),
+        json!({ "q": "hobbit", "attributesToRetrieve": ["*"] }),
+    ] {
+        let (response, code) = index.search_post(query).await;
+        assert_eq!(code, 200, "{}", response);
+        let keys: Vec<_> = response["hits"][0].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["year", "title", "id"]);
+    }
+}
+
+#[actix_rt::test]
+async fn search_facets_distribution_with_pattern() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({
+            "filterableAttributes": ["attr_color", "attr_size", "price"]
+        }))
+        .await;
+    let documents = json!([
+        { "id": 1, "attr_color": "red", "attr_size": "m", "price": 10 },
+        { "id": 2, "attr_color": "blue", "attr_size": "m", "price": 20 },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "facetsDistribution": ["attr_*"] }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["facetsDistribution"],
+                    json!({
+                        "attr_color": { "blue": 1, "red": 1 },
+                        "attr_size": { "m": 2 },
+                    })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "facetsDistribution": ["*"] }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            let facets = response["facetsDistribution"].as_object().unwrap();
+            assert_eq!(facets.len(), 3);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_escape_html() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "<script>alert(1)</script> The <em>Hobbit</em>" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "attributesToHighlight": ["title"], "escapeHtml": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["hits"][0]["_formatted"]["title"],
+                    json!("&lt;script&gt;alert(1)&lt;/script&gt; The &lt;em&gt;<em>Hobbit</em>&lt;/em&gt;")
+                );
+                // the document itself is left untouched.
+                assert_eq!(
+                    response["hits"][0]["title"],
+                    json!("<script>alert(1)</script> The <em>Hobbit</em>")
+                );
+            },
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_candidates_count() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({ "filterableAttributes": ["genre"] }))
+        .await;
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit", "genre": "fantasy" },
+        { "id": 2, "title": "The Hobbit returns", "genre": "fantasy" },
+        { "id": 3, "title": "The Shining", "genre": "horror" },
+        { "id": 4, "title": "A hobbit in the dark", "genre": "horror" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    index
+        .search(
+            json!({ "filter": "genre = horror", "showCandidatesCount": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["candidatesCount"],
+                    json!({ "beforeFilter": 4, "afterFilter": 2 })
+                );
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit", "filter": "genre = horror", "showCandidatesCount": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(
+                    response["candidatesCount"],
+                    json!({ "beforeFilter": 3, "afterFilter": 1 })
+                );
+                assert_eq!(response["nbHits"], json!(1));
+            },
+        )
+        .await;
+
+    let (response, code) = index.search_post(json!({ "q": "hobbit" })).await;
+    assert_eq!(code, 200, "{}", response);
+    assert!(response.get("candidatesCount").is_none());
+}
+
+#[actix_rt::test]
+async fn search_with_default_search_query() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    index
+        .update_settings(json!({
+            "filterableAttributes": ["color"],
+            "defaultSearchQuery": {
+                "filter": "color = blue",
+                "attributesToRetrieve": ["id"],
+                "limit": 1,
+                "showTypos": true
+            }
+        }))
+        .await;
+    let documents = json!([
+        { "id": 1, "name": "shirt", "color": "blue" },
+        { "id": 2, "name": "shirt", "color": "blue" },
+        { "id": 3, "name": "shirt", "color": "red" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(1).await;
+
+    let (response, code) = index.settings().await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(
+        response["defaultSearchQuery"],
+        json!({
+            "filter": "color = blue",
+            "attributesToRetrieve": ["id"],
+            "limit": 1,
+            "showTypos": true
+        })
+    );
+
+    index
+        .search(json!({ "q": "shirt" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["hits"], json!([{ "id": 1, "_typos": 0 }]));
+            assert_eq!(response["nbHits"], 2);
+        })
+        .await;
+
+    // the parameters of the search override the default ones, even set to their default value,
+    // and both filters must match.
+    let (response, code) = index
+        .search_post(json!({
+            "q": "shirt",
+            "limit": 20,
+            "showTypos": false,
+            "attributesToRetrieve": ["id", "color"],
+            "filter": "id > 1"
+        }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"], json!([{ "id": 2, "color": "blue" }]));
+
+    // the page of the search replaces the limit of the default query.
+    let (response, code) = index
+        .search_post(json!({ "q": "shirt", "page": 1, "hitsPerPage": 2 }))
+        .await;
+    assert_eq!(code, 200, "{}", response);
+    assert_eq!(response["hits"].as_array().unwrap().len(), 2);
+
+    let (response, code) = index
+        .update_settings(json!({ "defaultSearchQuery": { "limit": "ten" } }))
+        .await;
+    assert_eq!(code, 400, "{}", response);
+
+    index
+        .update_settings(json!({ "defaultSearchQuery": null }))
+        .await;
+    index.wait_task(2).await;
+    index
+        .search(json!({ "q": "shirt" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["nbHits"], 3);
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_show_ranking_score() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit and the dragon" },
+        { "id": 2, "title": "The Hobit and the dragon" },
+        { "id": 3, "title": "The dragon" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit dragon", "showRankingScore": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hits = response["hits"].as_array().unwrap();
+                assert_eq!(hits.len(), 3);
+                let scores: Vec<f64> = hits
+                    .iter()
+                    .map(|hit| hit["_rankingScore"].as_f64().unwrap())
+                    .collect();
+                assert_eq!(hits[0]["id"], json!(1));
+                assert_eq!(scores[0], 1.0);
+                assert!(scores[0] > scores[1], "{:?}", scores);
+                assert!(scores[1] > scores[2], "{:?}", scores);
+                assert!(scores[2] >= 0.0, "{:?}", scores);
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "showRankingScore": true }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            let hits = response["hits"].as_array().unwrap();
+            assert!(hits.iter().all(|hit| hit["_rankingScore"] == json!(1.0)));
+        })
+        .await;
+
+    index
+        .search(json!({ "q": "hobbit dragon" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response["hits"][0].get("_rankingScore").is_none());
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_ranking_score_threshold() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit and the dragon" },
+        { "id": 2, "title": "The Hobit and the dragon" },
+        { "id": 3, "title": "The dragon" },
+        { "id": 4, "title": "The dragon returns" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit dragon", "rankingScoreThreshold": 0.0 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["hits"].as_array().unwrap().len(), 4);
+                assert_eq!(response["nbHits"], json!(4));
+            },
+        )
+        .await;
+
+    index
+        .search(
+            json!({ "q": "hobbit dragon", "rankingScoreThreshold": 1.0, "showRankingScore": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hits = response["hits"].as_array().unwrap();
+                assert_eq!(hits.len(), 1);
+                assert_eq!(hits[0]["id"], json!(1));
+                assert_eq!(hits[0]["_rankingScore"], json!(1.0));
+                assert_eq!(response["nbHits"], json!(1));
+            },
+        )
+        .await;
+
+    // both words are matched by the first two documents only.
+    index
+        .search(
+            json!({ "q": "hobbit dragon", "rankingScoreThreshold": 0.8, "showRankingScore": true }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hits = response["hits"].as_array().unwrap();
+                assert_eq!(hits.len(), 2);
+                assert!(hits
+                    .iter()
+                    .all(|hit| hit["_rankingScore"].as_f64().unwrap() >= 0.8));
+                assert_eq!(response["nbHits"], json!(2));
+            },
+        )
+        .await;
+
+    // the hits below the threshold are left out before the pagination.
+    index
+        .search(
+            json!({ "q": "hobbit dragon", "rankingScoreThreshold": 0.8, "offset": 1 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                let hits = response["hits"].as_array().unwrap();
+                assert_eq!(hits.len(), 1);
+                assert_eq!(hits[0]["id"], json!(2));
+            },
+        )
+        .await;
+
+    // all the hits of a placeholder search score 1.0.
+    index
+        .search(json!({ "rankingScoreThreshold": 1.0 }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert_eq!(response["nbHits"], json!(4));
+        })
+        .await;
+}
+
+#[actix_rt::test]
+async fn search_unfiltered_hits() {
+    let server = Server::new().await;
+    let index = server.index("test");
+
+    let documents = json!([
+        { "id": 1, "title": "The Hobbit and the dragon" },
+        { "id": 2, "title": "The Hobit and the dragon" },
+        { "id": 3, "title": "The dragon" },
+    ]);
+    index.add_documents(documents, None).await;
+    index.wait_task(0).await;
+
+    index
+        .search(
+            json!({ "q": "hobbit dragon", "rankingScoreThreshold": 0.8 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["nbHits"], json!(2));
+                assert_eq!(response["unfilteredHits"], json!(3));
+            },
+        )
+        .await;
+
+    // the field is omitted when the threshold leaves no hit out.
+    index
+        .search(
+            json!({ "q": "hobbit dragon", "rankingScoreThreshold": 0.0 }),
+            |response, code| {
+                assert_eq!(code, 200, "{}", response);
+                assert_eq!(response["nbHits"], json!(3));
+                assert!(response.get("unfilteredHits").is_none());
+            },
+        )
+        .await;
+
+    index
+        .search(json!({ "q": "hobbit dragon" }), |response, code| {
+            assert_eq!(code, 200, "{}", response);
+            assert!(response.get("unfilteredHits").is_none());
+        })
+        .await;
+}
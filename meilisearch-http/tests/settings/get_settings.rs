@@ -24,6 +24,8 @@ static DEFAULT_SETTINGS_VALUES: Lazy<HashMap<&'static str, Value>> = Lazy::new(|
     );
     map.insert("stop_words", json!([]));
     map.insert("synonyms", json!({}));
+    map.insert("default_search_query", json!(Value::Null));
+    map.insert("max_total_hits", json!(Value::Null));
     map
 });
 
@@ -43,7 +45,7 @@ async fn get_settings() {
     let (response, code) = index.settings().await;
     assert_eq!(code, 200);
     let settings = response.as_object().unwrap();
-    assert_eq!(settings.keys().len(), 9);
+    assert_eq!(settings.keys().len(), 11);
     assert_eq!(settings["displayedAttributes"], json!(["*"]));
     assert_eq!(settings["searchableAttributes"], json!(["*"]));
     assert_eq!(settings["filterableAttributes"], json!([]));
@@ -61,6 +63,8 @@ async fn get_settings() {
         ])
     );
     assert_eq!(settings["stopWords"], json!([]));
+    assert_eq!(settings["defaultSearchQuery"], json!(null));
+    assert_eq!(settings["maxTotalHits"], json!(null));
 }
 
 #[actix_rt::test]
@@ -264,7 +268,9 @@ test_setting_routes!(
     distinct_attribute,
     stop_words,
     ranking_rules,
-    synonyms
+    synonyms,
+    default_search_query,
+    max_total_hits
 );
 
 #[actix_rt::test]
@@ -292,6 +298,34 @@ async fn error_set_invalid_ranking_rules() {
     assert_eq!(response["error"], expected_error);
 }
 
+#[actix_rt::test]
+async fn error_set_zero_max_total_hits() {
+    let server = Server::new().await;
+    let index = server.index("test");
+    index.create(None).await;
+
+    let expected_error = json!({
+        "message": "Invalid settings: `maxTotalHits` must be greater than 0.",
+        "code": "invalid_settings",
+        "type": "invalid_request",
+        "link": "https://docs.meilisearch.com/errors#invalid_settings"
+    });
+
+    let (response, code) = index.update_settings(json!({ "maxTotalHits": 0 })).await;
+    assert_eq!(code, 400, "{}", response);
+    assert_eq!(response, expected_error);
+
+    let (response, code) = server
+        .service
+        .post("/indexes/test/settings/max-total-hits", json!(0))
+        .await;
+    assert_eq!(code, 400, "{}", response);
+    assert_eq!(response, expected_error);
+
+    let (response, code) = index.update_settings(json!({ "maxTotalHits": 1 })).await;
+    assert_eq!(code, 202, "{}", response);
+}
+
 #[actix_rt::test]
 async fn set_and_reset_distinct_attribute_with_dedicated_route() {
     let server = Server::new().await;
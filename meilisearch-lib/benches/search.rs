@@ -0,0 +1,142 @@
+use std::io::{Seek, SeekFrom};
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use meilisearch_lib::document_formats::read_json;
+use meilisearch_lib::index::{Index, SearchQuery};
+use meilisearch_lib::milli::documents::DocumentBatchReader;
+use meilisearch_lib::milli::tokenizer::{Analyzer, AnalyzerConfig};
+use meilisearch_lib::milli::update::{IndexDocuments, IndexDocumentsConfig, IndexerConfig};
+use serde_json::{json, Map, Value};
+use tempfile::TempDir;
+use uuid::Uuid;
+
+const TEXT: &str = "in a hole in the ground there lived a hobbit, not a nasty dirty wet hole \
+    filled with the ends of worms and an oozy smell";
+
+/// Returns an index holding `documents`.
+fn index_with_documents(documents: &[Value]) -> (TempDir, Index) {
+    let dir = tempfile::tempdir().unwrap();
+    let index = Index::open(
+        dir.path(),
+        1024 * 1024 * 1024,
+        Uuid::new_v4(),
+        Arc::new(IndexerConfig::default()),
+    )
+    .unwrap();
+
+    let mut documents_file = tempfile::tempfile().unwrap();
+    read_json(json!(documents).to_string().as_bytes(), &mut documents_file).unwrap();
+    documents_file.seek(SeekFrom::Start(0)).unwrap();
+    let reader = DocumentBatchReader::from_reader(documents_file).unwrap();
+
+    let mut txn = index.write_txn().unwrap();
+    let mut builder = IndexDocuments::new(
+        &mut txn,
+        &index,
+        index.indexer_config.as_ref(),
+        IndexDocumentsConfig::default(),
+        |_| (),
+    )
+    .unwrap();
+    builder.add_documents(reader).unwrap();
+    builder.execute().unwrap();
+    txn.commit().unwrap();
+
+    (dir, index)
+}
+
+/// Returns `count` documents of `fields` text fields, named `field_0`, `field_1` and so on.
+fn documents(count: usize, fields: usize) -> Vec<Value> {
+    (0..count)
+        .map(|id| {
+            let mut document = Map::new();
+            document.insert("id".to_string(), json!(id));
+            for field in 0..fields {
+                document.insert(format!("field_{}", field), json!(TEXT));
+            }
+            Value::Object(document)
+        })
+        .collect()
+}
+
+/// Compares building an analyzer for each search, as it was done before the analyzer was shared
+/// by the searches, with reusing the same analyzer.
+fn shared_analyzer(c: &mut Criterion) {
+    let q = "the fellowship of the ring";
+    let stop_words = fst::Set::default();
+    let analyzer = |stop_words| {
+        let mut config = AnalyzerConfig::<Vec<u8>>::default();
+        config.stop_words(stop_words);
+        Analyzer::new(config)
+    };
+
+    let mut group = c.benchmark_group("analyzer");
+    group.bench_function("per search", |b| {
+        b.iter(|| analyzer(&stop_words).analyze(black_box(q)).tokens().count())
+    });
+    let shared = analyzer(&stop_words);
+    group.bench_function("shared", |b| {
+        b.iter(|| shared.analyze(black_box(q)).tokens().count())
+    });
+    group.finish();
+}
+
+/// Searches pages of growing sizes, with and without formatting their 10 fields. The pages
+/// smaller than `PARALLEL_FORMATTING_MIN_HITS` are formatted serially and the others in parallel,
+/// the formatting time per hit on both sides of the threshold shows whether it is well chosen.
+/// It must be measured on a machine with several cores.
+fn parallel_formatting(c: &mut Criterion) {
+    let (_dir, index) = index_with_documents(&documents(1000, 10));
+    let fields: Vec<_> = (0..10).map(|field| format!("field_{}", field)).collect();
+
+    let mut group = c.benchmark_group("formatting");
+    for hits in [8, 16, 32, 64, 128, 256, 1000] {
+        let unformatted = json!({ "q": "hobbit", "limit": hits });
+        let formatted = json!({
+            "q": "hobbit",
+            "limit": hits,
+            "attributesToHighlight": fields,
+            "attributesToCrop": fields,
+            "cropLength": 10,
+        });
+
+        group.throughput(Throughput::Elements(hits as u64));
+        for (name, query) in [("unformatted", unformatted), ("formatted", formatted)] {
+            let query: SearchQuery = serde_json::from_value(query).unwrap();
+            group.bench_with_input(BenchmarkId::new(name, hits), &query, |b, query| {
+                b.iter(|| index.perform_search(query.clone()).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Searches documents of 200 fields, formatting 2 of them. The documents are formatted without
+/// being cloned, so the formatting time must not grow with their width.
+fn format_wide_documents(c: &mut Criterion) {
+    let (_dir, index) = index_with_documents(&documents(1000, 200));
+    let query: SearchQuery = serde_json::from_value(json!({
+        "q": "hobbit",
+        "limit": 1000,
+        "attributesToHighlight": ["field_0", "field_1"],
+        "attributesToCrop": ["field_0", "field_1"],
+        "cropLength": 10,
+    }))
+    .unwrap();
+
+    let mut group = c.benchmark_group("wide documents");
+    group.throughput(Throughput::Elements(1000));
+    group.bench_function("format 2 of 200 fields", |b| {
+        b.iter(|| index.perform_search(query.clone()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    shared_analyzer,
+    parallel_formatting,
+    format_wide_documents
+);
+criterion_main!(benches);
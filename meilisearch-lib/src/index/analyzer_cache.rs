@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use milli::tokenizer::{Analyzer, AnalyzerConfig};
+use parking_lot::Mutex;
+
+use super::error::Result;
+
+/// The analyzer of an index having stop words, kept between the searches since building an
+/// analyzer is costly.
+///
+/// The analyzer is rebuilt whenever the stop words read by a search differ from the ones it was
+/// built with, a search never uses the analyzer of stop words it didn't read.
+#[derive(Default)]
+pub struct AnalyzerCache {
+    analyzer: Mutex<Option<Arc<StopWordsAnalyzer>>>,
+}
+
+impl AnalyzerCache {
+    /// Returns the analyzer of `stop_words`, building it if the cached one has other stop words.
+    pub fn get(&self, stop_words: &fst::Set<&[u8]>) -> Result<Arc<StopWordsAnalyzer>> {
+        let mut analyzer = self.analyzer.lock();
+        match &*analyzer {
+            Some(cached)
+                if cached.stop_words.as_fst().as_bytes() == stop_words.as_fst().as_bytes() =>
+            {
+                Ok(cached.clone())
+            }
+            _ => {
+                let stop_words = fst::Set::new(stop_words.as_fst().as_bytes().to_vec())?;
+                let built = Arc::new(StopWordsAnalyzer::new(stop_words));
+                *analyzer = Some(built.clone());
+                Ok(built)
+            }
+        }
+    }
+}
+
+/// An analyzer owning the stop words it is built with.
+pub struct StopWordsAnalyzer {
+    // Declared before `stop_words`, so that it is dropped before the stop words it borrows.
+    analyzer: Analyzer<'static, Vec<u8>>,
+    stop_words: Box<fst::Set<Vec<u8>>>,
+}
+
+impl StopWordsAnalyzer {
+    pub fn new(stop_words: fst::Set<Vec<u8>>) -> Self {
+        let stop_words = Box::new(stop_words);
+        // SAFETY: the stop words are boxed, they don't move with `Self`. They are never mutated
+        // and are dropped after the analyzer, which is only lent out for the lifetime of `self`.
+        let borrowed: &'static fst::Set<Vec<u8>> = unsafe { &*(&*stop_words as *const _) };
+        Self {
+            analyzer: stop_words_analyzer(borrowed),
+            stop_words,
+        }
+    }
+
+    pub fn analyzer(&self) -> &Analyzer<'_, Vec<u8>> {
+        &self.analyzer
+    }
+}
+
+/// Returns the analyzer of an index having `stop_words`. The stop words are neither matched nor
+/// highlighted, the shared `ANALYZER` of the searches being used when the index has none.
+pub fn stop_words_analyzer(stop_words: &fst::Set<Vec<u8>>) -> Analyzer<Vec<u8>> {
+    let mut config = AnalyzerConfig::default();
+    config.stop_words(stop_words);
+    Analyzer::new(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stop_words(words: &[&str]) -> fst::Set<Vec<u8>> {
+        fst::Set::from_iter(words).unwrap()
+    }
+
+    #[test]
+    fn analyzer_is_rebuilt_when_the_stop_words_change() {
+        let cache = AnalyzerCache::default();
+        let the = stop_words(&["the"]);
+        let the = fst::Set::new(the.as_fst().as_bytes()).unwrap();
+
+        let first = cache.get(&the).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.get(&the).unwrap()));
+        let tokens: Vec<_> = first.analyzer().analyze("the hobbit").tokens().collect();
+        assert!(tokens[0].is_stopword());
+
+        let a = stop_words(&["a"]);
+        let a = fst::Set::new(a.as_fst().as_bytes()).unwrap();
+        let second = cache.get(&a).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        let tokens: Vec<_> = second.analyzer().analyze("the hobbit").tokens().collect();
+        assert!(!tokens[0].is_stopword());
+    }
+}
@@ -0,0 +1,360 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use fst::automaton::Str;
+use fst::{Automaton, IntoStreamer, Set, Streamer};
+use milli::heed::RoTxn;
+use milli::tokenizer::TokenKind;
+
+use super::error::Result;
+use super::index::Index;
+use super::search::ANALYZER;
+
+/// The words shorter than this are never corrected.
+const MIN_CORRECTED_WORD_LEN: usize = 3;
+/// The words at least this long are corrected with up to two typos, the shorter ones with one.
+const TWO_TYPOS_WORD_LEN: usize = 5;
+
+impl Index {
+    /// Returns up to `limit` words of the index completing the last word of `prefix`, from the
+    /// most to the least frequent.
+    ///
+    /// Only the dictionary of the index is read, no document is retrieved. Nothing is completed
+    /// when `prefix` doesn't end with a word.
+    pub fn complete_term(&self, prefix: &str, limit: usize) -> Result<Vec<String>> {
+        let rtxn = self.read_txn()?;
+        let words = self.words_fst(&rtxn)?;
+        complete_term(&words, prefix, limit, |word| {
+            self.word_frequency(&rtxn, word)
+        })
+    }
+
+    /// Returns `q` with its words missing from the index replaced by their closest word of the
+    /// index, or `None` if no word of `q` could be corrected.
+    pub(super) fn suggest_query(&self, rtxn: &RoTxn, q: &str) -> Result<Option<String>> {
+        let words = self.words_fst(rtxn)?;
+        suggest_query(&words, q, |word| self.word_frequency(rtxn, word))
+    }
+
+    /// Returns the number of documents containing `word`.
+    fn word_frequency(&self, rtxn: &RoTxn, word: &str) -> Result<u64> {
+        Ok(self
+            .word_docids
+            .get(rtxn, word)?
+            .map_or(0, |docids| docids.len()))
+    }
+}
+
+fn complete_term<A: AsRef<[u8]>>(
+    words: &Set<A>,
+    prefix: &str,
+    limit: usize,
+    frequency: impl Fn(&str) -> Result<u64>,
+) -> Result<Vec<String>> {
+    let analyzed = ANALYZER.analyze(prefix);
+    let last_word = match analyzed.tokens().last() {
+        Some(token) if token.is_word() || token.is_stopword() => token,
+        _ => return Ok(Vec::new()),
+    };
+
+    // The heap keeps the `limit` best completions, its top being the worst of them.
+    let mut completions = BinaryHeap::with_capacity(limit + 1);
+    let mut stream = words
+        .search(Str::new(last_word.text()).starts_with())
+        .into_stream();
+    while let Some(word) = stream.next() {
+        let word = match std::str::from_utf8(word) {
+            Ok(word) => word,
+            Err(_) => continue,
+        };
+
+        completions.push((Reverse(frequency(word)?), word.to_string()));
+        if completions.len() > limit {
+            completions.pop();
+        }
+    }
+
+    Ok(completions
+        .into_sorted_vec()
+        .into_iter()
+        .map(|(_, word)| word)
+        .collect())
+}
+
+fn suggest_query<A: AsRef<[u8]>>(
+    words: &Set<A>,
+    q: &str,
+    frequency: impl Fn(&str) -> Result<u64>,
+) -> Result<Option<String>> {
+    let analyzed = ANALYZER.analyze(q);
+    let tokens: Vec<_> = analyzed.reconstruct().collect();
+
+    let mut corrected = false;
+    let mut suggestion = String::with_capacity(q.len());
+    for (i, (original, token)) in tokens.iter().enumerate() {
+        // Like in milli, the last word of the query is a prefix unless it is followed by a
+        // separator.
+        let is_prefix = i + 1 == tokens.len();
+        let correction = match token.kind {
+            TokenKind::Word if !is_known(words, token.text(), is_prefix) => {
+                correct_word(words, token.text(), &frequency)?
+            }
+            _ => None,
+        };
+
+        match correction {
+            Some(word) => {
+                corrected = true;
+                suggestion.push_str(&word);
+            }
+            None => suggestion.push_str(original),
+        }
+    }
+
+    if corrected {
+        Ok(Some(suggestion))
+    } else {
+        Ok(None)
+    }
+}
+
+fn is_known<A: AsRef<[u8]>>(words: &Set<A>, word: &str, is_prefix: bool) -> bool {
+    if is_prefix {
+        let mut stream = words.search(Str::new(word).starts_with()).into_stream();
+        stream.next().is_some()
+    } else {
+        words.contains(word)
+    }
+}
+
+/// Returns the closest word of the index to `word`, the most frequent one between words as close,
+/// or `None` if no word is close enough.
+fn correct_word<A: AsRef<[u8]>>(
+    words: &Set<A>,
+    word: &str,
+    frequency: impl Fn(&str) -> Result<u64>,
+) -> Result<Option<String>> {
+    let len = word.chars().count();
+    if len < MIN_CORRECTED_WORD_LEN {
+        return Ok(None);
+    }
+
+    let max_typos = if len < TWO_TYPOS_WORD_LEN { 1 } else { 2 };
+    let automaton = Levenshtein::new(word, max_typos);
+    let mut stream = words.search(&automaton).into_stream();
+
+    let mut best: Option<((usize, Reverse<u64>), String)> = None;
+    while let Some(candidate) = stream.next() {
+        let candidate = match std::str::from_utf8(candidate) {
+            Ok(candidate) => candidate,
+            Err(_) => continue,
+        };
+
+        let rank = (
+            automaton.distance(candidate),
+            Reverse(frequency(candidate)?),
+        );
+        if best.as_ref().map_or(true, |(best, _)| rank < *best) {
+            best = Some((rank, candidate.to_string()));
+        }
+    }
+
+    Ok(best.map(|(_, word)| word))
+}
+
+/// Matches the words within `max_distance` edits of a word.
+///
+/// The distance is computed on the bytes of the words, a non-ASCII character thus counts as many
+/// typos as it has bytes.
+pub(super) struct Levenshtein<'a> {
+    word: &'a [u8],
+    max_distance: usize,
+}
+
+impl<'a> Levenshtein<'a> {
+    pub(super) fn new(word: &'a str, max_distance: usize) -> Self {
+        Self {
+            word: word.as_bytes(),
+            max_distance,
+        }
+    }
+
+    /// Returns the distance between the word and `other`, or `usize::MAX` if `other` was found to
+    /// be too far from the word before its end.
+    pub(super) fn distance(&self, other: &str) -> usize {
+        other
+            .bytes()
+            .fold(self.start(), |state, byte| self.accept(&state, byte))
+            .map_or(usize::MAX, |row| row[row.len() - 1])
+    }
+
+    /// Returns the smallest distance between the word and a prefix of `other`, the word being
+    /// considered as the prefix of a longer word. It can exceed the maximum distance.
+    pub(super) fn prefix_distance(&self, other: &str) -> usize {
+        let mut state = self.start();
+        let mut distance = self.word.len();
+        for byte in other.bytes() {
+            state = self.accept(&state, byte);
+            match state {
+                Some(ref row) => distance = distance.min(row[row.len() - 1]),
+                None => break,
+            }
+        }
+        distance
+    }
+}
+
+impl Automaton for Levenshtein<'_> {
+    /// The last row of the Levenshtein matrix, or `None` once the distance exceeds the maximum.
+    type State = Option<Vec<usize>>;
+
+    fn start(&self) -> Self::State {
+        Some((0..=self.word.len()).collect())
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        state
+            .as_ref()
+            .map_or(false, |row| row[row.len() - 1] <= self.max_distance)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some()
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        let row = state.as_ref()?;
+        let mut next = Vec::with_capacity(row.len());
+        next.push(row[0] + 1);
+        for (i, &expected) in self.word.iter().enumerate() {
+            let substitution = row[i] + (expected != byte) as usize;
+            next.push(substitution.min(row[i + 1] + 1).min(next[i] + 1));
+        }
+
+        let min_distance = next.iter().copied().min().unwrap_or_default();
+        if min_distance <= self.max_distance {
+            Some(next)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::index::search::test::index_with_documents;
+
+    fn words(words: &[(&str, u64)]) -> (Set<Vec<u8>>, HashMap<String, u64>) {
+        let mut sorted: Vec<_> = words.iter().map(|(word, _)| *word).collect();
+        sorted.sort_unstable();
+        let set = Set::from_iter(sorted).unwrap();
+        let frequencies = words
+            .iter()
+            .map(|(word, frequency)| (word.to_string(), *frequency))
+            .collect();
+        (set, frequencies)
+    }
+
+    #[test]
+    fn completions_are_ranked_by_frequency() {
+        let (set, frequencies) = words(&[
+            ("hobbit", 3),
+            ("hobbits", 5),
+            ("hobby", 3),
+            ("hole", 8),
+            ("the", 10),
+        ]);
+        let frequency = |word: &str| Ok(frequencies[word]);
+
+        let completions = complete_term(&set, "the hob", 10, frequency).unwrap();
+        assert_eq!(completions, ["hobbits", "hobbit", "hobby"]);
+
+        let completions = complete_term(&set, "The HOB", 2, frequency).unwrap();
+        assert_eq!(completions, ["hobbits", "hobbit"]);
+
+        assert!(complete_term(&set, "the hob", 0, frequency)
+            .unwrap()
+            .is_empty());
+        assert!(complete_term(&set, "the hob ", 10, frequency)
+            .unwrap()
+            .is_empty());
+        assert!(complete_term(&set, "dragon", 10, frequency)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn complete_term_over_an_index() {
+        let (_dir, index) = index_with_documents(
+            json!([
+                { "id": 1, "title": "The Hobbit" },
+                { "id": 2, "title": "The Hobbit: An Unexpected Journey" },
+                { "id": 3, "title": "Hobby Horse" },
+                { "id": 4, "title": "Hocus Pocus" },
+            ]),
+            Default::default(),
+        );
+
+        let completions = index.complete_term("the hob", 10).unwrap();
+        assert_eq!(completions, ["hobbit", "hobby"]);
+        assert_eq!(index.complete_term("ho", 1).unwrap(), ["hobbit"]);
+        assert!(index.complete_term("dragon", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        let automaton = Levenshtein::new("hobbit", 2);
+        assert_eq!(automaton.distance("hobbit"), 0);
+        assert_eq!(automaton.distance("hobit"), 1);
+        assert_eq!(automaton.distance("habbits"), 2);
+        assert_eq!(automaton.distance("rabbit"), 2);
+        assert_eq!(automaton.distance("hob"), 3);
+        assert_eq!(automaton.distance("dragon"), usize::MAX);
+
+        let automaton = Levenshtein::new("hobit", 1);
+        assert_eq!(automaton.prefix_distance("hobits"), 0);
+        assert_eq!(automaton.prefix_distance("hobbits"), 1);
+        assert_eq!(automaton.prefix_distance("hob"), 2);
+        assert_eq!(automaton.prefix_distance("dragon"), 5);
+    }
+
+    #[test]
+    fn misspelled_query_is_corrected() {
+        let (set, frequencies) = words(&[
+            ("hobbit", 3),
+            ("habit", 1),
+            ("rabbit", 5),
+            ("the", 10),
+            ("lord", 2),
+        ]);
+        let frequency = |word: &str| Ok(frequencies[word]);
+
+        let suggestion = suggest_query(&set, "the hobbbit ", frequency).unwrap();
+        assert_eq!(suggestion.as_deref(), Some("the hobbit "));
+
+        // between two words as close, the most frequent one is suggested.
+        let suggestion = suggest_query(&set, "Lord robbit ", frequency).unwrap();
+        assert_eq!(suggestion.as_deref(), Some("Lord rabbit "));
+
+        let suggestion = suggest_query(&set, "lors", frequency).unwrap();
+        assert_eq!(suggestion.as_deref(), Some("lord"));
+    }
+
+    #[test]
+    fn known_query_is_not_corrected() {
+        let (set, frequencies) = words(&[("hobbit", 3), ("the", 10)]);
+        let frequency = |word: &str| Ok(frequencies[word]);
+
+        assert_eq!(suggest_query(&set, "the hobbit", frequency).unwrap(), None);
+        // the last word is a prefix.
+        assert_eq!(suggest_query(&set, "the hob", frequency).unwrap(), None);
+        // words too far from the index are left as they are.
+        assert_eq!(suggest_query(&set, "the dragon", frequency).unwrap(), None);
+        assert_eq!(suggest_query(&set, "", frequency).unwrap(), None);
+    }
+}
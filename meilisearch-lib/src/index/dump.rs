@@ -10,7 +10,7 @@ use milli::update::{IndexDocumentsConfig, IndexerConfig};
 use serde::{Deserialize, Serialize};
 
 use crate::document_formats::read_ndjson;
-use crate::index::updates::apply_settings_to_builder;
+use crate::index::updates::{apply_settings_to_builder, store_search_settings};
 
 use super::error::Result;
 use super::{index::Index, Settings, Unchecked};
@@ -119,6 +119,7 @@ impl Index {
         apply_settings_to_builder(&settings, &mut builder);
 
         builder.execute(|_| ())?;
+        store_search_settings(&index, &mut txn, &settings)?;
 
         let document_file_path = src.as_ref().join(DATA_FILE_NAME);
         let reader = BufReader::new(File::open(&document_file_path)?);
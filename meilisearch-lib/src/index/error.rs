@@ -13,9 +13,13 @@ pub enum IndexError {
     Internal(Box<dyn Error + Send + Sync + 'static>),
     #[error("Document `{0}` not found.")]
     DocumentNotFound(String),
+    #[error("Invalid settings: {0}")]
+    InvalidSettings(String),
     #[error("{0}")]
     Facet(#[from] FacetError),
     #[error("{0}")]
+    Search(#[from] SearchError),
+    #[error("{0}")]
     Milli(#[from] milli::Error),
 }
 
@@ -33,7 +37,9 @@ impl ErrorCode for IndexError {
         match self {
             IndexError::Internal(_) => Code::Internal,
             IndexError::DocumentNotFound(_) => Code::DocumentNotFound,
+            IndexError::InvalidSettings(_) => Code::InvalidSettings,
             IndexError::Facet(e) => e.error_code(),
+            IndexError::Search(e) => e.error_code(),
             IndexError::Milli(e) => MilliError(e).error_code(),
         }
     }
@@ -43,12 +49,84 @@ impl ErrorCode for IndexError {
 pub enum FacetError {
     #[error("Invalid syntax for the filter parameter: `expected {}, found: {1}`.", .0.join(", "))]
     InvalidExpression(&'static [&'static str], Value),
+    #[error(
+        "Attributes `{}` of `facetsDistribution` are not filterable. Available filterable attributes are: `{}`.",
+        .0.join(", "),
+        .1.join(", ")
+    )]
+    InvalidFacetsDistribution(Vec<String>, Vec<String>),
+    #[error(
+        "Attribute `{0}` is not filterable, its values cannot be searched. Available filterable attributes are: `{}`.",
+        .1.join(", ")
+    )]
+    InvalidFacetSearch(String, Vec<String>),
 }
 
 impl ErrorCode for FacetError {
     fn error_code(&self) -> Code {
         match self {
             FacetError::InvalidExpression(_, _) => Code::Filter,
+            FacetError::InvalidFacetsDistribution(_, _) | FacetError::InvalidFacetSearch(_, _) => {
+                Code::Facet
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SearchError {
+    #[error("The `page` and `hitsPerPage` parameters cannot be used together with the `offset` and `limit` parameters.")]
+    MixedPagination,
+    #[error("The `cursor` is invalid or was returned by a search with a different `sort`.")]
+    InvalidCursor,
+    #[error("The `cursor` parameter cannot be used with `{0}`.")]
+    CursorNotSupported(&'static str),
+    #[error("The `page` parameter must be greater than 0.")]
+    InvalidPage,
+    #[error("The `rankingScoreThreshold` parameter must be between 0.0 and 1.0, found `{0}`.")]
+    InvalidRankingScoreThreshold(f64),
+    #[error("Invalid coordinates in `{0}`, the latitude and longitude must be valid numbers.")]
+    InvalidGeoPoint(String),
+    #[error("Invalid `_geoBoundingBox` expression `{0}`. Expected `_geoBoundingBox([topRightLat, topRightLng], [bottomLeftLat, bottomLeftLng])` with valid coordinates, alone in its filter string. Use the array syntax to combine it with other filters.")]
+    InvalidGeoBoundingBox(String),
+    #[error("Invalid `_geoDistance` expression `{0}`. Expected `_geoDistance` followed by `<`, `<=`, `>` or `>=` and a number, alone in its filter string and outside of any OR group.")]
+    InvalidGeoDistance(String),
+    #[error("Filtering on `_geoDistance` requires a `_geoPoint` in `sort` or the `geoDistanceFrom` parameter.")]
+    MissingGeoDistanceBase,
+    #[error("Filtering on `_geoDistance` requires the `_geo` attribute to be filterable.")]
+    GeoDistanceNotFilterable,
+    #[error("Attribute `{1}` of `{0}` does not exist or is not displayed.")]
+    UnknownAttribute(&'static str, String),
+    #[error("Invalid attribute regex `{0}`: {1}")]
+    InvalidAttributeRegex(String, String),
+    #[error(
+        "The `offset` ({0}) and `limit` ({1}) go past the {2} documents that a search can return."
+    )]
+    HardLimitExceeded(usize, usize, usize),
+    #[error("The `{0}` typo tolerance setting of the index cannot be overridden by the `typoTolerance` parameter, only `enabled` can.")]
+    TypoToleranceOverride(&'static str),
+    #[error("The search exceeded its timeout of {0}ms and was aborted after {1}ms while {2}.")]
+    Timeout(u64, u128, &'static str),
+}
+
+impl ErrorCode for SearchError {
+    fn error_code(&self) -> Code {
+        match self {
+            SearchError::MixedPagination
+            | SearchError::InvalidCursor
+            | SearchError::CursorNotSupported(_)
+            | SearchError::InvalidPage
+            | SearchError::InvalidRankingScoreThreshold(_)
+            | SearchError::UnknownAttribute(_, _)
+            | SearchError::InvalidAttributeRegex(_, _)
+            | SearchError::TypoToleranceOverride(_)
+            | SearchError::HardLimitExceeded(_, _, _) => Code::BadRequest,
+            SearchError::InvalidGeoPoint(_) => Code::Sort,
+            SearchError::InvalidGeoBoundingBox(_)
+            | SearchError::InvalidGeoDistance(_)
+            | SearchError::MissingGeoDistanceBase
+            | SearchError::GeoDistanceNotFilterable => Code::Filter,
+            SearchError::Timeout(_, _, _) => Code::SearchTimeout,
         }
     }
 }
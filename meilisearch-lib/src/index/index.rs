@@ -6,6 +6,7 @@ use std::path::Path;
 use std::sync::Arc;
 
 use fst::IntoStreamer;
+use milli::heed::types::{SerdeJson, Str};
 use milli::heed::{EnvOpenOptions, RoTxn};
 use milli::update::{IndexerConfig, Setting};
 use milli::{obkv_to_json, FieldDistribution, FieldId};
@@ -16,10 +17,14 @@ use uuid::Uuid;
 
 use crate::EnvSizer;
 
+use super::analyzer_cache::AnalyzerCache;
 use super::error::IndexError;
 use super::error::Result;
-use super::updates::{MinWordLengthTypoSetting, TypoSettings};
-use super::{Checked, Settings};
+use super::search_cache::SearchCache;
+use super::updates::{
+    MinWordLengthTypoSetting, TypoSettings, DEFAULT_SEARCH_QUERY_KEY, MAX_TOTAL_HITS_KEY,
+};
+use super::{Checked, DefaultSearchQuery, Settings};
 
 pub type Document = Map<String, Value>;
 
@@ -72,6 +77,14 @@ pub struct Index {
     pub inner: Arc<milli::Index>,
     #[derivative(Debug = "ignore")]
     pub indexer_config: Arc<IndexerConfig>,
+    #[derivative(Debug = "ignore")]
+    pub search_cache: Option<Arc<SearchCache>>,
+    /// The analyzer built with the stop words of the index, shared by its searches.
+    #[derivative(Debug = "ignore")]
+    pub analyzer_cache: Arc<AnalyzerCache>,
+    /// The maximum number of hits the searches can page through and report when the index has
+    /// no `maxTotalHits` setting.
+    pub default_max_total_hits: Option<usize>,
 }
 
 impl Deref for Index {
@@ -98,9 +111,33 @@ impl Index {
             inner,
             uuid,
             indexer_config: update_handler,
+            search_cache: None,
+            analyzer_cache: Arc::default(),
+            default_max_total_hits: None,
         })
     }
 
+    /// Caches the results of the last `capacity` different searches, the cache is disabled when
+    /// `capacity` is `0`.
+    pub fn with_search_cache(mut self, capacity: usize) -> Self {
+        self.search_cache = (capacity > 0).then(|| Arc::new(SearchCache::new(capacity)));
+        self
+    }
+
+    /// Caps the number of hits the searches can page through and report when the index has no
+    /// `maxTotalHits` setting, they are not capped by default.
+    pub fn with_max_total_hits(mut self, max_total_hits: Option<usize>) -> Self {
+        self.default_max_total_hits = max_total_hits;
+        self
+    }
+
+    /// Empties the search cache, it must be called after each write to the index.
+    pub(super) fn clear_search_cache(&self) {
+        if let Some(ref cache) = self.search_cache {
+            cache.clear();
+        }
+    }
+
     /// Asynchronously close the underlying index
     pub fn close(self) {
         self.inner.as_ref().clone().prepare_for_closing();
@@ -129,6 +166,27 @@ impl Index {
         self.uuid
     }
 
+    /// Returns the search query completing all the searches of the index, if any.
+    pub fn default_search_query(&self, txn: &RoTxn) -> Result<Option<DefaultSearchQuery>> {
+        Ok(self
+            .main
+            .get::<_, Str, SerdeJson<DefaultSearchQuery>>(txn, DEFAULT_SEARCH_QUERY_KEY)?)
+    }
+
+    /// Returns the maximum number of hits the searches can page through and report: the
+    /// `maxTotalHits` setting of the index, or the default one of the instance.
+    pub fn max_total_hits(&self, txn: &RoTxn) -> Result<Option<usize>> {
+        Ok(self
+            .max_total_hits_setting(txn)?
+            .or(self.default_max_total_hits))
+    }
+
+    fn max_total_hits_setting(&self, txn: &RoTxn) -> Result<Option<usize>> {
+        Ok(self
+            .main
+            .get::<_, Str, SerdeJson<usize>>(txn, MAX_TOTAL_HITS_KEY)?)
+    }
+
     pub fn settings_txn(&self, txn: &RoTxn) -> Result<Settings<Checked>> {
         let displayed_attributes = self
             .displayed_fields(txn)?
@@ -214,6 +272,14 @@ impl Index {
             },
             synonyms: Setting::Set(synonyms),
             typo: Setting::Set(typo_tolerance),
+            default_search_query: match self.default_search_query(txn)? {
+                Some(query) => Setting::Set(query),
+                None => Setting::Reset,
+            },
+            max_total_hits: match self.max_total_hits_setting(txn)? {
+                Some(max_total_hits) => Setting::Set(max_total_hits),
+                None => Setting::Reset,
+            },
             _kind: PhantomData,
         })
     }
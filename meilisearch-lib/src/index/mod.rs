@@ -1,12 +1,19 @@
 pub use search::{
-    default_crop_length, default_crop_marker, default_highlight_post_tag,
-    default_highlight_pre_tag, SearchQuery, SearchResult, DEFAULT_SEARCH_LIMIT,
+    default_crop_marker, default_highlight_numbers, default_highlight_post_tag,
+    default_highlight_pre_tag, AttributeToCrop, AttributeToHighlight, CandidatesCount,
+    CropAttribute, CropStrategy, CropUnit, DefaultSearchQuery, ExternalDocumentId, FacetSortBy,
+    FacetStats, FacetValueHit, FacetValues, FieldOrder, HighlightAttribute, HitContent,
+    MatchingStrategy, ProcessingTimes, ResultFormat, SearchQuery, SearchQueryBuilder, SearchResult,
+    DEFAULT_SEARCH_LIMIT,
 };
 pub use updates::{apply_settings_to_builder, Checked, Facets, Settings, Unchecked};
 
+mod analyzer_cache;
+mod dictionary;
 mod dump;
 pub mod error;
 mod search;
+mod search_cache;
 pub mod updates;
 
 #[allow(clippy::module_inception)]
@@ -36,7 +43,9 @@ pub mod test {
 
     use super::error::Result;
     use super::index::Index;
-    use super::{Checked, IndexMeta, IndexStats, SearchQuery, SearchResult, Settings};
+    use super::{
+        Checked, FacetValueHit, IndexMeta, IndexStats, SearchQuery, SearchResult, Settings,
+    };
     use crate::update_file_store::UpdateFileStore;
 
     #[derive(Clone)]
@@ -60,6 +69,22 @@ pub mod test {
             Ok(Self::Real(index))
         }
 
+        pub fn with_search_cache(self, capacity: usize) -> Self {
+            match self {
+                MockIndex::Real(index) => MockIndex::Real(index.with_search_cache(capacity)),
+                MockIndex::Mock(_) => self,
+            }
+        }
+
+        pub fn with_max_total_hits(self, max_total_hits: Option<usize>) -> Self {
+            match self {
+                MockIndex::Real(index) => {
+                    MockIndex::Real(index.with_max_total_hits(max_total_hits))
+                }
+                MockIndex::Mock(_) => self,
+            }
+        }
+
         pub fn load_dump(
             src: impl AsRef<Path>,
             dst: impl AsRef<Path>,
@@ -149,6 +174,46 @@ pub mod test {
             }
         }
 
+        pub fn perform_multi_search(
+            &self,
+            queries: Vec<SearchQuery>,
+        ) -> Result<Vec<Result<SearchResult>>> {
+            match self {
+                MockIndex::Real(index) => index.perform_multi_search(queries),
+                MockIndex::Mock(m) => unsafe { m.get("perform_multi_search").call(queries) },
+            }
+        }
+
+        pub fn complete_term(&self, prefix: &str, limit: usize) -> Result<Vec<String>> {
+            match self {
+                MockIndex::Real(index) => index.complete_term(prefix, limit),
+                MockIndex::Mock(m) => unsafe { m.get("complete_term").call((prefix, limit)) },
+            }
+        }
+
+        pub fn explain_filter(&self, filter: &Value) -> Result<String> {
+            match self {
+                MockIndex::Real(index) => index.explain_filter(filter),
+                MockIndex::Mock(m) => unsafe { m.get("explain_filter").call(filter) },
+            }
+        }
+
+        pub fn search_facet_values(
+            &self,
+            facet: &str,
+            query: &str,
+            limit: usize,
+            filter: Option<&Value>,
+        ) -> Result<Vec<FacetValueHit>> {
+            match self {
+                MockIndex::Real(index) => index.search_facet_values(facet, query, limit, filter),
+                MockIndex::Mock(m) => unsafe {
+                    m.get("search_facet_values")
+                        .call((facet, query, limit, filter))
+                },
+            }
+        }
+
         pub fn dump(&self, path: impl AsRef<Path>) -> Result<()> {
             match self {
                 MockIndex::Real(index) => index.dump(path),
@@ -1,45 +1,116 @@
-use std::cmp::min;
-use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::borrow::Cow;
+use std::cmp::{max, min, Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hasher;
+use std::ops::Range;
 use std::str::FromStr;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use either::Either;
-use indexmap::IndexMap;
-use milli::tokenizer::{Analyzer, AnalyzerConfig, Token};
-use milli::{AscDesc, FieldId, FieldsIdsMap, Filter, MatchingWords, SortError};
+use indexmap::{IndexMap, IndexSet};
+use milli::tokenizer::{Analyzer, AnalyzerConfig, SeparatorKind, Token};
+use milli::update::Setting;
+use milli::{AscDesc, Criterion, FieldId, FieldsIdsMap, Filter, MatchingWords, Member, SortError};
+use rayon::prelude::*;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use siphasher::sip::SipHasher13;
 
-use crate::index::error::FacetError;
+use crate::index::error::{FacetError, SearchError};
 
+use super::analyzer_cache::StopWordsAnalyzer;
+use super::dictionary::Levenshtein;
 use super::error::{IndexError, Result};
 use super::index::Index;
+use super::search_cache::SearchCache;
+use super::updates::TypoSettings;
 
 pub type Document = IndexMap<String, Value>;
 type MatchesInfo = BTreeMap<String, Vec<MatchInfo>>;
 
 #[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct MatchInfo {
     start: usize,
     length: usize,
+    /// The normalized matched part of the word, only computed when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matched_term: Option<String>,
 }
 
-pub const DEFAULT_SEARCH_LIMIT: usize = 20;
-const fn default_search_limit() -> usize {
-    DEFAULT_SEARCH_LIMIT
+/// A part of a formatted string, highlighted or not. Wrapping the highlighted segments in the
+/// highlight tags and concatenating them gives back the formatted string.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct Segment {
+    text: String,
+    highlighted: bool,
 }
 
-pub const DEFAULT_CROP_LENGTH: usize = 10;
-pub const fn default_crop_length() -> usize {
-    DEFAULT_CROP_LENGTH
+/// Appends `text` to the last segment if it is highlighted the same way, to a new one otherwise.
+fn push_segment(segments: &mut Vec<Segment>, text: &str, highlighted: bool) {
+    if text.is_empty() {
+        return;
+    }
+
+    match segments.last_mut() {
+        Some(last) if last.highlighted == highlighted => last.text.push_str(text),
+        _ => segments.push(Segment {
+            text: text.to_string(),
+            highlighted,
+        }),
+    }
+}
+
+impl MatchInfo {
+    /// Returns the normalized text of the `length` first bytes of `word`, matched by the query.
+    fn matched_term(word: &str, token: &Token, length: usize) -> String {
+        let chars = word.get(..length).unwrap_or(word).chars().count();
+        token.text().chars().take(chars).collect()
+    }
+}
+
+/// The unit in which the offsets of a `MatchInfo` are expressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OffsetUnit {
+    Bytes,
+    /// Unicode scalar values.
+    Chars,
+}
+
+impl OffsetUnit {
+    fn len(self, s: &str) -> usize {
+        match self {
+            OffsetUnit::Bytes => s.len(),
+            OffsetUnit::Chars => s.chars().count(),
+        }
+    }
+
+    /// Converts the `length` bytes of `word` returned by a `Matcher` into this unit. In
+    /// characters, the whole word is matched if `length` falls in the middle of a character.
+    fn match_len(self, word: &str, length: usize) -> usize {
+        match self {
+            OffsetUnit::Bytes => length,
+            OffsetUnit::Chars => word.get(..length).unwrap_or(word).chars().count(),
+        }
+    }
 }
 
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+pub const DEFAULT_CROP_LENGTH: usize = 10;
+
 const DEFAULT_CROP_MARKER: &str = "…";
 pub fn default_crop_marker() -> String {
     DEFAULT_CROP_MARKER.to_string()
 }
 
+pub const fn default_highlight_numbers() -> bool {
+    true
+}
+
 const DEFAULT_HIGHLIGHT_PRE_TAG: &str = "<em>";
 pub fn default_highlight_pre_tag() -> String {
     DEFAULT_HIGHLIGHT_PRE_TAG.to_string()
@@ -50,1555 +121,10943 @@ pub fn default_highlight_post_tag() -> String {
     DEFAULT_HIGHLIGHT_POST_TAG.to_string()
 }
 
+/// Deserializes a field that is present, even `null`, as `Some`.
+fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
 /// The maximimum number of results that the engine
 /// will be able to return in one search call.
 pub const HARD_RESULT_LIMIT: usize = 1000;
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+/// The maximum number of values that milli returns for a facet
+/// in a facet distribution.
+pub const MAX_VALUES_PER_FACET: usize = 1000;
+
+/// A correction of the query is only suggested when it has less hits than this.
+const SUGGESTION_HITS_THRESHOLD: u64 = 5;
+
+/// The hits of a page are only formatted in parallel from this many hits, below it dispatching
+/// them to the thread pool costs more than it saves. The pages of the default limit are thus
+/// formatted on the searching thread. It is measured by the `formatting` benchmark.
+const PARALLEL_FORMATTING_MIN_HITS: usize = 64;
+
+/// The prefix of the attributes to retrieve, highlight or crop given as a regex matched against
+/// the names of the fields, like `re:^price_`.
+const ATTRIBUTE_REGEX_PREFIX: &str = "re:";
+
+/// The maximum size in bytes of a compiled attribute regex, bigger regexes are rejected.
+const ATTRIBUTE_REGEX_SIZE_LIMIT: usize = 1 << 16;
+
+lazy_static::lazy_static! {
+    static ref STOP_WORDS: fst::Set<Vec<u8>> = fst::Set::default();
+    /// The analyzer used to format and match the documents. Building it is costly, it is thus
+    /// built once and shared by all the searches.
+    pub(super) static ref ANALYZER: Analyzer<'static, Vec<u8>> = {
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&STOP_WORDS);
+        Analyzer::new(config)
+    };
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct SearchQuery {
     pub q: Option<String>,
     pub offset: Option<usize>,
-    #[serde(default = "default_search_limit")]
-    pub limit: usize,
-    pub attributes_to_retrieve: Option<BTreeSet<String>>,
-    pub attributes_to_crop: Option<Vec<String>>,
-    #[serde(default = "default_crop_length")]
-    pub crop_length: usize,
-    pub attributes_to_highlight: Option<HashSet<String>>,
+    pub limit: Option<usize>,
+    pub attributes_to_retrieve: Option<IndexSet<String>>,
+    pub attributes_to_crop: Option<Vec<AttributeToCrop>>,
+    pub crop_length: Option<usize>,
+    pub attributes_to_highlight: Option<Vec<AttributeToHighlight>>,
     // Default to false
-    #[serde(default = "Default::default")]
-    pub matches: bool,
+    pub matches: Option<bool>,
     pub filter: Option<Value>,
     pub sort: Option<Vec<String>>,
     pub facets_distribution: Option<Vec<String>>,
-    #[serde(default = "default_highlight_pre_tag")]
-    pub highlight_pre_tag: String,
-    #[serde(default = "default_highlight_post_tag")]
-    pub highlight_post_tag: String,
-    #[serde(default = "default_crop_marker")]
-    pub crop_marker: String,
-}
-
-#[derive(Debug, Clone, Serialize, PartialEq)]
-pub struct SearchHit {
-    #[serde(flatten)]
-    pub document: Document,
-    #[serde(rename = "_formatted", skip_serializing_if = "Document::is_empty")]
-    pub formatted: Document,
-    #[serde(rename = "_matchesInfo", skip_serializing_if = "Option::is_none")]
-    pub matches_info: Option<MatchesInfo>,
-}
-
-#[derive(Serialize, Debug, Clone, PartialEq)]
-#[serde(rename_all = "camelCase")]
-pub struct SearchResult {
-    pub hits: Vec<SearchHit>,
-    pub nb_hits: u64,
-    pub exhaustive_nb_hits: bool,
-    pub query: String,
-    pub limit: usize,
-    pub offset: usize,
-    pub processing_time_ms: u128,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub facets_distribution: Option<BTreeMap<String, BTreeMap<String, u64>>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight_pre_tag: Option<String>,
+    pub highlight_post_tag: Option<String>,
+    pub crop_marker: Option<String>,
+    /// Overrides `crop_marker` before the cropped text.
+    pub crop_marker_before: Option<String>,
+    /// Overrides `crop_marker` after the cropped text.
+    pub crop_marker_after: Option<String>,
+    // Default to false
+    pub prefer_exact_matches: Option<bool>,
+    // Default to false
+    pub show_crop_info: Option<bool>,
+    pub min_prefix_length: Option<usize>,
+    // Default to false
+    pub show_result_hash: Option<bool>,
+    // Default to false
     pub exhaustive_facets_count: Option<bool>,
+    pub page: Option<usize>,
+    pub hits_per_page: Option<usize>,
+    pub attributes_to_search_on: Option<Vec<String>>,
+    pub matching_strategy: Option<MatchingStrategy>,
+    // Default to false
+    pub matches_on_formatted: Option<bool>,
+    // Default to false
+    pub matches_char_offsets: Option<bool>,
+    // Default to false
+    pub matches_by_pointer: Option<bool>,
+    // Default to false
+    pub show_matched_terms: Option<bool>,
+    // Default to false
+    pub strict_attributes: Option<bool>,
+    // `null` is distinguished from a missing field, it disables the distinct attribute.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub distinct: Option<Option<String>>,
+    // Default to false
+    pub show_processing_times: Option<bool>,
+    // Default to false
+    pub strict_limits: Option<bool>,
+    // Default to false
+    pub min_geo_distance: Option<bool>,
+    pub geo_distance_decimals: Option<u32>,
+    pub geo_distance_from: Option<[f64; 2]>,
+    // Default to false
+    pub show_facet_stats: Option<bool>,
+    pub max_values_per_facet: Option<usize>,
+    pub facet_sort: Option<FacetSortBy>,
+    pub crop_strategy: Option<CropStrategy>,
+    pub crop_unit: Option<CropUnit>,
+    // Default to false
+    pub crop_on_sentences: Option<bool>,
+    // Default to true, numbers are formatted as strings
+    pub highlight_numbers: Option<bool>,
+    pub exclude_document_ids: Option<Vec<ExternalDocumentId>>,
+    pub raw_attributes: Option<Vec<String>>,
+    // Default to false
+    pub show_filter_fields: Option<bool>,
+    // Default to false
+    pub disjunctive_facets: Option<bool>,
+    // Default to false
+    pub show_suggestion: Option<bool>,
+    pub timeout_ms: Option<u64>,
+    pub hit_content: Option<HitContent>,
+    // Default to false
+    pub show_ranking_rules: Option<bool>,
+    // Default to false
+    pub show_typos: Option<bool>,
+    // Default to false
+    pub show_matched_words_count: Option<bool>,
+    // Default to false
+    pub show_normalized_query: Option<bool>,
+    // Default to false
+    pub formatted_segments: Option<bool>,
+    pub crop_snippets: Option<usize>,
+    // Default to false
+    pub show_truncation: Option<bool>,
+    // Default to true
+    pub sort_ties_by_id: Option<bool>,
+    // Default to false
+    pub show_size_bytes: Option<bool>,
+    pub typo_tolerance: Option<TypoSettings>,
+    // Default to false, only the attributes listed in `attributes_to_retrieve` are returned when
+    // true
+    pub exclude_attributes_by_default: Option<bool>,
+    // Default to false
+    pub show_field_coverage: Option<bool>,
+    // Default to false, the case of the query words must match to be highlighted when true
+    pub highlight_case_sensitive: Option<bool>,
+    // Default to false, the diacritics of the query words must match to be highlighted when true
+    pub highlight_diacritic_sensitive: Option<bool>,
+    /// The matches shorter than this length, in the unit of their offsets, are omitted.
+    pub min_match_length: Option<usize>,
+    // Default to false
+    pub flatten_documents: Option<bool>,
+    pub field_order: Option<FieldOrder>,
+    pub format: Option<ResultFormat>,
+    /// The maximum number of highlighted parts in each formatted string, unlimited by default.
+    pub max_highlights_per_field: Option<usize>,
+    /// Resumes the search after the last hit of the page that returned this `next_cursor`, an
+    /// empty cursor returns the first page.
+    pub cursor: Option<String>,
+    /// The ranking rules of this search, in place of the ones of the index.
+    pub ranking_rules: Option<Vec<String>>,
+    // Default to false
+    pub escape_html: Option<bool>,
+    // Default to false
+    pub show_candidates_count: Option<bool>,
+    // Default to false
+    pub show_ranking_score: Option<bool>,
+    /// The hits with a `_rankingScore` below this threshold, between `0.0` and `1.0`, are left out.
+    pub ranking_score_threshold: Option<f64>,
 }
 
-#[derive(Copy, Clone, Default)]
-struct FormatOptions {
-    highlight: bool,
-    crop: Option<usize>,
-}
-
-impl FormatOptions {
-    pub fn merge(self, other: Self) -> Self {
+/// The defaults are the ones of an empty JSON query.
+impl Default for SearchQuery {
+    fn default() -> Self {
         Self {
-            highlight: self.highlight || other.highlight,
-            crop: self.crop.or(other.crop),
+            q: None,
+            offset: None,
+            limit: None,
+            attributes_to_retrieve: None,
+            attributes_to_crop: None,
+            crop_length: None,
+            attributes_to_highlight: None,
+            matches: None,
+            filter: None,
+            sort: None,
+            facets_distribution: None,
+            highlight_pre_tag: None,
+            highlight_post_tag: None,
+            crop_marker: None,
+            crop_marker_before: None,
+            crop_marker_after: None,
+            prefer_exact_matches: None,
+            show_crop_info: None,
+            min_prefix_length: None,
+            show_result_hash: None,
+            exhaustive_facets_count: None,
+            page: None,
+            hits_per_page: None,
+            attributes_to_search_on: None,
+            matching_strategy: None,
+            matches_on_formatted: None,
+            matches_char_offsets: None,
+            matches_by_pointer: None,
+            show_matched_terms: None,
+            strict_attributes: None,
+            distinct: None,
+            show_processing_times: None,
+            strict_limits: None,
+            min_geo_distance: None,
+            geo_distance_decimals: None,
+            geo_distance_from: None,
+            show_facet_stats: None,
+            max_values_per_facet: None,
+            facet_sort: None,
+            crop_strategy: None,
+            crop_unit: None,
+            crop_on_sentences: None,
+            highlight_numbers: None,
+            exclude_document_ids: None,
+            raw_attributes: None,
+            show_filter_fields: None,
+            disjunctive_facets: None,
+            show_suggestion: None,
+            timeout_ms: None,
+            hit_content: None,
+            show_ranking_rules: None,
+            show_typos: None,
+            show_matched_words_count: None,
+            show_normalized_query: None,
+            formatted_segments: None,
+            crop_snippets: None,
+            show_truncation: None,
+            sort_ties_by_id: None,
+            show_size_bytes: None,
+            typo_tolerance: None,
+            exclude_attributes_by_default: None,
+            show_field_coverage: None,
+            highlight_case_sensitive: None,
+            highlight_diacritic_sensitive: None,
+            min_match_length: None,
+            flatten_documents: None,
+            field_order: None,
+            format: None,
+            max_highlights_per_field: None,
+            cursor: None,
+            ranking_rules: None,
+            escape_html: None,
+            show_candidates_count: None,
+            show_ranking_score: None,
+            ranking_score_threshold: None,
         }
     }
 }
 
-impl Index {
-    pub fn perform_search(&self, query: SearchQuery) -> Result<SearchResult> {
-        let before_search = Instant::now();
-        let rtxn = self.read_txn()?;
-
-        let mut search = self.search(&rtxn);
+impl SearchQuery {
+    /// Returns a builder of a query, the parameters it doesn't set keep their default value.
+    pub fn builder() -> SearchQueryBuilder {
+        SearchQueryBuilder::default()
+    }
 
-        if let Some(ref query) = query.q {
-            search.query(query);
+    /// Returns this query with the parameters it doesn't set taken from `template`, the other
+    /// ones overriding the ones of the template, the attribute lists included, even when they are
+    /// set to their default value. The filters of both are combined with an `AND`.
+    ///
+    /// The pagination parameters are taken as a whole: the `offset`, `limit`, `page` and
+    /// `hitsPerPage` of the template are only used when the query sets none of them nor a
+    /// `cursor`. The `cursor` of the template is never used, it only makes sense for the search
+    /// that returned it.
+    pub fn with_template(mut self, template: &SearchQuery) -> SearchQuery {
+        macro_rules! take_unset_from_template {
+            ($($field:ident),* $(,)?) => {
+                // The destructuring only compiles when all the fields are listed.
+                let SearchQuery {
+                    filter: _,
+                    offset: _,
+                    limit: _,
+                    page: _,
+                    hits_per_page: _,
+                    cursor: _,
+                    $($field: _),*
+                } = template;
+                $(
+                    if self.$field.is_none() {
+                        self.$field = template.$field.clone();
+                    }
+                )*
+            };
         }
 
-        // Make sure that a user can't get more documents than the hard limit,
-        // we align that on the offset too.
-        let offset = min(query.offset.unwrap_or(0), HARD_RESULT_LIMIT);
-        let limit = min(query.limit, HARD_RESULT_LIMIT.saturating_sub(offset));
+        take_unset_from_template!(
+            q,
+            attributes_to_retrieve,
+            attributes_to_crop,
+            crop_length,
+            attributes_to_highlight,
+            matches,
+            sort,
+            facets_distribution,
+            highlight_pre_tag,
+            highlight_post_tag,
+            crop_marker,
+            crop_marker_before,
+            crop_marker_after,
+            prefer_exact_matches,
+            show_crop_info,
+            min_prefix_length,
+            show_result_hash,
+            exhaustive_facets_count,
+            attributes_to_search_on,
+            matching_strategy,
+            matches_on_formatted,
+            matches_char_offsets,
+            matches_by_pointer,
+            show_matched_terms,
+            strict_attributes,
+            distinct,
+            show_processing_times,
+            strict_limits,
+            min_geo_distance,
+            geo_distance_decimals,
+            geo_distance_from,
+            show_facet_stats,
+            max_values_per_facet,
+            facet_sort,
+            crop_strategy,
+            crop_unit,
+            crop_on_sentences,
+            highlight_numbers,
+            exclude_document_ids,
+            raw_attributes,
+            show_filter_fields,
+            disjunctive_facets,
+            show_suggestion,
+            timeout_ms,
+            hit_content,
+            show_ranking_rules,
+            show_typos,
+            show_matched_words_count,
+            show_normalized_query,
+            formatted_segments,
+            crop_snippets,
+            show_truncation,
+            sort_ties_by_id,
+            show_size_bytes,
+            typo_tolerance,
+            exclude_attributes_by_default,
+            show_field_coverage,
+            highlight_case_sensitive,
+            highlight_diacritic_sensitive,
+            min_match_length,
+            flatten_documents,
+            field_order,
+            format,
+            max_highlights_per_field,
+            ranking_rules,
+            escape_html,
+            show_candidates_count,
+            show_ranking_score,
+            ranking_score_threshold,
+        );
 
-        search.offset(offset);
-        search.limit(limit);
+        let paginated = self.offset.is_some()
+            || self.limit.is_some()
+            || self.page.is_some()
+            || self.hits_per_page.is_some()
+            || self.cursor.is_some();
+        if !paginated {
+            self.offset = template.offset;
+            self.limit = template.limit;
+            self.page = template.page;
+            self.hits_per_page = template.hits_per_page;
+        }
 
-        if let Some(ref filter) = query.filter {
-            if let Some(facets) = parse_filter(filter)? {
-                search.filter(facets);
+        self.filter = match (self.filter.take(), template.filter.clone()) {
+            (Some(filter), Some(template_filter)) => {
+                let clauses = |filter| match filter {
+                    Value::Array(clauses) => clauses,
+                    filter => vec![filter],
+                };
+                Some(Value::Array(
+                    [clauses(filter), clauses(template_filter)].concat(),
+                ))
             }
-        }
+            (filter, template_filter) => filter.or(template_filter),
+        };
 
-        if let Some(ref sort) = query.sort {
-            let sort = match sort.iter().map(|s| AscDesc::from_str(s)).collect() {
-                Ok(sorts) => sorts,
-                Err(asc_desc_error) => {
-                    return Err(IndexError::Milli(SortError::from(asc_desc_error).into()))
-                }
-            };
+        self
+    }
+}
 
-            search.sort_criteria(sort);
-        }
+/// Builds a `SearchQuery` from the most common parameters, the other ones can be set on the
+/// built query.
+#[derive(Debug, Clone, Default)]
+pub struct SearchQueryBuilder {
+    query: SearchQuery,
+}
 
-        let milli::SearchResult {
-            documents_ids,
-            matching_words,
-            candidates,
-            ..
-        } = search.execute()?;
+impl SearchQueryBuilder {
+    pub fn q(mut self, q: impl Into<String>) -> Self {
+        self.query.q = Some(q.into());
+        self
+    }
 
-        let fields_ids_map = self.fields_ids_map(&rtxn).unwrap();
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.query.offset = Some(offset);
+        self
+    }
 
-        let displayed_ids = self
-            .displayed_fields_ids(&rtxn)?
-            .map(|fields| fields.into_iter().collect::<BTreeSet<_>>())
-            .unwrap_or_else(|| fields_ids_map.iter().map(|(id, _)| id).collect());
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.query.limit = Some(limit);
+        self
+    }
 
-        let fids = |attrs: &BTreeSet<String>| {
-            let mut ids = BTreeSet::new();
-            for attr in attrs {
-                if attr == "*" {
-                    ids = displayed_ids.clone();
-                    break;
-                }
+    pub fn page(mut self, page: usize) -> Self {
+        self.query.page = Some(page);
+        self
+    }
 
-                if let Some(id) = fields_ids_map.id(attr) {
-                    ids.insert(id);
-                }
-            }
-            ids
-        };
+    pub fn hits_per_page(mut self, hits_per_page: usize) -> Self {
+        self.query.hits_per_page = Some(hits_per_page);
+        self
+    }
 
-        // The attributes to retrieve are the ones explicitly marked as to retrieve (all by default),
-        // but these attributes must be also be present
-        // - in the fields_ids_map
-        // - in the the displayed attributes
-        let to_retrieve_ids: BTreeSet<_> = query
-            .attributes_to_retrieve
-            .as_ref()
-            .map(fids)
-            .unwrap_or_else(|| displayed_ids.clone())
-            .intersection(&displayed_ids)
-            .cloned()
-            .collect();
+    /// Paginates the search with a cursor, an empty one for the first page.
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.query.cursor = Some(cursor.into());
+        self
+    }
 
-        let attr_to_highlight = query.attributes_to_highlight.unwrap_or_default();
+    /// Sets the filter, either a string or an array of strings and arrays of strings.
+    pub fn filter(mut self, filter: Value) -> Self {
+        self.query.filter = Some(filter);
+        self
+    }
 
-        let attr_to_crop = query.attributes_to_crop.unwrap_or_default();
+    pub fn sort<I: IntoIterator<Item = S>, S: Into<String>>(mut self, sort: I) -> Self {
+        self.query.sort = Some(sort.into_iter().map(Into::into).collect());
+        self
+    }
 
-        // Attributes in `formatted_options` correspond to the attributes that will be in `_formatted`
-        // These attributes are:
-        // - the attributes asked to be highlighted or cropped (with `attributesToCrop` or `attributesToHighlight`)
-        // - the attributes asked to be retrieved: these attributes will not be highlighted/cropped
-        // But these attributes must be also present in displayed attributes
-        let formatted_options = compute_formatted_options(
-            &attr_to_highlight,
-            &attr_to_crop,
-            query.crop_length,
-            &to_retrieve_ids,
-            &fields_ids_map,
-            &displayed_ids,
-        );
+    pub fn ranking_rules<I: IntoIterator<Item = S>, S: Into<String>>(mut self, rules: I) -> Self {
+        self.query.ranking_rules = Some(rules.into_iter().map(Into::into).collect());
+        self
+    }
 
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
+    pub fn facets_distribution<I: IntoIterator<Item = S>, S: Into<String>>(
+        mut self,
+        facets: I,
+    ) -> Self {
+        self.query.facets_distribution = Some(facets.into_iter().map(Into::into).collect());
+        self
+    }
 
-        let formatter = Formatter::new(
-            &analyzer,
-            (query.highlight_pre_tag, query.highlight_post_tag),
-            query.crop_marker,
-        );
+    pub fn attributes_to_retrieve<I: IntoIterator<Item = S>, S: Into<String>>(
+        mut self,
+        attributes: I,
+    ) -> Self {
+        self.query.attributes_to_retrieve = Some(attributes.into_iter().map(Into::into).collect());
+        self
+    }
 
-        let mut documents = Vec::new();
+    pub fn attributes_to_highlight<I: IntoIterator<Item = S>, S: Into<String>>(
+        mut self,
+        attributes: I,
+    ) -> Self {
+        let attributes = attributes
+            .into_iter()
+            .map(|attr| AttributeToHighlight::Name(attr.into()))
+            .collect();
+        self.query.attributes_to_highlight = Some(attributes);
+        self
+    }
 
-        let documents_iter = self.documents(&rtxn, documents_ids)?;
+    /// Sets the attributes to crop, an attribute can be followed by `:` and its crop length.
+    pub fn attributes_to_crop<I: IntoIterator<Item = S>, S: Into<String>>(
+        mut self,
+        attributes: I,
+    ) -> Self {
+        let attributes = attributes
+            .into_iter()
+            .map(|attr| AttributeToCrop::Name(attr.into()))
+            .collect();
+        self.query.attributes_to_crop = Some(attributes);
+        self
+    }
 
-        for (_id, obkv) in documents_iter {
-            let mut document = make_document(&to_retrieve_ids, &fields_ids_map, obkv)?;
+    pub fn crop_length(mut self, crop_length: usize) -> Self {
+        self.query.crop_length = Some(crop_length);
+        self
+    }
 
-            let matches_info = query
-                .matches
-                .then(|| compute_matches(&matching_words, &document, &analyzer));
+    pub fn crop_marker(mut self, crop_marker: impl Into<String>) -> Self {
+        self.query.crop_marker = Some(crop_marker.into());
+        self
+    }
 
-            let formatted = format_fields(
-                &document,
-                &fields_ids_map,
-                &formatter,
-                &matching_words,
-                &formatted_options,
-            )?;
+    pub fn crop_markers(mut self, before: impl Into<String>, after: impl Into<String>) -> Self {
+        self.query.crop_marker_before = Some(before.into());
+        self.query.crop_marker_after = Some(after.into());
+        self
+    }
 
-            if let Some(sort) = query.sort.as_ref() {
-                insert_geo_distance(sort, &mut document);
-            }
+    pub fn highlight_tags(
+        mut self,
+        pre_tag: impl Into<String>,
+        post_tag: impl Into<String>,
+    ) -> Self {
+        self.query.highlight_pre_tag = Some(pre_tag.into());
+        self.query.highlight_post_tag = Some(post_tag.into());
+        self
+    }
 
-            let hit = SearchHit {
-                document,
-                formatted,
-                matches_info,
-            };
-            documents.push(hit);
-        }
+    pub fn matches(mut self, matches: bool) -> Self {
+        self.query.matches = Some(matches);
+        self
+    }
 
-        let nb_hits = candidates.len();
+    pub fn matching_strategy(mut self, matching_strategy: MatchingStrategy) -> Self {
+        self.query.matching_strategy = Some(matching_strategy);
+        self
+    }
 
-        let facets_distribution = match query.facets_distribution {
-            Some(ref fields) => {
-                let mut facets_distribution = self.facets_distribution(&rtxn);
-                if fields.iter().all(|f| f != "*") {
-                    facets_distribution.facets(fields);
-                }
-                let distribution = facets_distribution.candidates(candidates).execute()?;
+    pub fn build(self) -> SearchQuery {
+        self.query
+    }
+}
 
-                Some(distribution)
-            }
-            None => None,
-        };
+/// The search query of the settings of an index, completing all its searches, see
+/// `SearchQuery::with_template`. It is kept as it was sent, only its parameters are returned with
+/// the settings.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+#[serde(transparent)]
+pub struct DefaultSearchQuery(Map<String, Value>);
 
-        let exhaustive_facets_count = facets_distribution.as_ref().map(|_| false); // not implemented yet
+impl DefaultSearchQuery {
+    pub fn to_query(&self) -> Result<SearchQuery> {
+        Ok(serde_json::from_value(Value::Object(self.0.clone()))?)
+    }
+}
 
-        let result = SearchResult {
-            exhaustive_nb_hits: false, // not implemented yet
-            hits: documents,
-            nb_hits,
-            query: query.q.clone().unwrap_or_default(),
-            limit: query.limit,
-            offset: query.offset.unwrap_or_default(),
-            processing_time_ms: before_search.elapsed().as_millis(),
-            facets_distribution,
-            exhaustive_facets_count,
-        };
-        Ok(result)
+/// The default search query must be a valid search query.
+impl<'de> Deserialize<'de> for DefaultSearchQuery {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let query = Map::deserialize(deserializer)?;
+        SearchQuery::deserialize(Value::Object(query.clone())).map_err(serde::de::Error::custom)?;
+        Ok(DefaultSearchQuery(query))
     }
 }
 
-fn insert_geo_distance(sorts: &[String], document: &mut Document) {
-    lazy_static::lazy_static! {
-        static ref GEO_REGEX: Regex =
-            Regex::new(r"_geoPoint\(\s*([[:digit:].\-]+)\s*,\s*([[:digit:].\-]+)\s*\)").unwrap();
-    };
-    if let Some(capture_group) = sorts.iter().find_map(|sort| GEO_REGEX.captures(sort)) {
-        // TODO: TAMO: milli encountered an internal error, what do we want to do?
-        let base = [
-            capture_group[1].parse().unwrap(),
-            capture_group[2].parse().unwrap(),
-        ];
-        let geo_point = &document.get("_geo").unwrap_or(&json!(null));
-        if let Some((lat, lng)) = geo_point["lat"].as_f64().zip(geo_point["lng"].as_f64()) {
-            let distance = milli::distance_between_two_points(&base, &[lat, lng]);
-            document.insert("_geoDistance".to_string(), json!(distance.round() as usize));
+/// An attribute to crop, either given by its name, optionally followed by `:` and its crop length,
+/// or by an object. The crop length of a regex selector is only recognized when it is a number,
+/// since the regex can contain `:`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AttributeToCrop {
+    Name(String),
+    Object(CropAttribute),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CropAttribute {
+    pub attribute: String,
+    pub crop_length: Option<usize>,
+}
+
+impl AttributeToCrop {
+    /// Returns the name of the attribute and its crop length, `crop_length` being used when the
+    /// attribute doesn't specify one.
+    fn name_and_length(&self, crop_length: usize) -> (&str, usize) {
+        match self {
+            AttributeToCrop::Name(attr) => {
+                let mut split = attr.rsplitn(2, ':');
+                match split.next().zip(split.next()) {
+                    Some((len, name))
+                        if !attr.starts_with(ATTRIBUTE_REGEX_PREFIX)
+                            || (name.len() > ATTRIBUTE_REGEX_PREFIX.len()
+                                && len.parse::<usize>().is_ok()) =>
+                    {
+                        let crop_len = len.parse::<usize>().unwrap_or(crop_length);
+                        (name, crop_len)
+                    }
+                    _ => (attr.as_str(), crop_length),
+                }
+            }
+            AttributeToCrop::Object(CropAttribute {
+                attribute,
+                crop_length: attr_length,
+            }) => (attribute.as_str(), attr_length.unwrap_or(crop_length)),
         }
     }
 }
 
-fn compute_matches<A: AsRef<[u8]>>(
-    matcher: &impl Matcher,
-    document: &Document,
-    analyzer: &Analyzer<A>,
-) -> MatchesInfo {
-    let mut matches = BTreeMap::new();
+/// The id of a document as given in the document, either a string or an integer.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ExternalDocumentId {
+    String(String),
+    Integer(u64),
+}
 
-    for (key, value) in document {
-        let mut infos = Vec::new();
-        compute_value_matches(&mut infos, value, matcher, analyzer);
-        if !infos.is_empty() {
-            matches.insert(key.clone(), infos);
+impl ExternalDocumentId {
+    /// Returns the id as it is stored by milli.
+    fn to_external_id(&self) -> String {
+        match self {
+            ExternalDocumentId::String(id) => id.clone(),
+            ExternalDocumentId::Integer(id) => id.to_string(),
         }
     }
-    matches
 }
 
-fn compute_value_matches<'a, A: AsRef<[u8]>>(
-    infos: &mut Vec<MatchInfo>,
-    value: &Value,
-    matcher: &impl Matcher,
-    analyzer: &Analyzer<'a, A>,
-) {
-    match value {
-        Value::String(s) => {
-            let analyzed = analyzer.analyze(s);
-            let mut start = 0;
-            for (word, token) in analyzed.reconstruct() {
-                if token.is_word() {
-                    if let Some(length) = matcher.matches(&token) {
-                        infos.push(MatchInfo { start, length });
-                    }
-                }
+/// An attribute to highlight, either given by its name or by an object specifying the highlight
+/// tags to use for this attribute.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AttributeToHighlight {
+    Name(String),
+    Object(HighlightAttribute),
+}
 
-                start += word.len();
-            }
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct HighlightAttribute {
+    pub attribute: String,
+    pub highlight_pre_tag: Option<String>,
+    pub highlight_post_tag: Option<String>,
+}
+
+impl AttributeToHighlight {
+    fn name(&self) -> &str {
+        match self {
+            AttributeToHighlight::Name(attr) => attr,
+            AttributeToHighlight::Object(HighlightAttribute { attribute, .. }) => attribute,
         }
-        Value::Array(vals) => vals
-            .iter()
-            .for_each(|val| compute_value_matches(infos, val, matcher, analyzer)),
-        Value::Object(vals) => vals
-            .values()
-            .for_each(|val| compute_value_matches(infos, val, matcher, analyzer)),
-        Value::Number(number) => {
-            compute_value_matches(infos, &Value::String(number.to_string()), matcher, analyzer)
+    }
+
+    fn highlight_tags(&self) -> Option<&HighlightAttribute> {
+        match self {
+            AttributeToHighlight::Name(_) => None,
+            AttributeToHighlight::Object(object) => Some(object),
         }
-        _ => (),
     }
 }
 
-fn compute_formatted_options(
-    attr_to_highlight: &HashSet<String>,
-    attr_to_crop: &[String],
-    query_crop_length: usize,
-    to_retrieve_ids: &BTreeSet<FieldId>,
-    fields_ids_map: &FieldsIdsMap,
-    displayed_ids: &BTreeSet<FieldId>,
-) -> BTreeMap<FieldId, FormatOptions> {
-    let mut formatted_options = BTreeMap::new();
+/// The strategy used by the search when not all the query words can be matched by a document.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum MatchingStrategy {
+    /// All the query words must be matched by the returned documents.
+    All,
+    /// The last query words are progressively dropped until documents are found.
+    Last,
+}
 
-    add_highlight_to_formatted_options(
-        &mut formatted_options,
-        attr_to_highlight,
-        fields_ids_map,
-        displayed_ids,
-    );
+impl Default for MatchingStrategy {
+    fn default() -> Self {
+        MatchingStrategy::Last
+    }
+}
 
-    add_crop_to_formatted_options(
-        &mut formatted_options,
-        attr_to_crop,
-        query_crop_length,
-        fields_ids_map,
-        displayed_ids,
-    );
+/// How the part of a cropped attribute to keep is chosen.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CropStrategy {
+    /// The crop starts a few words before the first match.
+    FirstMatch,
+    /// The crop keeps the window containing the most matches.
+    BestWindow,
+}
 
-    // Should not return `_formatted` if no valid attributes to highlight/crop
-    if !formatted_options.is_empty() {
-        add_non_formatted_ids_to_formatted_options(&mut formatted_options, to_retrieve_ids);
+impl Default for CropStrategy {
+    fn default() -> Self {
+        CropStrategy::FirstMatch
     }
+}
 
-    formatted_options
+/// The parts of the documents returned in the hits.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum HitContent {
+    /// The document and its `_formatted` version.
+    Both,
+    /// The document only, `_formatted` is never returned.
+    Document,
+    /// The `_formatted` version of the document only. The `_geoDistance` of the document is still
+    /// returned, but the hits of the documents which are not formatted are empty.
+    Formatted,
+    /// The id of the document and its `_matchesInfo` only, computed on the attributes to retrieve.
+    /// The document is not formatted.
+    Matches,
 }
 
-fn add_highlight_to_formatted_options(
-    formatted_options: &mut BTreeMap<FieldId, FormatOptions>,
-    attr_to_highlight: &HashSet<String>,
-    fields_ids_map: &FieldsIdsMap,
-    displayed_ids: &BTreeSet<FieldId>,
-) {
-    for attr in attr_to_highlight {
-        let new_format = FormatOptions {
-            highlight: true,
-            crop: None,
-        };
+impl Default for HitContent {
+    fn default() -> Self {
+        HitContent::Both
+    }
+}
 
-        if attr == "*" {
-            for id in displayed_ids {
-                formatted_options.insert(*id, new_format);
-            }
-            break;
-        }
+/// The unit in which the crop length is counted.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CropUnit {
+    /// The crop length is a number of words.
+    Word,
+    /// The crop length is a number of characters, separators included. Tokens are never split, so
+    /// the crop can be slightly longer than the crop length.
+    Char,
+    /// The crop length is a number of tokens, each word and each separator counting once, so that
+    /// punctuation-dense texts are not cropped wider than the others.
+    Token,
+}
 
-        if let Some(id) = fields_ids_map.id(attr) {
-            if displayed_ids.contains(&id) {
-                formatted_options.insert(id, new_format);
-            }
+impl CropUnit {
+    /// Returns the length of the token in this unit.
+    fn len(self, word: &str, token: &Token) -> usize {
+        match self {
+            CropUnit::Word => is_word(token) as usize,
+            CropUnit::Char => word.chars().count(),
+            CropUnit::Token => 1,
         }
     }
 }
 
-fn add_crop_to_formatted_options(
-    formatted_options: &mut BTreeMap<FieldId, FormatOptions>,
-    attr_to_crop: &[String],
-    crop_length: usize,
-    fields_ids_map: &FieldsIdsMap,
-    displayed_ids: &BTreeSet<FieldId>,
-) {
-    for attr in attr_to_crop {
-        let mut split = attr.rsplitn(2, ':');
-        let (attr_name, attr_len) = match split.next().zip(split.next()) {
-            Some((len, name)) => {
-                let crop_len = len.parse::<usize>().unwrap_or(crop_length);
-                (name, crop_len)
-            }
-            None => (attr.as_str(), crop_length),
-        };
-
-        if attr_name == "*" {
-            for id in displayed_ids {
-                formatted_options
-                    .entry(*id)
-                    .and_modify(|f| f.crop = Some(attr_len))
-                    .or_insert(FormatOptions {
-                        highlight: false,
-                        crop: Some(attr_len),
-                    });
-            }
-        }
+impl Default for CropUnit {
+    fn default() -> Self {
+        CropUnit::Word
+    }
+}
 
-        if let Some(id) = fields_ids_map.id(attr_name) {
-            if displayed_ids.contains(&id) {
-                formatted_options
-                    .entry(id)
-                    .and_modify(|f| f.crop = Some(attr_len))
-                    .or_insert(FormatOptions {
-                        highlight: false,
-                        crop: Some(attr_len),
-                    });
-            }
-        }
+/// The order of the fields of the returned documents.
+///
+/// milli stores the fields of a document by field id and not in the order they were sent in, this
+/// order is lost. The field ids are assigned in the order the fields were first seen in the index.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FieldOrder {
+    /// The fields are in the order of `attributesToRetrieve`, the other ones follow by field id.
+    /// The fields retrieved with `*`, or by default, are in the order of the displayed attributes.
+    AttributesToRetrieve,
+    /// The fields are always ordered by field id.
+    FieldId,
+}
+
+impl Default for FieldOrder {
+    fn default() -> Self {
+        FieldOrder::AttributesToRetrieve
     }
 }
 
-fn add_non_formatted_ids_to_formatted_options(
-    formatted_options: &mut BTreeMap<FieldId, FormatOptions>,
-    to_retrieve_ids: &BTreeSet<FieldId>,
-) {
-    for id in to_retrieve_ids {
-        formatted_options.entry(*id).or_insert(FormatOptions {
-            highlight: false,
-            crop: None,
-        });
+/// The serialization of the search result.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ResultFormat {
+    /// The hits are in the `hits` array of the result.
+    Json,
+    /// The result is a GeoJSON `FeatureCollection`, see `SearchResult::to_geojson`.
+    GeoJson,
+}
+
+impl Default for ResultFormat {
+    fn default() -> Self {
+        ResultFormat::Json
     }
 }
 
-fn make_document(
-    attributes_to_retrieve: &BTreeSet<FieldId>,
-    field_ids_map: &FieldsIdsMap,
-    obkv: obkv::KvReaderU16,
-) -> Result<Document> {
-    let mut document = serde_json::Map::new();
+/// The order of the values of each facet in the facets distribution.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FacetSortBy {
+    /// The values are sorted alphabetically.
+    Alpha,
+    /// The values are sorted by decreasing number of documents.
+    Count,
+}
 
-    // recreate the original json
-    for (key, value) in obkv.iter() {
-        let value = serde_json::from_slice(value)?;
-        let key = field_ids_map
-            .name(key)
-            .expect("Missing field name")
-            .to_string();
+impl Default for FacetSortBy {
+    fn default() -> Self {
+        FacetSortBy::Alpha
+    }
+}
 
-        document.insert(key, value);
+impl SearchQuery {
+    fn is_paginated(&self) -> bool {
+        self.page.is_some() || self.hits_per_page.is_some()
     }
 
-    // select the attributes to retrieve
-    let attributes_to_retrieve = attributes_to_retrieve
-        .iter()
-        .map(|&fid| field_ids_map.name(fid).expect("Missing field name"));
+    /// Returns the offset and limit requested by this query, either directly or through the
+    /// `page` and `hitsPerPage` parameters.
+    ///
+    /// With `strictLimits`, requesting documents past the `max_total_hits` first hits is an error
+    /// instead of the offset and limit being clamped by the search.
+    fn offset_and_limit(&self, max_total_hits: usize) -> Result<(usize, usize)> {
+        let (offset, limit) = if self.is_paginated() {
+            self.page_offset_and_limit()?
+        } else {
+            (
+                self.offset.unwrap_or_default(),
+                self.limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+            )
+        };
 
-    let document = permissive_json_pointer::select_values(&document, attributes_to_retrieve);
+        if self.strict_limits.unwrap_or_default() && offset.saturating_add(limit) > max_total_hits {
+            return Err(SearchError::HardLimitExceeded(offset, limit, max_total_hits).into());
+        }
 
-    // then we need to convert the `serde_json::Map` into an `IndexMap`.
-    let document = document.into_iter().collect();
+        Ok((offset, limit))
+    }
 
-    Ok(document)
+    fn page_offset_and_limit(&self) -> Result<(usize, usize)> {
+        if self.offset.is_some() || self.limit.is_some() {
+            return Err(SearchError::MixedPagination.into());
+        }
+
+        let page = self.page.unwrap_or(1);
+        let hits_per_page = self.hits_per_page.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        match page.checked_sub(1) {
+            Some(previous_pages) => {
+                Ok((previous_pages.saturating_mul(hits_per_page), hits_per_page))
+            }
+            None => Err(SearchError::InvalidPage.into()),
+        }
+    }
 }
 
-fn format_fields<A: AsRef<[u8]>>(
-    document: &Document,
-    field_ids_map: &FieldsIdsMap,
-    formatter: &Formatter<A>,
-    matching_words: &impl Matcher,
-    formatted_options: &BTreeMap<FieldId, FormatOptions>,
-) -> Result<Document> {
-    // Convert the `IndexMap` into a `serde_json::Map`.
-    let document = document
-        .iter()
-        .map(|(k, v)| (k.clone(), v.clone()))
-        .collect();
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub document: Document,
+    #[serde(rename = "_formatted", skip_serializing_if = "Document::is_empty")]
+    pub formatted: Document,
+    #[serde(rename = "_matchesInfo", skip_serializing_if = "Option::is_none")]
+    pub matches_info: Option<MatchesInfo>,
+    #[serde(rename = "_croppedAttributes", skip_serializing_if = "Option::is_none")]
+    pub cropped_attributes: Option<Vec<String>>,
+    /// The number of typos the document needed to match the query.
+    #[serde(rename = "_typos", skip_serializing_if = "Option::is_none")]
+    pub typos: Option<u32>,
+    /// Whether each formatted attribute has been truncated by a crop.
+    #[serde(
+        rename = "_formattedTruncated",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub formatted_truncated: Option<BTreeMap<String, bool>>,
+    /// The length of the serialized document, without its `_formatted` values.
+    #[serde(rename = "_sizeBytes", skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<usize>,
+    /// The number of distinct query words the document contains.
+    #[serde(rename = "_matchedWordsCount", skip_serializing_if = "Option::is_none")]
+    pub matched_words_count: Option<usize>,
+    /// The relevance of the document to the query, between `0.0` and `1.0`, see `ranking_score`.
+    #[serde(rename = "_rankingScore", skip_serializing_if = "Option::is_none")]
+    pub ranking_score: Option<f64>,
+}
 
-    let selectors: Vec<_> = formatted_options
-        .keys()
-        // This unwrap must be safe since we got the ids from the fields_ids_map just
-        // before.
-        .map(|&fid| field_ids_map.name(fid).unwrap())
-        .collect();
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub hits: Vec<SearchHit>,
+    pub nb_hits: u64,
+    pub exhaustive_nb_hits: bool,
+    pub query: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub processing_time_ms: u128,
+    /// The processing time in microseconds, the processing time in milliseconds being `0` for the
+    /// fastest searches.
+    pub processing_time_us: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facets_distribution: Option<BTreeMap<String, FacetValues>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exhaustive_facets_count: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_hash: Option<String>,
+    /// `nb_hits` under its newer name, when it is an estimation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_total_hits: Option<u64>,
+    /// `nb_hits` under its newer name, when it is exhaustive, which is always the case with
+    /// pagination.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_hits: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_pages: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facet_stats: Option<BTreeMap<String, FacetStats>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub processing_times: Option<ProcessingTimes>,
+    /// The attributes referenced by the filter of the query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_fields: Option<BTreeSet<String>>,
+    /// A correction of the misspelled words of the query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+    /// The ranking rules applied to the query, in order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ranking_rules: Option<Vec<String>>,
+    /// The number of hits of the page having a non-null value for each displayed field.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_coverage: Option<BTreeMap<String, u64>>,
+    /// The parameters of the query that were ignored because they have no effect.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// The `cursor` of the next page of a cursor paginated search, if there are hits left.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// The words of `q` as the engine searches them, normalized and without the stop words.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_query: Option<Vec<String>>,
+    /// The number of candidates of the query with and without its filter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidates_count: Option<CandidatesCount>,
+    /// The number of hits before the `rankingScoreThreshold` was applied, when it left some hits
+    /// out of `nb_hits`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unfiltered_hits: Option<u64>,
+}
 
-    let mut document = permissive_json_pointer::select_values(&document, selectors.iter().copied());
+impl SearchResult {
+    /// Serializes the result as a GeoJSON `FeatureCollection` of the hits having a valid `_geo`
+    /// field, the other hits being skipped. The other fields of the result are kept as foreign
+    /// members of the collection.
+    pub fn to_geojson(&self) -> Result<Value> {
+        let mut result = match serde_json::to_value(self)? {
+            Value::Object(result) => result,
+            _ => Map::new(),
+        };
+        let features: Vec<_> = match result.remove("hits") {
+            Some(Value::Array(hits)) => hits.into_iter().filter_map(geojson_feature).collect(),
+            _ => Vec::new(),
+        };
 
-    permissive_json_pointer::map_leaf_values(&mut document, selectors, |key, value| {
-        // To get the formatting option of each key we need to see all the rules that applies
-        // to the value and merge them together. eg. If a user said he wanted to highlight `doggo`
-        // and crop `doggo.name`. `doggo.name` needs to be highlighted + cropped while `doggo.age` is only
-        // highlighted.
-        let format = formatted_options
-            .iter()
-            .filter(|(field, _option)| {
-                let name = field_ids_map.name(**field).unwrap();
-                milli::is_faceted_by(name, key) || milli::is_faceted_by(key, name)
-            })
-            .fold(FormatOptions::default(), |acc, (_, option)| {
-                acc.merge(*option)
-            });
-        // TODO: remove this useless clone
-        *value = formatter.format_value(value.clone(), matching_words, format);
-    });
+        let mut collection = Map::new();
+        collection.insert("type".to_string(), json!("FeatureCollection"));
+        collection.insert("features".to_string(), Value::Array(features));
+        collection.extend(result);
+        Ok(Value::Object(collection))
+    }
+}
 
-    // we need to convert back the `serde_json::Map` into an `IndexMap`.
-    let document = document.into_iter().collect();
+/// Returns the GeoJSON `Feature` of a serialized hit, its `_geo` field being its point geometry,
+/// given as `[lng, lat]`, and its other fields its properties. There is no feature without a valid
+/// `_geo` field.
+fn geojson_feature(hit: Value) -> Option<Value> {
+    let mut properties = match hit {
+        Value::Object(properties) => properties,
+        _ => return None,
+    };
+    let [lat, lng] = extract_geo_point(&properties.remove("_geo")?)?;
 
-    Ok(document)
+    Some(json!({
+        "type": "Feature",
+        "geometry": { "type": "Point", "coordinates": [lng, lat] },
+        "properties": properties,
+    }))
 }
 
-/// trait to allow unit testing of `format_fields`
-trait Matcher {
-    fn matches(&self, w: &Token) -> Option<usize>;
+/// The number of documents matching a query before and after its filter is applied, to measure the
+/// selectivity of the filter. The excluded documents are never counted.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidatesCount {
+    /// The documents matching the query words in the searched attributes, all the documents for
+    /// a placeholder search.
+    pub before_filter: u64,
+    /// The documents matching the query words in the searched attributes and the filter, before
+    /// the documents are made distinct.
+    pub after_filter: u64,
 }
 
-#[cfg(test)]
-impl Matcher for BTreeMap<&str, Option<usize>> {
-    fn matches(&self, w: &Token) -> Option<usize> {
-        self.get(w.text()).cloned().flatten()
-    }
+/// The time spent in each phase of a search, in milliseconds.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessingTimes {
+    /// The parsing of the query and its execution by milli.
+    pub search: f64,
+    /// The retrieval of the documents and their filtering and reordering after the search.
+    pub documents: f64,
+    /// The formatting of the hits.
+    pub formatting: f64,
+    /// The computation of the facets distribution and stats.
+    pub facets: f64,
 }
 
-impl Matcher for MatchingWords {
-    fn matches(&self, w: &Token) -> Option<usize> {
-        self.matching_bytes(w)
+impl ProcessingTimes {
+    fn between(start: Instant, end: Instant) -> f64 {
+        (end - start).as_secs_f64() * 1000.0
     }
 }
 
-struct Formatter<'a, A> {
-    analyzer: &'a Analyzer<'a, A>,
-    highlight_tags: (String, String),
-    crop_marker: String,
+/// Aborts a search once it ran for longer than the `timeoutMs` of its query.
+#[derive(Debug, Clone, Copy)]
+struct Deadline {
+    start: Instant,
+    timeout_ms: Option<u64>,
 }
 
-impl<'a, A: AsRef<[u8]>> Formatter<'a, A> {
-    pub fn new(
-        analyzer: &'a Analyzer<'a, A>,
-        highlight_tags: (String, String),
-        crop_marker: String,
-    ) -> Self {
-        Self {
-            analyzer,
-            highlight_tags,
-            crop_marker,
-        }
+impl Deadline {
+    fn new(start: Instant, timeout_ms: Option<u64>) -> Self {
+        Self { start, timeout_ms }
     }
 
-    fn format_value(
-        &self,
-        value: Value,
-        matcher: &impl Matcher,
-        format_options: FormatOptions,
-    ) -> Value {
-        match value {
-            Value::String(old_string) => {
-                let value = self.format_string(old_string, matcher, format_options);
-                Value::String(value)
-            }
-            Value::Array(values) => Value::Array(
-                values
-                    .into_iter()
-                    .map(|v| {
-                        self.format_value(
-                            v,
-                            matcher,
-                            FormatOptions {
-                                highlight: format_options.highlight,
-                                crop: None,
-                            },
-                        )
-                    })
-                    .collect(),
-            ),
-            Value::Object(object) => Value::Object(
-                object
-                    .into_iter()
-                    .map(|(k, v)| {
-                        (
-                            k,
-                            self.format_value(
-                                v,
-                                matcher,
-                                FormatOptions {
-                                    highlight: format_options.highlight,
-                                    crop: None,
-                                },
-                            ),
-                        )
-                    })
-                    .collect(),
-            ),
-            Value::Number(number) => {
-                let number_string_value =
-                    self.format_string(number.to_string(), matcher, format_options);
-                Value::String(number_string_value)
+    /// Returns a `SearchError::Timeout` if the timeout is exceeded, `phase` telling what the search
+    /// was doing.
+    fn check(&self, phase: &'static str) -> Result<()> {
+        match self.timeout_ms {
+            Some(timeout_ms) => {
+                let elapsed = self.start.elapsed();
+                if elapsed > Duration::from_millis(timeout_ms) {
+                    Err(SearchError::Timeout(timeout_ms, elapsed.as_millis(), phase).into())
+                } else {
+                    Ok(())
+                }
             }
-            value => value,
+            None => Ok(()),
         }
     }
+}
 
-    fn format_string(
-        &self,
-        s: String,
-        matcher: &impl Matcher,
-        format_options: FormatOptions,
-    ) -> String {
-        let analyzed = self.analyzer.analyze(&s);
-
-        let mut tokens = analyzed.reconstruct();
-        let mut crop_marker_before = false;
+/// The values of a facet with their number of documents, serialized as a map that keeps the order
+/// requested by `facetSort`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FacetValues(pub Vec<(String, u64)>);
+
+impl FacetValues {
+    fn new(values: BTreeMap<String, u64>, sort_by: FacetSortBy) -> Self {
+        let mut values: Vec<_> = values.into_iter().collect();
+        if sort_by == FacetSortBy::Count {
+            // the sort is stable, the values with the same count stay in alphabetical order
+            values.sort_by(|(_, a), (_, b)| b.cmp(a));
+        }
+        FacetValues(values)
+    }
 
-        let tokens_interval: Box<dyn Iterator<Item = (&str, Token)>> = match format_options.crop {
-            Some(crop_len) if crop_len > 0 => {
-                let mut buffer = Vec::new();
-                let mut tokens = tokens.by_ref().peekable();
+    /// Keeps only the first `max` values, returns whether some values were removed.
+    fn truncate(&mut self, max: usize) -> bool {
+        let truncated = self.0.len() > max;
+        self.0.truncate(max);
+        truncated
+    }
+}
 
-                while let Some((word, token)) =
-                    tokens.next_if(|(_, token)| matcher.matches(token).is_none())
-                {
-                    buffer.push((word, token));
-                }
+impl Serialize for FacetValues {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(value, count)| (value, count)))
+    }
+}
 
-                match tokens.next() {
-                    Some(token) => {
-                        let mut total_count: usize = buffer
-                            .iter()
-                            .filter(|(_, token)| token.is_separator().is_none())
-                            .count();
+/// A value of a facet matching the query of `Index::search_facet_values`, with its number of
+/// documents.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FacetValueHit {
+    pub value: String,
+    pub count: u64,
+}
 
-                        let crop_len_before = crop_len / 2;
-                        // check if start will be cropped.
-                        crop_marker_before = total_count > crop_len_before;
+/// The smallest and biggest numeric values of a facet among the candidates of a search.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct FacetStats {
+    pub min: f64,
+    pub max: f64,
+}
 
-                        let before_iter = buffer.into_iter().skip_while(move |(_, token)| {
-                            if token.is_separator().is_none() {
-                                total_count -= 1;
-                            }
-                            total_count >= crop_len_before
-                        });
+#[derive(Copy, Clone, Default)]
+struct FormatOptions<'a> {
+    highlight: bool,
+    crop: Option<usize>,
+    /// The highlight tags of the attribute, overriding the ones of the query.
+    highlight_tags: Option<&'a HighlightAttribute>,
+    /// Whether the crop boundaries are moved to the closest sentence boundaries.
+    crop_on_sentences: bool,
+}
 
-                        // rebalance remaining word count after the match.
-                        let crop_len_after = if crop_marker_before {
-                            crop_len.saturating_sub(crop_len_before + 1)
-                        } else {
-                            crop_len.saturating_sub(total_count + 1)
-                        };
-
-                        let mut taken_after = 0;
-                        let after_iter = tokens.take_while(move |(_, token)| {
-                            let take = taken_after < crop_len_after;
-                            if token.is_separator().is_none() {
-                                taken_after += 1;
-                            }
-                            take
-                        });
+impl<'a> FormatOptions<'a> {
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            highlight: self.highlight || other.highlight,
+            crop: self.crop.or(other.crop),
+            highlight_tags: self.highlight_tags.or(other.highlight_tags),
+            crop_on_sentences: self.crop_on_sentences || other.crop_on_sentences,
+        }
+    }
+}
 
-                        let iter = before_iter.chain(Some(token)).chain(after_iter);
+impl Index {
+    pub fn perform_search(&self, query: SearchQuery) -> Result<SearchResult> {
+        let generation = self.search_cache.as_ref().map(|cache| cache.generation());
+        // The default search query is read in the transaction of the search, for the search to
+        // apply the settings of the version of the index it searches.
+        let rtxn = self.read_txn()?;
+        let query = self.apply_default_search_query(&rtxn, query)?;
+        self.cached_search(query, generation, |query| {
+            self.execute_search(&rtxn, query, None)
+        })
+    }
 
-                        Box::new(iter)
-                    }
-                    // If no word matches in the attribute
-                    None => {
-                        let mut count = 0;
-                        let mut tokens = buffer.into_iter();
-                        let mut out: String = tokens
-                            .by_ref()
-                            .take_while(move |(_, token)| {
-                                let take = count < crop_len;
-                                if token.is_separator().is_none() {
-                                    count += 1;
-                                }
-                                take
-                            })
-                            .map(|(word, _)| word)
-                            .collect();
+    /// Performs all the `queries` like `perform_search`, on the same version of the index and
+    /// with the same analyzer. The results are returned in the order of the queries, a failing
+    /// query failing only its own result.
+    ///
+    /// The returned error is the one of the preparation shared by all the queries.
+    pub fn perform_multi_search(
+        &self,
+        queries: Vec<SearchQuery>,
+    ) -> Result<Vec<Result<SearchResult>>> {
+        // The generation is read before the transaction is opened, like in `perform_search`.
+        let generation = self.search_cache.as_ref().map(|cache| cache.generation());
+        let rtxn = self.read_txn()?;
+        let index_analyzer = self.stop_words_analyzer(&rtxn)?;
+        let analyzer = index_analyzer
+            .as_deref()
+            .map_or(&*ANALYZER, StopWordsAnalyzer::analyzer);
 
-                        // if there are remaining tokens after formatted interval,
-                        // put a crop marker at the end.
-                        if tokens.next().is_some() {
-                            out.push_str(&self.crop_marker);
-                        }
+        let results = queries
+            .into_iter()
+            .map(|query| {
+                let query = self.apply_default_search_query(&rtxn, query)?;
+                self.cached_search(query, generation, |query| {
+                    self.execute_search_in(&rtxn, analyzer, query, None)
+                })
+            })
+            .collect();
+        Ok(results)
+    }
 
-                        return out;
-                    }
-                }
+    /// Returns the result of `query` from the search cache, or computes it with `execute` and
+    /// caches it. `generation` is the generation of the cache read before `execute` opened its
+    /// transaction, the cache is bypassed when there is none.
+    fn cached_search(
+        &self,
+        query: SearchQuery,
+        generation: Option<u64>,
+        execute: impl FnOnce(SearchQuery) -> Result<SearchResult>,
+    ) -> Result<SearchResult> {
+        let (cache, generation) = match (&self.search_cache, generation) {
+            (Some(cache), Some(generation)) => (cache, generation),
+            _ => return execute(query),
+        };
+
+        let before_search = Instant::now();
+        let key = match SearchCache::key(&query) {
+            Some(key) => key,
+            None => return execute(query),
+        };
+        if let Some(mut result) = cache.get(&key) {
+            let processing_time = before_search.elapsed();
+            result.processing_time_ms = processing_time.as_millis();
+            result.processing_time_us = processing_time.as_micros();
+            result.processing_times = result.processing_times.map(|_| ProcessingTimes {
+                search: ProcessingTimes::between(before_search, Instant::now()),
+                documents: 0.0,
+                formatting: 0.0,
+                facets: 0.0,
+            });
+            return Ok(result);
+        }
+
+        let result = execute(query)?;
+        cache.insert(key, generation, result.clone());
+        Ok(result)
+    }
+
+    /// Performs the search requested by `query` like `perform_search`, but calls `on_hit` with
+    /// every hit as soon as it has been formatted instead of returning them.
+    ///
+    /// The hits are given in their ranking order, the returned result holds everything else. An
+    /// error returned by `on_hit` stops the search and is returned.
+    pub fn stream_search(
+        &self,
+        query: SearchQuery,
+        mut on_hit: impl FnMut(SearchHit) -> Result<()>,
+    ) -> Result<SearchResult> {
+        let rtxn = self.read_txn()?;
+        let query = self.apply_default_search_query(&rtxn, query)?;
+        self.execute_search(&rtxn, query, Some(&mut on_hit))
+    }
+
+    /// Completes `query` with the default search query of the settings of the index, if any.
+    fn apply_default_search_query(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: SearchQuery,
+    ) -> Result<SearchQuery> {
+        match self.default_search_query(rtxn)? {
+            Some(template) => Ok(query.with_template(&template.to_query()?)),
+            None => Ok(query),
+        }
+    }
+
+    /// Performs the search requested by `query` in `rtxn`. The hits are given to `on_hit` if any,
+    /// in which case they are formatted one at a time and not returned.
+    fn execute_search(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: SearchQuery,
+        on_hit: Option<&mut dyn FnMut(SearchHit) -> Result<()>>,
+    ) -> Result<SearchResult> {
+        let index_analyzer = self.stop_words_analyzer(rtxn)?;
+        let analyzer = index_analyzer
+            .as_deref()
+            .map_or(&*ANALYZER, StopWordsAnalyzer::analyzer);
+        self.execute_search_in(rtxn, analyzer, query, on_hit)
+    }
+
+    /// Returns the analyzer built with the stop words of the index, the searches analyze the
+    /// query and the documents with them, like when indexing. It is built once for the stop words
+    /// read in `rtxn`, `None` is returned when the index has no stop words.
+    fn stop_words_analyzer(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+    ) -> Result<Option<Arc<StopWordsAnalyzer>>> {
+        self.stop_words(rtxn)?
+            .map(|stop_words| self.analyzer_cache.get(&stop_words))
+            .transpose()
+    }
+
+    /// Performs the search requested by `query` in `rtxn`, like `execute_search`, `analyzer`
+    /// being built with the stop words of the index.
+    ///
+    /// The search goes through stages: the milli search and the filters milli can't evaluate
+    /// give the candidates, their documents are then post-processed and paginated, and the hits
+    /// are built from the documents of the page.
+    fn execute_search_in(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        analyzer: &Analyzer<Vec<u8>>,
+        query: SearchQuery,
+        on_hit: Option<&mut dyn FnMut(SearchHit) -> Result<()>>,
+    ) -> Result<SearchResult> {
+        let before_search = Instant::now();
+        let deadline = Deadline::new(before_search, query.timeout_ms);
+
+        let plan = self.plan_search(rtxn, &query)?;
+        let facets = match query.facets_distribution {
+            Some(ref facets) => {
+                check_facets(&query, &self.filterable_fields(rtxn)?)?;
+                Some(self.facets_to_count(rtxn, facets)?)
             }
-            _ => Box::new(tokens.by_ref()),
+            None => None,
+        };
+        let ranking_rules = if query.show_ranking_rules.unwrap_or_default() {
+            Some(applied_ranking_rules(&plan.criteria, &query))
+        } else {
+            None
         };
+        let filter_fields = query.show_filter_fields.unwrap_or_default().then(|| {
+            let mut fields = BTreeSet::new();
+            if let Some(ref filter) = query.filter {
+                collect_filter_fields(filter, &mut fields);
+            }
+            fields
+        });
+        let mut warnings = search_warnings(
+            &query,
+            &unknown_attributes(&query, &plan.fields_ids_map, &plan.displayed_ids),
+        );
 
-        let out = if crop_marker_before {
-            self.crop_marker.clone()
+        let milli::SearchResult {
+            documents_ids,
+            matching_words,
+            mut candidates,
+            ..
+        } = self.run_search(rtxn, &query, &plan)?;
+        let after_search = Instant::now();
+        deadline.check("searching")?;
+
+        // milli doesn't tell which matching words come from a phrase of the query.
+        let q = query.q.as_deref().unwrap_or_default();
+        let synonyms = self.synonyms(rtxn)?;
+        let words = QueryWords {
+            matcher: QueryMatcher::new(&matching_words, q, analyzer)
+                .with_synonyms(q, analyzer, &synonyms),
+            typo_words: (query.show_typos.unwrap_or_default()
+                || query.show_matched_words_count.unwrap_or_default()
+                || query.show_ranking_score.unwrap_or_default()
+                || query.ranking_score_threshold.is_some()
+                || query.cursor.is_some()
+                || query.ranking_rules.is_some())
+            .then(|| typo_words(q, analyzer)),
+        };
+        let normalized_query = query
+            .show_normalized_query
+            .unwrap_or_default()
+            .then(|| normalized_query(q, analyzer));
+
+        if let Some(ref searched_ids) = plan.searched_ids {
+            self.restrict_to_searched_fields(
+                rtxn,
+                searched_ids,
+                &words.matcher,
+                analyzer,
+                &mut candidates,
+            )?;
+        }
+        let mut state = self.filter_candidates(rtxn, &query, &plan, documents_ids, candidates)?;
+        let candidates_count = if query.show_candidates_count.unwrap_or_default() {
+            Some(self.candidates_count(rtxn, analyzer, &query, &plan, &words, &state.candidates)?)
         } else {
-            String::new()
+            None
         };
+        self.post_process(rtxn, analyzer, &query, &plan, &words, &mut state)?;
+        let (nb_hits, total_pages) = self.paginate(&query, &plan, &mut state);
 
-        let mut out = tokens_interval.fold(out, |mut out, (word, token)| {
-            // Check if we need to do highlighting or computed matches before calling
-            // Matcher::match since the call is expensive.
-            if format_options.highlight && token.is_word() {
-                if let Some(length) = matcher.matches(&token) {
-                    match word.get(..length).zip(word.get(length..)) {
-                        Some((head, tail)) => {
-                            out.push_str(&self.highlight_tags.0);
-                            out.push_str(head);
-                            out.push_str(&self.highlight_tags.1);
-                            out.push_str(tail);
-                        }
-                        // if we are in the middle of a character
-                        // or if all the word should be highlighted,
-                        // we highlight the complete word.
-                        None => {
-                            out.push_str(&self.highlight_tags.0);
-                            out.push_str(word);
-                            out.push_str(&self.highlight_tags.1);
-                        }
-                    }
-                    return out;
-                }
+        let before_formatting = Instant::now();
+        deadline.check("retrieving the documents")?;
+
+        let SearchState {
+            candidates,
+            documents,
+            next_cursor,
+            warnings: post_processing_warnings,
+            unfiltered_hits,
+        } = state;
+        let unfiltered_hits = unfiltered_hits
+            .map(|hits| plan.nb_hits(hits))
+            .filter(|hits| *hits > nb_hits);
+        warnings.extend(post_processing_warnings);
+        let field_coverage = query
+            .show_field_coverage
+            .unwrap_or_default()
+            .then(|| field_coverage(&plan.displayed_ids, &plan.fields_ids_map, &documents));
+        let (hits, result_hash) = build_hits(
+            analyzer, &query, &plan, &words, documents, &deadline, on_hit,
+        )?;
+        let after_formatting = Instant::now();
+
+        let before_facets = Instant::now();
+        let (facets_distribution, exhaustive_facets_count, facet_stats) = match facets {
+            Some(ref fields) => {
+                let facets = self.search_facets(
+                    rtxn,
+                    &query,
+                    fields.as_ref(),
+                    &candidates,
+                    &plan.excluded_ids,
+                )?;
+                (
+                    Some(facets.distribution),
+                    Some(facets.exhaustive_count),
+                    facets.stats,
+                )
             }
-            out.push_str(word);
-            out
+            None => (None, None, None),
+        };
+        let after_facets = Instant::now();
+
+        let processing_times =
+            query
+                .show_processing_times
+                .unwrap_or_default()
+                .then(|| ProcessingTimes {
+                    search: ProcessingTimes::between(before_search, after_search),
+                    documents: ProcessingTimes::between(after_search, before_formatting),
+                    formatting: ProcessingTimes::between(before_formatting, after_formatting),
+                    facets: ProcessingTimes::between(before_facets, after_facets),
+                });
+
+        let exhaustive_nb_hits = false; // not implemented yet
+        let (estimated_total_hits, total_hits) = match total_pages {
+            Some(_) => (None, Some(nb_hits)),
+            None if exhaustive_nb_hits => (None, Some(nb_hits)),
+            None => (Some(nb_hits), None),
+        };
+
+        let suggestion = match query.q.as_deref() {
+            Some(q)
+                if query.show_suggestion.unwrap_or_default()
+                    && nb_hits < SUGGESTION_HITS_THRESHOLD =>
+            {
+                self.suggest_query(rtxn, q)?
+            }
+            _ => None,
+        };
+
+        let processing_time = before_search.elapsed();
+        let result = SearchResult {
+            exhaustive_nb_hits,
+            hits,
+            nb_hits,
+            query: query.q.clone().unwrap_or_default(),
+            limit: plan.requested_limit,
+            offset: plan.requested_offset,
+            processing_time_ms: processing_time.as_millis(),
+            processing_time_us: processing_time.as_micros(),
+            facets_distribution,
+            exhaustive_facets_count,
+            result_hash,
+            estimated_total_hits,
+            total_hits,
+            total_pages,
+            facet_stats,
+            processing_times,
+            filter_fields,
+            suggestion,
+            ranking_rules,
+            field_coverage,
+            warnings,
+            next_cursor,
+            normalized_query,
+            candidates_count,
+            unfiltered_hits,
+        };
+        Ok(result)
+    }
+
+    /// Resolves the parameters of `query` against the index before searching: the filter, the
+    /// ranking rules, the page and the post-processing the documents returned by milli need.
+    fn plan_search(&self, rtxn: &milli::heed::RoTxn, query: &SearchQuery) -> Result<SearchPlan> {
+        let filter = query.filter.as_ref().map(SearchFilter::new).transpose()?;
+        let criteria = match query.ranking_rules {
+            Some(ref rules) => parse_ranking_rules(rules)?,
+            None => self.criteria(rtxn)?,
+        };
+
+        // The hits past the `maxTotalHits` of the index can never be reached.
+        let nb_hits_cap = self.max_total_hits(rtxn)?;
+        let max_total_hits =
+            nb_hits_cap.map_or(HARD_RESULT_LIMIT, |max| min(max, HARD_RESULT_LIMIT));
+
+        let (requested_offset, requested_limit) = query.offset_and_limit(max_total_hits)?;
+        if let Some(threshold) = query.ranking_score_threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(SearchError::InvalidRankingScoreThreshold(threshold).into());
+            }
+        }
+
+        // Make sure that a user can't get more documents than the hard limit,
+        // we align that on the offset too.
+        let offset = min(requested_offset, max_total_hits);
+        let limit = min(requested_limit, max_total_hits.saturating_sub(offset));
+
+        let fields_ids_map = self.fields_ids_map(rtxn).unwrap();
+
+        // The displayed fields in the order of the displayed attributes of the index, or by field
+        // id when all the fields are displayed. `*` retrieves them in this order.
+        let displayed_order: Vec<_> = self
+            .displayed_fields_ids(rtxn)?
+            .unwrap_or_else(|| fields_ids_map.iter().map(|(id, _)| id).collect());
+        let displayed_ids: BTreeSet<_> = displayed_order.iter().copied().collect();
+
+        check_attribute_regexes(query)?;
+        if query.strict_attributes.unwrap_or_default() {
+            check_attributes(query, &fields_ids_map, &displayed_ids)?;
+        }
+
+        let primary_key = self
+            .primary_key(rtxn)?
+            .and_then(|key| fields_ids_map.id(key));
+
+        let cursor = query
+            .cursor
+            .as_deref()
+            .map(|cursor| SearchCursor::new(query, cursor))
+            .transpose()?;
+
+        let searched_ids = self.searched_fields_ids(rtxn, query, &fields_ids_map)?;
+        let query_distinct = self.query_distinct(rtxn, query, &fields_ids_map)?;
+        let excluded_ids = self.excluded_documents_ids(rtxn, query)?;
+
+        let geo_filtered = filter.as_ref().map_or(false, |filter| {
+            !filter.bounding_boxes.is_empty() || !filter.distance_bounds.is_empty()
         });
 
-        // if there are remaining tokens after formatted interval,
-        // put a crop marker at the end.
-        if tokens.next().is_some() {
-            out.push_str(&self.crop_marker);
+        // The documents returned by milli are filtered and reordered after the search when the
+        // searched attributes are restricted, when the filter contains bounding boxes or
+        // `_geoDistance` bounds, when the
+        // query has its own distinct attribute, when documents are excluded, when the query has
+        // its own ranking rules, when the hits are cut at a ranking score threshold or when exact
+        // matches are preferred. In these cases milli must return the documents ranked before the
+        // requested page too.
+        let filtered = searched_ids.is_some()
+            || geo_filtered
+            || query_distinct.is_some()
+            || !excluded_ids.is_empty()
+            || query.ranking_rules.is_some()
+            || query.ranking_score_threshold.is_some()
+            || query.prefer_exact_matches.unwrap_or_default();
+
+        // milli returns the documents tying on every ranking rule in the order they were indexed
+        // in, they are instead ordered by id unless the query opts out. The documents ranked
+        // before the requested page must then be fetched to find the ties overlapping it. The
+        // pages of a cursor are already ordered by id after the sort.
+        let sort_ties_by_id =
+            query.sort_ties_by_id.unwrap_or(true) && cursor.is_none() && primary_key.is_some();
+        let post_processed = filtered || sort_ties_by_id;
+
+        Ok(SearchPlan {
+            filter,
+            criteria,
+            fields_ids_map,
+            displayed_order,
+            displayed_ids,
+            primary_key,
+            max_total_hits,
+            nb_hits_cap,
+            requested_offset,
+            requested_limit,
+            offset,
+            limit,
+            cursor,
+            searched_ids,
+            query_distinct,
+            excluded_ids,
+            geo_filtered,
+            filtered,
+            sort_ties_by_id,
+            post_processed,
+        })
+    }
+
+    /// Performs the milli search of `query`, milli returning the documents of the requested page
+    /// or, when they are post-processed, all the documents that can be reached.
+    fn run_search(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+        plan: &SearchPlan,
+    ) -> Result<milli::SearchResult> {
+        let mut search = self.search(rtxn);
+        configure_search(&mut search, query, plan.filter.as_ref())?;
+
+        // With a limit of 0 only the candidates are needed, the documents are not fetched unless
+        // they are required to restrict the candidates to the searched attributes or to the
+        // distinct documents. The pages of a cursor paginated search are computed from all the
+        // candidates, milli doesn't return any document.
+        let fetch_documents =
+            plan.limit > 0 || plan.searched_ids.is_some() || plan.query_distinct.is_some();
+        if !fetch_documents || plan.cursor.is_some() {
+            search.offset(0);
+            search.limit(0);
+        } else if plan.filtered || plan.sort_ties_by_id {
+            search.offset(0);
+            search.limit(plan.max_total_hits);
+        } else {
+            search.offset(plan.offset);
+            search.limit(plan.limit);
         }
 
-        out
+        Ok(search.execute()?)
     }
-}
 
-fn parse_filter(facets: &Value) -> Result<Option<Filter>> {
-    match facets {
-        Value::String(expr) => {
-            let condition = Filter::from_str(expr)?;
-            Ok(condition)
+    /// Returns the candidates of the search and the documents milli returned among them, without
+    /// the excluded documents nor the ones outside of the geo filters milli can't evaluate. The
+    /// documents milli left out because of the distinct attribute of the index are put back when
+    /// the query replaces it.
+    fn filter_candidates<'t>(
+        &self,
+        rtxn: &'t milli::heed::RoTxn,
+        query: &SearchQuery,
+        plan: &SearchPlan,
+        documents_ids: Vec<u32>,
+        mut candidates: RoaringBitmap,
+    ) -> Result<SearchState<'t>> {
+        candidates -= &plan.excluded_ids;
+        let mut documents = self.documents(rtxn, documents_ids)?;
+        if let Some(QueryDistinct {
+            index: Some(index_distinct),
+            ..
+        }) = plan.query_distinct
+        {
+            documents = self.undo_index_distinct(
+                rtxn,
+                index_distinct,
+                documents,
+                &candidates,
+                plan.max_total_hits,
+            )?;
         }
-        Value::Array(arr) => parse_filter_array(arr),
-        v => Err(FacetError::InvalidExpression(&["Array"], v.clone()).into()),
+        documents.retain(|(id, _)| candidates.contains(*id));
+
+        if let Some(filter) = plan.filter.as_ref().filter(|_| plan.geo_filtered) {
+            self.filter_geo(rtxn, query, filter, &mut candidates)?;
+            documents.retain(|(id, _)| candidates.contains(*id));
+        }
+
+        Ok(SearchState {
+            candidates,
+            documents,
+            next_cursor: None,
+            warnings: Vec::new(),
+            unfiltered_hits: None,
+        })
     }
-}
 
-fn parse_filter_array(arr: &[Value]) -> Result<Option<Filter>> {
-    let mut ands = Vec::new();
-    for value in arr {
-        match value {
-            Value::String(s) => ands.push(Either::Right(s.as_str())),
-            Value::Array(arr) => {
-                let mut ors = Vec::new();
-                for value in arr {
-                    match value {
-                        Value::String(s) => ors.push(s.as_str()),
-                        v => {
-                            return Err(FacetError::InvalidExpression(&["String"], v.clone()).into())
-                        }
+    /// Removes from `candidates` the documents matching the query words only outside of the
+    /// `searched_ids` fields.
+    ///
+    /// milli can't restrict a search to some attributes, all the candidates are checked, before
+    /// the number of hits, the facets and the page are computed from them.
+    fn restrict_to_searched_fields(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        searched_ids: &BTreeSet<FieldId>,
+        matcher: &impl Matcher,
+        analyzer: &Analyzer<Vec<u8>>,
+        candidates: &mut RoaringBitmap,
+    ) -> Result<()> {
+        let mut outside = RoaringBitmap::new();
+        for (id, obkv) in self.documents(rtxn, candidates.iter())? {
+            if !has_match_in_fields(&obkv, searched_ids, matcher, analyzer)? {
+                outside.insert(id);
+            }
+        }
+        *candidates -= outside;
+        Ok(())
+    }
+
+    /// Counts the candidates of the search before and after its filter is applied.
+    fn candidates_count(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        analyzer: &Analyzer<Vec<u8>>,
+        query: &SearchQuery,
+        plan: &SearchPlan,
+        words: &QueryWords,
+        candidates: &RoaringBitmap,
+    ) -> Result<CandidatesCount> {
+        let before_filter = match plan.filter {
+            Some(_) => {
+                let mut search = self.search(rtxn);
+                configure_search(&mut search, query, None)?;
+                search.limit(0);
+                let milli::SearchResult {
+                    candidates: mut unfiltered,
+                    ..
+                } = search.execute()?;
+                unfiltered -= &plan.excluded_ids;
+                if let Some(ref searched_ids) = plan.searched_ids {
+                    self.restrict_to_searched_fields(
+                        rtxn,
+                        searched_ids,
+                        &words.matcher,
+                        analyzer,
+                        &mut unfiltered,
+                    )?;
+                }
+                unfiltered.len()
+            }
+            None => candidates.len(),
+        };
+
+        Ok(CandidatesCount {
+            before_filter,
+            after_filter: candidates.len(),
+        })
+    }
+
+    /// Post-processes the documents returned by milli, in this order: the page of the cursor is
+    /// computed, the documents are restricted to the searched attributes and made distinct on the
+    /// distinct attribute of the query, then they are ranked by the ranking rules of the query,
+    /// cut at the ranking score threshold and ranked by their exact matches. The ties overlapping
+    /// the requested page are finally ordered by id.
+    fn post_process<'t>(
+        &self,
+        rtxn: &'t milli::heed::RoTxn,
+        analyzer: &Analyzer<Vec<u8>>,
+        query: &SearchQuery,
+        plan: &SearchPlan,
+        words: &QueryWords,
+        state: &mut SearchState<'t>,
+    ) -> Result<()> {
+        let mut distinct_id = plan.query_distinct.and_then(|distinct| distinct.query);
+        if let Some(ref cursor) = plan.cursor {
+            // The distinct attribute of the index is applied by milli to the returned documents
+            // only, the page must be made distinct too.
+            let page_distinct_id = match plan.query_distinct {
+                Some(_) => distinct_id.take(),
+                None => self
+                    .distinct_field(rtxn)?
+                    .and_then(|name| plan.fields_ids_map.id(name)),
+            };
+            // The hits of a keyword search are ordered by their ranking score first.
+            let typo_words = words
+                .typo_words
+                .as_deref()
+                .filter(|words| !words.is_empty());
+            let (page, next) = self.cursor_page(
+                rtxn,
+                cursor,
+                &plan.fields_ids_map,
+                page_distinct_id,
+                &mut state.candidates,
+                plan.limit,
+                |obkv| match typo_words {
+                    Some(typo_words) => {
+                        ranking_score(obkv, &plan.criteria, typo_words, &words.matcher, analyzer)
+                            .map(Some)
                     }
+                    None => Ok(None),
+                },
+            )?;
+            state.documents = page;
+            state.next_cursor = next.map(|key| cursor.next(key)).transpose()?;
+        }
+
+        if let Some(distinct_id) = distinct_id {
+            let mut seen = BTreeSet::new();
+            let mut distinct_documents = Vec::new();
+            for (id, obkv) in std::mem::take(&mut state.documents) {
+                if is_distinct(&obkv, distinct_id, &mut seen)? {
+                    distinct_documents.push((id, obkv));
+                } else {
+                    state.candidates.remove(id);
                 }
-                ands.push(Either::Left(ors));
             }
-            v => {
-                return Err(
-                    FacetError::InvalidExpression(&["String", "[String]"], v.clone()).into(),
-                )
+            state.documents = distinct_documents;
+        }
+
+        if query.ranking_rules.is_some() {
+            state.documents = rank_by_rules(
+                std::mem::take(&mut state.documents),
+                &plan.criteria,
+                words.typo_words.as_deref().unwrap_or_default(),
+                &words.matcher,
+                analyzer,
+                &plan.fields_ids_map,
+            )?;
+        }
+
+        if let (Some(threshold), Some(typo_words)) =
+            (query.ranking_score_threshold, words.typo_words.as_deref())
+        {
+            self.cut_at_ranking_score(analyzer, plan, words, typo_words, threshold, state)?;
+        }
+
+        // The documents are reranked among all the ones milli returned, the first max total hits
+        // whatever the requested page, for the pages to follow each other.
+        if query.prefer_exact_matches.unwrap_or_default() {
+            let documents = std::mem::take(&mut state.documents);
+            let mut whole_word_matches = Vec::with_capacity(documents.len());
+            for (_id, obkv) in &documents {
+                whole_word_matches.push(count_whole_word_matches(obkv, &words.matcher, analyzer)?);
             }
+
+            let mut ranked: Vec<_> = whole_word_matches.into_iter().zip(documents).collect();
+            // `sort_by_key` is stable, the milli ranking is kept between documents having the same
+            // number of whole word matches.
+            ranked.sort_by_key(|(count, _)| Reverse(*count));
+            state.documents = ranked.into_iter().map(|(_, document)| document).collect();
+        }
+
+        if let Some(primary_key) = plan.primary_key.filter(|_| plan.sort_ties_by_id) {
+            let ranking_fields = ranking_fields(&plan.criteria, query, &plan.fields_ids_map);
+            let matcher = match query.q.as_deref() {
+                Some(q) if !q.trim().is_empty() => Some(&words.matcher),
+                _ => None,
+            };
+            let page = plan.offset..plan.offset + plan.limit;
+            sort_ties_by_id(
+                &mut state.documents,
+                page,
+                |(_, obkv)| tie_key(obkv, &ranking_fields, matcher, analyzer),
+                |(_, obkv)| DocumentIdKey::new(obkv, primary_key),
+            )?;
         }
+
+        Ok(())
     }
 
-    Ok(Filter::from_array(ands)?)
-}
+    /// Removes the documents scoring below `threshold` from the documents and the candidates,
+    /// the documents being ranked by the ranking rules of the search.
+    ///
+    /// The ranking score never increases along the ranking: when the last document scores below
+    /// the threshold, so do all the candidates milli didn't return. They are kept otherwise, and
+    /// with a cursor whose pages follow the sort instead, the number of hits and the facet counts
+    /// are then upper bounds.
+    fn cut_at_ranking_score(
+        &self,
+        analyzer: &Analyzer<Vec<u8>>,
+        plan: &SearchPlan,
+        words: &QueryWords,
+        typo_words: &[(String, bool)],
+        threshold: f64,
+        state: &mut SearchState,
+    ) -> Result<()> {
+        let unfiltered_hits = state.candidates.len();
+        let mut last_score = None;
+        let mut kept_documents = Vec::new();
+        for (id, obkv) in std::mem::take(&mut state.documents) {
+            let score = ranking_score(&obkv, &plan.criteria, typo_words, &words.matcher, analyzer)?;
+            if score >= threshold {
+                kept_documents.push((id, obkv));
+            } else {
+                state.candidates.remove(id);
+            }
+            last_score = Some(score);
+        }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        if plan.cursor.is_none() && last_score.map_or(false, |score| score < threshold) {
+            state.candidates = kept_documents.iter().map(|(id, _)| *id).collect();
+        }
+        state.documents = kept_documents;
+        if state.candidates.len() < unfiltered_hits {
+            state.unfiltered_hits = Some(unfiltered_hits);
+        }
 
-    #[test]
-    fn no_ids_no_formatted() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
-        );
+        Ok(())
+    }
 
-        let mut fields = FieldsIdsMap::new();
-        fields.insert("test").unwrap();
+    /// Returns the number of hits of the search and, when it is paginated with `page` and
+    /// `hitsPerPage`, its number of pages. The post-processed documents are reduced to the
+    /// requested page, milli only returned this page otherwise.
+    fn paginate(
+        &self,
+        query: &SearchQuery,
+        plan: &SearchPlan,
+        state: &mut SearchState,
+    ) -> (u64, Option<u64>) {
+        let nb_hits = plan.nb_hits(state.candidates.len());
+
+        if plan.post_processed {
+            state.documents = std::mem::take(&mut state.documents)
+                .into_iter()
+                .skip(plan.offset)
+                .take(plan.limit)
+                .collect();
+        }
 
-        let document: serde_json::Value = json!({
-            "test": "hello",
+        // Only the pages containing documents that can be returned are counted.
+        let total_pages = query.is_paginated().then(|| {
+            let reachable_hits = min(nb_hits, plan.max_total_hits as u64);
+            match plan.requested_limit as u64 {
+                0 => 0,
+                hits_per_page => (reachable_hits + hits_per_page - 1) / hits_per_page,
+            }
         });
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
+        (nb_hits, total_pages)
+    }
+
+    /// Counts the values of the facets of the search among its candidates, the `None` facets
+    /// standing for all the filterable attributes.
+    fn search_facets(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+        fields: Option<&BTreeSet<String>>,
+        candidates: &RoaringBitmap,
+        excluded_ids: &RoaringBitmap,
+    ) -> Result<SearchFacets> {
+        let mut facets_distribution = self.facets_distribution(rtxn);
+        if let Some(fields) = fields {
+            facets_distribution.facets(fields);
+        }
+        let mut distribution = facets_distribution
+            .candidates(candidates.clone())
+            .execute()?;
+
+        // The values of a facet filtered by the query are counted as if the clauses of the filter
+        // on this facet were not applied.
+        if let Some(filter) = query
+            .filter
+            .as_ref()
+            .filter(|_| query.disjunctive_facets.unwrap_or_default())
+        {
+            for (facet, values) in distribution.iter_mut() {
+                let candidates = match self.disjunctive_facet_candidates(
+                    rtxn,
+                    query,
+                    filter,
+                    facet,
+                    excluded_ids,
+                )? {
+                    Some(candidates) => candidates,
+                    None => continue,
+                };
+
+                let mut facets_distribution = self.facets_distribution(rtxn);
+                facets_distribution.facets(std::iter::once(facet));
+                *values = facets_distribution
+                    .candidates(candidates)
+                    .execute()?
+                    .remove(facet)
+                    .unwrap_or_default();
+            }
+        }
+
+        // milli stops counting the values of a facet once it reached `MAX_VALUES_PER_FACET`
+        // values, we consider these facets as capped.
+        let capped: Vec<_> = distribution
+            .iter()
+            .filter(|(_, values)| values.len() >= MAX_VALUES_PER_FACET)
+            .map(|(facet, _)| facet.clone())
+            .collect();
+
+        let mut exhaustive_count =
+            if query.exhaustive_facets_count.unwrap_or_default() && !capped.is_empty() {
+                let exhaustive =
+                    self.exhaustive_facets_distribution(rtxn, &capped, candidates.iter())?;
+                let exhaustive_count = capped.iter().all(|facet| exhaustive.contains_key(facet));
+                distribution.extend(exhaustive);
+                exhaustive_count
+            } else {
+                capped.is_empty()
+            };
+
+        let facet_sort = query.facet_sort.unwrap_or_default();
+        let mut distribution: BTreeMap<_, _> = distribution
             .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+            .map(|(facet, values)| (facet, FacetValues::new(values, facet_sort)))
+            .collect();
+
+        if let Some(max_values_per_facet) = query.max_values_per_facet {
+            let mut truncated = false;
+            for values in distribution.values_mut() {
+                truncated |= values.truncate(max_values_per_facet);
+            }
+            if truncated {
+                exhaustive_count = false;
+            }
+        }
+
+        let stats = if query.show_facet_stats.unwrap_or_default() {
+            let fields: Vec<_> = distribution.keys().cloned().collect();
+            Some(self.facet_stats(rtxn, &fields, candidates)?)
+        } else {
+            None
+        };
+
+        Ok(SearchFacets {
+            distribution,
+            exhaustive_count,
+            stats,
+        })
+    }
+
+    /// Returns the facets of `facets_distribution` whose values are counted, or `None` when all
+    /// the filterable attributes are, with `*`.
+    ///
+    /// A facet containing `*` is a pattern, like `attributes.*`, standing for the filterable
+    /// attributes and the fields nested in them that it matches. A pattern must match at least one
+    /// of them.
+    fn facets_to_count(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        facets_distribution: &[String],
+    ) -> Result<Option<BTreeSet<String>>> {
+        if facets_distribution.iter().any(|facet| facet == "*") {
+            return Ok(None);
+        }
+
+        let (patterns, names): (Vec<_>, Vec<_>) = facets_distribution
+            .iter()
+            .partition(|facet| facet.contains('*'));
+        let mut facets: BTreeSet<_> = names.into_iter().cloned().collect();
+        if patterns.is_empty() {
+            return Ok(Some(facets));
+        }
+
+        let filterable_fields = self.filterable_fields(rtxn)?;
+        let fields_ids_map = self.fields_ids_map(rtxn)?;
+        let faceted_names: BTreeSet<_> = fields_ids_map
+            .iter()
+            .map(|(_, name)| name)
+            .filter(|name| {
+                filterable_fields
+                    .iter()
+                    .any(|field| milli::is_faceted_by(name, field))
+            })
+            .chain(filterable_fields.iter().map(String::as_str))
+            .collect();
+
+        let mut invalid = Vec::new();
+        for pattern in patterns {
+            let matched: Vec<_> = faceted_names
+                .iter()
+                .filter(|name| glob_matches(pattern, name))
+                .collect();
+            if matched.is_empty() {
+                invalid.push(pattern.clone());
+            }
+            facets.extend(matched.into_iter().map(|name| name.to_string()));
+        }
+
+        if invalid.is_empty() {
+            Ok(Some(facets))
+        } else {
+            let mut valid: Vec<_> = filterable_fields.into_iter().collect();
+            valid.sort_unstable();
+            Err(FacetError::InvalidFacetsDistribution(invalid, valid).into())
+        }
+    }
+
+    /// Returns the ids of the fields the query must match in, or `None` if the query can match in
+    /// all the searchable fields.
+    ///
+    /// The unknown and non-searchable attributes of `attributesToSearchOn` are ignored and `*`
+    /// stands for all the searchable attributes.
+    ///
+    /// milli can't restrict a search to some attributes, its candidates are checked after the
+    /// search instead.
+    fn searched_fields_ids(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+        fields_ids_map: &FieldsIdsMap,
+    ) -> Result<Option<BTreeSet<FieldId>>> {
+        let attributes = match query.attributes_to_search_on {
+            Some(ref attributes) if attributes.iter().all(|attr| attr != "*") => attributes,
+            _ => return Ok(None),
+        };
+
+        // Without any query words there is nothing to restrict.
+        if query.q.as_ref().map_or(true, |q| q.trim().is_empty()) {
+            return Ok(None);
+        }
+
+        let searchable_ids: BTreeSet<_> = self
+            .searchable_fields_ids(rtxn)?
+            .map(|fields| fields.into_iter().collect())
+            .unwrap_or_else(|| fields_ids_map.iter().map(|(id, _)| id).collect());
+
+        let searched_ids: BTreeSet<_> = attributes
+            .iter()
+            .filter_map(|attr| fields_ids_map.id(attr))
+            .filter(|id| searchable_ids.contains(id))
+            .collect();
+
+        if searched_ids == searchable_ids {
+            Ok(None)
+        } else {
+            Ok(Some(searched_ids))
+        }
+    }
+
+    /// Returns the distinct attribute of the query when it replaces the one of the index, or
+    /// `None` if the query doesn't set one or sets the one of the index, which milli applies.
+    ///
+    /// `null` and `""` disable the distinct attribute of the index. An unknown attribute has no
+    /// value, all the documents are distinct.
+    fn query_distinct(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+        fields_ids_map: &FieldsIdsMap,
+    ) -> Result<Option<QueryDistinct>> {
+        let distinct = match query.distinct {
+            Some(ref distinct) => distinct.as_deref().filter(|name| !name.is_empty()),
+            None => return Ok(None),
+        };
+
+        let index_distinct = self.distinct_field(rtxn)?;
+        if index_distinct == distinct {
+            return Ok(None);
+        }
+
+        let index = index_distinct.and_then(|name| fields_ids_map.id(name));
+        let query = distinct.and_then(|name| fields_ids_map.id(name));
+        if index.is_none() && query.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(QueryDistinct { index, query }))
+    }
+
+    /// Puts back the candidates that milli left out of `documents` because they share a value of
+    /// the distinct attribute of the index with one of them, so that another distinct attribute
+    /// or none can be applied instead.
+    ///
+    /// milli doesn't tell where these documents rank, they are inserted right after the document
+    /// that milli kept, in the order of their internal ids. The documents are truncated to
+    /// `max_documents`.
+    fn undo_index_distinct<'t>(
+        &self,
+        rtxn: &'t milli::heed::RoTxn,
+        index_distinct: FieldId,
+        documents: Vec<(u32, obkv::KvReaderU16<'t>)>,
+        candidates: &RoaringBitmap,
+        max_documents: usize,
+    ) -> Result<Vec<(u32, obkv::KvReaderU16<'t>)>> {
+        let mut placed: RoaringBitmap = documents.iter().map(|(id, _)| *id).collect();
+        let mut expanded = Vec::with_capacity(documents.len());
+        for (id, obkv) in documents {
+            if expanded.len() >= max_documents {
+                break;
+            }
+
+            let mut group = self.distinct_group(rtxn, index_distinct, &obkv)?;
+            group &= candidates;
+            group -= &placed;
+            placed |= &group;
+
+            expanded.push((id, obkv));
+            let remaining = max_documents - expanded.len();
+            expanded.extend(self.documents(rtxn, group.into_iter().take(remaining))?);
+        }
+
+        Ok(expanded)
+    }
+
+    /// Returns the documents sharing a value of the `distinct` attribute with the document. The
+    /// values are looked up in the facet databases, like milli does to make the documents
+    /// distinct.
+    fn distinct_group(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        distinct: FieldId,
+        obkv: &obkv::KvReaderU16,
+    ) -> Result<RoaringBitmap> {
+        let value: Value = match obkv.get(distinct) {
+            Some(value) => serde_json::from_slice(value)?,
+            None => return Ok(RoaringBitmap::new()),
+        };
+
+        let mut group = RoaringBitmap::new();
+        let mut values = vec![&value];
+        while let Some(value) = values.pop() {
+            let docids = match value {
+                Value::String(string) => {
+                    let normalized = string.trim().to_lowercase();
+                    self.facet_id_string_docids
+                        .get(rtxn, &(distinct, normalized.as_str()))?
+                        .map(|(_original, docids)| docids)
+                }
+                Value::Bool(boolean) => {
+                    let boolean = boolean.to_string();
+                    self.facet_id_string_docids
+                        .get(rtxn, &(distinct, boolean.as_str()))?
+                        .map(|(_original, docids)| docids)
+                }
+                Value::Number(number) => match number.as_f64() {
+                    Some(number) => self
+                        .facet_id_f64_docids
+                        .get(rtxn, &(distinct, 0, number, number))?,
+                    None => None,
+                },
+                Value::Array(array) => {
+                    values.extend(array);
+                    None
+                }
+                Value::Null | Value::Object(_) => None,
+            };
+            if let Some(docids) = docids {
+                group |= docids;
+            }
+        }
+
+        Ok(group)
+    }
+
+    /// Returns the candidates of `query` once the clauses of its `filter` on `facet` are removed,
+    /// or `None` if no clause of the filter is on `facet`.
+    ///
+    /// The clauses are the elements of the array form of the filter, or the expressions joined by
+    /// the top-level `AND`s of the string form. The candidates are only restricted by the filter and
+    /// the excluded documents, not by the searched attributes nor by the distinct attribute of the
+    /// query.
+    fn disjunctive_facet_candidates(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+        filter: &Value,
+        facet: &str,
+        excluded_ids: &RoaringBitmap,
+    ) -> Result<Option<RoaringBitmap>> {
+        let filter = match filter_without_facet(filter, facet) {
+            Some(filter) => SearchFilter::new(&filter)?,
+            None => return Ok(None),
+        };
+
+        let mut search = self.search(rtxn);
+        configure_search(&mut search, query, Some(&filter))?;
+        search.limit(0);
+
+        let milli::SearchResult { mut candidates, .. } = search.execute()?;
+        self.filter_geo(rtxn, query, &filter, &mut candidates)?;
+        candidates -= excluded_ids;
+        Ok(Some(candidates))
+    }
+
+    /// Returns the internal ids of the documents excluded by the query, the unknown ids being
+    /// ignored.
+    fn excluded_documents_ids(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+    ) -> Result<RoaringBitmap> {
+        let ids = match query.exclude_document_ids {
+            Some(ref ids) => ids,
+            None => return Ok(RoaringBitmap::new()),
+        };
+
+        let external_documents_ids = self.external_documents_ids(rtxn)?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| external_documents_ids.get(id.to_external_id()))
+            .collect())
+    }
+
+    /// Removes from `candidates` the documents that don't match the geo expressions of `filter`
+    /// that milli doesn't support.
+    fn filter_geo(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+        filter: &SearchFilter,
+        candidates: &mut RoaringBitmap,
+    ) -> Result<()> {
+        self.filter_bounding_boxes(rtxn, filter, candidates)?;
+        self.filter_geo_distances(rtxn, query, filter, candidates)
+    }
+
+    /// Removes from `candidates` the documents whose `_geoDistance`, as it is returned in the hits,
+    /// is out of the `_geoDistance` bounds of `filter`. The documents without `_geo` are removed.
+    ///
+    /// The points are read from the geo rtree of the index instead of the documents. When the
+    /// distance has an upper bound, only the points around the bases are visited, nearest first.
+    ///
+    /// The candidates being filtered before the pagination, `nbHits` only counts the documents
+    /// within the bounds.
+    fn filter_geo_distances(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        query: &SearchQuery,
+        filter: &SearchFilter,
+        candidates: &mut RoaringBitmap,
+    ) -> Result<()> {
+        if filter.distance_bounds.is_empty() {
+            return Ok(());
+        }
+
+        let bases = match query.geo_distance_from {
+            Some(base) => vec![base],
+            None => sort_geo_points(
+                query.sort.as_deref().unwrap_or_default(),
+                query.min_geo_distance.unwrap_or_default(),
+            )?,
+        };
+        if bases.is_empty() {
+            return Err(SearchError::MissingGeoDistanceBase.into());
+        }
+        if !self.filterable_fields(rtxn)?.contains("_geo") {
+            return Err(SearchError::GeoDistanceNotFilterable.into());
+        }
+
+        // There is no rtree when no document has a `_geo` field.
+        let rtree = match self.geo_rtree(rtxn)? {
+            Some(rtree) => rtree,
+            None => {
+                candidates.clear();
+                return Ok(());
+            }
+        };
+
+        // The distance of each candidate to its closest base.
+        let mut distances = HashMap::new();
+        let mut insert_distance = |id, distance: f64| {
+            distances
+                .entry(id)
+                .and_modify(|min: &mut f64| *min = min.min(distance))
+                .or_insert(distance);
+        };
+        let upper_bound = filter
+            .distance_bounds
+            .iter()
+            .filter_map(GeoDistanceBound::upper_bound)
+            .reduce(f64::min);
+        match upper_bound {
+            Some(upper_bound) => {
+                for base in &bases {
+                    for point in rtree.nearest_neighbor_iter(&milli::lat_lng_to_xyz(base)) {
+                        let (id, lat_lng) = point.data;
+                        let distance = milli::distance_between_two_points(base, &lat_lng);
+                        // The distance is checked once rounded, which can bring it back within
+                        // the bound.
+                        if distance > upper_bound + 1.0 {
+                            break;
+                        }
+                        if candidates.contains(id) {
+                            insert_distance(id, distance);
+                        }
+                    }
+                }
+            }
+            None => {
+                for point in rtree.iter() {
+                    let (id, lat_lng) = point.data;
+                    if candidates.contains(id) {
+                        for base in &bases {
+                            insert_distance(id, milli::distance_between_two_points(base, &lat_lng));
+                        }
+                    }
+                }
+            }
+        }
+
+        *candidates = distances
+            .into_iter()
+            .filter(|(_, distance)| {
+                let distance = round_distance(*distance, query.geo_distance_decimals);
+                filter
+                    .distance_bounds
+                    .iter()
+                    .all(|bound| bound.contains(distance))
+            })
+            .map(|(id, _)| id)
+            .collect();
+        Ok(())
+    }
+
+    /// Removes from `candidates` the documents that are not in the bounding boxes of `filter`,
+    /// unless they match the other expressions of the OR group of the bounding boxes.
+    ///
+    /// The points of each box are read from the geo rtree of the index, nearest to the center of
+    /// the box first, up to the radius of the circle containing the box.
+    fn filter_bounding_boxes(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        filter: &SearchFilter,
+        candidates: &mut RoaringBitmap,
+    ) -> Result<()> {
+        if filter.bounding_boxes.is_empty() {
+            return Ok(());
+        }
+
+        // There is no rtree when no document has a `_geo` field.
+        let rtree = self.geo_rtree(rtxn)?;
+        for group in &filter.bounding_boxes {
+            let mut matching = match Filter::from_array(Some(Either::Left(
+                group.others.iter().map(String::as_str),
+            )))? {
+                Some(others) => others.evaluate(rtxn, self)?,
+                None => RoaringBitmap::new(),
+            };
+
+            if let Some(ref rtree) = rtree {
+                for bounding_box in &group.boxes {
+                    let (center, radius) = bounding_box.enclosing_circle();
+                    for point in rtree.nearest_neighbor_iter(&milli::lat_lng_to_xyz(&center)) {
+                        let (id, lat_lng) = point.data;
+                        if milli::distance_between_two_points(&center, &lat_lng) > radius {
+                            break;
+                        }
+                        if bounding_box.contains(lat_lng) {
+                            matching.insert(id);
+                        }
+                    }
+                }
+            }
+
+            *candidates &= matching;
+        }
+
+        Ok(())
+    }
+
+    /// Counts all the values of the `fields` facets in the `documents_ids` documents, without any
+    /// cap on the number of values per facet.
+    ///
+    /// The values are read from the documents themselves and are normalized the same way milli
+    /// does: the strings are grouped by their trimmed lowercase form and reported with their first
+    /// original form, while the numbers are reported as floats.
+    fn exhaustive_facets_distribution(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        fields: &[String],
+        documents_ids: impl IntoIterator<Item = u32>,
+    ) -> Result<BTreeMap<String, BTreeMap<String, u64>>> {
+        let fields_ids_map = self.fields_ids_map(rtxn)?;
+        let fields: Vec<_> = fields
+            .iter()
+            .filter_map(|field| Some((fields_ids_map.id(field)?, field)))
+            .collect();
+
+        let mut normalized_distributions = vec![BTreeMap::new(); fields.len()];
+        for (_id, obkv) in self.documents(rtxn, documents_ids)? {
+            for ((fid, _), distribution) in fields.iter().zip(&mut normalized_distributions) {
+                let value = match obkv.get(*fid) {
+                    Some(value) => serde_json::from_slice(value)?,
+                    None => continue,
+                };
+
+                let mut values = BTreeMap::new();
+                insert_facet_values(&value, &mut values);
+                for (normalized, original) in values {
+                    let (_, count) = distribution
+                        .entry(normalized)
+                        .or_insert_with(|| (original, 0));
+                    *count += 1;
+                }
+            }
+        }
+
+        let distribution = fields
+            .into_iter()
+            .zip(normalized_distributions)
+            .map(|((_, field), distribution)| {
+                let values = distribution.into_values().collect();
+                (field.clone(), values)
+            })
+            .collect();
+
+        Ok(distribution)
+    }
+
+    /// Computes the stats of the numeric values of `fields` among the `candidates`.
+    ///
+    /// The fields without any numeric value are omitted. The level 0 of the facet number database
+    /// is sorted by value, the bounds are the first and the last values of the facet whose
+    /// documents intersect the candidates.
+    fn facet_stats(
+        &self,
+        rtxn: &milli::heed::RoTxn,
+        fields: &[String],
+        candidates: &RoaringBitmap,
+    ) -> Result<BTreeMap<String, FacetStats>> {
+        let fields_ids_map = self.fields_ids_map(rtxn)?;
+        let mut stats = BTreeMap::new();
+        for field in fields {
+            let fid = match fields_ids_map.id(field) {
+                Some(fid) => fid,
+                None => continue,
+            };
+
+            let level_zero = (fid, 0, f64::MIN, f64::MIN)..=(fid, 0, f64::MAX, f64::MAX);
+            let mut min = None;
+            for result in self.facet_id_f64_docids.range(rtxn, &level_zero)? {
+                let ((_, _, value, _), docids) = result?;
+                if !docids.is_disjoint(candidates) {
+                    min = Some(value);
+                    break;
+                }
+            }
+            let min = match min {
+                Some(min) => min,
+                None => continue,
+            };
+
+            let mut max = min;
+            for result in self.facet_id_f64_docids.rev_range(rtxn, &level_zero)? {
+                let ((_, _, value, _), docids) = result?;
+                if !docids.is_disjoint(candidates) {
+                    max = value;
+                    break;
+                }
+            }
+
+            stats.insert(field.clone(), FacetStats { min, max });
+        }
+
+        Ok(stats)
+    }
+
+    /// Returns the page of at most `limit` candidates following the position of `cursor`, in the
+    /// order of their ranking `score` in a keyword search, of the sort and then by id, and the
+    /// position of the last hit of the page if hits are left after it.
+    ///
+    /// The pages don't depend on `HARD_RESULT_LIMIT` and don't shift when documents are added or
+    /// removed before the position. Only the sort values of the candidates are read, and only the
+    /// hits of the page are ordered among the candidates after the position. When the candidates
+    /// are made distinct on `distinct_id`, keeping the first document of each value, all of them
+    /// are ordered to know which document comes first.
+    fn cursor_page<'t>(
+        &self,
+        rtxn: &'t milli::heed::RoTxn,
+        cursor: &SearchCursor,
+        fields_ids_map: &FieldsIdsMap,
+        distinct_id: Option<FieldId>,
+        candidates: &mut RoaringBitmap,
+        limit: usize,
+        mut score: impl FnMut(&obkv::KvReaderU16) -> Result<Option<f64>>,
+    ) -> Result<(Vec<(u32, obkv::KvReaderU16<'t>)>, Option<CursorKey>)> {
+        let primary_key = self
+            .primary_key(rtxn)?
+            .and_then(|key| fields_ids_map.id(key));
+        let geo_id = fields_ids_map.id("_geo");
+        let is_after = |key: &CursorKey| {
+            cursor.after.as_ref().map_or(true, |after| {
+                key.cmp_by(after, &cursor.sort) == Ordering::Greater
+            })
+        };
+
+        let mut documents = Vec::new();
+        for (id, obkv) in self.documents(rtxn, candidates.iter())? {
+            let key = CursorKey::new(
+                &obkv,
+                score(&obkv)?,
+                &cursor.sort,
+                fields_ids_map,
+                geo_id,
+                primary_key,
+            )?;
+            if distinct_id.is_some() || is_after(&key) {
+                documents.push((key, (id, obkv)));
+            }
+        }
+
+        if let Some(distinct_id) = distinct_id {
+            documents.sort_by(|(a, _), (b, _)| a.cmp_by(b, &cursor.sort));
+            let mut seen = BTreeSet::new();
+            let mut distinct_documents = Vec::new();
+            for (key, (id, obkv)) in documents {
+                if !is_distinct(&obkv, distinct_id, &mut seen)? {
+                    candidates.remove(id);
+                } else if is_after(&key) {
+                    distinct_documents.push((key, (id, obkv)));
+                }
+            }
+            documents = distinct_documents;
+        } else if documents.len() > limit + 1 {
+            // Only the hits of the page and the one telling if hits are left are ordered.
+            documents.select_nth_unstable_by(limit, |(a, _), (b, _)| a.cmp_by(b, &cursor.sort));
+            documents.truncate(limit + 1);
+            documents.sort_by(|(a, _), (b, _)| a.cmp_by(b, &cursor.sort));
+        } else {
+            documents.sort_by(|(a, _), (b, _)| a.cmp_by(b, &cursor.sort));
+        }
+
+        let mut documents = documents.into_iter();
+        let mut page = Vec::with_capacity(limit);
+        let mut last = None;
+        for (key, document) in documents.by_ref().take(limit) {
+            page.push(document);
+            last = Some(key);
+        }
+
+        let next = if documents.next().is_some() {
+            last
+        } else {
+            None
+        };
+        Ok((page, next))
+    }
+
+    /// Computes the facet distribution requested by `query` one facet at a time, calling
+    /// `callback` with every `(facet, value, count)` bucket as soon as its facet has been counted.
+    ///
+    /// The buckets are the same as the ones returned in `SearchResult::facets_distribution`, but
+    /// they can be forwarded to the client before the whole distribution is computed.
+    pub fn stream_facets_distribution(
+        &self,
+        query: &SearchQuery,
+        mut callback: impl FnMut(&str, &str, u64),
+    ) -> Result<()> {
+        let fields = match query.facets_distribution {
+            Some(ref fields) => fields,
+            None => return Ok(()),
+        };
+
+        let rtxn = self.read_txn()?;
+
+        let filter = query.filter.as_ref().map(SearchFilter::new).transpose()?;
+        check_facets(query, &self.filterable_fields(&rtxn)?)?;
+
+        let mut search = self.search(&rtxn);
+        configure_search(&mut search, query, filter.as_ref())?;
+        search.limit(0);
+
+        let milli::SearchResult { mut candidates, .. } = search.execute()?;
+        if let Some(ref filter) = filter {
+            self.filter_geo(&rtxn, query, filter, &mut candidates)?;
+        }
+
+        let fields = match self.facets_to_count(&rtxn, fields)? {
+            Some(fields) => fields,
+            None => self.filterable_fields(&rtxn)?.into_iter().collect(),
+        };
+
+        for field in &fields {
+            let mut facets_distribution = self.facets_distribution(&rtxn);
+            facets_distribution.facets(std::iter::once(field));
+            let distribution = facets_distribution
+                .candidates(candidates.clone())
+                .execute()?;
+
+            for (facet, values) in distribution {
+                let mut values = FacetValues::new(values, query.facet_sort.unwrap_or_default());
+                if let Some(max_values_per_facet) = query.max_values_per_facet {
+                    values.truncate(max_values_per_facet);
+                }
+                for (value, count) in values.0 {
+                    callback(&facet, &value, count);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the syntax of `filter` and returns its normalized form, like
+    /// `genre = action AND (year > 2000 OR NOT (rating < 3))`, or an empty string if it is empty.
+    ///
+    /// The filter is only parsed: no document is read and its attributes are not checked against
+    /// the filterable attributes of the index.
+    pub fn explain_filter(&self, filter: &Value) -> Result<String> {
+        explain_filter(filter)
+    }
+
+    /// Returns up to `limit` values of the `facet` attribute containing `query`, with their number
+    /// of documents among the ones matching `filter`, or among all the documents without filter.
+    ///
+    /// The values are read from the facet databases and are compared to the query in their
+    /// normalized form, ignoring the case and the diacritics. Only the documents of the matching
+    /// values are intersected with the candidates. See `rank_facet_values` for their order.
+    pub fn search_facet_values(
+        &self,
+        facet: &str,
+        query: &str,
+        limit: usize,
+        filter: Option<&Value>,
+    ) -> Result<Vec<FacetValueHit>> {
+        let rtxn = self.read_txn()?;
+
+        let filterable_fields = self.filterable_fields(&rtxn)?;
+        if !filterable_fields
+            .iter()
+            .any(|field| facet == field || milli::is_faceted_by(facet, field))
+        {
+            let mut valid: Vec<_> = filterable_fields.into_iter().collect();
+            valid.sort_unstable();
+            return Err(FacetError::InvalidFacetSearch(facet.to_string(), valid).into());
+        }
+
+        let fid = match self.fields_ids_map(&rtxn)?.id(facet) {
+            Some(fid) => fid,
+            None => return Ok(Vec::new()),
+        };
+
+        let candidates = match filter {
+            Some(filter) => {
+                let filter = SearchFilter::new(filter)?;
+                let query = SearchQuery::default();
+                let mut search = self.search(&rtxn);
+                configure_search(&mut search, &query, Some(&filter))?;
+                search.limit(0);
+
+                let milli::SearchResult { mut candidates, .. } = search.execute()?;
+                self.filter_geo(&rtxn, &query, &filter, &mut candidates)?;
+                candidates
+            }
+            None => self.documents_ids(&rtxn)?,
+        };
+
+        let normalized_query = normalize_facet_value(query.trim(), &ANALYZER);
+        let mut values = BTreeMap::new();
+        let mut insert = |value: String, docids: RoaringBitmap| {
+            if normalize_facet_value(&value, &ANALYZER).contains(&normalized_query) {
+                let count = docids.intersection_len(&candidates);
+                if count > 0 {
+                    *values.entry(value).or_insert(0) += count;
+                }
+            }
+        };
+
+        // The strings are stored in their normalized form along with their original one, the
+        // numbers in the level 0 of the facet number database.
+        for result in self.facet_id_string_docids.prefix_iter(&rtxn, &(fid, ""))? {
+            let (_, (original, docids)) = result?;
+            insert(original.to_string(), docids);
+        }
+        let level_zero = (fid, 0, f64::MIN, f64::MIN)..=(fid, 0, f64::MAX, f64::MAX);
+        for result in self.facet_id_f64_docids.range(&rtxn, &level_zero)? {
+            let ((_, _, number, _), docids) = result?;
+            insert(number.to_string(), docids);
+        }
+
+        Ok(rank_facet_values(values, query, limit, &ANALYZER))
+    }
+}
+
+/// Builds the hits from the documents of the page: the retrieved attributes, their formatted
+/// values and the matches. The hits are given to `on_hit` if any, in which case they are built
+/// one at a time and not returned. The hash of the hits is returned when the query asks for it.
+/// Counts, for each of the `fields_ids`, the `documents` having a value other than `null` for it.
+///
+/// milli only counts the fields over the whole index, and counting them over all the candidates
+/// would read every candidate. Only the documents of the page, already read to build the hits,
+/// are thus counted.
+fn field_coverage(
+    fields_ids: &BTreeSet<FieldId>,
+    fields_ids_map: &FieldsIdsMap,
+    documents: &[(u32, obkv::KvReaderU16)],
+) -> BTreeMap<String, u64> {
+    let mut counts = vec![0; fields_ids.len()];
+    for (_id, obkv) in documents {
+        for (fid, count) in fields_ids.iter().zip(&mut counts) {
+            // The values are stored serialized, `null` is always stored the same way.
+            if obkv.get(*fid).map_or(false, |value| value != b"null") {
+                *count += 1;
+            }
+        }
+    }
+
+    fields_ids
+        .iter()
+        .zip(counts)
+        .filter_map(|(fid, count)| Some((fields_ids_map.name(*fid)?.to_string(), count)))
+        .collect()
+}
+
+fn build_hits(
+    analyzer: &Analyzer<Vec<u8>>,
+    query: &SearchQuery,
+    plan: &SearchPlan,
+    words: &QueryWords,
+    documents: Vec<(u32, obkv::KvReaderU16)>,
+    deadline: &Deadline,
+    on_hit: Option<&mut dyn FnMut(SearchHit) -> Result<()>>,
+) -> Result<(Vec<SearchHit>, Option<String>)> {
+    let fields_ids_map = &plan.fields_ids_map;
+    let fids = |attrs: &IndexSet<String>| {
+        // An attribute prefixed by `-` is excluded, when only exclusions are given they are
+        // excluded from all the displayed attributes, unless the attributes are excluded by
+        // default.
+        let (excluded, included): (Vec<_>, Vec<_>) =
+            attrs.iter().partition(|attr| attr.starts_with('-'));
+
+        let mut ids = IndexSet::new();
+        if included.is_empty()
+            && !excluded.is_empty()
+            && !query.exclude_attributes_by_default.unwrap_or_default()
+        {
+            ids = plan.displayed_order.iter().copied().collect();
+        }
+        for attr in included {
+            if attr == "*" {
+                ids = plan.displayed_order.iter().copied().collect();
+                break;
+            }
+
+            match attribute_regex(attr) {
+                Some(regex) => ids.extend(regex_fields_ids(regex, fields_ids_map)),
+                None => ids.extend(fields_ids_map.id(attr)),
+            }
+        }
+
+        for attr in excluded {
+            let attr = &attr[1..];
+            match attribute_regex(attr) {
+                Some(regex) => regex_fields_ids(regex, fields_ids_map).for_each(|id| {
+                    ids.shift_remove(&id);
+                }),
+                None => {
+                    if let Some(id) = fields_ids_map.id(attr) {
+                        ids.shift_remove(&id);
+                    }
+                }
+            }
+        }
+        ids
+    };
+
+    // The attributes to retrieve are the ones explicitly marked as to retrieve (all by default,
+    // none if `exclude_attributes_by_default`), minus the excluded ones, but these attributes
+    // must be also be present
+    // - in the fields_ids_map
+    // - in the the displayed attributes
+    // They are kept in the order they were requested in.
+    let to_retrieve_ids: IndexSet<_> = query
+        .attributes_to_retrieve
+        .as_ref()
+        .map(fids)
+        .unwrap_or_else(|| {
+            if query.exclude_attributes_by_default.unwrap_or_default() {
+                IndexSet::new()
+            } else {
+                plan.displayed_order.iter().copied().collect()
+            }
+        })
+        .into_iter()
+        .filter(|id| plan.displayed_ids.contains(id))
+        .collect();
+
+    // The raw attributes are returned as they are stored, they are never formatted.
+    let raw_ids: BTreeSet<_> = query
+        .raw_attributes
+        .iter()
+        .flatten()
+        .filter_map(|attr| fields_ids_map.id(attr))
+        .filter(|id| plan.displayed_ids.contains(id))
+        .collect();
+    let to_retrieve_ids: IndexSet<_> = to_retrieve_ids
+        .into_iter()
+        .chain(raw_ids.iter().copied())
+        .collect();
+
+    let attr_to_highlight = query.attributes_to_highlight.as_deref().unwrap_or_default();
+
+    let attr_to_crop = query.attributes_to_crop.as_deref().unwrap_or_default();
+
+    // Attributes in `formatted_options` correspond to the attributes that will be in `_formatted`
+    // These attributes are:
+    // - the attributes asked to be highlighted or cropped (with `attributesToCrop` or `attributesToHighlight`)
+    // - the attributes asked to be retrieved: these attributes will not be highlighted/cropped
+    // But these attributes must be also present in displayed attributes
+    let formatted_options = compute_formatted_options(
+        attr_to_highlight,
+        attr_to_crop,
+        query.crop_length.unwrap_or(DEFAULT_CROP_LENGTH),
+        query.crop_on_sentences.unwrap_or_default(),
+        &to_retrieve_ids,
+        &raw_ids,
+        fields_ids_map,
+        &plan.displayed_ids,
+    );
+
+    let q = query.q.as_deref().unwrap_or_default();
+    let offset_unit = if query.matches_char_offsets.unwrap_or_default() {
+        OffsetUnit::Chars
+    } else {
+        OffsetUnit::Bytes
+    };
+
+    let formatter = Formatter::new(
+        analyzer,
+        (
+            query
+                .highlight_pre_tag
+                .clone()
+                .unwrap_or_else(default_highlight_pre_tag),
+            query
+                .highlight_post_tag
+                .clone()
+                .unwrap_or_else(default_highlight_post_tag),
+        ),
+        (
+            query
+                .crop_marker_before
+                .clone()
+                .or_else(|| query.crop_marker.clone())
+                .unwrap_or_else(default_crop_marker),
+            query
+                .crop_marker_after
+                .clone()
+                .or_else(|| query.crop_marker.clone())
+                .unwrap_or_else(default_crop_marker),
+        ),
+    )
+    .with_offset_unit(offset_unit)
+    .with_matches_by_pointer(query.matches_by_pointer.unwrap_or_default())
+    .with_matched_terms(query.show_matched_terms.unwrap_or_default())
+    .with_crop_strategy(query.crop_strategy.unwrap_or_default())
+    .with_crop_unit(query.crop_unit.unwrap_or_default())
+    .with_highlight_numbers(
+        query
+            .highlight_numbers
+            .unwrap_or_else(default_highlight_numbers),
+    )
+    .with_segments(query.formatted_segments.unwrap_or_default())
+    .with_snippets(query.crop_snippets.unwrap_or(1))
+    .with_sensitivity(HighlightSensitivity::new(
+        q,
+        analyzer,
+        query.highlight_case_sensitive.unwrap_or_default(),
+        query.highlight_diacritic_sensitive.unwrap_or_default(),
+    ))
+    .with_max_highlights(query.max_highlights_per_field)
+    .with_escape_html(query.escape_html.unwrap_or_default());
+
+    let matching_words = &words.matcher;
+    let matches_only = query.hit_content == Some(HitContent::Matches);
+    let format_hit = |(_id, obkv)| {
+        deadline.check("formatting the hits")?;
+        let typos = match words.typo_words {
+            Some(ref words) if query.show_typos.unwrap_or_default() => {
+                Some(count_typos(&obkv, words, matching_words, analyzer)?)
+            }
+            _ => None,
+        };
+        let matched_words_count = match words.typo_words {
+            Some(ref words) if query.show_matched_words_count.unwrap_or_default() => {
+                Some(count_matched_words(&obkv, words, matching_words, analyzer)?)
+            }
+            _ => None,
+        };
+        let ranking_score =
+            match words.typo_words {
+                Some(ref words) if query.show_ranking_score.unwrap_or_default() => Some(
+                    ranking_score(&obkv, &plan.criteria, words, matching_words, analyzer)?,
+                ),
+                _ => None,
+            };
+        let document_id = match plan.primary_key {
+            Some(primary_key) if matches_only => obkv
+                .get(primary_key)
+                .map(serde_json::from_slice::<Value>)
+                .transpose()?
+                .zip(fields_ids_map.name(primary_key)),
+            _ => None,
+        };
+        let mut document = make_document(&to_retrieve_ids, &raw_ids, fields_ids_map, obkv)?;
+        if query.field_order == Some(FieldOrder::FieldId) {
+            sort_by_field_id(&mut document, fields_ids_map);
+        }
+
+        let mut formatted_matches_info = (query.matches.unwrap_or_default()
+            && query.matches_on_formatted.unwrap_or_default())
+        .then(MatchesInfo::new);
+
+        let (mut formatted, truncated) = if matches_only {
+            Default::default()
+        } else {
+            format_fields(
+                &mut document,
+                fields_ids_map,
+                &formatter,
+                matching_words,
+                &formatted_options,
+                formatted_matches_info.as_mut(),
+            )?
+        };
+        if !formatted.is_empty() {
+            insert_raw_values(&document, &raw_ids, fields_ids_map, &mut formatted);
+        }
+
+        // The matches can only be computed on the `_formatted` values if the document is
+        // formatted, otherwise they are computed on the document.
+        let mut matches_info = match formatted_matches_info {
+            Some(matches_info) if !formatted.is_empty() => Some(matches_info),
+            _ => (query.matches.unwrap_or_default() || matches_only).then(|| {
+                compute_matches(
+                    matching_words,
+                    &document,
+                    analyzer,
+                    offset_unit,
+                    query.matches_by_pointer.unwrap_or_default(),
+                    query.show_matched_terms.unwrap_or_default(),
+                )
+            }),
+        };
+        if let Some(ref mut matches_info) = matches_info {
+            retain_long_matches(matches_info, query.min_match_length.unwrap_or_default());
+        }
+        let cropped_attributes = query.show_crop_info.unwrap_or_default().then(|| {
+            truncated
+                .iter()
+                .filter(|(_, &truncated)| truncated)
+                .map(|(attribute, _)| attribute.clone())
+                .collect()
+        });
+        let formatted_truncated = if query.show_truncation.unwrap_or_default() {
+            Some(truncated)
+        } else {
+            None
+        };
+
+        if let Some(base) = query.geo_distance_from {
+            insert_distance(&[base], query.geo_distance_decimals, &mut document);
+        } else if let Some(sort) = query.sort.as_ref() {
+            insert_geo_distance(
+                sort,
+                query.min_geo_distance.unwrap_or_default(),
+                query.geo_distance_decimals,
+                &mut document,
+            )?;
+        }
+
+        match query.hit_content.unwrap_or_default() {
+            HitContent::Both => (),
+            HitContent::Document => formatted.clear(),
+            HitContent::Formatted => document.retain(|key, _| key == "_geoDistance"),
+            HitContent::Matches => {
+                document.clear();
+                if let Some((id, name)) = document_id {
+                    document.insert(name.to_string(), id);
+                }
+            }
+        }
+
+        if query.flatten_documents.unwrap_or_default() {
+            document = flatten_document(document);
+        }
+
+        let size_bytes = if query.show_size_bytes.unwrap_or_default() {
+            Some(serialized_size(&document)?)
+        } else {
+            None
+        };
+
+        Ok(SearchHit {
+            document,
+            formatted,
+            matches_info,
+            cropped_attributes,
+            typos,
+            formatted_truncated,
+            size_bytes,
+            matched_words_count,
+            ranking_score,
+        })
+    };
+
+    match on_hit {
+        Some(on_hit) => {
+            let mut hasher = query
+                .show_result_hash
+                .unwrap_or_default()
+                .then(ResultHasher::new);
+            for document in documents {
+                let hit = format_hit(document)?;
+                if let Some(ref mut hasher) = hasher {
+                    hasher.write(&hit)?;
+                }
+                on_hit(hit)?;
+            }
+            Ok((Vec::new(), hasher.map(|hasher| hasher.finish())))
+        }
+        // The documents of the large pages are formatted in parallel. Their bytes are borrowed
+        // from the read transaction, which is not itself sent to the other threads.
+        None => {
+            let hits = if documents.len() < PARALLEL_FORMATTING_MIN_HITS {
+                documents
+                    .into_iter()
+                    .map(format_hit)
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                documents
+                    .into_par_iter()
+                    .map(format_hit)
+                    .collect::<Result<Vec<_>>>()?
+            };
+            let result_hash = query
+                .show_result_hash
+                .unwrap_or_default()
+                .then(|| compute_result_hash(&hits))
+                .transpose()?;
+            Ok((hits, result_hash))
+        }
+    }
+}
+
+/// Applies the parts of `query` that restrict and order the candidates to `search`.
+fn configure_search<'a>(
+    search: &mut milli::Search<'a>,
+    query: &'a SearchQuery,
+    filter: Option<&'a SearchFilter>,
+) -> Result<()> {
+    if let Some(ref q) = query.q {
+        match query.min_prefix_length {
+            // milli only considers the last word as a prefix if nothing follows it, adding a
+            // separator after a too short last word makes milli search for the exact word.
+            Some(min_prefix_length)
+                if last_word_len(q).map_or(false, |len| len < min_prefix_length) =>
+            {
+                search.query(format!("{} ", q));
+            }
+            _ => {
+                search.query(q);
+            }
+        }
+    }
+
+    match query.matching_strategy.unwrap_or_default() {
+        MatchingStrategy::All => search.optional_words(false),
+        MatchingStrategy::Last => search.optional_words(true),
+    };
+
+    // milli only allows the typos when both the index and the search allow them, so a search can
+    // disable the typos but not enable them on an index where they are disabled.
+    if let Some(ref typo_tolerance) = query.typo_tolerance {
+        check_typo_tolerance(typo_tolerance)?;
+        match typo_tolerance.enabled {
+            Setting::Set(enabled) => search.authorize_typos(enabled),
+            Setting::Reset => search.authorize_typos(true),
+            Setting::NotSet => search,
+        };
+    }
+
+    if let Some(filter) = filter {
+        if let Some(facets) = parse_filter(&filter.milli_filter)? {
+            search.filter(facets);
+        }
+    }
+
+    if let Some(ref sort) = query.sort {
+        let sort = match sort.iter().map(|s| AscDesc::from_str(s)).collect() {
+            Ok(sorts) => sorts,
+            Err(asc_desc_error) => {
+                return Err(IndexError::Milli(SortError::from(asc_desc_error).into()))
+            }
+        };
+
+        search.sort_criteria(sort);
+    }
+
+    Ok(())
+}
+
+/// Parses the `rankingRules` of a query, with the syntax of the ranking rules of the index.
+fn parse_ranking_rules(rules: &[String]) -> Result<Vec<Criterion>> {
+    rules
+        .iter()
+        .map(|rule| Criterion::from_str(rule).map_err(|error| IndexError::Milli(error.into())))
+        .collect()
+}
+
+/// The value of a document for a ranking rule evaluated after the search.
+enum RuleValue {
+    /// The documents with the highest count come first.
+    Most(usize),
+    /// The documents with the lowest count come first.
+    Fewest(u32),
+    /// The documents are ordered by the value of a field, in descending order if `true`.
+    Field(SortValue, bool),
+}
+
+impl RuleValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Most(a), Self::Most(b)) => b.cmp(a),
+            (Self::Fewest(a), Self::Fewest(b)) => a.cmp(b),
+            (Self::Field(a, desc), Self::Field(b, _)) => a.cmp_by(b, *desc),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+/// Reorders `documents`, ranked by milli with the ranking rules of the index, with the ranking
+/// rules of the query.
+///
+/// milli can't rank with other rules than the ones of the index, the rules are thus applied to the
+/// returned documents. `words`, `typo`, `exactness` and the `attribute:asc` and `attribute:desc`
+/// rules are evaluated on each document, up to the first `proximity`, `attribute` or `sort` rule from which the milli ranking
+/// is kept.
+fn rank_by_rules<'t, A: AsRef<[u8]>>(
+    documents: Vec<(u32, obkv::KvReaderU16<'t>)>,
+    criteria: &[Criterion],
+    words: &[(String, bool)],
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+    fields_ids_map: &FieldsIdsMap,
+) -> Result<Vec<(u32, obkv::KvReaderU16<'t>)>> {
+    let criteria: Vec<_> = criteria
+        .iter()
+        .take_while(|criterion| {
+            !matches!(
+                criterion,
+                Criterion::Proximity | Criterion::Attribute | Criterion::Sort
+            )
+        })
+        .collect();
+    if criteria.is_empty() {
+        return Ok(documents);
+    }
+
+    let mut ranked = Vec::with_capacity(documents.len());
+    for document in documents {
+        let obkv = &document.1;
+        let mut values = Vec::with_capacity(criteria.len());
+        for criterion in &criteria {
+            let value = match criterion {
+                Criterion::Words => {
+                    RuleValue::Most(count_matched_words(obkv, words, matcher, analyzer)?)
+                }
+                Criterion::Typo => RuleValue::Fewest(count_typos(obkv, words, matcher, analyzer)?),
+                Criterion::Exactness => {
+                    RuleValue::Most(count_whole_word_matches(obkv, matcher, analyzer)?)
+                }
+                Criterion::Asc(field) | Criterion::Desc(field) => {
+                    let desc = matches!(criterion, Criterion::Desc(_));
+                    let value = match fields_ids_map.id(field).and_then(|id| obkv.get(id)) {
+                        Some(value) => serde_json::from_slice(value)?,
+                        None => Value::Null,
+                    };
+                    RuleValue::Field(SortValue::new(value, desc), desc)
+                }
+                Criterion::Proximity | Criterion::Attribute | Criterion::Sort => continue,
+            };
+            values.push(value);
+        }
+        ranked.push((values, document));
+    }
+
+    // `sort_by` is stable, the milli ranking is kept between the documents tying on all the rules.
+    ranked.sort_by(|(a, _), (b, _)| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.cmp(b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(ranked.into_iter().map(|(_, document)| document).collect())
+}
+
+/// Returns the ranking rules of the index applied to `query`, in order.
+///
+/// The `sort` ranking rule is replaced by the sort criteria of the query, one rule per criterion
+/// like `sort(price:asc)`, and is dropped when the query doesn't sort. The rules depending on the
+/// query words are dropped for a placeholder search, which has no words to rank.
+fn applied_ranking_rules(criteria: &[Criterion], query: &SearchQuery) -> Vec<String> {
+    let placeholder = query.q.as_deref().map_or(true, |q| q.trim().is_empty());
+    let sort = query.sort.as_deref().unwrap_or_default();
+
+    let mut rules = Vec::new();
+    for criterion in criteria {
+        match criterion {
+            Criterion::Sort => rules.extend(sort.iter().map(|sort| format!("sort({})", sort))),
+            Criterion::Words
+            | Criterion::Typo
+            | Criterion::Proximity
+            | Criterion::Attribute
+            | Criterion::Exactness
+                if placeholder => {}
+            criterion => rules.push(criterion.to_string()),
+        }
+    }
+    rules
+}
+
+/// Computes a hash of the returned hits, suitable to be used as an ETag.
+///
+/// The hits are hashed in order with their retrieved and formatted fields, the hash thus changes
+/// when the ranking or the content of a returned document changes. A `SipHasher13` with fixed keys
+/// is used so that the hash is the same across runs and instances.
+fn compute_result_hash(hits: &[SearchHit]) -> Result<String> {
+    let mut hasher = ResultHasher::new();
+    for hit in hits {
+        hasher.write(hit)?;
+    }
+    Ok(hasher.finish())
+}
+
+/// Hashes the hits one at a time, for the hash to be computed while they are streamed.
+struct ResultHasher(SipHasher13);
+
+impl ResultHasher {
+    fn new() -> Self {
+        Self(SipHasher13::new())
+    }
+
+    fn write(&mut self, hit: &SearchHit) -> Result<()> {
+        self.0.write(&serde_json::to_vec(hit)?);
+        Ok(())
+    }
+
+    fn finish(&self) -> String {
+        format!("{:016x}", self.0.finish())
+    }
+}
+
+/// Returns the length of the JSON serialization of `document`, counting the bytes as they are
+/// written instead of allocating the serialization.
+fn serialized_size(document: &Document) -> Result<usize> {
+    struct Counter(usize);
+
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, document)?;
+    Ok(counter.0)
+}
+
+/// Returns the number of characters of the last word of `q`, or `None` if `q` doesn't end with a
+/// word.
+fn last_word_len(q: &str) -> Option<usize> {
+    let last_word = q.rsplit(|c: char| !c.is_alphanumeric()).next()?;
+    (!last_word.is_empty()).then(|| last_word.chars().count())
+}
+
+/// Inserts the `_geoDistance` of the document from the first `_geoPoint` of the sort criteria or,
+/// when `min_distance` is set, the smallest distance from all the `_geoPoint`s.
+///
+/// The distance is rounded to an integer number of meters, unless `decimals` is given, in which
+/// case it is a float rounded to this number of decimal places.
+fn insert_geo_distance(
+    sorts: &[String],
+    min_distance: bool,
+    decimals: Option<u32>,
+    document: &mut Document,
+) -> Result<()> {
+    let bases = sort_geo_points(sorts, min_distance)?;
+    insert_distance(&bases, decimals, document);
+
+    Ok(())
+}
+
+/// Returns the first `_geoPoint` of the sort criteria or, when `all` is set, all of them.
+fn sort_geo_points(sorts: &[String], all: bool) -> Result<Vec<[f64; 2]>> {
+    lazy_static::lazy_static! {
+        static ref GEO_REGEX: Regex =
+            Regex::new(r"_geoPoint\(\s*([[:digit:].\-]+)\s*,\s*([[:digit:].\-]+)\s*\)").unwrap();
+    };
+    let mut bases = Vec::new();
+    for capture_group in sorts.iter().filter_map(|sort| GEO_REGEX.captures(sort)) {
+        let parse_coordinate = |coordinate: &str| match coordinate.parse::<f64>() {
+            Ok(coordinate) if coordinate.is_finite() => Ok(coordinate),
+            _ => Err(SearchError::InvalidGeoPoint(capture_group[0].to_string())),
+        };
+        bases.push([
+            parse_coordinate(&capture_group[1])?,
+            parse_coordinate(&capture_group[2])?,
+        ]);
+        if !all {
+            break;
+        }
+    }
+
+    Ok(bases)
+}
+
+/// Inserts the `_geoDistance` of the document from the closest of the `bases` points, rounded as
+/// described in `insert_geo_distance`. Nothing is inserted if the document has no `_geo` field.
+fn insert_distance(bases: &[[f64; 2]], decimals: Option<u32>, document: &mut Document) {
+    let geo_point = &document.get("_geo").unwrap_or(&json!(null));
+    if let Some((lat, lng)) = geo_point["lat"].as_f64().zip(geo_point["lng"].as_f64()) {
+        let distance = bases
+            .iter()
+            .map(|base| milli::distance_between_two_points(base, &[lat, lng]))
+            .reduce(f64::min);
+        if let Some(distance) = distance {
+            let distance = match decimals {
+                Some(_) => json!(round_distance(distance, decimals)),
+                None => json!(round_distance(distance, decimals) as usize),
+            };
+            document.insert("_geoDistance".to_string(), distance);
+        }
+    }
+}
+
+/// Rounds `distance` to an integer number of meters, or to `decimals` decimal places.
+fn round_distance(distance: f64, decimals: Option<u32>) -> f64 {
+    match decimals {
+        Some(decimals) => {
+            let factor = 10_f64.powi(decimals.min(f64::DIGITS) as i32);
+            (distance * factor).round() / factor
+        }
+        None => distance.round(),
+    }
+}
+
+/// Flattens the nested objects and arrays of `document`, the values being keyed by their path:
+/// the keys of the objects are joined by dots and the indexes of the arrays are bracketed, like
+/// `authors[0].name`. The empty objects and arrays are kept as they are.
+fn flatten_document(document: Document) -> Document {
+    let mut flattened = Document::new();
+    for (key, value) in document {
+        flatten_value(key, value, &mut flattened);
+    }
+    flattened
+}
+
+fn flatten_value(path: String, value: Value, flattened: &mut Document) {
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (key, value) in object {
+                flatten_value(format!("{}.{}", path, key), value, flattened);
+            }
+        }
+        Value::Array(array) if !array.is_empty() => {
+            for (i, value) in array.into_iter().enumerate() {
+                flatten_value(format!("{}[{}]", path, i), value, flattened);
+            }
+        }
+        value => {
+            flattened.insert(path, value);
+        }
+    }
+}
+
+/// Computes the matches of `document`, grouped by top-level attribute or, if `by_pointer` is
+/// set, keyed by the JSON pointer of each matching value. The matched terms are only returned if
+/// `with_terms` is set.
+fn compute_matches<A: AsRef<[u8]>>(
+    matcher: &impl Matcher,
+    document: &Document,
+    analyzer: &Analyzer<A>,
+    offset_unit: OffsetUnit,
+    by_pointer: bool,
+    with_terms: bool,
+) -> MatchesInfo {
+    let mut matches = BTreeMap::new();
+
+    for (key, value) in document {
+        if by_pointer {
+            let pointer = format!("/{}", escape_pointer_token(key));
+            compute_pointer_matches(
+                &mut matches,
+                pointer,
+                value,
+                matcher,
+                analyzer,
+                offset_unit,
+                with_terms,
+            );
+            continue;
+        }
+
+        let mut infos = Vec::new();
+        compute_value_matches(
+            &mut infos,
+            value,
+            matcher,
+            analyzer,
+            offset_unit,
+            with_terms,
+        );
+        if !infos.is_empty() {
+            matches.insert(key.clone(), infos);
+        }
+    }
+    matches
+}
+
+/// Removes the matches shorter than `min_length`, and the attributes left without any match.
+fn retain_long_matches(matches: &mut MatchesInfo, min_length: usize) {
+    if min_length == 0 {
+        return;
+    }
+
+    for infos in matches.values_mut() {
+        infos.retain(|info| info.length >= min_length);
+    }
+    matches.retain(|_, infos| !infos.is_empty());
+}
+
+/// Escapes a key to be used as a JSON pointer reference token, as described by RFC 6901.
+fn escape_pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Returns the flattened name of the field designated by `attr`, a JSON pointer like
+/// `/author/name` being turned into `author.name`. The other attributes are returned as is.
+fn field_name_from_pointer(attr: &str) -> Cow<str> {
+    match attr.strip_prefix('/') {
+        Some(pointer) => Cow::Owned(
+            pointer
+                .split('/')
+                .map(|token| token.replace("~1", "/").replace("~0", "~"))
+                .collect::<Vec<_>>()
+                .join("."),
+        ),
+        None => Cow::Borrowed(attr),
+    }
+}
+
+/// Computes the matches of each string or number nested in `value`, under its own JSON pointer
+/// built from `pointer`, the pointer of `value`.
+fn compute_pointer_matches<A: AsRef<[u8]>>(
+    matches: &mut MatchesInfo,
+    pointer: String,
+    value: &Value,
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+    offset_unit: OffsetUnit,
+    with_terms: bool,
+) {
+    match value {
+        Value::Array(vals) => {
+            for (i, val) in vals.iter().enumerate() {
+                let pointer = format!("{}/{}", pointer, i);
+                compute_pointer_matches(
+                    matches,
+                    pointer,
+                    val,
+                    matcher,
+                    analyzer,
+                    offset_unit,
+                    with_terms,
+                );
+            }
+        }
+        Value::Object(vals) => {
+            for (key, val) in vals {
+                let pointer = format!("{}/{}", pointer, escape_pointer_token(key));
+                compute_pointer_matches(
+                    matches,
+                    pointer,
+                    val,
+                    matcher,
+                    analyzer,
+                    offset_unit,
+                    with_terms,
+                );
+            }
+        }
+        _ => {
+            let mut infos = Vec::new();
+            compute_value_matches(
+                &mut infos,
+                value,
+                matcher,
+                analyzer,
+                offset_unit,
+                with_terms,
+            );
+            if !infos.is_empty() {
+                matches.insert(pointer, infos);
+            }
+        }
+    }
+}
+
+fn compute_value_matches<'a, A: AsRef<[u8]>>(
+    infos: &mut Vec<MatchInfo>,
+    value: &Value,
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<'a, A>,
+    offset_unit: OffsetUnit,
+    with_terms: bool,
+) {
+    match value {
+        Value::String(s) => {
+            let analyzed = analyzer.analyze(s);
+            let mut start = 0;
+            for (word, token) in analyzed.reconstruct() {
+                if token.is_word() {
+                    if let Some(length) = matcher.matches(&token) {
+                        let matched_term =
+                            with_terms.then(|| MatchInfo::matched_term(word, &token, length));
+                        let length = offset_unit.match_len(word, length);
+                        infos.push(MatchInfo {
+                            start,
+                            length,
+                            matched_term,
+                        });
+                    }
+                }
+
+                start += offset_unit.len(word);
+            }
+        }
+        Value::Array(vals) => vals.iter().for_each(|val| {
+            compute_value_matches(infos, val, matcher, analyzer, offset_unit, with_terms)
+        }),
+        Value::Object(vals) => vals.values().for_each(|val| {
+            compute_value_matches(infos, val, matcher, analyzer, offset_unit, with_terms)
+        }),
+        Value::Number(number) => compute_value_matches(
+            infos,
+            &Value::String(number.to_string()),
+            matcher,
+            analyzer,
+            offset_unit,
+            with_terms,
+        ),
+        _ => (),
+    }
+}
+
+/// Returns whether any word of the `fields` of the document is matched by the query.
+fn has_match_in_fields<A: AsRef<[u8]>>(
+    obkv: &obkv::KvReaderU16,
+    fields: &BTreeSet<FieldId>,
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+) -> Result<bool> {
+    for (fid, value) in obkv.iter() {
+        if fields.contains(&fid) {
+            let value = serde_json::from_slice(value)?;
+            let mut infos = Vec::new();
+            compute_value_matches(
+                &mut infos,
+                &value,
+                matcher,
+                analyzer,
+                OffsetUnit::Bytes,
+                false,
+            );
+            if !infos.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Counts the words of the document that are entirely matched by the query, as opposed to the
+/// words that are only matched by a prefix of a query word.
+fn count_whole_word_matches<A: AsRef<[u8]>>(
+    obkv: &obkv::KvReaderU16,
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+) -> Result<usize> {
+    let mut count = 0;
+    for (_fid, value) in obkv.iter() {
+        let value = serde_json::from_slice(value)?;
+        count += count_value_whole_word_matches(&value, matcher, analyzer);
+    }
+
+    Ok(count)
+}
+
+fn count_value_whole_word_matches<'a, A: AsRef<[u8]>>(
+    value: &Value,
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<'a, A>,
+) -> usize {
+    match value {
+        Value::String(s) => analyzer
+            .analyze(s)
+            .reconstruct()
+            .filter(|(word, token)| {
+                token.is_word()
+                    && matcher
+                        .matches(token)
+                        .map_or(false, |length| length >= word.len())
+            })
+            .count(),
+        Value::Array(vals) => vals
+            .iter()
+            .map(|val| count_value_whole_word_matches(val, matcher, analyzer))
+            .sum(),
+        Value::Object(vals) => vals
+            .values()
+            .map(|val| count_value_whole_word_matches(val, matcher, analyzer))
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Returns an error listing the facets of `facetsDistribution` that are neither filterable
+/// attributes nor nested in one, along with the filterable attributes. milli would otherwise
+/// ignore them.
+fn check_facets(query: &SearchQuery, filterable_fields: &HashSet<String>) -> Result<()> {
+    let invalid: Vec<_> = query
+        .facets_distribution
+        .iter()
+        .flatten()
+        .filter(|&facet| {
+            // The patterns are checked when they are resolved, see `Index::facets_to_count`.
+            !facet.contains('*')
+                && !filterable_fields
+                    .iter()
+                    .any(|field| facet == field || milli::is_faceted_by(facet, field))
+        })
+        .cloned()
+        .collect();
+
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        let mut valid: Vec<_> = filterable_fields.iter().cloned().collect();
+        valid.sort_unstable();
+        Err(FacetError::InvalidFacetsDistribution(invalid, valid).into())
+    }
+}
+
+/// Returns whether `name` is matched by `pattern`, in which `*` matches any sequence of characters.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<_> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some((last, middle)) => (*last, middle),
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Returns an error if `typo_tolerance` overrides another setting than `enabled`. milli reads the
+/// minimum word sizes and the words and attributes without typos from the index during the search.
+fn check_typo_tolerance(typo_tolerance: &TypoSettings) -> Result<()> {
+    let overridden = if !typo_tolerance.min_word_length_for_typo.is_not_set() {
+        Some("minWordLengthForTypo")
+    } else if !typo_tolerance.disable_on_words.is_not_set() {
+        Some("disableOnWords")
+    } else if !typo_tolerance.disable_on_attributes.is_not_set() {
+        Some("disableOnAttributes")
+    } else {
+        None
+    };
+
+    match overridden {
+        Some(setting) => Err(SearchError::TypoToleranceOverride(setting).into()),
+        None => Ok(()),
+    }
+}
+
+/// Returns the words of `q` a document can match with typos, and whether each word is a prefix. Like
+/// in milli, the last word of the query is a prefix unless it is followed by a separator.
+fn typo_words<A: AsRef<[u8]>>(q: &str, analyzer: &Analyzer<A>) -> Vec<(String, bool)> {
+    let analyzed = analyzer.analyze(q);
+    let tokens: Vec<_> = analyzed.tokens().collect();
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| is_word(token))
+        .map(|(i, token)| (token.text().to_string(), i + 1 == tokens.len()))
+        .collect()
+}
+
+/// Returns the words of `q` once normalized by `analyzer`, the stop words being removed.
+fn normalized_query<A: AsRef<[u8]>>(q: &str, analyzer: &Analyzer<A>) -> Vec<String> {
+    analyzer
+        .analyze(q)
+        .tokens()
+        .filter(|token| is_word(token) && !token.is_stopword())
+        .map(|token| token.text().to_string())
+        .collect()
+}
+
+/// Returns, for each query word, the fewest typos between the query word and the document words
+/// matching the query, or `None` if the document doesn't contain it.
+///
+/// Like in milli, a query word is matched with one typo from 5 characters and with two typos from
+/// 9 characters.
+fn query_words_typos<A: AsRef<[u8]>>(
+    obkv: &obkv::KvReaderU16,
+    words: &[(String, bool)],
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+) -> Result<Vec<Option<u32>>> {
+    let automata: Vec<_> = words
+        .iter()
+        .map(|(word, is_prefix)| {
+            let max_typos = max_typos(word);
+            (Levenshtein::new(word, max_typos), max_typos, *is_prefix)
+        })
+        .collect();
+
+    let mut typos = vec![None; automata.len()];
+    for (_fid, value) in obkv.iter() {
+        let value = serde_json::from_slice(value)?;
+        min_value_typos(&value, &automata, matcher, analyzer, &mut typos);
+    }
+
+    Ok(typos)
+}
+
+/// Returns the number of typos the document needed to match the query: the sum, for each query
+/// word, of its fewest typos. The query words the document doesn't contain are not counted.
+fn count_typos<A: AsRef<[u8]>>(
+    obkv: &obkv::KvReaderU16,
+    words: &[(String, bool)],
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+) -> Result<u32> {
+    let typos = query_words_typos(obkv, words, matcher, analyzer)?;
+    Ok(typos.into_iter().flatten().sum())
+}
+
+/// Returns the number of distinct query `words` the document contains, with as many typos as
+/// they are matched with. Unlike the matches, each query word counts once however many times it
+/// occurs.
+fn count_matched_words<A: AsRef<[u8]>>(
+    obkv: &obkv::KvReaderU16,
+    words: &[(String, bool)],
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+) -> Result<usize> {
+    let typos = query_words_typos(obkv, words, matcher, analyzer)?;
+    let matched: HashSet<_> = words
+        .iter()
+        .zip(typos)
+        .filter(|(_, typos)| typos.is_some())
+        .map(|((word, _), _)| word)
+        .collect();
+    Ok(matched.len())
+}
+
+/// Returns the ranking score of the document, between `0.0` and `1.0`, from its evaluation by the
+/// ranking `criteria` depending on the query `words`.
+///
+/// Each rule splits the range of scores left by the previous rules into buckets, one per value
+/// the rule can give to a document, the documents of its best bucket keeping the top of the
+/// range:
+/// - `words`: the number of query words the document contains;
+/// - `typo`: the number of typos the document needed, up to the most typos the words allow;
+/// - `exactness`: the number of document words that are whole query words, up to one per query
+///   word.
+///
+/// The rules are evaluated in order until the first `proximity`, `attribute`, `sort`, `asc` or
+/// `desc` rule. The score thus never increases along the ranking of the search: the documents
+/// tying on the evaluated rules tie on their score, the rules after them only ordering the
+/// documents within a score. A placeholder search has no words to rank, all its hits score `1.0`.
+fn ranking_score<A: AsRef<[u8]>>(
+    obkv: &obkv::KvReaderU16,
+    criteria: &[Criterion],
+    words: &[(String, bool)],
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+) -> Result<f64> {
+    if words.is_empty() {
+        return Ok(1.0);
+    }
+
+    let mut typos = None;
+    let mut score = 1.0;
+    let mut bucket_width = 1.0;
+    for criterion in criteria {
+        let (rank, buckets) = match criterion {
+            Criterion::Words | Criterion::Typo => {
+                let typos = match typos {
+                    Some(ref typos) => typos,
+                    None => typos.insert(query_words_typos(obkv, words, matcher, analyzer)?),
+                };
+                if let Criterion::Words = criterion {
+                    let matched = typos.iter().filter(|typos| typos.is_some()).count();
+                    (words.len() - matched, words.len() + 1)
+                } else {
+                    let max_typos: usize = words.iter().map(|(word, _)| max_typos(word)).sum();
+                    let typos = typos.iter().flatten().sum::<u32>() as usize;
+                    (min(typos, max_typos), max_typos + 1)
+                }
+            }
+            Criterion::Exactness => {
+                let exact = count_whole_word_matches(obkv, matcher, analyzer)?;
+                (words.len() - min(exact, words.len()), words.len() + 1)
+            }
+            _ => break,
+        };
+        bucket_width /= buckets as f64;
+        score -= rank as f64 * bucket_width;
+    }
+
+    Ok(score)
+}
+
+/// Returns the number of typos a query word is matched with, like in milli.
+fn max_typos(word: &str) -> usize {
+    match word.chars().count() {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    }
+}
+
+fn min_value_typos<A: AsRef<[u8]>>(
+    value: &Value,
+    automata: &[(Levenshtein, usize, bool)],
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+    typos: &mut [Option<u32>],
+) {
+    match value {
+        Value::String(s) => {
+            let analyzed = analyzer.analyze(s);
+            for token in analyzed.tokens() {
+                if !token.is_word() || matcher.matches(&token).is_none() {
+                    continue;
+                }
+
+                for ((automaton, max_typos, is_prefix), min_typos) in
+                    automata.iter().zip(&mut *typos)
+                {
+                    let distance = if *is_prefix {
+                        automaton.prefix_distance(token.text())
+                    } else {
+                        automaton.distance(token.text())
+                    };
+                    if distance <= *max_typos {
+                        let distance = distance as u32;
+                        *min_typos = Some(min_typos.map_or(distance, |min| min.min(distance)));
+                    }
+                }
+            }
+        }
+        Value::Array(vals) => vals
+            .iter()
+            .for_each(|val| min_value_typos(val, automata, matcher, analyzer, typos)),
+        Value::Object(vals) => vals
+            .values()
+            .for_each(|val| min_value_typos(val, automata, matcher, analyzer, typos)),
+        _ => (),
+    }
+}
+
+/// Returns an error naming the first attribute to retrieve, highlight or crop which is not a
+/// displayed attribute of the index, these attributes are ignored otherwise.
+fn check_attributes(
+    query: &SearchQuery,
+    fields_ids_map: &FieldsIdsMap,
+    displayed_ids: &BTreeSet<FieldId>,
+) -> Result<()> {
+    match unknown_attributes(query, fields_ids_map, displayed_ids).first() {
+        Some(&(parameter, name)) => {
+            Err(SearchError::UnknownAttribute(parameter, name.to_string()).into())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Returns the attributes to retrieve, highlight or crop which are not displayed attributes of the
+/// index, along with the parameter they were given in.
+fn unknown_attributes<'a>(
+    query: &'a SearchQuery,
+    fields_ids_map: &FieldsIdsMap,
+    displayed_ids: &BTreeSet<FieldId>,
+) -> Vec<(&'static str, &'a str)> {
+    let to_retrieve = query.attributes_to_retrieve.iter().flatten().map(|attr| {
+        let name = attr.strip_prefix('-').unwrap_or(attr);
+        let displayed = match attribute_regex(name) {
+            Some(regex) => {
+                regex_fields_ids(regex, fields_ids_map).any(|id| displayed_ids.contains(&id))
+            }
+            None => fields_ids_map
+                .id(name)
+                .map_or(false, |id| displayed_ids.contains(&id)),
+        };
+        ("attributesToRetrieve", name, displayed)
+    });
+    // The attributes to highlight and to crop can be given as JSON pointers or as `parent.*`.
+    let to_highlight = query.attributes_to_highlight.iter().flatten().map(|attr| {
+        let name = attr.name();
+        let fields = formatted_fields_ids(name, fields_ids_map, displayed_ids);
+        ("attributesToHighlight", name, !fields.is_empty())
+    });
+    let to_crop = query.attributes_to_crop.iter().flatten().map(|attr| {
+        let name = attr.name_and_length(0).0;
+        let fields = formatted_fields_ids(name, fields_ids_map, displayed_ids);
+        ("attributesToCrop", name, !fields.is_empty())
+    });
+
+    to_retrieve
+        .chain(to_highlight)
+        .chain(to_crop)
+        .filter(|(_, name, displayed)| *name != "*" && !displayed)
+        .map(|(parameter, name, _)| (parameter, name))
+        .collect()
+}
+
+/// Returns a warning for each parameter of `query` that has no effect: the `unknown_attributes`,
+/// and the parameters set without the parameter they apply to.
+fn search_warnings(query: &SearchQuery, unknown_attributes: &[(&str, &str)]) -> Vec<String> {
+    let mut warnings: Vec<_> = unknown_attributes
+        .iter()
+        .map(|(parameter, name)| {
+            format!(
+                "Attribute `{}` of `{}` does not exist or is not displayed, it is ignored.",
+                name, parameter
+            )
+        })
+        .collect();
+
+    let no_crop = query
+        .attributes_to_crop
+        .as_ref()
+        .map_or(true, Vec::is_empty);
+    let no_highlight = query
+        .attributes_to_highlight
+        .as_ref()
+        .map_or(true, Vec::is_empty);
+    let no_facets = query.facets_distribution.is_none();
+    let no_matches = !query.matches.unwrap_or_default();
+    let dependent_parameters = [
+        (
+            query.crop_length.is_some() && no_crop,
+            "cropLength",
+            "attributesToCrop",
+        ),
+        (
+            query.crop_marker.is_some() && no_crop,
+            "cropMarker",
+            "attributesToCrop",
+        ),
+        (
+            query.highlight_pre_tag.is_some() && no_highlight,
+            "highlightPreTag",
+            "attributesToHighlight",
+        ),
+        (
+            query.highlight_post_tag.is_some() && no_highlight,
+            "highlightPostTag",
+            "attributesToHighlight",
+        ),
+        (
+            query.max_highlights_per_field.is_some() && no_highlight,
+            "maxHighlightsPerField",
+            "attributesToHighlight",
+        ),
+        (
+            query.show_facet_stats.unwrap_or_default() && no_facets,
+            "showFacetStats",
+            "facetsDistribution",
+        ),
+        (
+            query.max_values_per_facet.is_some() && no_facets,
+            "maxValuesPerFacet",
+            "facetsDistribution",
+        ),
+        (
+            query.facet_sort.is_some() && no_facets,
+            "facetSort",
+            "facetsDistribution",
+        ),
+        (
+            query.matches_by_pointer.unwrap_or_default() && no_matches,
+            "matchesByPointer",
+            "matches",
+        ),
+        (
+            query.show_matched_terms.unwrap_or_default() && no_matches,
+            "showMatchedTerms",
+            "matches",
+        ),
+        (
+            query.min_match_length.unwrap_or_default() > 0 && no_matches,
+            "minMatchLength",
+            "matches",
+        ),
+    ];
+    for (ignored, parameter, required) in dependent_parameters {
+        if ignored {
+            warnings.push(format!(
+                "`{}` is ignored because `{}` is not set.",
+                parameter, required
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// The parameters of a search resolved against the index, shared by the stages of
+/// `Index::execute_search_in`.
+struct SearchPlan {
+    filter: Option<SearchFilter>,
+    /// The ranking rules of the query, or the ones of the index.
+    criteria: Vec<Criterion>,
+    fields_ids_map: FieldsIdsMap,
+    /// The displayed fields in the order of the displayed attributes of the index, or by field id
+    /// when all the fields are displayed. `*` retrieves them in this order.
+    displayed_order: Vec<FieldId>,
+    displayed_ids: BTreeSet<FieldId>,
+    primary_key: Option<FieldId>,
+    /// The number of hits that can be reached, capped to the hard limit.
+    max_total_hits: usize,
+    /// The `maxTotalHits` of the index, capping the number of hits reported when it is set.
+    nb_hits_cap: Option<usize>,
+    /// The offset and limit of the query, before they are capped to `max_total_hits`.
+    requested_offset: usize,
+    requested_limit: usize,
+    offset: usize,
+    limit: usize,
+    cursor: Option<SearchCursor>,
+    searched_ids: Option<BTreeSet<FieldId>>,
+    query_distinct: Option<QueryDistinct>,
+    excluded_ids: RoaringBitmap,
+    /// Whether the filter contains bounding boxes or `_geoDistance` bounds that milli can't
+    /// evaluate.
+    geo_filtered: bool,
+    /// Whether the documents returned by milli are filtered or reordered after the search.
+    filtered: bool,
+    sort_ties_by_id: bool,
+    /// Whether the documents are paginated after the search instead of by milli.
+    post_processed: bool,
+}
+
+impl SearchPlan {
+    /// Returns the number of hits reported for `candidates`. It is capped to not advertise the
+    /// hits that can't be reached, all the hits can be reached with a cursor.
+    fn nb_hits(&self, candidates: u64) -> u64 {
+        match self.nb_hits_cap {
+            Some(max) if self.cursor.is_none() => min(candidates, max as u64),
+            _ => candidates,
+        }
+    }
+}
+
+/// The candidates of a search and their documents, passed from one stage of the search to the
+/// next one.
+struct SearchState<'t> {
+    candidates: RoaringBitmap,
+    /// The documents returned by milli, ranked.
+    documents: Vec<(u32, obkv::KvReaderU16<'t>)>,
+    next_cursor: Option<String>,
+    warnings: Vec<String>,
+    /// The number of candidates before the ranking score threshold left some of them out.
+    unfiltered_hits: Option<u64>,
+}
+
+/// The words of the query, matched in the documents when they are post-processed and formatted.
+struct QueryWords<'m> {
+    matcher: QueryMatcher<'m, MatchingWords>,
+    /// The words of the query and whether each one is the last, only when the typos, the matched
+    /// words or the ranking scores are computed, or the query has its own ranking rules or a
+    /// cursor.
+    typo_words: Option<Vec<(String, bool)>>,
+}
+
+/// The facets distribution of a search.
+struct SearchFacets {
+    distribution: BTreeMap<String, FacetValues>,
+    exhaustive_count: bool,
+    stats: Option<BTreeMap<String, FacetStats>>,
+}
+
+/// The distinct attribute of a query replacing the one of the index.
+#[derive(Debug, Clone, Copy)]
+struct QueryDistinct {
+    /// The distinct attribute of the index, milli makes the documents it returns distinct on it.
+    index: Option<FieldId>,
+    /// The distinct attribute of the query, `None` when the query disables it.
+    query: Option<FieldId>,
+}
+
+/// Returns whether none of the values of the `distinct` attribute of the document is in `seen`,
+/// like milli the values are normalized as facet values. They are then added to `seen`.
+fn is_distinct(
+    obkv: &obkv::KvReaderU16,
+    distinct: FieldId,
+    seen: &mut BTreeSet<String>,
+) -> Result<bool> {
+    let value: Value = match obkv.get(distinct) {
+        Some(value) => serde_json::from_slice(value)?,
+        None => return Ok(true),
+    };
+
+    let mut values = BTreeMap::new();
+    insert_facet_values(&value, &mut values);
+    if values.keys().any(|value| seen.contains(value)) {
+        return Ok(false);
+    }
+    seen.extend(values.into_keys());
+    Ok(true)
+}
+
+fn compute_formatted_options<'a>(
+    attr_to_highlight: &'a [AttributeToHighlight],
+    attr_to_crop: &[AttributeToCrop],
+    query_crop_length: usize,
+    crop_on_sentences: bool,
+    to_retrieve_ids: &IndexSet<FieldId>,
+    raw_ids: &BTreeSet<FieldId>,
+    fields_ids_map: &FieldsIdsMap,
+    displayed_ids: &BTreeSet<FieldId>,
+) -> BTreeMap<FieldId, FormatOptions<'a>> {
+    let mut formatted_options = BTreeMap::new();
+
+    add_highlight_to_formatted_options(
+        &mut formatted_options,
+        attr_to_highlight,
+        fields_ids_map,
+        displayed_ids,
+    );
+
+    add_crop_to_formatted_options(
+        &mut formatted_options,
+        attr_to_crop,
+        query_crop_length,
+        crop_on_sentences,
+        fields_ids_map,
+        displayed_ids,
+    );
+
+    // The raw attributes, and the attributes nested in them, are never formatted.
+    let raw_names: Vec<_> = raw_ids
+        .iter()
+        .filter_map(|&id| fields_ids_map.name(id))
+        .collect();
+    let is_raw = |id: FieldId| {
+        let name = fields_ids_map.name(id).unwrap_or_default();
+        raw_names.iter().any(|raw| milli::is_faceted_by(name, raw))
+    };
+    formatted_options.retain(|&id, _| !is_raw(id));
+
+    // Should not return `_formatted` if no valid attributes to highlight/crop
+    if !formatted_options.is_empty() {
+        let to_format_ids = to_retrieve_ids
+            .iter()
+            .copied()
+            .filter(|&id| !is_raw(id))
+            .collect();
+        add_non_formatted_ids_to_formatted_options(&mut formatted_options, &to_format_ids);
+    }
+
+    formatted_options
+}
+
+/// The key ordering the documents by id, the integer ids before the string ones.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum DocumentIdKey {
+    Integer(i64),
+    String(String),
+    Missing,
+}
+
+impl DocumentIdKey {
+    fn new(obkv: &obkv::KvReaderU16, primary_key: FieldId) -> Self {
+        let value = obkv
+            .get(primary_key)
+            .and_then(|value| serde_json::from_slice(value).ok());
+        match value {
+            Some(Value::Number(number)) => number.as_i64().map_or(Self::Missing, Self::Integer),
+            Some(Value::String(string)) => Self::String(string),
+            _ => Self::Missing,
+        }
+    }
+}
+
+/// Orders by id the runs of consecutive `documents` tying on every ranking rule that overlap
+/// `page`, the documents being ranked. The documents tie when they have the same `tie_key`.
+///
+/// Only the runs overlapping the page are sorted, the keys of the documents before and after them
+/// are not computed.
+fn sort_ties_by_id<T, K: PartialEq>(
+    documents: &mut [T],
+    page: Range<usize>,
+    mut tie_key: impl FnMut(&T) -> Result<K>,
+    mut id: impl FnMut(&T) -> DocumentIdKey,
+) -> Result<()> {
+    let end = min(page.end, documents.len());
+    if page.start >= end {
+        return Ok(());
+    }
+
+    // The run containing the first document of the page may start before the page.
+    let mut run_key = tie_key(&documents[page.start])?;
+    let mut run_start = page.start;
+    while run_start > 0 && tie_key(&documents[run_start - 1])? == run_key {
+        run_start -= 1;
+    }
+
+    let mut position = page.start + 1;
+    loop {
+        let key = match documents.get(position) {
+            Some(document) => Some(tie_key(document)?),
+            None => None,
+        };
+        if key.as_ref() != Some(&run_key) {
+            documents[run_start..position].sort_by_cached_key(&mut id);
+            // The run containing the last document of the page may end after the page.
+            match key {
+                Some(key) if position < end => {
+                    run_key = key;
+                    run_start = position;
+                }
+                _ => return Ok(()),
+            }
+        }
+        position += 1;
+    }
+}
+
+/// The fields ranking the documents besides the query words: the fields of the `asc` and `desc`
+/// ranking rules and the ones the query is sorted on.
+fn ranking_fields(
+    criteria: &[Criterion],
+    query: &SearchQuery,
+    fields_ids_map: &FieldsIdsMap,
+) -> Vec<Option<FieldId>> {
+    let mut fields = Vec::new();
+    for criterion in criteria {
+        match criterion {
+            Criterion::Asc(field) | Criterion::Desc(field) => fields.push(fields_ids_map.id(field)),
+            Criterion::Sort => {
+                for sort in query.sort.iter().flatten() {
+                    let member = match AscDesc::from_str(sort) {
+                        Ok(AscDesc::Asc(member) | AscDesc::Desc(member)) => member,
+                        Err(_) => continue,
+                    };
+                    fields.push(match member {
+                        Member::Field(name) => fields_ids_map.id(&name),
+                        Member::Geo(_) => fields_ids_map.id("_geo"),
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+    fields
+}
+
+/// The parts of a document its ranking depends on, the documents having the same key tie on every
+/// ranking rule.
+#[derive(PartialEq)]
+struct TieKey<'t> {
+    /// The tokens of the fields matching the query words, from which the `words`, `typo`,
+    /// `proximity`, `attribute` and `exactness` rules are computed.
+    matched_fields: Vec<(FieldId, Vec<TieToken>)>,
+    /// The values of the `ranking_fields`.
+    ranking_values: Vec<Option<&'t [u8]>>,
+}
+
+/// A token of a field matching the query words.
+#[derive(PartialEq)]
+enum TieToken {
+    /// A word, with its text when it matches the query words.
+    Word(Option<String>),
+    /// A sequence of separators, `true` when one of them is a hard separator.
+    Separator(bool),
+}
+
+/// Returns the `TieKey` of a document, the query words are matched with `matcher` unless the
+/// search is a placeholder search.
+fn tie_key<'t, A: AsRef<[u8]>>(
+    obkv: &obkv::KvReaderU16<'t>,
+    ranking_fields: &[Option<FieldId>],
+    matcher: Option<&impl Matcher>,
+    analyzer: &Analyzer<A>,
+) -> Result<TieKey<'t>> {
+    let mut matched_fields = Vec::new();
+    if let Some(matcher) = matcher {
+        for (fid, value) in obkv.iter() {
+            let mut tokens = Vec::new();
+            push_tie_tokens(
+                &serde_json::from_slice(value)?,
+                matcher,
+                analyzer,
+                &mut tokens,
+            );
+            if tokens
+                .iter()
+                .any(|token| matches!(token, TieToken::Word(Some(_))))
+            {
+                matched_fields.push((fid, tokens));
+            }
+        }
+    }
+
+    let ranking_values = ranking_fields
+        .iter()
+        .map(|fid| fid.and_then(|fid| obkv.get(fid)))
+        .collect();
+
+    Ok(TieKey {
+        matched_fields,
+        ranking_values,
+    })
+}
+
+fn push_tie_tokens<A: AsRef<[u8]>>(
+    value: &Value,
+    matcher: &impl Matcher,
+    analyzer: &Analyzer<A>,
+    tokens: &mut Vec<TieToken>,
+) {
+    let push_separator = |tokens: &mut Vec<TieToken>, hard: bool| match tokens.last_mut() {
+        Some(TieToken::Separator(last_hard)) => *last_hard |= hard,
+        _ => tokens.push(TieToken::Separator(hard)),
+    };
+
+    match value {
+        Value::Null => (),
+        Value::Array(values) => {
+            for value in values {
+                push_tie_tokens(value, matcher, analyzer, tokens);
+                push_separator(tokens, true);
+            }
+        }
+        Value::Object(values) => {
+            for (key, value) in values {
+                push_tie_tokens(&Value::String(key.clone()), matcher, analyzer, tokens);
+                push_tie_tokens(value, matcher, analyzer, tokens);
+                push_separator(tokens, true);
+            }
+        }
+        Value::String(text) => {
+            for token in analyzer.analyze(text).tokens() {
+                match token.is_separator() {
+                    Some(kind) => push_separator(tokens, matches!(kind, SeparatorKind::Hard)),
+                    None => {
+                        let matched = token.is_word() && matcher.matches(&token).is_some();
+                        tokens.push(TieToken::Word(matched.then(|| token.text().to_string())));
+                    }
+                }
+            }
+        }
+        value => push_tie_tokens(&Value::String(value.to_string()), matcher, analyzer, tokens),
+    }
+}
+
+/// The position of a cursor paginated search, the hits of its page being the ones ordered `after`
+/// the position, or the first ones.
+#[derive(Debug)]
+struct SearchCursor {
+    sort: Vec<AscDesc>,
+    raw_sort: Vec<String>,
+    after: Option<CursorKey>,
+}
+
+impl SearchCursor {
+    /// Parses `cursor`, an empty string or a `next_cursor` returned with the same `sort`.
+    fn new(query: &SearchQuery, cursor: &str) -> Result<Self> {
+        // The position of a hit can't be resumed at an offset.
+        if query.offset.is_some() {
+            return Err(SearchError::CursorNotSupported("offset").into());
+        }
+        if query.is_paginated() {
+            return Err(SearchError::CursorNotSupported("page").into());
+        }
+        if query.ranking_rules.is_some() {
+            return Err(SearchError::CursorNotSupported("rankingRules").into());
+        }
+
+        let raw_sort = query.sort.clone().unwrap_or_default();
+        let sort = match raw_sort.iter().map(|s| AscDesc::from_str(s)).collect() {
+            Ok(sort) => sort,
+            Err(asc_desc_error) => {
+                return Err(IndexError::Milli(SortError::from(asc_desc_error).into()))
+            }
+        };
+
+        if cursor.is_empty() {
+            return Ok(Self {
+                sort,
+                raw_sort,
+                after: None,
+            });
+        }
+
+        let (cursor_sort, after): (Vec<String>, CursorKey) = decode_hex(cursor)
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(SearchError::InvalidCursor)?;
+        let keyword_search = !query.q.as_deref().map_or(true, |q| q.trim().is_empty());
+        if cursor_sort != raw_sort
+            || after.values.len() != sort.len()
+            || after.score.is_some() != keyword_search
+        {
+            return Err(SearchError::InvalidCursor.into());
+        }
+
+        Ok(Self {
+            sort,
+            raw_sort,
+            after: Some(after),
+        })
+    }
+
+    /// Returns the cursor of the page following the hit at `key`.
+    fn next(&self, key: CursorKey) -> Result<String> {
+        let bytes = serde_json::to_vec(&(&self.raw_sort, key))?;
+        Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The position of a document in a cursor paginated search: its ranking score in a keyword
+/// search, its values for each sort criterion and its id to order the documents having the same
+/// score and values.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct CursorKey {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    values: Vec<SortValue>,
+    id: DocumentIdKey,
+}
+
+impl CursorKey {
+    fn new(
+        obkv: &obkv::KvReaderU16,
+        score: Option<f64>,
+        sort: &[AscDesc],
+        fields_ids_map: &FieldsIdsMap,
+        geo_id: Option<FieldId>,
+        primary_key: Option<FieldId>,
+    ) -> Result<Self> {
+        let mut values = Vec::with_capacity(sort.len());
+        for criterion in sort {
+            let (member, desc) = match criterion {
+                AscDesc::Asc(member) => (member, false),
+                AscDesc::Desc(member) => (member, true),
+            };
+            let value = match member {
+                Member::Field(name) => match fields_ids_map.id(name).and_then(|id| obkv.get(id)) {
+                    Some(value) => SortValue::new(serde_json::from_slice(value)?, desc),
+                    None => SortValue::Missing,
+                },
+                Member::Geo(base) => match geo_id.and_then(|id| obkv.get(id)) {
+                    Some(value) => extract_geo_point(&serde_json::from_slice(value)?)
+                        .map_or(SortValue::Missing, |point| {
+                            SortValue::Number(milli::distance_between_two_points(base, &point))
+                        }),
+                    None => SortValue::Missing,
+                },
+            };
+            values.push(value);
+        }
+
+        let id = match primary_key {
+            Some(primary_key) => DocumentIdKey::new(obkv, primary_key),
+            None => DocumentIdKey::Missing,
+        };
+
+        Ok(Self { score, values, id })
+    }
+
+    /// Compares the positions of two documents in a search sorted by `sort`, the highest scores
+    /// first.
+    fn cmp_by(&self, other: &Self, sort: &[AscDesc]) -> Ordering {
+        let by_score = match (self.score, other.score) {
+            (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        };
+        by_score.then_with(|| {
+            self.values
+                .iter()
+                .zip(&other.values)
+                .zip(sort)
+                .map(|((a, b), criterion)| a.cmp_by(b, matches!(criterion, AscDesc::Desc(_))))
+                .find(|ordering| *ordering != Ordering::Equal)
+                .unwrap_or_else(|| self.id.cmp(&other.id))
+        })
+    }
+}
+
+/// The value of a document for a sort criterion. The numbers come before the strings, which are
+/// compared in lowercase, and the documents without value come last in both directions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum SortValue {
+    Number(f64),
+    String(String),
+    Missing,
+}
+
+impl SortValue {
+    /// An array is sorted by its value that comes first in the direction of the sort.
+    fn new(value: Value, desc: bool) -> Self {
+        match value {
+            Value::Number(number) => number.as_f64().map_or(Self::Missing, Self::Number),
+            Value::String(string) => Self::String(string.to_lowercase()),
+            Value::Array(values) => values
+                .into_iter()
+                .map(|value| Self::new(value, desc))
+                .reduce(|a, b| {
+                    if a.cmp_by(&b, desc) == Ordering::Greater {
+                        b
+                    } else {
+                        a
+                    }
+                })
+                .unwrap_or(Self::Missing),
+            _ => Self::Missing,
+        }
+    }
+
+    fn cmp_by(&self, other: &Self, desc: bool) -> Ordering {
+        let direction = |ordering: Ordering| if desc { ordering.reverse() } else { ordering };
+        match (self, other) {
+            (Self::Missing, Self::Missing) => Ordering::Equal,
+            (Self::Missing, _) => Ordering::Greater,
+            (_, Self::Missing) => Ordering::Less,
+            (Self::Number(_), Self::String(_)) => Ordering::Less,
+            (Self::String(_), Self::Number(_)) => Ordering::Greater,
+            (Self::Number(a), Self::Number(b)) => {
+                direction(a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            }
+            (Self::String(a), Self::String(b)) => direction(a.cmp(b)),
+        }
+    }
+}
+
+/// Returns the regex of `attr` when it is an attribute selector prefixed by `re:`, an invalid regex
+/// selecting no field. The regexes are checked beforehand by `check_attribute_regexes`.
+fn attribute_regex(attr: &str) -> Option<Option<Regex>> {
+    attr.strip_prefix(ATTRIBUTE_REGEX_PREFIX)
+        .map(|pattern| build_attribute_regex(pattern).ok())
+}
+
+fn build_attribute_regex(pattern: &str) -> std::result::Result<Regex, regex::Error> {
+    regex::RegexBuilder::new(pattern)
+        .size_limit(ATTRIBUTE_REGEX_SIZE_LIMIT)
+        .dfa_size_limit(ATTRIBUTE_REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// Returns the ids of the fields whose name is matched by `regex`.
+fn regex_fields_ids(
+    regex: Option<Regex>,
+    fields_ids_map: &FieldsIdsMap,
+) -> impl Iterator<Item = FieldId> + '_ {
+    fields_ids_map
+        .iter()
+        .filter(move |(_, name)| regex.as_ref().map_or(false, |regex| regex.is_match(name)))
+        .map(|(id, _)| id)
+}
+
+/// Checks that the attributes to retrieve, highlight and crop given as regexes are valid regexes,
+/// small enough once compiled.
+fn check_attribute_regexes(query: &SearchQuery) -> Result<()> {
+    let to_retrieve = query
+        .attributes_to_retrieve
+        .iter()
+        .flatten()
+        .map(|attr| attr.strip_prefix('-').unwrap_or(attr));
+    let to_highlight = query
+        .attributes_to_highlight
+        .iter()
+        .flatten()
+        .map(|attr| attr.name());
+    let to_crop = query
+        .attributes_to_crop
+        .iter()
+        .flatten()
+        .map(|attr| attr.name_and_length(0).0);
+
+    for attr in to_retrieve.chain(to_highlight).chain(to_crop) {
+        if let Some(pattern) = attr.strip_prefix(ATTRIBUTE_REGEX_PREFIX) {
+            if let Err(error) = build_attribute_regex(pattern) {
+                return Err(SearchError::InvalidAttributeRegex(
+                    attr.to_string(),
+                    error.to_string(),
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the ids of the displayed fields designated by `attr`, an attribute to highlight or to
+/// crop, possibly given as a JSON pointer.
+///
+/// `*` designates all the displayed fields, a name ending with `.*` an attribute and all the
+/// fields nested in it, like `author.*` for `author`, `author.name` and `author.bio`, and a name
+/// prefixed by `re:` the fields whose name is matched by the regex that follows, like `re:^price_`.
+fn formatted_fields_ids(
+    attr: &str,
+    fields_ids_map: &FieldsIdsMap,
+    displayed_ids: &BTreeSet<FieldId>,
+) -> Vec<FieldId> {
+    if let Some(regex) = attribute_regex(attr) {
+        return regex_fields_ids(regex, fields_ids_map)
+            .filter(|id| displayed_ids.contains(id))
+            .collect();
+    }
+
+    let attr = field_name_from_pointer(attr);
+    if attr == "*" {
+        return displayed_ids.iter().copied().collect();
+    }
+
+    match attr.strip_suffix(".*") {
+        Some(parent) => displayed_ids
+            .iter()
+            .copied()
+            .filter(|&id| {
+                fields_ids_map
+                    .name(id)
+                    .map_or(false, |name| milli::is_faceted_by(name, parent))
+            })
+            .collect(),
+        None => fields_ids_map
+            .id(&attr)
+            .filter(|id| displayed_ids.contains(id))
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn add_highlight_to_formatted_options<'a>(
+    formatted_options: &mut BTreeMap<FieldId, FormatOptions<'a>>,
+    attr_to_highlight: &'a [AttributeToHighlight],
+    fields_ids_map: &FieldsIdsMap,
+    displayed_ids: &BTreeSet<FieldId>,
+) {
+    // The attributes given by name can override the highlight tags given to all the attributes
+    // with `*`, so they are applied last.
+    let (all, named): (Vec<_>, Vec<_>) = attr_to_highlight
+        .iter()
+        .partition(|attr| attr.name() == "*");
+
+    for attr in all.into_iter().chain(named) {
+        let new_format = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: attr.highlight_tags(),
+            crop_on_sentences: false,
+        };
+
+        for id in formatted_fields_ids(attr.name(), fields_ids_map, displayed_ids) {
+            formatted_options.insert(id, new_format);
+        }
+    }
+}
+
+fn add_crop_to_formatted_options(
+    formatted_options: &mut BTreeMap<FieldId, FormatOptions>,
+    attr_to_crop: &[AttributeToCrop],
+    crop_length: usize,
+    crop_on_sentences: bool,
+    fields_ids_map: &FieldsIdsMap,
+    displayed_ids: &BTreeSet<FieldId>,
+) {
+    // The crop lengths given in objects take precedence over the ones given with the `attr:len`
+    // syntax, so the objects are applied last.
+    let (objects, names): (Vec<_>, Vec<_>) = attr_to_crop
+        .iter()
+        .partition(|attr| matches!(attr, AttributeToCrop::Object(_)));
+
+    for attr in names.into_iter().chain(objects) {
+        let (attr_name, attr_len) = attr.name_and_length(crop_length);
+
+        for id in formatted_fields_ids(attr_name, fields_ids_map, displayed_ids) {
+            formatted_options
+                .entry(id)
+                .and_modify(|f| {
+                    f.crop = Some(attr_len);
+                    f.crop_on_sentences = crop_on_sentences;
+                })
+                .or_insert(FormatOptions {
+                    highlight: false,
+                    crop: Some(attr_len),
+                    highlight_tags: None,
+                    crop_on_sentences,
+                });
+        }
+    }
+}
+
+fn add_non_formatted_ids_to_formatted_options(
+    formatted_options: &mut BTreeMap<FieldId, FormatOptions>,
+    to_retrieve_ids: &IndexSet<FieldId>,
+) {
+    for id in to_retrieve_ids {
+        formatted_options.entry(*id).or_insert(FormatOptions {
+            highlight: false,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        });
+    }
+}
+
+/// Inserts the values of the raw attributes of `document` in its `formatted` version, unchanged.
+fn insert_raw_values(
+    document: &Document,
+    raw_ids: &BTreeSet<FieldId>,
+    field_ids_map: &FieldsIdsMap,
+    formatted: &mut Document,
+) {
+    for name in raw_ids.iter().filter_map(|&id| field_ids_map.name(id)) {
+        if let Some(value) = document.get(name) {
+            formatted.insert(name.to_string(), value.clone());
+        }
+    }
+}
+
+/// Returns up to `limit` of the `values` containing `query` once both are normalized by `analyzer`.
+///
+/// The values starting with the query come first, then the ones with a word starting with it and
+/// then the other ones. The values are ranked by count in each group, then alphabetically. All the
+/// values match an empty query.
+fn rank_facet_values<A: AsRef<[u8]>>(
+    values: BTreeMap<String, u64>,
+    query: &str,
+    limit: usize,
+    analyzer: &Analyzer<A>,
+) -> Vec<FacetValueHit> {
+    let query = normalize_facet_value(query.trim(), analyzer);
+    let mut ranked: Vec<_> = values
+        .into_iter()
+        .filter_map(|(value, count)| {
+            let normalized = normalize_facet_value(&value, analyzer);
+            let position = normalized.find(&query)?;
+            let group = match normalized[..position].chars().last() {
+                None => 0,
+                Some(previous) if !previous.is_alphanumeric() => 1,
+                Some(_) => 2,
+            };
+            Some(((group, Reverse(count)), value))
+        })
+        .collect();
+    ranked.sort_unstable();
+
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(|((_, Reverse(count)), value)| FacetValueHit { value, count })
+        .collect()
+}
+
+/// Returns the normalized form of a facet value or of the query searching the facet values, in
+/// which they are compared.
+fn normalize_facet_value<A: AsRef<[u8]>>(text: &str, analyzer: &Analyzer<A>) -> String {
+    let analyzed = analyzer.analyze(text);
+    analyzed
+        .tokens()
+        .map(|token| token.text().to_string())
+        .collect()
+}
+
+/// Inserts the facet values contained in `value` in `output`, as a map from their normalized to
+/// their original form.
+fn insert_facet_values(value: &Value, output: &mut BTreeMap<String, String>) {
+    match value {
+        Value::String(original) => {
+            let normalized = original.trim().to_lowercase();
+            output.entry(normalized).or_insert_with(|| original.clone());
+        }
+        Value::Number(number) => {
+            if let Some(number) = number.as_f64() {
+                let number = number.to_string();
+                output.entry(number.clone()).or_insert(number);
+            }
+        }
+        Value::Bool(boolean) => {
+            let boolean = boolean.to_string();
+            output.entry(boolean.clone()).or_insert(boolean);
+        }
+        Value::Array(values) => values
+            .iter()
+            .for_each(|value| insert_facet_values(value, output)),
+        Value::Null | Value::Object(_) => (),
+    }
+}
+
+fn make_document(
+    attributes_to_retrieve: &IndexSet<FieldId>,
+    raw_ids: &BTreeSet<FieldId>,
+    field_ids_map: &FieldsIdsMap,
+    obkv: obkv::KvReaderU16,
+) -> Result<Document> {
+    let mut document = serde_json::Map::new();
+
+    // recreate the original json
+    for (key, value) in obkv.iter() {
+        let value = serde_json::from_slice(value)?;
+        let key = field_ids_map
+            .name(key)
+            .expect("Missing field name")
+            .to_string();
+
+        document.insert(key, value);
+    }
+
+    // select the attributes to retrieve
+    let attributes_to_retrieve: Vec<_> = attributes_to_retrieve
+        .iter()
+        .map(|&fid| field_ids_map.name(fid).expect("Missing field name"))
+        .collect();
+
+    // The raw attributes are not selected, their values are moved from the original document as
+    // they are.
+    let mut selected = permissive_json_pointer::select_values(
+        &document,
+        attributes_to_retrieve.iter().copied().filter(|attr| {
+            raw_ids
+                .iter()
+                .all(|&id| field_ids_map.name(id) != Some(*attr))
+        }),
+    );
+    for &id in raw_ids {
+        let name = field_ids_map.name(id).expect("Missing field name");
+        if let Some(value) = document.remove(name) {
+            selected.insert(name.to_string(), value);
+        }
+    }
+
+    // then we need to convert the `serde_json::Map` into an `IndexMap`, the keys being moved in
+    // the order of the attributes to retrieve.
+    let mut document = Document::with_capacity(selected.len());
+    for attr in attributes_to_retrieve {
+        let keys: Vec<_> = selected
+            .keys()
+            .filter(|key| milli::is_faceted_by(key, attr) || milli::is_faceted_by(attr, key))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(value) = selected.remove(&key) {
+                document.insert(key, value);
+            }
+        }
+    }
+    document.extend(selected);
+
+    Ok(document)
+}
+
+/// Sorts the fields of `document` by field id, the fields unknown to `field_ids_map` last.
+fn sort_by_field_id(document: &mut Document, field_ids_map: &FieldsIdsMap) {
+    document.sort_by(|key1, _, key2, _| {
+        let id1 = field_ids_map.id(key1).unwrap_or(FieldId::MAX);
+        let id2 = field_ids_map.id(key2).unwrap_or(FieldId::MAX);
+        id1.cmp(&id2)
+    });
+}
+
+/// Formats the attributes of `document` described by `formatted_options`, and tells for each
+/// formatted attribute whether it has been truncated by a crop.
+///
+/// The document is only borrowed mutably to be converted to a `serde_json::Map` and back without
+/// being cloned, it is left unchanged.
+fn format_fields<A: AsRef<[u8]>>(
+    document: &mut Document,
+    field_ids_map: &FieldsIdsMap,
+    formatter: &Formatter<A>,
+    matching_words: &impl Matcher,
+    formatted_options: &BTreeMap<FieldId, FormatOptions>,
+    mut matches_info: Option<&mut MatchesInfo>,
+) -> Result<(Document, BTreeMap<String, bool>)> {
+    // Move the content of the `IndexMap` into a `serde_json::Map`, only the selected values are
+    // cloned by `select_values`.
+    let original: serde_json::Map<String, Value> = std::mem::take(document).into_iter().collect();
+
+    let selectors: Vec<_> = formatted_options
+        .keys()
+        // This unwrap must be safe since we got the ids from the fields_ids_map just
+        // before.
+        .map(|&fid| field_ids_map.name(fid).unwrap())
+        .collect();
+
+    let mut formatted =
+        permissive_json_pointer::select_values(&original, selectors.iter().copied());
+    *document = original.into_iter().collect();
+
+    let mut truncated = BTreeMap::new();
+
+    permissive_json_pointer::map_leaf_values(&mut formatted, selectors, |key, value| {
+        // To get the formatting option of each key we need to see all the rules that applies
+        // to the value and merge them together. eg. If a user said he wanted to highlight `doggo`
+        // and crop `doggo.name`. `doggo.name` needs to be highlighted + cropped while `doggo.age` is only
+        // highlighted.
+        let format = formatted_options
+            .iter()
+            .filter(|(field, _option)| {
+                let name = field_ids_map.name(**field).unwrap();
+                milli::is_faceted_by(name, key) || milli::is_faceted_by(key, name)
+            })
+            .fold(FormatOptions::default(), |acc, (_, option)| {
+                acc.merge(*option)
+            });
+        let mut matches = matches_info.is_some().then(Vec::new);
+        let (formatted, cropped) = formatter.format_value(
+            std::mem::take(value),
+            matching_words,
+            format,
+            matches.as_mut(),
+        );
+        *value = formatted;
+        *truncated.entry(key.to_string()).or_default() |= cropped;
+
+        // The matches of the nested values are grouped under their top-level attribute, like in
+        // `compute_matches`, or keyed by their JSON pointer. The position of the values in the
+        // arrays is unknown here, the matches of the elements of an array share the same pointer.
+        if let Some((matches_info, matches)) = matches_info.as_deref_mut().zip(matches) {
+            if !matches.is_empty() {
+                let attribute = if formatter.matches_by_pointer {
+                    key.split('.')
+                        .map(|token| format!("/{}", escape_pointer_token(token)))
+                        .collect()
+                } else {
+                    key.split('.').next().unwrap_or(key).to_string()
+                };
+                matches_info.entry(attribute).or_default().extend(matches);
+            }
+        }
+    });
+
+    // we need to convert back the `serde_json::Map` into an `IndexMap`.
+    let formatted = formatted.into_iter().collect();
+
+    Ok((formatted, truncated))
+}
+
+/// trait to allow unit testing of `format_fields`
+trait Matcher {
+    fn matches(&self, w: &Token) -> Option<usize>;
+
+    /// The normalized words of each phrase of the query.
+    fn phrases(&self) -> &[Vec<String>] {
+        &[]
+    }
+}
+
+/// The maximum number of consecutive words of a query milli looks up in the synonyms.
+const MAX_SYNONYM_WORDS: usize = 3;
+
+/// A `Matcher` aware of the phrases of the query, the words written between double quotes.
+struct QueryMatcher<'a, M> {
+    matcher: &'a M,
+    phrases: Vec<Vec<String>>,
+    /// The synonyms of the words of the query made of a single word.
+    synonyms: HashSet<String>,
+}
+
+impl<'a, M> QueryMatcher<'a, M> {
+    fn new<A: AsRef<[u8]>>(matcher: &'a M, query: &str, analyzer: &Analyzer<A>) -> Self {
+        // An unclosed double quote starts a phrase ending with the query.
+        let phrases = query
+            .split('"')
+            .skip(1)
+            .step_by(2)
+            .map(|phrase| {
+                analyzer
+                    .analyze(phrase)
+                    .tokens()
+                    .filter(is_word)
+                    .map(|token| token.text().to_string())
+                    .collect::<Vec<_>>()
+            })
+            // a single word is highlighted like the other words.
+            .filter(|words| words.len() > 1)
+            .collect();
+
+        Self {
+            matcher,
+            phrases,
+            synonyms: HashSet::new(),
+        }
+    }
+
+    /// Also matches the synonyms of the words of `query`. The synonyms made of several words are
+    /// matched like phrases.
+    fn with_synonyms<A: AsRef<[u8]>>(
+        mut self,
+        query: &str,
+        analyzer: &Analyzer<A>,
+        synonyms: &HashMap<Vec<String>, Vec<Vec<String>>>,
+    ) -> Self {
+        if synonyms.is_empty() {
+            return self;
+        }
+
+        let words: Vec<_> = analyzer
+            .analyze(query)
+            .tokens()
+            .filter(is_word)
+            .map(|token| token.text().to_string())
+            .collect();
+
+        for start in 0..words.len() {
+            let max_end = min(words.len(), start + MAX_SYNONYM_WORDS);
+            for end in start + 1..=max_end {
+                for synonym in synonyms.get(&words[start..end]).into_iter().flatten() {
+                    match synonym.as_slice() {
+                        [word] => {
+                            self.synonyms.insert(word.clone());
+                        }
+                        _ => self.phrases.push(synonym.clone()),
+                    }
+                }
+            }
+        }
+
+        self
+    }
+}
+
+impl<M: Matcher> Matcher for QueryMatcher<'_, M> {
+    fn matches(&self, w: &Token) -> Option<usize> {
+        match self.matcher.matches(w) {
+            Some(length) => Some(length),
+            None if self.synonyms.contains(w.text()) => Some(w.byte_len()),
+            None => None,
+        }
+    }
+
+    fn phrases(&self) -> &[Vec<String>] {
+        &self.phrases
+    }
+}
+
+#[cfg(test)]
+impl Matcher for BTreeMap<&str, Option<usize>> {
+    fn matches(&self, w: &Token) -> Option<usize> {
+        self.get(w.text()).cloned().flatten()
+    }
+}
+
+impl Matcher for MatchingWords {
+    fn matches(&self, w: &Token) -> Option<usize> {
+        self.matching_bytes(w)
+    }
+}
+
+struct Formatter<'a, A> {
+    analyzer: &'a Analyzer<'a, A>,
+    highlight_tags: (String, String),
+    crop_markers: (String, String),
+    offset_unit: OffsetUnit,
+    crop_strategy: CropStrategy,
+    crop_unit: CropUnit,
+    matches_by_pointer: bool,
+    matched_terms: bool,
+    highlight_numbers: bool,
+    segments: bool,
+    snippets: usize,
+    sensitivity: Option<HighlightSensitivity>,
+    max_highlights: Option<usize>,
+    escape_html: bool,
+}
+
+impl<'a, A: AsRef<[u8]>> Formatter<'a, A> {
+    pub fn new(
+        analyzer: &'a Analyzer<'a, A>,
+        highlight_tags: (String, String),
+        crop_markers: (String, String),
+    ) -> Self {
+        Self {
+            analyzer,
+            highlight_tags,
+            crop_markers,
+            offset_unit: OffsetUnit::Bytes,
+            crop_strategy: CropStrategy::default(),
+            crop_unit: CropUnit::default(),
+            matches_by_pointer: false,
+            matched_terms: false,
+            highlight_numbers: true,
+            segments: false,
+            snippets: 1,
+            sensitivity: None,
+            max_highlights: None,
+            escape_html: false,
+        }
+    }
+
+    /// Sets the strategy choosing the part of the cropped attributes to keep.
+    pub fn with_crop_strategy(mut self, crop_strategy: CropStrategy) -> Self {
+        self.crop_strategy = crop_strategy;
+        self
+    }
+
+    /// Sets the unit in which the crop lengths are counted.
+    pub fn with_crop_unit(mut self, crop_unit: CropUnit) -> Self {
+        self.crop_unit = crop_unit;
+        self
+    }
+
+    /// Sets the unit of the offsets of the matches computed while formatting.
+    pub fn with_offset_unit(mut self, offset_unit: OffsetUnit) -> Self {
+        self.offset_unit = offset_unit;
+        self
+    }
+
+    /// Keys the matches computed while formatting by the JSON pointer of the formatted values
+    /// instead of their top-level attribute.
+    pub fn with_matches_by_pointer(mut self, matches_by_pointer: bool) -> Self {
+        self.matches_by_pointer = matches_by_pointer;
+        self
+    }
+
+    /// Returns the matched terms in the matches computed while formatting.
+    pub fn with_matched_terms(mut self, matched_terms: bool) -> Self {
+        self.matched_terms = matched_terms;
+        self
+    }
+
+    /// Formats the numbers as strings, like the other values, instead of leaving them untouched.
+    pub fn with_highlight_numbers(mut self, highlight_numbers: bool) -> Self {
+        self.highlight_numbers = highlight_numbers;
+        self
+    }
+
+    /// Formats the strings as the list of their segments instead of a string holding the highlight
+    /// tags, for the clients rendering the highlighted parts themselves.
+    pub fn with_segments(mut self, segments: bool) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    /// Crops the attributes around up to `snippets` clusters of matches instead of a single one,
+    /// the snippets being separated by a crop marker.
+    pub fn with_snippets(mut self, snippets: usize) -> Self {
+        self.snippets = snippets;
+        self
+    }
+
+    /// Only highlights the words matching the query with the case or the diacritics of the query
+    /// words, the words are otherwise matched on their normalized form.
+    pub fn with_sensitivity(mut self, sensitivity: Option<HighlightSensitivity>) -> Self {
+        self.sensitivity = sensitivity;
+        self
+    }
+
+    /// Wraps at most `max_highlights` highlighted parts of each formatted string in the highlight
+    /// tags, the following matches are left unhighlighted. The matches are still computed.
+    pub fn with_max_highlights(mut self, max_highlights: Option<usize>) -> Self {
+        self.max_highlights = max_highlights;
+        self
+    }
+
+    /// Escapes the HTML special characters of the formatted text, the highlight tags and the crop
+    /// markers being inserted as they are. The segments are never escaped.
+    pub fn with_escape_html(mut self, escape_html: bool) -> Self {
+        self.escape_html = escape_html;
+        self
+    }
+
+    /// Returns `text` as it is inserted in a formatted string.
+    fn escape<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if self.escape_html {
+            escape_html(text)
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
+    /// Returns the highlight tags of the attribute, falling back on the ones of the query.
+    fn highlight_tags<'b>(&'b self, format_options: &FormatOptions<'b>) -> (&'b str, &'b str) {
+        let tags = format_options.highlight_tags;
+        let pre_tag = tags.and_then(|tags| tags.highlight_pre_tag.as_deref());
+        let post_tag = tags.and_then(|tags| tags.highlight_post_tag.as_deref());
+        (
+            pre_tag.unwrap_or(&self.highlight_tags.0),
+            post_tag.unwrap_or(&self.highlight_tags.1),
+        )
+    }
+
+    /// Formats `value` according to `format_options`, the returned boolean tells if a crop marker
+    /// has been inserted in the formatted value.
+    ///
+    /// When `matches` is given, the matches found in the formatted value are pushed in it, with
+    /// offsets relative to the formatted strings.
+    fn format_value(
+        &self,
+        value: Value,
+        matcher: &impl Matcher,
+        format_options: FormatOptions,
+        mut matches: Option<&mut Vec<MatchInfo>>,
+    ) -> (Value, bool) {
+        match value {
+            Value::String(old_string) => {
+                self.format_text(old_string, matcher, format_options, matches)
+            }
+            Value::Array(values) => {
+                let values = values
+                    .into_iter()
+                    .map(|v| {
+                        self.format_value(
+                            v,
+                            matcher,
+                            FormatOptions {
+                                highlight: format_options.highlight,
+                                crop: None,
+                                highlight_tags: format_options.highlight_tags,
+                                crop_on_sentences: false,
+                            },
+                            matches.as_deref_mut(),
+                        )
+                        .0
+                    })
+                    .collect();
+                (Value::Array(values), false)
+            }
+            Value::Object(object) => {
+                let object = object
+                    .into_iter()
+                    .map(|(k, v)| {
+                        (
+                            k,
+                            self.format_value(
+                                v,
+                                matcher,
+                                FormatOptions {
+                                    highlight: format_options.highlight,
+                                    crop: None,
+                                    highlight_tags: format_options.highlight_tags,
+                                    crop_on_sentences: false,
+                                },
+                                matches.as_deref_mut(),
+                            )
+                            .0,
+                        )
+                    })
+                    .collect();
+                (Value::Object(object), false)
+            }
+            Value::Number(number) if !self.highlight_numbers => {
+                // the matches are still computed, on the number written as a string.
+                if matches.is_some() {
+                    let format_options = FormatOptions {
+                        highlight: false,
+                        crop: None,
+                        ..format_options
+                    };
+                    self.format_string(number.to_string(), matcher, format_options, matches);
+                }
+                (Value::Number(number), false)
+            }
+            Value::Number(number) => {
+                let only_cropped = format_options.crop.is_some() && !format_options.highlight;
+                let (value, cropped) =
+                    self.format_text(number.to_string(), matcher, format_options, matches);
+                // A number that is only cropped, like with a wildcard crop, stays a number when the
+                // crop leaves it whole. The numbers that are only retrieved are still strings.
+                match value {
+                    Value::String(s) if only_cropped && s == number.to_string() => {
+                        (Value::Number(number), cropped)
+                    }
+                    value => (value, cropped),
+                }
+            }
+            value => (value, false),
+        }
+    }
+
+    /// Formats `s` as a string, or as the list of its segments when requested.
+    fn format_text(
+        &self,
+        s: String,
+        matcher: &impl Matcher,
+        format_options: FormatOptions,
+        matches: Option<&mut Vec<MatchInfo>>,
+    ) -> (Value, bool) {
+        if self.segments {
+            let mut segments = Vec::new();
+            let (_, cropped) = self.format_string_with_segments(
+                s,
+                matcher,
+                format_options,
+                matches,
+                Some(&mut segments),
+            );
+            (json!(segments), cropped)
+        } else {
+            let (value, cropped) = self.format_string(s, matcher, format_options, matches);
+            (Value::String(value), cropped)
+        }
+    }
+
+    fn format_string(
+        &self,
+        s: String,
+        matcher: &impl Matcher,
+        format_options: FormatOptions,
+        matches: Option<&mut Vec<MatchInfo>>,
+    ) -> (String, bool) {
+        self.format_string_with_segments(s, matcher, format_options, matches, None)
+    }
+
+    /// Formats `s`, pushing the segments of the formatted string in `segments` when given.
+    fn format_string_with_segments(
+        &self,
+        s: String,
+        matcher: &impl Matcher,
+        format_options: FormatOptions,
+        mut matches: Option<&mut Vec<MatchInfo>>,
+        mut segments: Option<&mut Vec<Segment>>,
+    ) -> (String, bool) {
+        let analyzed = self.analyzer.analyze(&s);
+        let tokens: Vec<_> = analyzed.reconstruct().collect();
+
+        let snippets = match format_options.crop {
+            Some(crop_len) if crop_len > 0 && self.snippets > 1 => {
+                let matched: Vec<_> = tokens
+                    .iter()
+                    .map(|(_, token)| matcher.matches(token).is_some())
+                    .collect();
+                crop_snippets(&tokens, &matched, crop_len, self.crop_unit, self.snippets)
+            }
+            _ => None,
+        };
+
+        let (interval, crop_marker_before, crop_marker_after) = match format_options.crop {
+            Some(crop_len) if crop_len > 0 && snippets.is_none() => {
+                let unit = self.crop_unit;
+                let is_match = |(_, token): &(&str, Token)| matcher.matches(token).is_some();
+                let crop = match self.crop_strategy {
+                    CropStrategy::FirstMatch => tokens
+                        .iter()
+                        .position(is_match)
+                        .map(|first_match| crop_around_match(&tokens, first_match, crop_len, unit)),
+                    CropStrategy::BestWindow => {
+                        let matched: Vec<_> = tokens.iter().map(is_match).collect();
+                        crop_best_window(&tokens, &matched, crop_len, unit)
+                    }
+                };
+
+                // If no word matches in the attribute, the beginning of the text is kept.
+                let crop = crop.unwrap_or_else(|| {
+                    let end = crop_end(&tokens, 0, crop_len, unit);
+                    (0..end, false, has_words_after(&tokens, end))
+                });
+
+                let crop = if format_options.crop_on_sentences {
+                    let matched: Vec<_> = tokens.iter().map(is_match).collect();
+                    snap_to_sentences(&tokens, &matched, crop, crop_len / 2, unit)
+                } else {
+                    crop
+                };
+
+                match unit {
+                    CropUnit::Word => crop,
+                    CropUnit::Char | CropUnit::Token => trim_separators(&tokens, crop),
+                }
+            }
+            _ => (0..tokens.len(), false, false),
+        };
+        let (windows, crop_marker_before, crop_marker_after) =
+            snippets.unwrap_or_else(|| (vec![interval], crop_marker_before, crop_marker_after));
+        let phrase_spans = if format_options.highlight {
+            phrase_spans(&tokens, matcher.phrases())
+        } else {
+            Vec::new()
+        };
+        let tokens_interval = tokens
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| windows.iter().any(|window| window.contains(i)));
+
+        let (pre_tag, post_tag) = self.highlight_tags(&format_options);
+
+        let out = if crop_marker_before {
+            self.crop_markers.0.clone()
+        } else {
+            String::new()
+        };
+        if let Some(segments) = segments.as_deref_mut() {
+            push_segment(segments, &out, false);
+        }
+
+        // The highlighted parts of consecutive tokens are wrapped in a single pair of tags, so the
+        // words of a phrase, or adjacent matches, never produce nested or empty tags.
+        let mut highlighting = false;
+        let mut highlights = 0;
+        let mut previous = None;
+        let mut out = tokens_interval.fold(out, |mut out, (i, (word, token))| {
+            // the snippets are separated by a crop marker.
+            if previous.map_or(false, |previous| i > previous + 1) {
+                if highlighting {
+                    out.push_str(post_tag);
+                    highlighting = false;
+                }
+                out.push_str(&self.crop_markers.1);
+                if let Some(segments) = segments.as_deref_mut() {
+                    push_segment(segments, &self.crop_markers.1, false);
+                }
+            }
+            previous = Some(i);
+
+            let in_phrase = phrase_spans.iter().any(|span| span.contains(&i));
+
+            // Check if we need to do highlighting or computed matches before calling
+            // Matcher::match since the call is expensive.
+            let matched = if (format_options.highlight || matches.is_some()) && token.is_word() {
+                matcher.matches(&token)
+            } else {
+                None
+            };
+            let (matched, in_phrase) = match self.sensitivity {
+                Some(ref sensitivity)
+                    if (matched.is_some() || in_phrase)
+                        && !sensitivity.accepts(word, token.text()) =>
+                {
+                    (None, false)
+                }
+                _ => (matched, in_phrase),
+            };
+            // if we are in the middle of a character
+            // or if all the word should be highlighted,
+            // we highlight the complete word.
+            let matched = matched.map(|length| (length, word.get(..length).unwrap_or(word)));
+
+            let highlighted = if in_phrase {
+                word.len()
+            } else if format_options.highlight {
+                matched.map_or(0, |(_, head)| head.len())
+            } else {
+                0
+            };
+            // a highlighted part already open is continued, but no other one is started once
+            // the maximum is reached.
+            let highlighted = match self.max_highlights {
+                Some(max) if !highlighting && highlights >= max => 0,
+                _ => highlighted,
+            };
+
+            if highlighted > 0 && !highlighting {
+                out.push_str(pre_tag);
+                highlighting = true;
+                highlights += 1;
+            } else if highlighted == 0 && highlighting {
+                out.push_str(post_tag);
+                highlighting = false;
+            }
+
+            if let (Some(matches), Some((length, head))) = (matches.as_deref_mut(), matched) {
+                matches.push(MatchInfo {
+                    start: self.offset_unit.len(&out),
+                    length: self.offset_unit.len(&self.escape(head)),
+                    matched_term: self
+                        .matched_terms
+                        .then(|| MatchInfo::matched_term(word, &token, length)),
+                });
+            }
+
+            let (highlighted, rest) = word.split_at(highlighted);
+            if let Some(segments) = segments.as_deref_mut() {
+                push_segment(segments, highlighted, true);
+                push_segment(segments, rest, false);
+            }
+            out.push_str(&self.escape(highlighted));
+            if !rest.is_empty() {
+                if highlighting {
+                    out.push_str(post_tag);
+                    highlighting = false;
+                }
+                out.push_str(&self.escape(rest));
+            }
+            out
+        });
+        if highlighting {
+            out.push_str(post_tag);
+        }
+
+        // if there are remaining tokens after formatted interval,
+        // put a crop marker at the end.
+        if crop_marker_after {
+            out.push_str(&self.crop_markers.1);
+            if let Some(segments) = segments {
+                push_segment(segments, &self.crop_markers.1, false);
+            }
+        }
+
+        (out, crop_marker_before || crop_marker_after)
+    }
+}
+
+/// Escapes the characters of `text` that are special in HTML.
+fn escape_html(text: &str) -> Cow<str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Filters the words matched on their normalized form, lowercased and without diacritics, down to
+/// the ones also matching the query with the case or the diacritics of the query words.
+struct HighlightSensitivity {
+    /// The words of the query in their sensitive form, in their normalized form, and whether they
+    /// are a prefix.
+    words: Vec<(String, String, bool)>,
+    case: bool,
+    diacritics: bool,
+}
+
+impl HighlightSensitivity {
+    fn new<A: AsRef<[u8]>>(
+        q: &str,
+        analyzer: &Analyzer<A>,
+        case: bool,
+        diacritics: bool,
+    ) -> Option<Self> {
+        if !case && !diacritics {
+            return None;
+        }
+
+        let analyzed = analyzer.analyze(q);
+        let tokens: Vec<_> = analyzed.reconstruct().collect();
+        let words = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, token))| is_word(token))
+            .map(|(i, (word, token))| {
+                (
+                    sensitive_form(word, token.text(), case, diacritics),
+                    token.text().to_string(),
+                    i + 1 == tokens.len(),
+                )
+            })
+            .collect();
+
+        Some(Self {
+            words,
+            case,
+            diacritics,
+        })
+    }
+
+    /// Returns whether the `word` matched on its `normalized` form still matches a query word
+    /// with as few typos once the case or the diacritics are taken into account.
+    ///
+    /// The words matching none of the query words, like the synonyms, are accepted.
+    fn accepts(&self, word: &str, normalized: &str) -> bool {
+        let form = sensitive_form(word, normalized, self.case, self.diacritics);
+        let distance = |query_word: &str, max_typos: usize, other: &str, is_prefix: bool| {
+            let automaton = Levenshtein::new(query_word, max_typos);
+            if is_prefix {
+                automaton.prefix_distance(other)
+            } else {
+                automaton.distance(other)
+            }
+        };
+
+        let mut matched = false;
+        for (query_form, query_normalized, is_prefix) in &self.words {
+            let max_typos = max_typos(query_normalized);
+            let typos = distance(query_normalized, max_typos, normalized, *is_prefix);
+            if typos > max_typos {
+                continue;
+            }
+
+            matched = true;
+            if distance(query_form, max_typos, &form, *is_prefix) <= typos {
+                return true;
+            }
+        }
+
+        !matched
+    }
+}
+
+/// Returns the form of `word` keeping its case or its diacritics from its `normalized` form.
+///
+/// The case is only restored when each character of the word is normalized into a single one,
+/// the normalized characters can't be aligned with the original ones otherwise.
+fn sensitive_form(word: &str, normalized: &str, case: bool, diacritics: bool) -> String {
+    match (case, diacritics) {
+        (true, true) => word.to_string(),
+        (false, true) => word.to_lowercase(),
+        (true, false) if word.chars().count() == normalized.chars().count() => {
+            let mut form = String::with_capacity(normalized.len());
+            for (original, normalized) in word.chars().zip(normalized.chars()) {
+                if original.is_uppercase() {
+                    form.extend(normalized.to_uppercase());
+                } else {
+                    form.push(normalized);
+                }
+            }
+            form
+        }
+        (_, false) => normalized.to_string(),
+    }
+}
+
+/// Returns the intervals of `tokens` containing one of the `phrases`, its words being separated by
+/// soft separators only.
+fn phrase_spans(tokens: &[(&str, Token)], phrases: &[Vec<String>]) -> Vec<Range<usize>> {
+    let phrase_end = |start: usize, phrase: &[String]| {
+        let mut words = phrase.iter();
+        let mut expected = words.next();
+        for (i, (_, token)) in tokens.iter().enumerate().skip(start) {
+            match (expected, token.is_separator()) {
+                (None, _) => return Some(i),
+                (Some(_), Some(SeparatorKind::Soft)) => (),
+                (Some(word), None) if token.text() == word => expected = words.next(),
+                _ => return None,
+            }
+        }
+        if expected.is_none() {
+            Some(tokens.len())
+        } else {
+            None
+        }
+    };
+
+    // overlapping phrases are merged in a single span.
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    for start in (0..tokens.len()).filter(|&i| is_word(&tokens[i].1)) {
+        let end = phrases
+            .iter()
+            .filter_map(|phrase| phrase_end(start, phrase))
+            .max();
+
+        match (spans.last_mut(), end) {
+            (Some(last), Some(end)) if start < last.end => last.end = max(last.end, end),
+            (_, Some(end)) => spans.push(start..end),
+            (_, None) => (),
+        }
+    }
+    spans
+}
+
+/// The interval of tokens kept by a crop, and whether crop markers must be inserted before and
+/// after it.
+type CropInterval = (Range<usize>, bool, bool);
+
+fn is_word(token: &Token) -> bool {
+    token.is_separator().is_none()
+}
+
+/// Crops around the `count` clusters of matches holding the most matches, each cluster being kept
+/// in a window of `crop_len` like with a single crop. The windows are returned in the order of the
+/// text, along with whether crop markers must be inserted before the first one and after the last
+/// one. Returns `None` if no token matches.
+fn crop_snippets(
+    tokens: &[(&str, Token)],
+    matched: &[bool],
+    crop_len: usize,
+    unit: CropUnit,
+    count: usize,
+) -> Option<(Vec<Range<usize>>, bool, bool)> {
+    // The clusters are delimited by cropping around the first match that is not in a window yet.
+    let mut windows: Vec<(Range<usize>, usize)> = Vec::new();
+    let mut next = 0;
+    while let Some(first_match) = matched[next..].iter().position(|&m| m).map(|i| next + i) {
+        let (window, _, _) = crop_around_match(tokens, first_match, crop_len, unit);
+        let start = max(window.start, next);
+        let end = max(window.end, first_match + 1);
+        let window = match unit {
+            CropUnit::Word => start..end,
+            CropUnit::Char | CropUnit::Token => trim_separators(tokens, (start..end, true, true)).0,
+        };
+        let matches = matched[window.clone()].iter().filter(|&&m| m).count();
+        windows.push((window, matches));
+        next = end;
+    }
+
+    if windows.is_empty() {
+        return None;
+    }
+
+    // `sort_by_key` is stable, between clusters holding as many matches the first ones are kept.
+    windows.sort_by_key(|(_, matches)| Reverse(*matches));
+    windows.truncate(count);
+    let mut windows: Vec<_> = windows.into_iter().map(|(window, _)| window).collect();
+    windows.sort_by_key(|window| window.start);
+
+    // The windows only separated by separators are merged, no crop marker is needed between them.
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(windows.len());
+    for window in windows {
+        match merged.last_mut() {
+            Some(last)
+                if !tokens[last.end..window.start]
+                    .iter()
+                    .any(|(_, t)| is_word(t)) =>
+            {
+                last.end = window.end;
+            }
+            _ => merged.push(window),
+        }
+    }
+
+    let crop_marker_before = tokens[..merged[0].start]
+        .iter()
+        .any(|(_, token)| is_word(token));
+    let crop_marker_after = has_words_after(tokens, merged[merged.len() - 1].end);
+    Some((merged, crop_marker_before, crop_marker_after))
+}
+
+/// Returns whether a crop ending at `end` leaves out some words, in which case a crop marker
+/// must be put at the end. Trailing separators alone do not count.
+fn has_words_after(tokens: &[(&str, Token)], end: usize) -> bool {
+    tokens[end..].iter().any(|(_, token)| is_word(token))
+}
+
+/// Returns the sum of the lengths of `tokens` in `unit`.
+fn crop_len_of(tokens: &[(&str, Token)], unit: CropUnit) -> usize {
+    tokens
+        .iter()
+        .map(|(word, token)| unit.len(word, token))
+        .sum()
+}
+
+/// Returns the end of the interval starting at `start` and of length `len` in `unit`.
+///
+/// The token following the interval is dropped from the output without a crop marker when it is
+/// the last one, this is why callers only insert a marker when at least two tokens remain.
+fn crop_end(tokens: &[(&str, Token)], start: usize, len: usize, unit: CropUnit) -> usize {
+    let mut taken = 0;
+    for (i, (word, token)) in tokens.iter().enumerate().skip(start) {
+        if taken >= len {
+            return i;
+        }
+        taken += unit.len(word, token);
+    }
+    tokens.len()
+}
+
+/// Crops around the match at `match_index`, keeping half of the `crop_len` before it.
+fn crop_around_match(
+    tokens: &[(&str, Token)],
+    match_index: usize,
+    crop_len: usize,
+    unit: CropUnit,
+) -> CropInterval {
+    let len_before = crop_len_of(&tokens[..match_index], unit);
+
+    let crop_len_before = crop_len / 2;
+    // check if start will be cropped.
+    let crop_marker_before = len_before > crop_len_before;
+
+    let mut remaining = len_before;
+    let mut start = match_index;
+    for (i, (word, token)) in tokens[..match_index].iter().enumerate() {
+        remaining -= unit.len(word, token);
+        if remaining < crop_len_before {
+            start = i;
+            break;
+        }
+    }
+
+    // rebalance remaining crop length after the match.
+    let (match_word, match_token) = &tokens[match_index];
+    let taken = crop_len_of(&tokens[start..match_index], unit) + unit.len(match_word, match_token);
+    let crop_len_after = crop_len.saturating_sub(taken);
+
+    let end = crop_end(tokens, match_index + 1, crop_len_after, unit);
+    (start..end, crop_marker_before, has_words_after(tokens, end))
+}
+
+/// Removes the separators at the cropped boundaries of `crop`, so that a crop counted in
+/// characters neither starts nor ends in the middle of a punctuation.
+fn trim_separators(tokens: &[(&str, Token)], crop: CropInterval) -> CropInterval {
+    let (mut interval, crop_marker_before, crop_marker_after) = crop;
+    if crop_marker_before {
+        while interval.start < interval.end && !is_word(&tokens[interval.start].1) {
+            interval.start += 1;
+        }
+    }
+    if crop_marker_after {
+        while interval.start < interval.end && !is_word(&tokens[interval.end - 1].1) {
+            interval.end -= 1;
+        }
+    }
+    (interval, crop_marker_before, crop_marker_after)
+}
+
+fn is_sentence_end(word: &str, token: &Token) -> bool {
+    !is_word(token) && word.contains(&['.', '!', '?'][..])
+}
+
+/// Moves the boundaries of `crop` to the closest sentence boundaries that are at most `tolerance`
+/// away in `unit`, without removing the matches it contains. A boundary is left unchanged when no
+/// sentence boundary is close enough.
+fn snap_to_sentences(
+    tokens: &[(&str, Token)],
+    matched: &[bool],
+    crop: CropInterval,
+    tolerance: usize,
+    unit: CropUnit,
+) -> CropInterval {
+    let (interval, mut crop_marker_before, mut crop_marker_after) = crop;
+    let first_match = interval.clone().find(|&i| matched[i]);
+    let last_match = interval.clone().rev().find(|&i| matched[i]);
+    let words_between = |a: usize, b: usize| crop_len_of(&tokens[min(a, b)..max(a, b)], unit);
+
+    // A sentence starts at the first word of the text or at the first word following a sentence
+    // end, it must not start after the first match.
+    let mut sentence_starts = Vec::new();
+    let mut new_sentence = true;
+    for (i, (word, token)) in tokens.iter().enumerate() {
+        if is_word(token) {
+            if new_sentence && first_match.map_or(true, |first| i <= first) {
+                sentence_starts.push(i);
+            }
+            new_sentence = false;
+        } else if is_sentence_end(word, token) {
+            new_sentence = true;
+        }
+    }
+    let start = sentence_starts
+        .into_iter()
+        .map(|start| (words_between(start, interval.start), start))
+        .filter(|(distance, _)| *distance <= tolerance)
+        .min()
+        .map_or(interval.start, |(_, start)| start);
+
+    // A sentence ends after its terminator, it must not end before the last match.
+    let sentence_ends = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, (word, token))| is_sentence_end(word, token))
+        .map(|(i, _)| i + 1)
+        .filter(|&end| end > start && last_match.map_or(true, |last| end > last));
+    let end = sentence_ends
+        .map(|end| (words_between(interval.end, end), end))
+        .filter(|(distance, _)| *distance <= tolerance)
+        .min()
+        .map_or(interval.end, |(_, end)| end);
+
+    if start != interval.start {
+        crop_marker_before = tokens[..start].iter().any(|(_, token)| is_word(token));
+    }
+    if end != interval.end {
+        crop_marker_after = has_words_after(tokens, end);
+    }
+
+    (start..end, crop_marker_before, crop_marker_after)
+}
+
+/// Crops on the window of `crop_len` containing the most matches, a window starting and ending on
+/// a word. When several windows contain as many matches, the one where the matches are the most
+/// centered is chosen.
+///
+/// Returns `None` if no token matches.
+fn crop_best_window(
+    tokens: &[(&str, Token)],
+    matched: &[bool],
+    crop_len: usize,
+    unit: CropUnit,
+) -> Option<CropInterval> {
+    let words: Vec<_> = (0..tokens.len())
+        .filter(|&i| is_word(&tokens[i].1))
+        .collect();
+    if !matched.iter().any(|&matched| matched) {
+        return None;
+    }
+    // the length of `tokens[..i]` is `prefix_len[i]`.
+    let prefix_len: Vec<_> = std::iter::once(0)
+        .chain(tokens.iter().scan(0, |len, (word, token)| {
+            *len += unit.len(word, token);
+            Some(*len)
+        }))
+        .collect();
+    if prefix_len[tokens.len()] <= crop_len {
+        return Some((0..tokens.len(), false, false));
+    }
+
+    let mut best = None;
+    let mut last = 0;
+    for first in 0..words.len() {
+        // extend the window to the last word fitting in the crop length, a window always contains
+        // at least one word.
+        last = max(last, first);
+        while last + 1 < words.len()
+            && prefix_len[words[last + 1] + 1] - prefix_len[words[first]] <= crop_len
+        {
+            last += 1;
+        }
+        let window = &words[first..=last];
+
+        let matches: Vec<_> = window.iter().filter(|&&i| matched[i]).collect();
+        if let (Some(&&first_match), Some(&&last_match)) = (matches.first(), matches.last()) {
+            // the length around the matches of the window, the lower the better.
+            let len_before = prefix_len[first_match] - prefix_len[window[0]];
+            let len_after = prefix_len[window[window.len() - 1] + 1] - prefix_len[last_match + 1];
+            let imbalance = max(len_before, len_after) - min(len_before, len_after);
+            let score = (matches.len(), Reverse(imbalance));
+            if best
+                .as_ref()
+                .map_or(true, |(best_score, _)| score > *best_score)
+            {
+                best = Some((score, window));
+            }
+        }
+
+        // the following windows are all included in this one.
+        if last + 1 == words.len() {
+            break;
+        }
+    }
+
+    let (_, window) = best?;
+    let start = window[0];
+    let end = window[window.len() - 1] + 1;
+    let crop_marker_before = words[0] < start;
+    let crop_marker_after = words.last().map_or(false, |&last| last >= end);
+    Some((start..end, crop_marker_before, crop_marker_after))
+}
+
+/// A `_geoBoundingBox([topRightLat, topRightLng], [bottomLeftLat, bottomLeftLng])` filter
+/// expression. The box crosses the antimeridian when its top right longitude is lower than its
+/// bottom left longitude.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GeoBoundingBox {
+    top_right: [f64; 2],
+    bottom_left: [f64; 2],
+}
+
+impl GeoBoundingBox {
+    /// Parses `expr` if it is a `_geoBoundingBox` expression. A `_geoBoundingBox` can't be
+    /// combined with other expressions in the same string, the array syntax must be used instead.
+    fn parse(expr: &str) -> Result<Option<Self>> {
+        lazy_static::lazy_static! {
+            static ref GEO_BOUNDING_BOX_REGEX: Regex = Regex::new(
+                r"^\s*_geoBoundingBox\(\s*\[\s*([^,\]]+?)\s*,\s*([^,\]]+?)\s*\]\s*,\s*\[\s*([^,\]]+?)\s*,\s*([^,\]]+?)\s*\]\s*\)\s*$"
+            )
+            .unwrap();
+        };
+
+        if !expr.contains("_geoBoundingBox") {
+            return Ok(None);
+        }
+
+        let invalid = || SearchError::InvalidGeoBoundingBox(expr.to_string());
+        let capture_group = GEO_BOUNDING_BOX_REGEX.captures(expr).ok_or_else(invalid)?;
+        let mut coordinates = [0.0; 4];
+        for (coordinate, capture) in coordinates.iter_mut().zip(capture_group.iter().skip(1)) {
+            *coordinate = capture
+                .and_then(|capture| capture.as_str().parse::<f64>().ok())
+                .ok_or_else(invalid)?;
+        }
+
+        let [top_lat, right_lng, bottom_lat, left_lng] = coordinates;
+        let valid_lat = |lat: f64| (-90.0..=90.0).contains(&lat);
+        let valid_lng = |lng: f64| (-180.0..=180.0).contains(&lng);
+        if !(valid_lat(top_lat)
+            && valid_lat(bottom_lat)
+            && valid_lng(right_lng)
+            && valid_lng(left_lng))
+            || top_lat < bottom_lat
+        {
+            return Err(invalid().into());
+        }
+
+        Ok(Some(Self {
+            top_right: [top_lat, right_lng],
+            bottom_left: [bottom_lat, left_lng],
+        }))
+    }
+
+    fn crosses_antimeridian(&self) -> bool {
+        self.top_right[1] < self.bottom_left[1]
+    }
+
+    fn contains(&self, [lat, lng]: [f64; 2]) -> bool {
+        let in_lat = (self.bottom_left[0]..=self.top_right[0]).contains(&lat);
+        let in_lng = if self.crosses_antimeridian() {
+            lng >= self.bottom_left[1] || lng <= self.top_right[1]
+        } else {
+            (self.bottom_left[1]..=self.top_right[1]).contains(&lng)
+        };
+        in_lat && in_lng
+    }
+
+    /// Returns a `_geoRadius` expression matching all the points of the box, and some points
+    /// around it.
+    fn to_geo_radius(self) -> String {
+        let ([lat, lng], radius) = self.enclosing_circle();
+        format!("_geoRadius({}, {}, {})", lat, lng, radius)
+    }
+
+    /// Returns the center and the radius, in meters, of a circle containing the box.
+    fn enclosing_circle(&self) -> ([f64; 2], f64) {
+        let [top, right] = self.top_right;
+        let [bottom, left] = self.bottom_left;
+
+        let width = if self.crosses_antimeridian() {
+            right + 360.0 - left
+        } else {
+            right - left
+        };
+        let mut center_lng = left + width / 2.0;
+        if center_lng > 180.0 {
+            center_lng -= 360.0;
+        }
+        let center = [(top + bottom) / 2.0, center_lng];
+
+        // The distance to the center is the greatest at the corners of the box.
+        let radius = [[top, right], [top, left], [bottom, right], [bottom, left]]
+            .iter()
+            .map(|corner| milli::distance_between_two_points(&center, corner))
+            .fold(0.0, f64::max);
+
+        // one meter is added to the radius to be safe from rounding errors
+        (center, radius.ceil() + 1.0)
+    }
+}
+
+/// The bounding boxes of a filter that must be checked after the search. A document matches the
+/// group if it is in one of its `boxes` or if it matches one of the `others` expressions of the
+/// OR group the boxes belong to.
+#[derive(Debug, Clone, PartialEq)]
+struct BoundingBoxesGroup {
+    boxes: Vec<GeoBoundingBox>,
+    others: Vec<String>,
+}
+
+/// A bound on the `_geoDistance` of the documents, `_geoDistance < 500` for example.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GeoDistanceBound {
+    operator: GeoDistanceOperator,
+    distance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GeoDistanceOperator {
+    Lower,
+    LowerOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl GeoDistanceBound {
+    /// Parses `expr` if it is a `_geoDistance` expression, which can't be combined with other
+    /// expressions in the same string.
+    fn parse(expr: &str) -> Result<Option<Self>> {
+        lazy_static::lazy_static! {
+            static ref GEO_DISTANCE_REGEX: Regex =
+                Regex::new(r"^\s*_geoDistance\s*(<=|>=|<|>)\s*(\S+)\s*$").unwrap();
+        };
+
+        if !expr.contains("_geoDistance") {
+            return Ok(None);
+        }
+
+        let invalid = || SearchError::InvalidGeoDistance(expr.to_string());
+        let capture_group = GEO_DISTANCE_REGEX.captures(expr).ok_or_else(invalid)?;
+        let operator = match &capture_group[1] {
+            "<" => GeoDistanceOperator::Lower,
+            "<=" => GeoDistanceOperator::LowerOrEqual,
+            ">" => GeoDistanceOperator::Greater,
+            _ => GeoDistanceOperator::GreaterOrEqual,
+        };
+        let distance = capture_group[2]
+            .parse::<f64>()
+            .ok()
+            .filter(|distance| distance.is_finite())
+            .ok_or_else(invalid)?;
+
+        Ok(Some(Self { operator, distance }))
+    }
+
+    /// Returns the distance of the bound if the documents must be closer than it.
+    fn upper_bound(&self) -> Option<f64> {
+        match self.operator {
+            GeoDistanceOperator::Lower | GeoDistanceOperator::LowerOrEqual => Some(self.distance),
+            GeoDistanceOperator::Greater | GeoDistanceOperator::GreaterOrEqual => None,
+        }
+    }
+
+    fn contains(&self, distance: f64) -> bool {
+        match self.operator {
+            GeoDistanceOperator::Lower => distance < self.distance,
+            GeoDistanceOperator::LowerOrEqual => distance <= self.distance,
+            GeoDistanceOperator::Greater => distance > self.distance,
+            GeoDistanceOperator::GreaterOrEqual => distance >= self.distance,
+        }
+    }
+}
+
+/// A search filter in which the `_geoBoundingBox` expressions, that milli doesn't support, are
+/// replaced by the `_geoRadius` of the circle containing each box. The documents matched by milli
+/// must then be filtered with the `bounding_boxes`.
+///
+/// The `_geoDistance` expressions are removed from the filter given to milli, the distance of the
+/// documents being only known after the search. The documents are then filtered with the
+/// `distance_bounds`, which must all hold.
+#[derive(Debug, Clone, PartialEq)]
+struct SearchFilter {
+    milli_filter: Value,
+    bounding_boxes: Vec<BoundingBoxesGroup>,
+    distance_bounds: Vec<GeoDistanceBound>,
+}
+
+impl SearchFilter {
+    fn new(filter: &Value) -> Result<Self> {
+        let mut bounding_boxes = Vec::new();
+        let mut distance_bounds = Vec::new();
+        let milli_filter = match filter {
+            Value::String(expr) => match GeoDistanceBound::parse(expr)? {
+                Some(bound) => {
+                    distance_bounds.push(bound);
+                    Value::Array(Vec::new())
+                }
+                None => match GeoBoundingBox::parse(expr)? {
+                    Some(bounding_box) => {
+                        bounding_boxes.push(BoundingBoxesGroup {
+                            boxes: vec![bounding_box],
+                            others: Vec::new(),
+                        });
+                        Value::String(bounding_box.to_geo_radius())
+                    }
+                    None => filter.clone(),
+                },
+            },
+            Value::Array(ands) => {
+                let mut milli_ands = Vec::with_capacity(ands.len());
+                for value in ands {
+                    let value = &rewrite_negation(value);
+                    match value {
+                        Value::String(expr) => match GeoDistanceBound::parse(expr)? {
+                            Some(bound) => distance_bounds.push(bound),
+                            None => match GeoBoundingBox::parse(expr)? {
+                                Some(bounding_box) => {
+                                    bounding_boxes.push(BoundingBoxesGroup {
+                                        boxes: vec![bounding_box],
+                                        others: Vec::new(),
+                                    });
+                                    milli_ands.push(Value::String(bounding_box.to_geo_radius()));
+                                }
+                                None => milli_ands.push(value.clone()),
+                            },
+                        },
+                        Value::Array(ors) => {
+                            let mut group = BoundingBoxesGroup {
+                                boxes: Vec::new(),
+                                others: Vec::new(),
+                            };
+                            let mut milli_ors = Vec::with_capacity(ors.len());
+                            for value in ors {
+                                let value = &rewrite_negation(value);
+                                match value {
+                                    Value::String(expr) if expr.contains("_geoDistance") => {
+                                        return Err(
+                                            SearchError::InvalidGeoDistance(expr.clone()).into()
+                                        );
+                                    }
+                                    Value::String(expr) => match GeoBoundingBox::parse(expr)? {
+                                        Some(bounding_box) => {
+                                            group.boxes.push(bounding_box);
+                                            milli_ors
+                                                .push(Value::String(bounding_box.to_geo_radius()));
+                                        }
+                                        None => {
+                                            group.others.push(expr.clone());
+                                            milli_ors.push(value.clone());
+                                        }
+                                    },
+                                    value => milli_ors.push(value.clone()),
+                                }
+                            }
+
+                            if !group.boxes.is_empty() {
+                                bounding_boxes.push(group);
+                            }
+                            milli_ands.push(Value::Array(milli_ors));
+                        }
+                        value => milli_ands.push(value.clone()),
+                    }
+                }
+                Value::Array(milli_ands)
+            }
+            value => value.clone(),
+        };
+
+        Ok(Self {
+            milli_filter,
+            bounding_boxes,
+            distance_bounds,
+        })
+    }
+}
+
+/// Rewrites the negations of the array form of the filters, `{"not": "expression"}`, in the string
+/// syntax understood by milli, other values are returned unchanged.
+fn rewrite_negation(value: &Value) -> Value {
+    match value {
+        Value::Object(object) if object.len() == 1 => match object.get("not") {
+            Some(Value::String(expr)) => Value::String(format!("NOT ({})", expr)),
+            _ => value.clone(),
+        },
+        value => value.clone(),
+    }
+}
+
+/// Returns the `[lat, lng]` coordinates of a `_geo` field. Like milli, the coordinates can be
+/// given as numbers or strings.
+fn extract_geo_point(geo: &Value) -> Option<[f64; 2]> {
+    let coordinate = |value: &Value| match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    };
+    Some([coordinate(geo.get("lat")?)?, coordinate(geo.get("lng")?)?])
+}
+
+fn explain_filter(filter: &Value) -> Result<String> {
+    let search_filter = SearchFilter::new(filter)?;
+    if parse_filter(&search_filter.milli_filter)?.is_none() {
+        return Ok(String::new());
+    }
+
+    // The expressions of the array form are combined, so they are parenthesized when they are
+    // themselves combinations.
+    let operand = |expr: &str| {
+        let expr = expr.trim();
+        let lowercase = expr.to_lowercase();
+        if lowercase.contains(" or ") || lowercase.contains(" and ") {
+            format!("({})", expr)
+        } else {
+            expr.to_string()
+        }
+    };
+
+    let explanation = match filter {
+        Value::Array(ands) => {
+            let mut clauses = Vec::with_capacity(ands.len());
+            for value in ands {
+                match rewrite_negation(value) {
+                    Value::String(expr) if !expr.trim().is_empty() => clauses.push(operand(&expr)),
+                    Value::Array(ors) => {
+                        let ors: Vec<_> = ors
+                            .iter()
+                            .filter_map(|value| match rewrite_negation(value) {
+                                Value::String(expr) if !expr.trim().is_empty() => {
+                                    Some(operand(&expr))
+                                }
+                                _ => None,
+                            })
+                            .collect();
+                        match ors.len() {
+                            0 => (),
+                            1 => clauses.extend(ors),
+                            _ => clauses.push(format!("({})", ors.join(" OR "))),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            clauses.join(" AND ")
+        }
+        Value::String(expr) => expr.trim().to_string(),
+        _ => String::new(),
+    };
+
+    Ok(explanation)
+}
+
+fn parse_filter(facets: &Value) -> Result<Option<Filter>> {
+    match facets {
+        Value::String(expr) => {
+            let condition = Filter::from_str(expr)?;
+            Ok(condition)
+        }
+        Value::Array(arr) => parse_filter_array(arr),
+        v => Err(FacetError::InvalidExpression(&["Array"], v.clone()).into()),
+    }
+}
+
+fn parse_filter_array(arr: &[Value]) -> Result<Option<Filter>> {
+    let mut ands = Vec::new();
+    for value in arr {
+        match value {
+            Value::String(s) => ands.push(Either::Right(s.as_str())),
+            Value::Array(arr) => {
+                let mut ors = Vec::new();
+                for value in arr {
+                    match value {
+                        Value::String(s) => ors.push(s.as_str()),
+                        v => {
+                            return Err(FacetError::InvalidExpression(
+                                &["String", "{\"not\": String}"],
+                                v.clone(),
+                            )
+                            .into())
+                        }
+                    }
+                }
+                ands.push(Either::Left(ors));
+            }
+            v => {
+                return Err(FacetError::InvalidExpression(
+                    &["String", "[String]", "{\"not\": String}"],
+                    v.clone(),
+                )
+                .into())
+            }
+        }
+    }
+
+    Ok(Filter::from_array(ands)?)
+}
+
+/// Returns `filter` without its clauses referencing `facet`, or `None` if none of them does.
+fn filter_without_facet(filter: &Value, facet: &str) -> Option<Value> {
+    let clauses: Vec<Value> = match filter {
+        Value::String(expr) => split_top_level_and(expr)
+            .into_iter()
+            .map(|clause| Value::String(clause.to_string()))
+            .collect(),
+        Value::Array(clauses) => clauses.clone(),
+        _ => return None,
+    };
+
+    let (removed, kept): (Vec<_>, Vec<_>) = clauses.into_iter().partition(|clause| {
+        let mut fields = BTreeSet::new();
+        collect_filter_fields(clause, &mut fields);
+        fields
+            .iter()
+            .any(|field| milli::is_faceted_by(field, facet))
+    });
+
+    if removed.is_empty() {
+        None
+    } else {
+        Some(Value::Array(kept))
+    }
+}
+
+/// Splits the string filter `expr` on its top-level `AND`s. As `AND` has precedence over `OR`, the
+/// expression is not split if it contains a top-level `OR`.
+fn split_top_level_and(expr: &str) -> Vec<&str> {
+    let bytes = expr.as_bytes();
+    let is_boundary = |i: usize| {
+        bytes
+            .get(i)
+            .map_or(true, |&c| c.is_ascii_whitespace() || c == b'(' || c == b')')
+    };
+
+    let mut ands = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (quote, bytes[i]) {
+            (Some(_), b'\\') => i += 1,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, c @ (b'\'' | b'"')) => quote = Some(c),
+            (None, b'(') => depth += 1,
+            (None, b')') => depth = depth.saturating_sub(1),
+            (None, _) if depth == 0 && (i == 0 || is_boundary(i - 1)) => {
+                if bytes[i..].starts_with(b"AND") && is_boundary(i + 3) {
+                    ands.push(i);
+                    i += 3;
+                    continue;
+                }
+                if bytes[i..].starts_with(b"OR") && is_boundary(i + 2) {
+                    return vec![expr.trim()];
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    let mut clauses = Vec::with_capacity(ands.len() + 1);
+    let mut start = 0;
+    for and in ands {
+        clauses.push(expr[start..and].trim());
+        start = and + 3;
+    }
+    clauses.push(expr[start..].trim());
+    clauses
+}
+
+/// Inserts the names of the attributes referenced by `filter` in `fields`, the geo expressions
+/// referencing `_geo`. The filter is expected to be valid, invalid values are ignored.
+fn collect_filter_fields(filter: &Value, fields: &mut BTreeSet<String>) {
+    match filter {
+        Value::String(expr) => collect_expression_fields(expr, fields),
+        Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_filter_fields(value, fields)),
+        Value::Object(object) => {
+            if let Some(negated) = object.get("not") {
+                collect_filter_fields(negated, fields);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Inserts the names of the attributes referenced by the string filter `expr` in `fields`.
+///
+/// milli doesn't expose the parsed conditions of a filter, the attributes are thus the first
+/// operand of each condition, conditions starting the expression or following a parenthesis or
+/// one of the `AND`, `OR` and `NOT` operators.
+fn collect_expression_fields(expr: &str, fields: &mut BTreeSet<String>) {
+    let mut chars = expr.chars().peekable();
+    let mut expects_field = true;
+    // The depth of the parentheses of the geo expression being skipped.
+    let mut geo_depth = 0;
+
+    while let Some(&c) = chars.peek() {
+        let (token, quoted) = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' | ')' => {
+                chars.next();
+                match (c, geo_depth) {
+                    ('(', 0) => expects_field = true,
+                    ('(', _) => geo_depth += 1,
+                    (_, 0) => expects_field = false,
+                    (_, _) => geo_depth -= 1,
+                }
+                continue;
+            }
+            '\'' | '"' => {
+                chars.next();
+                let mut token = String::new();
+                while let Some(next) = chars.next() {
+                    match next {
+                        '\\' => token.extend(chars.next()),
+                        next if next == c => break,
+                        next => token.push(next),
+                    }
+                }
+                (token, true)
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                continue;
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "()'\"=!<>".contains(next) {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+                (token, false)
+            }
+        };
+
+        if geo_depth > 0 {
+            continue;
+        }
+        match token.as_str() {
+            "AND" | "OR" | "NOT" if !quoted => expects_field = true,
+            "_geoRadius" | "_geoBoundingBox" if !quoted && expects_field => {
+                fields.insert(String::from("_geo"));
+                // the parenthesis of the geo expression is skipped with its content.
+                while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if chars.next_if_eq(&'(').is_some() {
+                    geo_depth = 1;
+                }
+                expects_field = false;
+            }
+            _ if expects_field => {
+                fields.insert(token);
+                expects_field = false;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use std::io::{Seek, SeekFrom};
+
+    use milli::documents::DocumentBatchReader;
+    use milli::update::{IndexDocumentsConfig, IndexerConfig, Setting};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::document_formats::read_json;
+    use crate::index::{Checked, Settings};
+
+    /// Creates a real index in a temporary directory, applies `settings` and indexes `documents`.
+    /// The returned `TempDir` must be kept alive as long as the index is used.
+    pub(in crate::index) fn index_with_documents(
+        documents: Value,
+        settings: Settings<Checked>,
+    ) -> (tempfile::TempDir, Index) {
+        let dir = tempfile::tempdir().unwrap();
+        let index = Index::open(
+            dir.path(),
+            100 * 1024 * 1024,
+            Uuid::new_v4(),
+            Arc::new(IndexerConfig::default()),
+        )
+        .unwrap();
+        index.update_settings(&settings).unwrap();
+
+        let mut documents_file = tempfile::tempfile().unwrap();
+        read_json(documents.to_string().as_bytes(), &mut documents_file).unwrap();
+        documents_file.seek(SeekFrom::Start(0)).unwrap();
+        let reader = DocumentBatchReader::from_reader(documents_file).unwrap();
+
+        let mut txn = index.write_txn().unwrap();
+        let mut builder = milli::update::IndexDocuments::new(
+            &mut txn,
+            &index,
+            index.indexer_config.as_ref(),
+            IndexDocumentsConfig::default(),
+            |_| (),
+        )
+        .unwrap();
+        builder.add_documents(reader).unwrap();
+        builder.execute().unwrap();
+        txn.commit().unwrap();
+
+        (dir, index)
+    }
+
+    #[test]
+    fn no_ids_no_formatted() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        fields.insert("test").unwrap();
+
+        let document: serde_json::Value = json!({
+            "test": "hello",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let formatted_options = BTreeMap::new();
+
+        let matching_words = MatchingWords::default();
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert!(value.is_empty());
+    }
+
+    #[test]
+    fn formatted_fields_report_truncation() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let overview = fields.insert("overview").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "overview": "A hobbit goes on a long journey to the Lonely Mountain with thirteen dwarves",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let crop = FormatOptions {
+            highlight: false,
+            crop: Some(5),
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(title, crop);
+        formatted_options.insert(overview, crop);
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+
+        let (value, truncated) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "The Hobbit");
+        assert_eq!(value["overview"], "A hobbit goes on a…");
+        assert!(!truncated["title"]);
+        assert!(truncated["overview"]);
+    }
+
+    #[test]
+    fn formatted_with_highlight_in_word() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "author": "J. R. R. Tolkien",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: true,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(3));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "The <em>Hob</em>bit");
+        assert_eq!(value["author"], "J. R. R. Tolkien");
+    }
+
+    #[test]
+    fn format_fields_leaves_the_document_unchanged() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        fields.insert("id").unwrap();
+        let title = fields.insert("title").unwrap();
+        fields.insert("tags").unwrap();
+
+        let document: serde_json::Value = json!({
+            "id": 1,
+            "title": "The Hobbit",
+            "tags": ["hobbit", { "name": "dragon" }],
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document: Document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let original = document.clone();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: true,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "The <em>Hobbit</em>");
+        assert_eq!(value.len(), 1);
+        assert_eq!(document, original);
+        assert!(document.keys().eq(["id", "title", "tags"].iter()));
+    }
+
+    #[test]
+    fn formatted_with_highlight_tags_per_attribute() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let body = fields.insert("body").unwrap();
+        let author = fields.insert("author").unwrap();
+        let displayed_ids: BTreeSet<_> = vec![title, body, author].into_iter().collect();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "body": "A hobbit leaves the shire",
+            "author": "Hobbit fan",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let attr_to_highlight: Vec<AttributeToHighlight> = serde_json::from_value(json!([
+            { "attribute": "title", "highlightPreTag": "<strong>", "highlightPostTag": "</strong>" },
+            "*",
+            { "attribute": "body", "highlightPreTag": "<mark>", "highlightPostTag": "</mark>" },
+        ]))
+        .unwrap();
+
+        let formatted_options = compute_formatted_options(
+            &attr_to_highlight,
+            &[],
+            10,
+            false,
+            &displayed_ids.iter().copied().collect(),
+            &BTreeSet::new(),
+            &fields,
+            &displayed_ids,
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "The <strong>Hobbit</strong>");
+        assert_eq!(value["body"], "A <mark>hobbit</mark> leaves the shire");
+        assert_eq!(value["author"], "<em>Hobbit</em> fan");
+    }
+
+    #[test]
+    fn formatted_with_pointer_attributes() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+        let author_name = fields.insert("author.name").unwrap();
+        let author_bio = fields.insert("author.bio").unwrap();
+        let displayed_ids: BTreeSet<_> = vec![title, author, author_name, author_bio]
+            .into_iter()
+            .collect();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "author": { "name": "Hobbit fan", "bio": "A hobbit lover" },
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document: Document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let attr_to_highlight = vec![AttributeToHighlight::Name(String::from("/author/name"))];
+        let formatted_options = compute_formatted_options(
+            &attr_to_highlight,
+            &[],
+            10,
+            false,
+            &displayed_ids.iter().copied().collect(),
+            &BTreeSet::new(),
+            &fields,
+            &displayed_ids,
+        );
+        assert!(formatted_options[&author_name].highlight);
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "The Hobbit");
+        assert_eq!(
+            value["author"],
+            json!({ "name": "<em>Hobbit</em> fan", "bio": "A hobbit lover" })
+        );
+    }
+
+    #[test]
+    fn formatted_options_with_nested_wildcards() {
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+        let author_name = fields.insert("author.name").unwrap();
+        let author_bio = fields.insert("author.bio").unwrap();
+        let authorship = fields.insert("authorship").unwrap();
+        let displayed_ids: BTreeSet<_> = vec![title, author, author_name, author_bio, authorship]
+            .into_iter()
+            .collect();
+
+        let attr_to_highlight = vec![AttributeToHighlight::Name(String::from("/author/*"))];
+        let attr_to_crop = vec![AttributeToCrop::Name(String::from("author.*:3"))];
+        let formatted_options = compute_formatted_options(
+            &attr_to_highlight,
+            &attr_to_crop,
+            10,
+            false,
+            &displayed_ids.iter().copied().collect(),
+            &BTreeSet::new(),
+            &fields,
+            &displayed_ids,
+        );
+
+        for id in [author, author_name, author_bio] {
+            assert!(formatted_options[&id].highlight);
+            assert_eq!(formatted_options[&id].crop, Some(3));
+        }
+        // the siblings of the attribute are left untouched.
+        for id in [title, authorship] {
+            assert!(!formatted_options[&id].highlight);
+            assert_eq!(formatted_options[&id].crop, None);
+        }
+
+        let query = |value: Value| -> SearchQuery { serde_json::from_value(value).unwrap() };
+        let valid = query(json!({
+            "attributesToHighlight": ["author.*"],
+            "attributesToCrop": ["/author/*"],
+        }));
+        assert!(check_attributes(&valid, &fields, &displayed_ids).is_ok());
+        let unknown = query(json!({ "attributesToCrop": ["editor.*"] }));
+        assert!(check_attributes(&unknown, &fields, &displayed_ids).is_err());
+    }
+
+    #[test]
+    fn test_field_name_from_pointer() {
+        assert_eq!(field_name_from_pointer("/author/name"), "author.name");
+        assert_eq!(field_name_from_pointer("/title"), "title");
+        assert_eq!(field_name_from_pointer("/a~1b/c~0d"), "a/b.c~d");
+        assert_eq!(field_name_from_pointer("author.name"), "author.name");
+        assert_eq!(field_name_from_pointer("*"), "*");
+    }
+
+    #[test]
+    fn raw_attributes_are_never_formatted() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let blob = fields.insert("blob").unwrap();
+        let blob_name = fields.insert("blob.name").unwrap();
+        let displayed_ids: BTreeSet<_> = vec![title, blob, blob_name].into_iter().collect();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "blob": { "name": "hobbit", "size": 1937 },
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document: Document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let attr_to_highlight = vec![AttributeToHighlight::Name(String::from("*"))];
+        let raw_ids: BTreeSet<_> = std::iter::once(blob).collect();
+        let formatted_options = compute_formatted_options(
+            &attr_to_highlight,
+            &[],
+            10,
+            false,
+            &displayed_ids.iter().copied().collect(),
+            &raw_ids,
+            &fields,
+            &displayed_ids,
+        );
+        assert_eq!(formatted_options.keys().collect::<Vec<_>>(), vec![&title]);
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+
+        let (mut value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+        insert_raw_values(&document, &raw_ids, &fields, &mut value);
+
+        assert_eq!(value["title"], "The <em>Hobbit</em>");
+        assert_eq!(value["blob"], json!({ "name": "hobbit", "size": 1937 }));
+    }
+
+    #[test]
+    fn formatted_matches_info_on_cropped_value() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let overview = fields.insert("overview").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "overview": "The story follows Bilbo Baggins who is reluctantly sent on a long journey \
+                to the Lonely Mountain, where he ends up stealing from a dragon as a hobbit",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            overview,
+            FormatOptions {
+                highlight: true,
+                crop: Some(4),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+
+        let mut matches_info = MatchesInfo::new();
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            Some(&mut matches_info),
+        )
+        .unwrap();
+
+        let overview = value["overview"].as_str().unwrap();
+        assert!(overview.starts_with('…'), "{}", overview);
+        assert_eq!(matches_info["overview"].len(), 1);
+        let MatchInfo { start, length, .. } = matches_info["overview"][0];
+        assert_eq!(&overview[start..start + length], "hobbit");
+        assert_eq!(&overview[..start], "…as a <em>");
+
+        let title = value["title"].as_str().unwrap();
+        let MatchInfo { start, length, .. } = matches_info["title"][0];
+        assert_eq!(&title[start..start + length], "Hobbit");
+    }
+
+    #[test]
+    fn formatted_with_highlight_in_number() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+        let publication_year = fields.insert("publication_year").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "author": "J. R. R. Tolkien",
+            "publication_year": 1937,
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            publication_year,
+            FormatOptions {
+                highlight: true,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("1937", Some(4));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "The Hobbit");
+        assert_eq!(value["author"], "J. R. R. Tolkien");
+        assert_eq!(value["publication_year"], "<em>1937</em>");
+    }
+
+    #[test]
+    fn formatted_without_highlight_in_number() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        )
+        .with_highlight_numbers(false);
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+        let publication_year = fields.insert("publication_year").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "The Hobbit",
+            "author": "J. R. R. Tolkien",
+            "publication_year": 1937,
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            publication_year,
+            FormatOptions {
+                highlight: true,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("1937", Some(4));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "The Hobbit");
+        assert_eq!(value["author"], "J. R. R. Tolkien");
+        assert_eq!(value["publication_year"], 1937);
+    }
+
+    /// https://github.com/meilisearch/meilisearch/issues/1368
+    #[test]
+    fn formatted_with_highlight_emoji() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Go💼od luck.",
+            "author": "JacobLey",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: true,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        // emojis are deunicoded during tokenization
+        // TODO Tokenizer should remove spaces after deunicode
+        matching_words.insert("gobriefcase od", Some(11));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "<em>Go💼od</em> luck.");
+        assert_eq!(value["author"], "JacobLey");
+    }
+
+    #[test]
+    fn formatted_with_highlight_in_unicode_word() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "étoile",
+            "author": "J. R. R. Tolkien",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: true,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("etoile", Some(1));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "<em>étoile</em>");
+        assert_eq!(value["author"], "J. R. R. Tolkien");
+    }
+
+    #[test]
+    fn formatted_with_crop_2() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Harry Potter and the Half-Blood Prince",
+            "author": "J. K. Rowling",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: Some(2),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("potter", Some(3));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "Harry Potter…");
+        assert_eq!(value["author"], "J. K. Rowling");
+    }
+
+    #[test]
+    fn formatted_with_one_sided_crop_markers() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Harry Potter and the Half-Blood Prince",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let document: IndexMap<String, Value> = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: Some(2),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("half", Some(4));
+
+        let format = |markers: (&str, &str)| {
+            let formatter = Formatter::new(
+                &analyzer,
+                (String::from("<em>"), String::from("</em>")),
+                (markers.0.to_string(), markers.1.to_string()),
+            );
+            let (value, _) = format_fields(
+                &mut document.clone(),
+                &fields,
+                &formatter,
+                &matching_words,
+                &formatted_options,
+                None,
+            )
+            .unwrap();
+            value["title"].clone()
+        };
+
+        assert_eq!(format(("‹…", "…›")), "‹…the Half…›");
+        assert_eq!(format(("", "…")), "the Half…");
+        assert_eq!(format(("…", "")), "…the Half");
+    }
+
+    #[test]
+    fn formatted_with_crop_and_match_on_last_word() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let author = fields.insert("author").unwrap();
+        let signature = fields.insert("signature").unwrap();
+
+        let document: serde_json::Value = json!({
+            "author": "J. K. Rowling",
+            "signature": "Written by J. K. Rowling... ",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        for field in [author, signature] {
+            formatted_options.insert(
+                field,
+                FormatOptions {
+                    highlight: true,
+                    crop: Some(2),
+                    highlight_tags: None,
+                    crop_on_sentences: false,
+                },
+            );
+        }
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("rowling", Some(7));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["author"], "…K. <em>Rowling</em>");
+        assert_eq!(value["signature"], "…K. <em>Rowling</em>");
+    }
+
+    #[test]
+    fn formatted_with_crop_best_window() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let text = "a hobbit walks along the river for many long days until night when the old \
+            dwarf and the hobbit meet";
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+        matching_words.insert("dwarf", Some(5));
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: Some(5),
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let (value, cropped) =
+            formatter.format_string(text.to_string(), &matching_words, format_options, None);
+        assert_eq!(value, "a <em>hobbit</em> walks along the…");
+        assert!(cropped);
+
+        // the second cluster contains more matches than the first one
+        let formatter = formatter.with_crop_strategy(CropStrategy::BestWindow);
+        let (value, cropped) =
+            formatter.format_string(text.to_string(), &matching_words, format_options, None);
+        assert_eq!(value, "…old <em>dwarf</em> and the <em>hobbit</em>…");
+        assert!(cropped);
+
+        // the matches are centered in the window
+        let (value, _) = formatter.format_string(
+            text.to_string(),
+            &matching_words,
+            FormatOptions {
+                crop: Some(8),
+                ..format_options
+            },
+            None,
+        );
+        assert_eq!(
+            value,
+            "…when the old <em>dwarf</em> and the <em>hobbit</em> meet"
+        );
+
+        // a text shorter than the crop length is not cropped
+        let (value, cropped) = formatter.format_string(
+            "the hobbit and the dwarf".to_string(),
+            &matching_words,
+            format_options,
+            None,
+        );
+        assert_eq!(value, "the <em>hobbit</em> and the <em>dwarf</em>");
+        assert!(!cropped);
+
+        // without any match the beginning of the text is kept
+        let (value, cropped) = formatter.format_string(
+            "many long days until night".to_string(),
+            &matching_words,
+            FormatOptions {
+                crop: Some(2),
+                ..format_options
+            },
+            None,
+        );
+        assert_eq!(value, "many long…");
+        assert!(cropped);
+    }
+
+    #[test]
+    fn formatted_with_crop_on_sentences() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let text = "Harry Potter is a young wizard. He lives with his aunt and uncle. One day he \
+            receives a letter from Hogwarts!";
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("wizard", Some(6));
+        matching_words.insert("uncle", Some(5));
+        let format_options = FormatOptions {
+            highlight: false,
+            crop: Some(10),
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+        let on_sentences = FormatOptions {
+            crop_on_sentences: true,
+            ..format_options
+        };
+
+        let crop = |text: &str, format_options| {
+            let (value, _) =
+                formatter.format_string(text.to_string(), &matching_words, format_options, None);
+            value
+        };
+
+        assert_eq!(
+            crop(text, format_options),
+            "Harry Potter is a young wizard. He lives with his…"
+        );
+        // the crop ends at the end of the second sentence rather than in its middle
+        assert_eq!(
+            crop(text, on_sentences),
+            "Harry Potter is a young wizard. He lives with his aunt and uncle. …"
+        );
+
+        // the crop starts at the beginning of the sentence containing the match
+        let text =
+            "Harry Potter is a young boy. He lives with his aunt and uncle in a small house.";
+        let format_options = FormatOptions {
+            crop: Some(6),
+            ..format_options
+        };
+        assert_eq!(crop(text, format_options), "…his aunt and uncle in a…");
+        assert_eq!(
+            crop(
+                text,
+                FormatOptions {
+                    crop_on_sentences: true,
+                    ..format_options
+                }
+            ),
+            "…He lives with his aunt and uncle in a small house."
+        );
+
+        // without a sentence boundary close enough the words are counted
+        let text = "one two three four five six seven eight nine ten eleven twelve uncle \
+            thirteen fourteen fifteen sixteen seventeen eighteen. nineteen";
+        assert_eq!(
+            crop(text, format_options),
+            crop(
+                text,
+                FormatOptions {
+                    crop_on_sentences: true,
+                    ..format_options
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn check_attributes_names_the_unknown_attribute() {
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let _hidden = fields.insert("hidden").unwrap();
+        let displayed_ids: BTreeSet<_> = std::iter::once(title).collect();
+
+        let query = |value: Value| -> SearchQuery { serde_json::from_value(value).unwrap() };
+
+        let valid = query(json!({
+            "attributesToRetrieve": ["*", "title"],
+            "attributesToHighlight": [{ "attribute": "title" }],
+            "attributesToCrop": ["title:5"],
+        }));
+        assert!(check_attributes(&valid, &fields, &displayed_ids).is_ok());
+
+        let check = |value| match check_attributes(&query(value), &fields, &displayed_ids) {
+            Err(IndexError::Search(SearchError::UnknownAttribute(parameter, name))) => {
+                (parameter, name)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(
+            check(json!({ "attributesToRetrieve": ["title", "tilte"] })),
+            ("attributesToRetrieve", "tilte".to_string())
+        );
+        assert_eq!(
+            check(json!({ "attributesToRetrieve": ["*", "-secret"] })),
+            ("attributesToRetrieve", "secret".to_string())
+        );
+        assert_eq!(
+            check(json!({ "attributesToHighlight": ["hidden"] })),
+            ("attributesToHighlight", "hidden".to_string())
+        );
+        assert_eq!(
+            check(json!({ "attributesToCrop": ["overview:10"] })),
+            ("attributesToCrop", "overview".to_string())
+        );
+    }
+
+    #[test]
+    fn formatted_without_stop_words() {
+        let stop_words = fst::Set::from_iter(["of", "the"]).unwrap();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("the", Some(3));
+        matching_words.insert("lord", Some(4));
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let (value, _) = formatter.format_string(
+            "The Lord of the Rings".to_string(),
+            &matching_words,
+            format_options,
+            None,
+        );
+        assert_eq!(value, "The <em>Lord</em> of the Rings");
+    }
+
+    #[test]
+    fn formatted_with_crop_snippets() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = |snippets| {
+            Formatter::new(
+                &analyzer,
+                (String::from("<em>"), String::from("</em>")),
+                (String::from("…"), String::from("…")),
+            )
+            .with_snippets(snippets)
+        };
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+        matching_words.insert("dragon", Some(6));
+        matching_words.insert("ring", Some(4));
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: Some(4),
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let text = "In a hole in the ground there lived a hobbit named Bilbo. Many years later \
+            he went to the mountain where the dragon Smaug was sleeping on the gold. On his way \
+            back through the dark caves he found a ring which was precious and dangerous.";
+        let crop = |snippets| {
+            formatter(snippets).format_string(
+                text.to_string(),
+                &matching_words,
+                format_options,
+                None,
+            )
+        };
+
+        assert_eq!(
+            crop(3),
+            (
+                "…lived a <em>hobbit</em> named…where the <em>dragon</em> Smaug…found a <em>ring</em> which…"
+                    .to_string(),
+                true
+            )
+        );
+        // between clusters holding as many matches, the first ones are kept.
+        assert_eq!(
+            crop(2).0,
+            "…lived a <em>hobbit</em> named…where the <em>dragon</em> Smaug…"
+        );
+        // a single window is cropped by default.
+        assert_eq!(crop(1), crop(0));
+        assert_eq!(crop(1).0, "…lived a <em>hobbit</em> named…");
+    }
+
+    #[test]
+    fn formatted_segments_round_trip() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+        let segments_formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        )
+        .with_segments(true);
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("half", Some(4));
+        matching_words.insert("blood", Some(5));
+        matching_words.insert("prince", Some(6));
+        matching_words.insert("potter", Some(3));
+        let matcher = QueryMatcher::new(&matching_words, "\"half blood\" prince pot", &analyzer);
+
+        let text = "Harry Potter and the Half-Blood Prince. Half of the blood.";
+        for crop in [None, Some(4)] {
+            let format_options = FormatOptions {
+                highlight: true,
+                crop,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            };
+
+            let (highlighted, cropped) =
+                formatter.format_string(text.to_string(), &matcher, format_options, None);
+            let (segments, segments_cropped) =
+                segments_formatter.format_value(json!(text), &matcher, format_options, None);
+            assert_eq!(cropped, segments_cropped);
+
+            let segments = segments.as_array().unwrap();
+            let round_trip: String = segments
+                .iter()
+                .map(|segment| {
+                    let text = segment["text"].as_str().unwrap();
+                    if segment["highlighted"] == json!(true) {
+                        format!("<em>{}</em>", text)
+                    } else {
+                        text.to_string()
+                    }
+                })
+                .collect();
+            assert_eq!(round_trip, highlighted);
+        }
+
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+        let (segments, _) = segments_formatter.format_value(
+            json!("the Half-Blood Prince"),
+            &matcher,
+            format_options,
+            None,
+        );
+        assert_eq!(
+            segments,
+            json!([
+                { "text": "the ", "highlighted": false },
+                { "text": "Half-Blood", "highlighted": true },
+                { "text": " ", "highlighted": false },
+                { "text": "Prince", "highlighted": true },
+            ])
+        );
+    }
+
+    #[test]
+    fn formatted_with_highlighted_phrase() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("half", Some(4));
+        matching_words.insert("blood", Some(5));
+        matching_words.insert("prince", Some(6));
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let text = "Harry Potter and the Half-Blood Prince. Half of the blood.";
+        let highlight = |query: &str| {
+            let matcher = QueryMatcher::new(&matching_words, query, &analyzer);
+            let (value, _) =
+                formatter.format_string(text.to_string(), &matcher, format_options, None);
+            value
+        };
+
+        assert_eq!(
+            highlight("half blood prince"),
+            "Harry Potter and the <em>Half</em>-<em>Blood</em> <em>Prince</em>. <em>Half</em> of the <em>blood</em>."
+        );
+        // only the consecutive words of the phrase are highlighted together
+        assert_eq!(
+            highlight("\"half blood\" prince"),
+            "Harry Potter and the <em>Half-Blood</em> <em>Prince</em>. <em>Half</em> of the <em>blood</em>."
+        );
+        // a phrase doesn't span over two sentences
+        assert_eq!(
+            highlight("\"half blood prince half\""),
+            "Harry Potter and the <em>Half</em>-<em>Blood</em> <em>Prince</em>. <em>Half</em> of the <em>blood</em>."
+        );
+
+        // the matches are still computed word by word
+        let matcher = QueryMatcher::new(&matching_words, "\"half blood\"", &analyzer);
+        let mut matches = Vec::new();
+        let (value, _) = formatter.format_string(
+            "Half-Blood".to_string(),
+            &matcher,
+            format_options,
+            Some(&mut matches),
+        );
+        assert_eq!(value, "<em>Half-Blood</em>");
+        assert_eq!(matches.len(), 2);
+
+        // the phrase is closed at the end of the crop
+        let (value, _) = formatter.format_string(
+            text.to_string(),
+            &matcher,
+            FormatOptions {
+                crop: Some(2),
+                ..format_options
+            },
+            None,
+        );
+        assert_eq!(value, "…the <em>Half</em>…");
+    }
+
+    #[test]
+    fn formatted_with_overlapping_matches() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("half", Some(4));
+        matching_words.insert("blood", Some(5));
+        matching_words.insert("prince", Some(6));
+        // a match longer than the word highlights the complete word.
+        matching_words.insert("potter", Some(10));
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let text = "Harry Potter and the Half-Blood Prince";
+        let highlight = |query: &str| {
+            let matcher = QueryMatcher::new(&matching_words, query, &analyzer);
+            let (value, _) =
+                formatter.format_string(text.to_string(), &matcher, format_options, None);
+            value
+        };
+
+        // the overlapping phrases are highlighted together
+        let value = highlight("\"half blood\" \"blood prince\" potter");
+        assert_eq!(
+            value,
+            "Harry <em>Potter</em> and the <em>Half-Blood Prince</em>"
+        );
+        assert!(!value.contains("<em><em>"));
+        assert!(!value.contains("</em><em>"));
+
+        let value = highlight("\"half blood prince\" \"blood prince\"");
+        assert_eq!(
+            value,
+            "Harry <em>Potter</em> and the <em>Half-Blood Prince</em>"
+        );
+    }
+
+    #[test]
+    fn formatted_with_synonyms() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("car", Some(3));
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let mut synonyms = HashMap::new();
+        synonyms.insert(
+            vec!["car".to_string()],
+            vec![
+                vec!["automobile".to_string()],
+                vec!["motor".to_string(), "vehicle".to_string()],
+            ],
+        );
+        synonyms.insert(vec!["bike".to_string()], vec![vec!["bicycle".to_string()]]);
+
+        let text = "A car, an Automobile or a motor vehicle, but not a motor";
+        let matcher = QueryMatcher::new(&matching_words, "car", &analyzer)
+            .with_synonyms("car", &analyzer, &synonyms);
+        let (value, _) = formatter.format_string(text.to_string(), &matcher, format_options, None);
+        assert_eq!(
+            value,
+            "A <em>car</em>, an <em>Automobile</em> or a <em>motor vehicle</em>, but not a motor"
+        );
+
+        // only the synonyms of the words of the query are matched.
+        let matcher = QueryMatcher::new(&matching_words, "bike", &analyzer)
+            .with_synonyms("bike", &analyzer, &synonyms);
+        let (value, _) = formatter.format_string(text.to_string(), &matcher, format_options, None);
+        assert_eq!(
+            value,
+            "A <em>car</em>, an Automobile or a motor vehicle, but not a motor"
+        );
+    }
+
+    #[test]
+    fn formatted_with_crop_in_chars() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        )
+        .with_crop_unit(CropUnit::Char);
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+        matching_words.insert("魔法", Some(6));
+        let format_options = FormatOptions {
+            highlight: false,
+            crop: Some(20),
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let crop = |text: &str, format_options| {
+            let (value, _) =
+                formatter.format_string(text.to_string(), &matching_words, format_options, None);
+            value
+        };
+
+        let text = "In a hole in the ground there lived a hobbit, not a nasty, dirty, wet hole.";
+        assert_eq!(crop(text, format_options), "…there lived a hobbit…");
+
+        // the crop never splits a word, even longer than the crop length
+        let text = "the hobbit lived in extraordinarilylongwordsville";
+        assert_eq!(
+            crop(
+                text,
+                FormatOptions {
+                    crop: Some(12),
+                    ..format_options
+                }
+            ),
+            "the hobbit lived…"
+        );
+
+        // multibyte characters are counted once and never split
+        let text = "霍格沃茨 是 一所 学校 ， 学生 在 那里 学习 魔法 和 历史 ， 也 学习 飞行 。";
+        let value = crop(
+            text,
+            FormatOptions {
+                crop: Some(10),
+                ..format_options
+            },
+        );
+        assert_eq!(value, "…那里 学习 魔法 和…");
+
+        // without any match the beginning of the text is kept
+        let text = "many long days until night";
+        assert_eq!(
+            crop(
+                text,
+                FormatOptions {
+                    crop: Some(9),
+                    ..format_options
+                }
+            ),
+            "many long…"
+        );
+    }
+
+    #[test]
+    fn formatted_with_crop_in_tokens() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+        let format_options = FormatOptions {
+            highlight: false,
+            crop: Some(6),
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+
+        let crop = |text: &str, unit| {
+            let formatter = Formatter::new(
+                &analyzer,
+                (String::from("<em>"), String::from("</em>")),
+                (String::from("…"), String::from("…")),
+            )
+            .with_crop_unit(unit);
+            let (value, _) =
+                formatter.format_string(text.to_string(), &matching_words, format_options, None);
+            value
+        };
+
+        let text = "one, two; three - the hobbit: four, five; six - seven";
+        assert_eq!(
+            crop(text, CropUnit::Word),
+            "…two; three - the hobbit: four, five…"
+        );
+        assert_eq!(crop(text, CropUnit::Token), "…the hobbit: four…");
+
+        // without punctuation, the separators still count.
+        let text = "one two three the hobbit four five six seven";
+        assert_eq!(
+            crop(text, CropUnit::Word),
+            "…two three the hobbit four five…"
+        );
+        assert_eq!(crop(text, CropUnit::Token), "…the hobbit four…");
+    }
+
+    #[test]
+    fn formatted_with_crop_5() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Harry Potter and the Half-Blood Prince",
+            "author": "J. K. Rowling",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: Some(5),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("potter", Some(5));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "Harry Potter and the Half…");
+        assert_eq!(value["author"], "J. K. Rowling");
+    }
+
+    #[test]
+    fn formatted_with_crop_0() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Harry Potter and the Half-Blood Prince",
+            "author": "J. K. Rowling",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: Some(0),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("potter", Some(6));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "Harry Potter and the Half-Blood Prince");
+        assert_eq!(value["author"], "J. K. Rowling");
+    }
+
+    #[test]
+    fn formatted_with_crop_and_no_match() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Harry Potter and the Half-Blood Prince",
+            "author": "J. K. Rowling",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: false,
+                crop: Some(1),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: Some(20),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("rowling", Some(3));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "Harry…");
+        assert_eq!(value["author"], "J. K. Rowling");
+    }
+
+    #[test]
+    fn formatted_with_crop_and_highlight() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Harry Potter and the Half-Blood Prince",
+            "author": "J. K. Rowling",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: true,
+                crop: Some(1),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("and", Some(3));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "…<em>and</em>…");
+        assert_eq!(value["author"], "J. K. Rowling");
+    }
+
+    #[test]
+    fn formatted_with_crop_and_highlight_in_word() {
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+        let formatter = Formatter::new(
+            &analyzer,
+            (String::from("<em>"), String::from("</em>")),
+            (String::from("…"), String::from("…")),
+        );
+
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let author = fields.insert("author").unwrap();
+
+        let document: serde_json::Value = json!({
+            "title": "Harry Potter and the Half-Blood Prince",
+            "author": "J. K. Rowling",
+        });
+
+        // we need to convert the `serde_json::Map` into an `IndexMap`.
+        let mut document = document
+            .as_object()
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut formatted_options = BTreeMap::new();
+        formatted_options.insert(
+            title,
+            FormatOptions {
+                highlight: true,
+                crop: Some(4),
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+        formatted_options.insert(
+            author,
+            FormatOptions {
+                highlight: false,
+                crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
+            },
+        );
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("blood", Some(3));
+
+        let (value, _) = format_fields(
+            &mut document,
+            &fields,
+            &formatter,
+            &matching_words,
+            &formatted_options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(value["title"], "…the Half-<em>Blo</em>od Prince");
+        assert_eq!(value["author"], "J. K. Rowling");
+    }
+
+    #[test]
+    fn test_compute_value_matches() {
+        let text = "Call me Ishmael. Some years ago—never mind how long precisely—having little or no money in my purse, and nothing particular to interest me on shore, I thought I would sail about a little and see the watery part of the world.";
+        let value = serde_json::json!(text);
+
+        let mut matcher = BTreeMap::new();
+        matcher.insert("ishmael", Some(3));
+        matcher.insert("little", Some(6));
+        matcher.insert("particular", Some(1));
+
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let mut infos = Vec::new();
+
+        compute_value_matches(
+            &mut infos,
+            &value,
+            &matcher,
+            &analyzer,
+            OffsetUnit::Bytes,
+            false,
+        );
+
+        let mut infos = infos.into_iter();
+        let crop = |info: MatchInfo| &text[info.start..info.start + info.length];
+
+        assert_eq!(crop(infos.next().unwrap()), "Ish");
+        assert_eq!(crop(infos.next().unwrap()), "little");
+        assert_eq!(crop(infos.next().unwrap()), "p");
+        assert_eq!(crop(infos.next().unwrap()), "little");
+        assert!(infos.next().is_none());
+    }
+
+    #[test]
+    fn sort_document_by_field_id() {
+        let mut fields = FieldsIdsMap::new();
+        for name in ["id", "title", "author"] {
+            fields.insert(name).unwrap();
+        }
+
+        let mut document: Document = serde_json::from_value(json!({
+            "_geoDistance": 12,
+            "author": "Tolkien",
+            "id": 1,
+            "title": "The Hobbit",
+        }))
+        .unwrap();
+        sort_by_field_id(&mut document, &fields);
+
+        let keys: Vec<_> = document.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["id", "title", "author", "_geoDistance"]);
+    }
+
+    #[test]
+    fn flatten_nested_document() {
+        let document: Document = serde_json::from_value(json!({
+            "id": 1,
+            "author": { "name": "Tolkien", "born": { "year": 1892 } },
+            "tags": ["fantasy", { "label": "classic" }],
+            "empty": {},
+            "none": [],
+        }))
+        .unwrap();
+
+        let flattened = flatten_document(document);
+        let keys: Vec<_> = flattened.keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            [
+                "id",
+                "author.name",
+                "author.born.year",
+                "tags[0]",
+                "tags[1].label",
+                "empty",
+                "none"
+            ]
+        );
+        assert_eq!(flattened["author.born.year"], json!(1892));
+        assert_eq!(flattened["tags[1].label"], json!("classic"));
+        assert_eq!(flattened["empty"], json!({}));
+        assert_eq!(flattened["none"], json!([]));
+    }
+
+    #[test]
+    fn retain_long_matches_drops_single_characters() {
+        let value = json!({ "title": "Call me Ishmael", "overview": "in particular" });
+        let value: Document = serde_json::from_value(value).unwrap();
+
+        let mut matcher = BTreeMap::new();
+        matcher.insert("ishmael", Some(3));
+        matcher.insert("particular", Some(1));
+
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let mut matches =
+            compute_matches(&matcher, &value, &analyzer, OffsetUnit::Bytes, false, false);
+        let all = matches.clone();
+        retain_long_matches(&mut matches, 0);
+        assert_eq!(matches, all);
+
+        retain_long_matches(&mut matches, 2);
+        let keys: Vec<_> = matches.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["title"]);
+        assert_eq!(matches["title"][0].length, 3);
+    }
+
+    #[test]
+    fn test_compute_value_matches_in_chars() {
+        let text = "Go💼od étoile, étoile";
+        let value = serde_json::json!(text);
+
+        let mut matcher = BTreeMap::new();
+        matcher.insert("etoile", Some(1));
+
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let mut infos = Vec::new();
+        compute_value_matches(
+            &mut infos,
+            &value,
+            &matcher,
+            &analyzer,
+            OffsetUnit::Chars,
+            false,
+        );
+        assert_eq!(
+            infos,
+            vec![
+                MatchInfo {
+                    start: 6,
+                    length: 6,
+                    matched_term: None
+                },
+                MatchInfo {
+                    start: 14,
+                    length: 6,
+                    matched_term: None
+                }
+            ]
+        );
+
+        let crop = |info: &MatchInfo| -> String {
+            text.chars().skip(info.start).take(info.length).collect()
+        };
+        assert!(infos.iter().all(|info| crop(info) == "étoile"));
+
+        // the offsets in bytes are kept by default
+        let mut infos = Vec::new();
+        compute_value_matches(
+            &mut infos,
+            &value,
+            &matcher,
+            &analyzer,
+            OffsetUnit::Bytes,
+            false,
+        );
+        assert_eq!(infos[0].start, 9);
+        assert_eq!(infos[1].start, 18);
+    }
+
+    #[test]
+    fn test_compute_match() {
+        let value = serde_json::from_str(r#"{
+            "color": "Green",
+            "name": "Lucas Hess",
+            "gender": "male",
+            "price": 3.5,
+            "address": "412 Losee Terrace, Blairstown, Georgia, 2825",
+            "about": "Mollit ad in exercitation quis Laboris . Anim est ut consequat fugiat duis magna aliquip velit nisi. Commodo eiusmod est consequat proident consectetur aliqua enim fugiat. Aliqua adipisicing laboris elit proident enim veniam laboris mollit. Incididunt fugiat minim ad nostrud deserunt tempor in. Id irure officia labore qui est labore nulla nisi. Magna sit quis tempor esse consectetur amet labore duis aliqua consequat.\r\n"
+  }"#).unwrap();
+        let mut matcher = BTreeMap::new();
+        matcher.insert("green", Some(5));
+        matcher.insert("mollit", Some(6));
+        matcher.insert("laboris", Some(7));
+        matcher.insert("3", Some(1));
+
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let matches = compute_matches(&matcher, &value, &analyzer, OffsetUnit::Bytes, false, false);
+        assert_eq!(
+            format!("{:?}", matches),
+            r##"{"about": [MatchInfo { start: 0, length: 6, matched_term: None }, MatchInfo { start: 31, length: 7, matched_term: None }, MatchInfo { start: 191, length: 7, matched_term: None }, MatchInfo { start: 225, length: 7, matched_term: None }, MatchInfo { start: 233, length: 6, matched_term: None }], "color": [MatchInfo { start: 0, length: 5, matched_term: None }], "price": [MatchInfo { start: 0, length: 1, matched_term: None }]}"##
+        );
+    }
+
+    #[test]
+    fn compute_matches_with_matched_terms() {
+        let value = json!({ "title": "The Hobbit meets a Dwarf in the hobbit hole" });
+        let value: Document = serde_json::from_value(value).unwrap();
+        let mut matcher = BTreeMap::new();
+        matcher.insert("hobbit", Some(6));
+        matcher.insert("dwarf", Some(5));
+
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let matches = compute_matches(&matcher, &value, &analyzer, OffsetUnit::Bytes, false, true);
+        let terms: Vec<_> = matches["title"]
+            .iter()
+            .map(|info| info.matched_term.as_deref())
+            .collect();
+        assert_eq!(terms, [Some("hobbit"), Some("dwarf"), Some("hobbit")]);
+
+        // the terms are not computed by default
+        let matches = compute_matches(&matcher, &value, &analyzer, OffsetUnit::Bytes, false, false);
+        assert!(matches["title"]
+            .iter()
+            .all(|info| info.matched_term.is_none()));
+    }
+
+    #[test]
+    fn compute_matches_by_pointer() {
+        let value = serde_json::from_str(
+            r#"{
+                "title": "The Lord of the Rings",
+                "authors": [
+                    { "name": "Christopher Tolkien" },
+                    { "name": "J. R. R. Tolkien", "born": 1892 }
+                ],
+                "a/b~c": "rings"
+            }"#,
+        )
+        .unwrap();
+        let mut matcher = BTreeMap::new();
+        matcher.insert("tolkien", Some(7));
+        matcher.insert("rings", Some(5));
+
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let analyzer = Analyzer::new(config);
+
+        let matches = compute_matches(&matcher, &value, &analyzer, OffsetUnit::Bytes, true, false);
+        let keys: Vec<_> = matches.keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            ["/authors/0/name", "/authors/1/name", "/a~1b~0c", "/title"]
+        );
+        assert_eq!(
+            matches["/authors/1/name"],
+            [MatchInfo {
+                start: 9,
+                length: 7,
+                matched_term: None
+            }]
+        );
+
+        // the matches of the array elements are grouped under their attribute by default
+        let matches = compute_matches(&matcher, &value, &analyzer, OffsetUnit::Bytes, false, false);
+        assert_eq!(matches["authors"].len(), 2);
+    }
+
+    #[test]
+    fn test_insert_geo_distance() {
+        let value: Document = serde_json::from_str(
+            r#"{
+      "_geo": {
+        "lat": 50.629973371633746,
+        "lng": 3.0569447399419567
+      },
+      "city": "Lille",
+      "id": "1"
+    }"#,
+        )
+        .unwrap();
+
+        let sorters = &["_geoPoint(50.629973371633746,3.0569447399419567):desc".to_string()];
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, None, &mut document).unwrap();
+        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+
+        let sorters = &["_geoPoint(50.629973371633746, 3.0569447399419567):asc".to_string()];
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, None, &mut document).unwrap();
+        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+
+        let sorters =
+            &["_geoPoint(   50.629973371633746   ,  3.0569447399419567   ):desc".to_string()];
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, None, &mut document).unwrap();
+        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+
+        let sorters = &[
+            "prix:asc",
+            "villeneuve:desc",
+            "_geoPoint(50.629973371633746, 3.0569447399419567):asc",
+            "ubu:asc",
+        ]
+        .map(|s| s.to_string());
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, None, &mut document).unwrap();
+        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+
+        // only the first geoPoint is used to compute the distance
+        let sorters = &[
+            "chien:desc",
+            "_geoPoint(50.629973371633746, 3.0569447399419567):asc",
+            "pangolin:desc",
+            "_geoPoint(100.0, -80.0):asc",
+            "chat:asc",
+        ]
+        .map(|s| s.to_string());
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, None, &mut document).unwrap();
+        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+
+        // a fractional distance is kept with the requested number of decimals
+        let sorters = &["_geoPoint(50.63, 3.057):asc".to_string()];
+        let exact = milli::distance_between_two_points(
+            &[50.63, 3.057],
+            &[50.629973371633746, 3.0569447399419567],
+        );
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, None, &mut document).unwrap();
+        assert_eq!(
+            document.get("_geoDistance"),
+            Some(&json!(exact.round() as usize))
+        );
+
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, Some(2), &mut document).unwrap();
+        let distance = document.get("_geoDistance").unwrap().as_f64().unwrap();
+        assert_eq!(distance, (exact * 100.0).round() / 100.0);
+        assert_ne!(distance, exact.round());
+
+        let mut document = value.clone();
+        insert_geo_distance(sorters, false, Some(0), &mut document).unwrap();
+        assert_eq!(document.get("_geoDistance"), Some(&json!(exact.round())));
+
+        // there was no _geoPoint so nothing is inserted in the document
+        let sorters = &["chien:asc".to_string()];
+        let mut document = value;
+        insert_geo_distance(sorters, false, None, &mut document).unwrap();
+        assert_eq!(document.get("_geoDistance"), None);
+    }
+
+    fn facet_values(values: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        values
+            .iter()
+            .map(|(value, count)| (value.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn test_truncate_facet_values() {
+        let values = facet_values(&[("blue", 3), ("green", 1), ("red", 8)]);
+        let mut values = FacetValues::new(values, FacetSortBy::Alpha);
+
+        assert!(!values.truncate(3));
+        assert_eq!(values.0.len(), 3);
+
+        assert!(values.truncate(2));
+        assert_eq!(
+            values.0,
+            vec![("blue".to_string(), 3), ("green".to_string(), 1)]
+        );
+
+        assert!(values.truncate(0));
+        assert!(values.0.is_empty());
+    }
+
+    #[test]
+    fn test_facet_values_sort() {
+        let values = facet_values(&[("blue", 3), ("green", 1), ("red", 8), ("yellow", 3)]);
+
+        let alpha = FacetValues::new(values.clone(), FacetSortBy::Alpha);
+        let order: Vec<_> = alpha.0.iter().map(|(value, _)| value.as_str()).collect();
+        assert_eq!(order, ["blue", "green", "red", "yellow"]);
+
+        let mut count = FacetValues::new(values, FacetSortBy::Count);
+        let order: Vec<_> = count.0.iter().map(|(value, _)| value.as_str()).collect();
+        assert_eq!(order, ["red", "blue", "yellow", "green"]);
+        assert_eq!(
+            serde_json::to_string(&count).unwrap(),
+            r#"{"red":8,"blue":3,"yellow":3,"green":1}"#
+        );
+
+        // the most frequent values are kept when truncating
+        count.truncate(1);
+        assert_eq!(count.0, vec![("red".to_string(), 8)]);
+    }
+
+    #[test]
+    fn test_insert_distance() {
+        let mut document: Document =
+            serde_json::from_str(r#"{ "_geo": { "lat": 50.63, "lng": 3.057 }, "id": "1" }"#)
+                .unwrap();
+        insert_distance(&[[50.63, 3.057]], None, &mut document);
+        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+
+        let mut document: Document = serde_json::from_str(r#"{ "id": "1" }"#).unwrap();
+        insert_distance(&[[50.63, 3.057]], None, &mut document);
+        assert_eq!(document.get("_geoDistance"), None);
+    }
+
+    #[test]
+    fn test_insert_min_geo_distance() {
+        let value: Document = serde_json::from_str(
+            r#"{
+      "_geo": {
+        "lat": 50.629973371633746,
+        "lng": 3.0569447399419567
+      },
+      "city": "Lille",
+      "id": "1"
+    }"#,
+        )
+        .unwrap();
+        let paris = "_geoPoint(48.8566, 2.3522):asc".to_string();
+        let roubaix = "_geoPoint(50.6942, 3.1746):asc".to_string();
+        let to_paris = milli::distance_between_two_points(
+            &[48.8566, 2.3522],
+            &[50.629973371633746, 3.0569447399419567],
+        )
+        .round() as usize;
+        let to_roubaix = milli::distance_between_two_points(
+            &[50.6942, 3.1746],
+            &[50.629973371633746, 3.0569447399419567],
+        )
+        .round() as usize;
+        assert!(to_roubaix < to_paris);
+
+        // the closest point is used, whatever its position in the sort criteria
+        for sorters in [
+            [paris.clone(), "chien:desc".to_string(), roubaix.clone()],
+            [roubaix.clone(), "chien:desc".to_string(), paris.clone()],
+        ] {
+            let mut document = value.clone();
+            insert_geo_distance(&sorters, true, None, &mut document).unwrap();
+            assert_eq!(document.get("_geoDistance"), Some(&json!(to_roubaix)));
+        }
+
+        // without the option only the first point is used
+        let mut document = value.clone();
+        insert_geo_distance(
+            &[paris.clone(), roubaix.clone()],
+            false,
+            None,
+            &mut document,
+        )
+        .unwrap();
+        assert_eq!(document.get("_geoDistance"), Some(&json!(to_paris)));
+
+        // a malformed point is an error even if it is not the closest one
+        let mut document = value;
+        let error = insert_geo_distance(
+            &[roubaix, "_geoPoint(-, 3.05):asc".to_string()],
+            true,
+            None,
+            &mut document,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            IndexError::Search(SearchError::InvalidGeoPoint(_))
+        ));
+    }
+
+    #[test]
+    fn test_insert_geo_distance_with_malformed_geo_point() {
+        let value: Document = serde_json::from_str(
+            r#"{
+      "_geo": {
+        "lat": 50.629973371633746,
+        "lng": 3.0569447399419567
+      },
+      "id": "1"
+    }"#,
+        )
+        .unwrap();
+
+        for sort in [
+            "_geoPoint(50.6.2, 3.05):asc",
+            "_geoPoint(-, 3.05):asc",
+            "_geoPoint(50.62, --3):desc",
+        ] {
+            let mut document = value.clone();
+            let error =
+                insert_geo_distance(&[sort.to_string()], false, None, &mut document).unwrap_err();
+            assert!(matches!(
+                error,
+                IndexError::Search(SearchError::InvalidGeoPoint(_))
+            ));
+            assert_eq!(document.get("_geoDistance"), None);
+        }
+
+        // a coordinate overflowing a f64
+        let overflow = format!("_geoPoint(1{}, 3.05):asc", "0".repeat(400));
+        let mut document = value;
+        let error = insert_geo_distance(&[overflow], false, None, &mut document).unwrap_err();
+        assert!(matches!(
+            error,
+            IndexError::Search(SearchError::InvalidGeoPoint(_))
+        ));
+    }
+
+    #[test]
+    fn stream_facets_distribution_matches_batch() {
+        let (_dir, index) = index_with_documents(
+            json!([
+                { "id": 1, "genre": "action", "year": 2001 },
+                { "id": 2, "genre": "action", "year": 2004 },
+                { "id": 3, "genre": "drama", "year": 2001 },
+                { "id": 4, "genre": "horror", "year": 1999 },
+            ]),
+            Settings {
+                filterable_attributes: Setting::Set(
+                    ["genre", "year"].iter().map(|s| s.to_string()).collect(),
+                ),
+                ..Default::default()
+            },
+        );
+
+        let query: SearchQuery =
+            serde_json::from_value(json!({ "facetsDistribution": ["*"] })).unwrap();
+
+        let mut streamed: BTreeMap<String, BTreeMap<String, u64>> = BTreeMap::new();
+        index
+            .stream_facets_distribution(&query, |facet, value, count| {
+                streamed
+                    .entry(facet.to_string())
+                    .or_default()
+                    .insert(value.to_string(), count);
+            })
+            .unwrap();
+
+        let batch: BTreeMap<_, BTreeMap<_, _>> = index
+            .perform_search(query)
+            .unwrap()
+            .facets_distribution
+            .unwrap()
+            .into_iter()
+            .map(|(facet, values)| (facet, values.0.into_iter().collect()))
+            .collect();
+
+        assert_eq!(streamed, batch);
+        assert_eq!(streamed["genre"].values().sum::<u64>(), 4);
+        assert_eq!(streamed["year"].values().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn stream_search_matches_batch() {
+        let documents: Vec<_> = (0..50)
+            .map(|id| json!({ "id": id, "rank": (id * 7) % 50, "title": "the hobbit" }))
+            .collect();
+        let (_dir, index) = index_with_documents(
+            json!(documents),
+            Settings {
+                sortable_attributes: Setting::Set(std::iter::once("rank".to_string()).collect()),
+                ..Default::default()
+            },
+        );
+
+        let query: SearchQuery = serde_json::from_value(json!({
+            "q": "hobbit",
+            "sort": ["rank:desc"],
+            "limit": 30,
+            "attributesToHighlight": ["title"],
+            "showResultHash": true,
+        }))
+        .unwrap();
+
+        let mut streamed = Vec::new();
+        let result = index
+            .stream_search(query.clone(), |hit| {
+                streamed.push(hit);
+                Ok(())
+            })
+            .unwrap();
+        let batch = index.perform_search(query.clone()).unwrap();
+
+        assert_eq!(streamed, batch.hits);
+        assert!(result.hits.is_empty());
+        assert_eq!(result.nb_hits, batch.nb_hits);
+        assert_eq!(result.result_hash, batch.result_hash);
+
+        // an error stops the stream and is returned.
+        let mut received = 0;
+        let result = index.stream_search(query, |_| {
+            received += 1;
+            if received == 3 {
+                Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into())
+            } else {
+                Ok(())
+            }
+        });
+        assert!(result.is_err());
+        assert_eq!(received, 3);
+    }
+
+    #[test]
+    fn shared_analyzer_matches_a_fresh_one() {
+        fn tokens(analyzer: &Analyzer<Vec<u8>>) -> Vec<(String, bool)> {
+            analyzer
+                .analyze("The Hobbit, l'été et Go💼od: 2 rues à Lille!")
+                .reconstruct()
+                .map(|(word, token)| (word.to_string(), token.is_word()))
+                .collect()
+        }
+
+        let stop_words = fst::Set::default();
+        let mut config = AnalyzerConfig::default();
+        config.stop_words(&stop_words);
+        let expected = tokens(&Analyzer::new(config));
+
+        // the analyzer is shared by the searches running concurrently
+        let handles: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| tokens(&ANALYZER)))
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn parallel_formatting_keeps_the_ranking_order() {
+        let documents: Vec<_> = (0..500)
+            .map(|id| {
+                json!({
+                    "id": id,
+                    "rank": (id * 7919) % 500,
+                    "overview": "a hobbit goes on a journey with dwarves to the lonely mountain",
+                })
+            })
+            .collect();
+        let (_dir, index) = index_with_documents(
+            json!(documents),
+            Settings {
+                sortable_attributes: Setting::Set(std::iter::once("rank".to_string()).collect()),
+                ..Default::default()
+            },
+        );
+
+        let query: SearchQuery = serde_json::from_value(json!({
+            "q": "hobbit",
+            "sort": ["rank:asc"],
+            "limit": 500,
+            "attributesToHighlight": ["overview"],
+            "attributesToCrop": ["overview"],
+            "cropLength": 3,
+            "matches": true,
+        }))
+        .unwrap();
+        let result = index.perform_search(query).unwrap();
+
+        let ranks: Vec<_> = result
+            .hits
+            .iter()
+            .map(|hit| hit.document["rank"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ranks, (0..500).collect::<Vec<_>>());
+        let formatted = &result.hits[0].formatted["overview"];
+        assert!(formatted.as_str().unwrap().contains("<em>hobbit</em>"));
+        assert!(result
+            .hits
+            .iter()
+            .all(|hit| &hit.formatted["overview"] == formatted));
+    }
+
+    #[test]
+    fn test_applied_ranking_rules() {
+        let criteria = vec![
+            Criterion::Words,
+            Criterion::Typo,
+            Criterion::Sort,
+            Criterion::Proximity,
+            Criterion::Desc(String::from("release_date")),
+        ];
+        let rules = |query: Value| {
+            let query: SearchQuery = serde_json::from_value(query).unwrap();
+            applied_ranking_rules(&criteria, &query)
+        };
+
+        assert_eq!(
+            rules(json!({ "q": "hobbit" })),
+            ["words", "typo", "proximity", "release_date:desc"]
+        );
+        assert_eq!(
+            rules(json!({ "q": "hobbit", "sort": ["price:asc", "title:desc"] })),
+            [
+                "words",
+                "typo",
+                "sort(price:asc)",
+                "sort(title:desc)",
+                "proximity",
+                "release_date:desc"
+            ]
+        );
+        assert_eq!(
+            rules(json!({ "sort": ["price:asc"] })),
+            ["sort(price:asc)", "release_date:desc"]
+        );
+    }
+
+    #[test]
+    fn test_deadline() {
+        let start = Instant::now() - Duration::from_millis(10);
+
+        assert!(Deadline::new(start, None).check("searching").is_ok());
+        assert!(Deadline::new(start, Some(60_000))
+            .check("searching")
+            .is_ok());
+
+        let error = Deadline::new(start, Some(5))
+            .check("formatting the hits")
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            IndexError::Search(SearchError::Timeout(5, elapsed, "formatting the hits")) if elapsed >= 10
+        ));
+    }
+
+    #[test]
+    fn test_last_word_len() {
+        assert_eq!(last_word_len("the hobbit"), Some(6));
+        assert_eq!(last_word_len("the h"), Some(1));
+        assert_eq!(last_word_len("the hobbit "), None);
+        assert_eq!(last_word_len("l'été"), Some(3));
+        assert_eq!(last_word_len(""), None);
+    }
+
+    #[test]
+    fn test_compute_result_hash() {
+        let hit = |id: u64, title: &str| SearchHit {
+            document: vec![
+                ("id".to_string(), json!(id)),
+                ("title".to_string(), json!(title)),
+            ]
+            .into_iter()
+            .collect(),
+            formatted: Document::new(),
+            matches_info: None,
+            cropped_attributes: None,
+            typos: None,
+            formatted_truncated: None,
+            size_bytes: None,
+            matched_words_count: None,
+            ranking_score: None,
+        };
+
+        let hash = compute_result_hash(&[hit(1, "hello"), hit(2, "world")]).unwrap();
+        assert_eq!(hash.len(), 16);
+        assert_eq!(
+            hash,
+            compute_result_hash(&[hit(1, "hello"), hit(2, "world")]).unwrap()
+        );
+        assert_ne!(
+            hash,
+            compute_result_hash(&[hit(2, "world"), hit(1, "hello")]).unwrap()
+        );
+        assert_ne!(
+            hash,
+            compute_result_hash(&[hit(1, "hello"), hit(2, "worlds")]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_insert_facet_values() {
+        let mut values = BTreeMap::new();
+        insert_facet_values(
+            &json!(["Action", " action", "Drama", 12, 4.5, true, null, { "a": "b" }, ["Comedy"]]),
+            &mut values,
+        );
+
+        let expected: BTreeMap<_, _> = vec![
+            ("action", "Action"),
+            ("drama", "Drama"),
+            ("12", "12"),
+            ("4.5", "4.5"),
+            ("true", "true"),
+            ("comedy", "Comedy"),
+        ]
+        .into_iter()
+        .map(|(normalized, original)| (normalized.to_string(), original.to_string()))
+        .collect();
+
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn test_offset_and_limit() {
+        let query = |value: Value| -> SearchQuery { serde_json::from_value(value).unwrap() };
+
+        assert_eq!(
+            query(json!({}))
+                .offset_and_limit(HARD_RESULT_LIMIT)
+                .unwrap(),
+            (0, 20)
+        );
+        assert_eq!(
+            query(json!({ "offset": 5, "limit": 10 }))
+                .offset_and_limit(HARD_RESULT_LIMIT)
+                .unwrap(),
+            (5, 10)
+        );
+        assert_eq!(
+            query(json!({ "page": 3, "hitsPerPage": 10 }))
+                .offset_and_limit(HARD_RESULT_LIMIT)
+                .unwrap(),
+            (20, 10)
+        );
+        assert_eq!(
+            query(json!({ "page": 2 }))
+                .offset_and_limit(HARD_RESULT_LIMIT)
+                .unwrap(),
+            (20, 20)
+        );
+        assert_eq!(
+            query(json!({ "hitsPerPage": 5 }))
+                .offset_and_limit(HARD_RESULT_LIMIT)
+                .unwrap(),
+            (0, 5)
+        );
+
+        assert!(matches!(
+            query(json!({ "page": 0 })).offset_and_limit(HARD_RESULT_LIMIT),
+            Err(IndexError::Search(SearchError::InvalidPage))
+        ));
+        assert!(matches!(
+            query(json!({ "page": 1, "offset": 0 })).offset_and_limit(HARD_RESULT_LIMIT),
+            Err(IndexError::Search(SearchError::MixedPagination))
+        ));
+        assert!(matches!(
+            query(json!({ "hitsPerPage": 1, "limit": 5 })).offset_and_limit(HARD_RESULT_LIMIT),
+            Err(IndexError::Search(SearchError::MixedPagination))
+        ));
+
+        // the offset and limit are only clamped by the search by default
+        let past_limit = json!({ "offset": HARD_RESULT_LIMIT + 1 });
+        assert_eq!(
+            query(past_limit)
+                .offset_and_limit(HARD_RESULT_LIMIT)
+                .unwrap(),
+            (HARD_RESULT_LIMIT + 1, 20)
+        );
+        assert!(matches!(
+            query(json!({ "offset": HARD_RESULT_LIMIT + 1, "strictLimits": true }))
+                .offset_and_limit(HARD_RESULT_LIMIT),
+            Err(IndexError::Search(SearchError::HardLimitExceeded(
+                1001, 20, 1000
+            )))
+        ));
+        assert!(matches!(
+            query(json!({ "page": 51, "strictLimits": true })).offset_and_limit(HARD_RESULT_LIMIT),
+            Err(IndexError::Search(SearchError::HardLimitExceeded(
+                1000, 20, 1000
+            )))
+        ));
+        assert_eq!(
+            query(json!({ "offset": 980, "strictLimits": true }))
+                .offset_and_limit(HARD_RESULT_LIMIT)
+                .unwrap(),
+            (980, 20)
+        );
+
+        // the index can lower the number of hits that can be reached
+        assert!(matches!(
+            query(json!({ "offset": 90, "limit": 20, "strictLimits": true })).offset_and_limit(100),
+            Err(IndexError::Search(SearchError::HardLimitExceeded(
+                90, 20, 100
+            )))
+        ));
+        assert_eq!(
+            query(json!({ "offset": 90, "limit": 20 }))
+                .offset_and_limit(100)
+                .unwrap(),
+            (90, 20)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_matching_strategy() {
+        let query: SearchQuery = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(query.matching_strategy, None);
+        assert_eq!(
+            query.matching_strategy.unwrap_or_default(),
+            MatchingStrategy::Last
+        );
+
+        let query: SearchQuery =
+            serde_json::from_value(json!({ "matchingStrategy": "all" })).unwrap();
+        assert_eq!(query.matching_strategy, Some(MatchingStrategy::All));
+
+        let query: SearchQuery =
+            serde_json::from_value(json!({ "matchingStrategy": "last" })).unwrap();
+        assert_eq!(query.matching_strategy, Some(MatchingStrategy::Last));
+
+        assert!(
+            serde_json::from_value::<SearchQuery>(json!({ "matchingStrategy": "All" })).is_err()
+        );
+    }
+
+    #[test]
+    fn search_query_builder_defaults() {
+        let query: SearchQuery = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(SearchQuery::default(), query);
+        assert_eq!(SearchQuery::builder().build(), query);
+
+        let query: SearchQuery = serde_json::from_value(json!({ "q": "hobbit" })).unwrap();
+        assert_eq!(SearchQuery::builder().q("hobbit").build(), query);
+    }
+
+    #[test]
+    fn search_query_builder() {
+        let query: SearchQuery = serde_json::from_value(json!({
+            "q": "hobbit",
+            "offset": 10,
+            "limit": 5,
+            "filter": ["genre = fantasy"],
+            "sort": ["year:asc"],
+            "facetsDistribution": ["genre"],
+            "attributesToRetrieve": ["title", "year"],
+            "attributesToHighlight": ["title"],
+            "attributesToCrop": ["overview:5"],
+            "cropLength": 20,
+            "cropMarker": "...",
+            "highlightPreTag": "<mark>",
+            "highlightPostTag": "</mark>",
+            "matches": true,
+            "matchingStrategy": "all",
+        }))
+        .unwrap();
+
+        let built = SearchQuery::builder()
+            .q("hobbit")
+            .offset(10)
+            .limit(5)
+            .filter(json!(["genre = fantasy"]))
+            .sort(["year:asc"])
+            .facets_distribution(["genre"])
+            .attributes_to_retrieve(["title", "year"])
+            .attributes_to_highlight(["title"])
+            .attributes_to_crop(["overview:5"])
+            .crop_length(20)
+            .crop_marker("...")
+            .highlight_tags("<mark>", "</mark>")
+            .matches(true)
+            .matching_strategy(MatchingStrategy::All)
+            .build();
+        assert_eq!(built, query);
+    }
+
+    #[test]
+    fn test_deserialize_distinct() {
+        let query: SearchQuery = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(query.distinct, None);
+
+        let query: SearchQuery = serde_json::from_value(json!({ "distinct": null })).unwrap();
+        assert_eq!(query.distinct, Some(None));
+
+        let query: SearchQuery = serde_json::from_value(json!({ "distinct": "color" })).unwrap();
+        assert_eq!(query.distinct, Some(Some(String::from("color"))));
+    }
+
+    #[test]
+    fn test_deserialize_exclude_document_ids() {
+        let query: SearchQuery =
+            serde_json::from_value(json!({ "excludeDocumentIds": [1, "two"] })).unwrap();
+        let ids = query.exclude_document_ids.unwrap();
+        assert_eq!(
+            ids,
+            vec![
+                ExternalDocumentId::Integer(1),
+                ExternalDocumentId::String(String::from("two"))
+            ]
+        );
+        let ids: Vec<_> = ids.iter().map(ExternalDocumentId::to_external_id).collect();
+        assert_eq!(ids, vec!["1", "two"]);
+    }
+
+    #[test]
+    fn test_split_top_level_and() {
+        assert_eq!(
+            split_top_level_and("genre = horror AND (year > 2000 AND rating > 3)"),
+            vec!["genre = horror", "(year > 2000 AND rating > 3)"]
+        );
+        assert_eq!(
+            split_top_level_and("NOT genre = horror AND title = 'AND OR' AND rating>3"),
+            vec!["NOT genre = horror", "title = 'AND OR'", "rating>3"]
+        );
+        // `AND` has precedence over `OR`
+        assert_eq!(
+            split_top_level_and("genre = horror AND year > 2000 OR rating > 3"),
+            vec!["genre = horror AND year > 2000 OR rating > 3"]
+        );
+        assert_eq!(
+            split_top_level_and("BRAND = ANDROID"),
+            vec!["BRAND = ANDROID"]
+        );
+    }
+
+    #[test]
+    fn test_filter_without_facet() {
+        assert_eq!(
+            filter_without_facet(&json!("genre = horror AND year > 2000"), "genre"),
+            Some(json!(["year > 2000"]))
+        );
+        assert_eq!(
+            filter_without_facet(&json!("genre = horror AND year > 2000"), "rating"),
+            None
+        );
+        assert_eq!(
+            filter_without_facet(
+                &json!([
+                    ["genre = horror", "genre = drama"],
+                    "year > 2000",
+                    "doggos.age = 2"
+                ]),
+                "doggos"
+            ),
+            Some(json!([["genre = horror", "genre = drama"], "year > 2000"]))
+        );
+        assert_eq!(
+            filter_without_facet(&json!([{ "not": "genre = horror" }]), "genre"),
+            Some(json!([]))
+        );
+    }
+
+    #[test]
+    fn test_collect_filter_fields() {
+        let fields = |filter: Value| {
+            let mut fields = BTreeSet::new();
+            collect_filter_fields(&filter, &mut fields);
+            fields.into_iter().collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            fields(json!(
+                "(genre = horror OR 'release date' 2000 TO 2010) AND NOT price > 10 AND title != \"AND or OR\""
+            )),
+            vec!["genre", "price", "release date", "title"]
+        );
+        assert_eq!(
+            fields(json!([
+                ["genre = horror", "genre = \"science fiction\""],
+                "_geoRadius(45.4, 3.2, 1000) AND doggos.age >= 2",
+                "_geoBoundingBox((45.5, 3.3), (45.4, 3.2)) OR rating<=3",
+            ])),
+            vec!["_geo", "doggos.age", "genre", "rating"]
+        );
+        assert!(fields(json!([])).is_empty());
+    }
+
+    #[test]
+    fn test_serialize_hits_count() {
+        let result = SearchResult {
+            hits: vec![],
+            nb_hits: 29,
+            exhaustive_nb_hits: false,
+            query: String::new(),
+            limit: 20,
+            offset: 0,
+            processing_time_ms: 0,
+            processing_time_us: 0,
+            facets_distribution: None,
+            exhaustive_facets_count: None,
+            result_hash: None,
+            estimated_total_hits: Some(29),
+            total_hits: None,
+            total_pages: None,
+            facet_stats: None,
+            processing_times: None,
+            filter_fields: None,
+            suggestion: None,
+            ranking_rules: None,
+            field_coverage: None,
+            warnings: Vec::new(),
+            next_cursor: None,
+            normalized_query: None,
+            candidates_count: None,
+            unfiltered_hits: None,
+        };
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(value["nbHits"], json!(29));
+        assert_eq!(value["exhaustiveNbHits"], json!(false));
+        assert_eq!(value["estimatedTotalHits"], json!(29));
+        assert!(value.get("totalHits").is_none());
+
+        let result = SearchResult {
+            exhaustive_nb_hits: true,
+            estimated_total_hits: None,
+            total_hits: Some(29),
+            ..result
+        };
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(value["nbHits"], json!(29));
+        assert_eq!(value["totalHits"], json!(29));
+        assert!(value.get("estimatedTotalHits").is_none());
+    }
+
+    #[test]
+    fn test_deserialize_mixed_attributes_to_crop() {
+        let query: SearchQuery = serde_json::from_value(json!({
+            "attributesToCrop": [
+                "title",
+                "overview:5",
+                { "attribute": "genre:name", "cropLength": 30 },
+                { "attribute": "author" },
+            ]
+        }))
+        .unwrap();
+
+        let attributes = query.attributes_to_crop.unwrap();
+        let lengths: Vec<_> = attributes
+            .iter()
+            .map(|attr| attr.name_and_length(10))
             .collect();
+        assert_eq!(
+            lengths,
+            vec![
+                ("title", 10),
+                ("overview", 5),
+                ("genre:name", 30),
+                ("author", 10)
+            ]
+        );
 
-        let formatted_options = BTreeMap::new();
+        assert!(serde_json::from_value::<SearchQuery>(json!({
+            "attributesToCrop": [{ "attribute": "title", "length": 30 }]
+        }))
+        .is_err());
+        assert!(serde_json::from_value::<SearchQuery>(json!({
+            "attributesToCrop": [{ "cropLength": 30 }]
+        }))
+        .is_err());
+    }
 
-        let matching_words = MatchingWords::default();
+    #[test]
+    fn test_object_crop_length_takes_precedence() {
+        let mut fields = FieldsIdsMap::new();
+        let title = fields.insert("title").unwrap();
+        let overview = fields.insert("overview").unwrap();
+        let displayed_ids = vec![title, overview].into_iter().collect();
+
+        let attr_to_crop: Vec<AttributeToCrop> = serde_json::from_value(json!([
+            { "attribute": "overview", "cropLength": 30 },
+            "overview:10",
+            "title:3",
+        ]))
+        .unwrap();
 
-        let value = format_fields(
-            &document,
+        let mut formatted_options = BTreeMap::new();
+        add_crop_to_formatted_options(
+            &mut formatted_options,
+            &attr_to_crop,
+            10,
+            false,
             &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
-        .unwrap();
+            &displayed_ids,
+        );
 
-        assert!(value.is_empty());
+        assert_eq!(formatted_options[&overview].crop, Some(30));
+        assert_eq!(formatted_options[&title].crop, Some(3));
     }
 
     #[test]
-    fn formatted_with_highlight_in_word() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
+    fn test_parse_geo_bounding_box() {
+        assert_eq!(GeoBoundingBox::parse("genre = action").unwrap(), None);
+        assert_eq!(
+            GeoBoundingBox::parse("_geoBoundingBox([50.7, 3.1], [50.5, 2.9])").unwrap(),
+            Some(GeoBoundingBox {
+                top_right: [50.7, 3.1],
+                bottom_left: [50.5, 2.9],
+            })
+        );
+        assert_eq!(
+            GeoBoundingBox::parse(" _geoBoundingBox( [ 10 , -170 ] , [ -10 , 170 ] ) ").unwrap(),
+            Some(GeoBoundingBox {
+                top_right: [10.0, -170.0],
+                bottom_left: [-10.0, 170.0],
+            })
         );
 
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
+        for expr in [
+            "_geoBoundingBox([50.7, 3.1])",
+            "_geoBoundingBox([a, 3.1], [50.5, 2.9])",
+            "_geoBoundingBox([95, 3.1], [50.5, 2.9])",
+            "_geoBoundingBox([50.7, 190], [50.5, 2.9])",
+            "_geoBoundingBox([50.5, 3.1], [50.7, 2.9])",
+            "genre = action AND _geoBoundingBox([50.7, 3.1], [50.5, 2.9])",
+        ] {
+            assert!(
+                matches!(
+                    GeoBoundingBox::parse(expr),
+                    Err(IndexError::Search(SearchError::InvalidGeoBoundingBox(_)))
+                ),
+                "{}",
+                expr
+            );
+        }
+    }
 
-        let document: serde_json::Value = json!({
-            "title": "The Hobbit",
-            "author": "J. R. R. Tolkien",
-        });
+    #[test]
+    fn test_geo_bounding_box_contains() {
+        let lille = GeoBoundingBox {
+            top_right: [50.7, 3.1],
+            bottom_left: [50.5, 2.9],
+        };
+        assert!(lille.contains([50.63, 3.05]));
+        assert!(lille.contains([50.7, 2.9]));
+        assert!(!lille.contains([48.85, 2.35]));
+        assert!(!lille.contains([50.63, 3.2]));
+
+        let fiji = GeoBoundingBox {
+            top_right: [-10.0, -175.0],
+            bottom_left: [-20.0, 175.0],
+        };
+        assert!(fiji.contains([-17.7, 178.0]));
+        assert!(fiji.contains([-17.7, -178.0]));
+        assert!(fiji.contains([-17.7, 180.0]));
+        assert!(!fiji.contains([-17.7, 170.0]));
+        assert!(!fiji.contains([-17.7, 0.0]));
+        assert!(!fiji.contains([-5.0, 178.0]));
+    }
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
+    #[test]
+    fn test_geo_bounding_box_to_geo_radius() {
+        let fiji = GeoBoundingBox {
+            top_right: [-10.0, -175.0],
+            bottom_left: [-20.0, 175.0],
+        };
+        let radius = fiji.to_geo_radius();
+        assert!(radius.starts_with("_geoRadius(-15, 180, "), "{}", radius);
+
+        let lille = GeoBoundingBox {
+            top_right: [50.7, 3.1],
+            bottom_left: [50.5, 2.9],
+        };
+        let radius = lille.to_geo_radius();
+        let distance: f64 = radius
+            .trim_end_matches(')')
+            .rsplit(", ")
+            .next()
             .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+            .parse()
+            .unwrap();
+        for corner in [[50.7, 3.1], [50.7, 2.9], [50.5, 3.1], [50.5, 2.9]] {
+            assert!(milli::distance_between_two_points(&[50.6, 3.0], &corner) < distance);
+        }
+    }
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: true,
-                crop: None,
-            },
+    #[test]
+    fn test_search_filter_with_bounding_boxes() {
+        let filter = SearchFilter::new(&json!("genre = action")).unwrap();
+        assert_eq!(filter.milli_filter, json!("genre = action"));
+        assert!(filter.bounding_boxes.is_empty());
+
+        let bounding_box = "_geoBoundingBox([50.7, 3.1], [50.5, 2.9])";
+        let geo_radius = GeoBoundingBox::parse(bounding_box)
+            .unwrap()
+            .unwrap()
+            .to_geo_radius();
+
+        let filter = SearchFilter::new(&json!(bounding_box)).unwrap();
+        assert_eq!(filter.milli_filter, json!(geo_radius));
+        assert_eq!(filter.bounding_boxes.len(), 1);
+
+        let filter = SearchFilter::new(&json!([
+            "year > 2000",
+            bounding_box,
+            ["genre = action", bounding_box],
+            ["genre = drama", "genre = comedy"],
+        ]))
+        .unwrap();
+        assert_eq!(
+            filter.milli_filter,
+            json!([
+                "year > 2000",
+                geo_radius,
+                ["genre = action", geo_radius],
+                ["genre = drama", "genre = comedy"],
+            ])
         );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        assert_eq!(filter.bounding_boxes.len(), 2);
+        assert!(filter.bounding_boxes[0].others.is_empty());
+        assert_eq!(filter.bounding_boxes[1].others, vec!["genre = action"]);
+    }
+
+    #[test]
+    fn test_search_filter_with_negations() {
+        let bounding_box = "_geoBoundingBox([50.7, 3.1], [50.5, 2.9])";
+        let geo_radius = GeoBoundingBox::parse(bounding_box)
+            .unwrap()
+            .unwrap()
+            .to_geo_radius();
+
+        let filter = SearchFilter::new(&json!([
+            { "not": "year > 2000" },
+            [{ "not": "genre = horror OR genre = drama" }, "rating > 3"],
+            [bounding_box, { "not": "genre = action" }],
+            { "other": "genre = action" },
+        ]))
+        .unwrap();
+        assert_eq!(
+            filter.milli_filter,
+            json!([
+                "NOT (year > 2000)",
+                ["NOT (genre = horror OR genre = drama)", "rating > 3"],
+                [geo_radius, "NOT (genre = action)"],
+                { "other": "genre = action" },
+            ])
+        );
+        // the negations are also checked with the bounding boxes of their OR group.
+        assert_eq!(
+            filter.bounding_boxes[0].others,
+            vec!["NOT (genre = action)"]
         );
 
-        let mut matching_words = BTreeMap::new();
-        matching_words.insert("hobbit", Some(3));
+        let error = parse_filter(&json!([{ "other": "genre = action" }])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid syntax for the filter parameter: `expected String, [String], {\"not\": String}, found: {\"other\":\"genre = action\"}`."
+        );
+    }
 
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
-        .unwrap();
+    #[test]
+    fn test_check_facets() {
+        let filterable: HashSet<_> = ["genre", "author"].iter().map(|s| s.to_string()).collect();
+        let query = |facets: &[&str]| {
+            SearchQuery::builder()
+                .facets_distribution(facets.iter().copied())
+                .build()
+        };
 
-        assert_eq!(value["title"], "The <em>Hob</em>bit");
-        assert_eq!(value["author"], "J. R. R. Tolkien");
+        assert!(check_facets(&query(&["genre"]), &filterable).is_ok());
+        assert!(check_facets(&query(&["*"]), &filterable).is_ok());
+        // the fields nested in a filterable attribute are valid facets.
+        assert!(check_facets(&query(&["author.name"]), &filterable).is_ok());
+        assert!(check_facets(&SearchQuery::default(), &filterable).is_ok());
+
+        match check_facets(&query(&["genre", "title", "authorship"]), &filterable) {
+            Err(IndexError::Facet(FacetError::InvalidFacetsDistribution(invalid, valid))) => {
+                assert_eq!(invalid, ["title", "authorship"]);
+                assert_eq!(valid, ["author", "genre"]);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 
     #[test]
-    fn formatted_with_highlight_in_number() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
+    fn test_explain_filter() {
+        let bounding_box = "_geoBoundingBox([50.7, 3.1], [50.5, 2.9])";
+        let explanation = explain_filter(&json!([
+            " genre = action ",
+            ["year > 2000", { "not": "rating < 3 OR rating > 8" }],
+            ["director = Jackson"],
+            bounding_box,
+        ]))
+        .unwrap();
+        assert_eq!(
+            explanation,
+            format!(
+                "genre = action AND (year > 2000 OR NOT (rating < 3 OR rating > 8)) \
+                 AND director = Jackson AND {}",
+                bounding_box
+            )
         );
 
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
-        let publication_year = fields.insert("publication_year").unwrap();
-
-        let document: serde_json::Value = json!({
-            "title": "The Hobbit",
-            "author": "J. R. R. Tolkien",
-            "publication_year": 1937,
-        });
+        let explanation = explain_filter(&json!("genre = action AND year > 2000")).unwrap();
+        assert_eq!(explanation, "genre = action AND year > 2000");
+        assert_eq!(explain_filter(&json!([])).unwrap(), "");
+    }
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+    #[test]
+    fn test_explain_filter_invalid_expressions() {
+        let invalid_expression = |filter: Value| match explain_filter(&filter) {
+            Err(IndexError::Facet(FacetError::InvalidExpression(expected, value))) => {
+                (expected, value)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        };
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        assert_eq!(invalid_expression(json!(42)), (&["Array"][..], json!(42)));
+        assert_eq!(
+            invalid_expression(json!({ "not": "genre = action" })),
+            (&["Array"][..], json!({ "not": "genre = action" }))
         );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        assert_eq!(
+            invalid_expression(json!(["genre = action", true])),
+            (
+                &["String", "[String]", "{\"not\": String}"][..],
+                json!(true)
+            )
         );
-        formatted_options.insert(
-            publication_year,
-            FormatOptions {
-                highlight: true,
-                crop: None,
-            },
+        assert_eq!(
+            invalid_expression(json!([["genre = action", ["year > 2000"]]])),
+            (&["String", "{\"not\": String}"][..], json!(["year > 2000"]))
+        );
+        assert_eq!(
+            invalid_expression(json!([{ "other": "genre = action" }])),
+            (
+                &["String", "[String]", "{\"not\": String}"][..],
+                json!({ "other": "genre = action" })
+            )
         );
+    }
 
-        let mut matching_words = BTreeMap::new();
-        matching_words.insert("1937", Some(4));
+    #[test]
+    fn document_id_keys_order_integers_before_strings() {
+        let keys: Vec<_> = [json!(12), json!(3), json!("b"), json!("a"), Value::Null]
+            .iter()
+            .map(|id| {
+                let mut writer = obkv::KvWriterU16::memory();
+                writer.insert(0, serde_json::to_vec(id).unwrap()).unwrap();
+                let bytes = writer.into_inner().unwrap();
+                DocumentIdKey::new(&obkv::KvReaderU16::new(&bytes), 0)
+            })
+            .collect();
 
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
-        .unwrap();
+        let mut sorted = keys.iter().collect::<Vec<_>>();
+        sorted.sort();
+        assert_eq!(
+            sorted,
+            [
+                &DocumentIdKey::Integer(3),
+                &DocumentIdKey::Integer(12),
+                &DocumentIdKey::String(String::from("a")),
+                &DocumentIdKey::String(String::from("b")),
+                &DocumentIdKey::Missing,
+            ]
+        );
+    }
 
-        assert_eq!(value["title"], "The Hobbit");
-        assert_eq!(value["author"], "J. R. R. Tolkien");
-        assert_eq!(value["publication_year"], "<em>1937</em>");
+    #[test]
+    fn ties_overlapping_the_page_are_sorted_by_id() {
+        // (tie key, id)
+        let documents = [
+            ('a', 9),
+            ('a', 2),
+            ('b', 7),
+            ('b', 4),
+            ('b', 1),
+            ('c', 6),
+            ('c', 3),
+            ('d', 8),
+            ('d', 5),
+        ];
+        let sort = |page: Range<usize>| {
+            let mut documents = documents;
+            sort_ties_by_id(
+                &mut documents,
+                page,
+                |(key, _)| Ok(*key),
+                |(_, id)| DocumentIdKey::Integer(*id),
+            )
+            .unwrap();
+            documents.iter().map(|(_, id)| *id).collect::<Vec<_>>()
+        };
+
+        // the runs overlapping the page are sorted from their first to their last document.
+        assert_eq!(sort(3..6), [9, 2, 1, 4, 7, 3, 6, 8, 5]);
+        assert_eq!(sort(0..1), [2, 9, 7, 4, 1, 6, 3, 8, 5]);
+        assert_eq!(sort(8..20), [9, 2, 7, 4, 1, 6, 3, 5, 8]);
+        assert_eq!(sort(0..9), [2, 9, 1, 4, 7, 3, 6, 5, 8]);
+        // an empty page or a page past the documents sorts nothing.
+        assert_eq!(sort(4..4), [9, 2, 7, 4, 1, 6, 3, 8, 5]);
+        assert_eq!(sort(12..15), [9, 2, 7, 4, 1, 6, 3, 8, 5]);
     }
 
-    /// https://github.com/meilisearch/meilisearch/issues/1368
     #[test]
-    fn formatted_with_highlight_emoji() {
+    fn serialized_size_matches_the_serialization() {
+        let document: Document = json!({
+            "id": 1,
+            "title": "Le Petit Prince",
+            "overview": "Un aviateur échoué dans le désert rencontre un petit garçon 🌹",
+            "tags": ["conte", "aviation"],
+        })
+        .as_object()
+        .unwrap()
+        .clone()
+        .into_iter()
+        .collect();
+
+        let size = serialized_size(&document).unwrap();
+        assert_eq!(size, serde_json::to_vec(&document).unwrap().len());
+    }
+
+    #[test]
+    fn typo_tolerance_overrides_only_enabled() {
+        let disabled = TypoSettings {
+            enabled: Setting::Set(false),
+            ..Default::default()
+        };
+        assert!(check_typo_tolerance(&disabled).is_ok());
+        assert!(check_typo_tolerance(&TypoSettings::default()).is_ok());
+
+        let words = TypoSettings {
+            disable_on_words: Setting::Set(std::iter::once("sku".to_string()).collect()),
+            ..Default::default()
+        };
+        match check_typo_tolerance(&words) {
+            Err(IndexError::Search(SearchError::TypoToleranceOverride(setting))) => {
+                assert_eq!(setting, "disableOnWords")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn formatted_with_case_and_diacritic_sensitive_highlight() {
         let stop_words = fst::Set::default();
         let mut config = AnalyzerConfig::default();
         config.stop_words(&stop_words);
         let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
-        );
-
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
-
-        let document: serde_json::Value = json!({
-            "title": "Go💼od luck.",
-            "author": "JacobLey",
-        });
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("etoile", Some(7));
+
+        let highlight = |q: &str, case: bool, diacritics: bool| {
+            let formatter = Formatter::new(
+                &analyzer,
+                (String::from("<em>"), String::from("</em>")),
+                (String::from("…"), String::from("…")),
+            )
+            .with_sensitivity(HighlightSensitivity::new(q, &analyzer, case, diacritics));
+            let text = "étoile Etoile etoile";
+            let (value, _) =
+                formatter.format_string(text.to_string(), &matching_words, format_options, None);
+            value
+        };
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: true,
-                crop: None,
-            },
+        assert_eq!(
+            highlight("etoile", false, false),
+            "<em>étoile</em> <em>Etoile</em> <em>etoile</em>"
         );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        assert_eq!(
+            highlight("etoile", false, true),
+            "étoile <em>Etoile</em> <em>etoile</em>"
+        );
+        assert_eq!(
+            highlight("étoile", false, true),
+            "<em>étoile</em> Etoile etoile"
+        );
+        assert_eq!(
+            highlight("etoile", true, false),
+            "<em>étoile</em> Etoile <em>etoile</em>"
+        );
+        assert_eq!(
+            highlight("etoile", true, true),
+            "étoile Etoile <em>etoile</em>"
         );
+    }
 
-        let mut matching_words = BTreeMap::new();
-        // emojis are deunicoded during tokenization
-        // TODO Tokenizer should remove spaces after deunicode
-        matching_words.insert("gobriefcase od", Some(11));
+    #[test]
+    fn sensitive_forms() {
+        assert_eq!(sensitive_form("Étoile", "etoile", true, false), "Etoile");
+        assert_eq!(sensitive_form("Étoile", "etoile", false, true), "étoile");
+        assert_eq!(sensitive_form("Étoile", "etoile", true, true), "Étoile");
+        // the characters can't be aligned, the case is lost.
+        assert_eq!(sensitive_form("Straße", "strasse", true, false), "strasse");
+    }
 
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
-        .unwrap();
+    #[test]
+    fn warnings_of_ineffective_parameters() {
+        assert!(search_warnings(&SearchQuery::default(), &[]).is_empty());
+
+        let query = SearchQuery {
+            crop_length: Some(5),
+            show_facet_stats: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            search_warnings(&query, &[("attributesToCrop", "secret")]),
+            [
+                "Attribute `secret` of `attributesToCrop` does not exist or is not displayed, it is ignored.",
+                "`cropLength` is ignored because `attributesToCrop` is not set.",
+                "`showFacetStats` is ignored because `facetsDistribution` is not set.",
+            ]
+        );
 
-        assert_eq!(value["title"], "<em>Go💼od</em> luck.");
-        assert_eq!(value["author"], "JacobLey");
+        let query = SearchQuery {
+            crop_length: Some(5),
+            attributes_to_crop: Some(vec![AttributeToCrop::Name(String::from("title"))]),
+            ..Default::default()
+        };
+        assert!(search_warnings(&query, &[]).is_empty());
     }
 
     #[test]
-    fn formatted_with_highlight_in_unicode_word() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
+    fn test_search_filter_with_geo_distance() {
+        let filter = SearchFilter::new(&json!("_geoDistance <= 1000.5")).unwrap();
+        assert_eq!(filter.milli_filter, json!([]));
+        assert_eq!(
+            filter.distance_bounds,
+            vec![GeoDistanceBound {
+                operator: GeoDistanceOperator::LowerOrEqual,
+                distance: 1000.5,
+            }]
         );
 
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
+        let filter = SearchFilter::new(&json!([
+            "genre = action",
+            "_geoDistance > 10",
+            "_geoDistance<50"
+        ]))
+        .unwrap();
+        assert_eq!(filter.milli_filter, json!(["genre = action"]));
+        assert_eq!(filter.distance_bounds.len(), 2);
+        assert!(!filter.distance_bounds[0].contains(10.));
+        assert!(filter.distance_bounds[0].contains(11.));
+        assert!(filter.distance_bounds[1].contains(49.));
+        assert!(!filter.distance_bounds[1].contains(50.));
+
+        for filter in [
+            json!("_geoDistance = 10"),
+            json!("_geoDistance < ten"),
+            json!("genre = action AND _geoDistance < 10"),
+            json!([{ "not": "_geoDistance < 10" }]),
+            json!([["genre = action", "_geoDistance < 10"]]),
+        ] {
+            let error = SearchFilter::new(&filter).unwrap_err();
+            assert!(error.to_string().contains("_geoDistance"), "{}", error);
+        }
+    }
 
-        let document: serde_json::Value = json!({
-            "title": "étoile",
-            "author": "J. R. R. Tolkien",
-        });
+    #[test]
+    fn cursor_keys_order_by_sort_values_then_id() {
+        let sort = [
+            AscDesc::Desc(Member::Field("rank".to_string())),
+            AscDesc::Asc(Member::Field("title".to_string())),
+        ];
+        let key = |rank: Value, title: Value, id: i64| CursorKey {
+            score: None,
+            values: vec![SortValue::new(rank, true), SortValue::new(title, false)],
+            id: DocumentIdKey::Integer(id),
+        };
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+        let mut keys = vec![
+            key(json!(null), json!("a"), 1),
+            key(json!(2), json!("B"), 2),
+            key(json!("high"), json!("a"), 3),
+            key(json!([1, 3]), json!("c"), 4),
+            key(json!(2), json!("a"), 5),
+            key(json!(2), json!("b"), 1),
+        ];
+        keys.sort_by(|a, b| a.cmp_by(b, &sort));
+        let ids: Vec<_> = keys
+            .iter()
+            .map(|key| match key.id {
+                DocumentIdKey::Integer(id) => id,
+                _ => unreachable!(),
+            })
             .collect();
+        // the numbers come first in both directions, then the strings and the missing values.
+        assert_eq!(ids, vec![4, 5, 1, 2, 3, 1]);
+
+        // the highest ranking scores come first in a keyword search.
+        let mut low = key(json!(3), json!("a"), 1);
+        low.score = Some(0.5);
+        let mut high = key(json!(1), json!("a"), 2);
+        high.score = Some(0.75);
+        assert_eq!(high.cmp_by(&low, &sort), Ordering::Less);
+    }
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: true,
-                crop: None,
-            },
+    #[test]
+    fn cursor_round_trip() {
+        let query = SearchQuery {
+            sort: Some(vec!["rank:desc".to_string()]),
+            ..SearchQuery::default()
+        };
+        let cursor = SearchCursor {
+            sort: vec![AscDesc::Desc(Member::Field("rank".to_string()))],
+            raw_sort: vec!["rank:desc".to_string()],
+            after: None,
+        };
+        let key = CursorKey {
+            score: None,
+            values: vec![SortValue::Number(2.5)],
+            id: DocumentIdKey::String("abc".to_string()),
+        };
+        let next = cursor.next(key).unwrap();
+        let (sort, after): (Vec<String>, CursorKey) =
+            serde_json::from_slice(&decode_hex(&next).unwrap()).unwrap();
+        assert_eq!(sort, query.sort.unwrap());
+        assert_eq!(after.values, vec![SortValue::Number(2.5)]);
+        assert_eq!(after.id, DocumentIdKey::String("abc".to_string()));
+
+        assert_eq!(decode_hex("0a1"), None);
+        assert_eq!(decode_hex("zz"), None);
+        let query = SearchQuery::default();
+        assert!(SearchCursor::new(&query, "7b7d").is_err());
+        assert!(SearchCursor::new(&query, &next).is_err());
+
+        // the cursor of a keyword search holds the ranking score of its last hit.
+        let query = SearchQuery {
+            q: Some("hobbit".to_string()),
+            sort: Some(vec!["rank:desc".to_string()]),
+            ..SearchQuery::default()
+        };
+        assert!(SearchCursor::new(&query, "").is_ok());
+        assert!(SearchCursor::new(&query, &next).is_err());
+        let key = CursorKey {
+            score: Some(0.75),
+            values: vec![SortValue::Number(2.5)],
+            id: DocumentIdKey::Integer(3),
+        };
+        let next = cursor.next(key).unwrap();
+        let after = SearchCursor::new(&query, &next).unwrap().after.unwrap();
+        assert_eq!(after.score, Some(0.75));
+    }
+
+    #[test]
+    fn facet_values_are_ranked_by_match_then_count() {
+        let values: BTreeMap<_, _> = [
+            ("Samsung", 3),
+            ("Sony", 8),
+            ("Sonos", 2),
+            ("Bose Sound", 5),
+            ("Parasonic", 9),
+            ("Apple", 12),
+        ]
+        .iter()
+        .map(|(value, count)| (value.to_string(), *count))
+        .collect();
+
+        let hit = |value: &str, count| FacetValueHit {
+            value: value.to_string(),
+            count,
+        };
+        assert_eq!(
+            rank_facet_values(values.clone(), "so", 10, &ANALYZER),
+            vec![
+                hit("Sony", 8),
+                hit("Sonos", 2),
+                hit("Bose Sound", 5),
+                hit("Parasonic", 9),
+            ]
         );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
+        assert_eq!(
+            rank_facet_values(values.clone(), " SÖN ", 1, &ANALYZER),
+            vec![hit("Sony", 8)]
+        );
+        assert_eq!(rank_facet_values(values.clone(), "", 2, &ANALYZER).len(), 2);
+        assert!(rank_facet_values(values, "lg", 10, &ANALYZER).is_empty());
+    }
+
+    #[test]
+    fn search_facet_values_over_an_index() {
+        let (_dir, index) = index_with_documents(
+            json!([
+                { "id": 1, "brand": "Sony", "price": 300 },
+                { "id": 2, "brand": "Sony", "price": 50 },
+                { "id": 3, "brand": "Sonos", "price": 400 },
+                { "id": 4, "brand": "Samsung", "price": 200 },
+                { "id": 5, "brand": ["Bose", "Sonos"], "price": 100 },
+            ]),
+            Settings {
+                filterable_attributes: Setting::Set(
+                    ["brand", "price"].iter().map(|s| s.to_string()).collect(),
+                ),
+                ..Default::default()
             },
         );
 
-        let mut matching_words = BTreeMap::new();
-        matching_words.insert("etoile", Some(1));
+        let hits = index.search_facet_values("brand", "so", 10, None).unwrap();
+        let hits: Vec<_> = hits
+            .iter()
+            .map(|hit| (hit.value.as_str(), hit.count))
+            .collect();
+        assert_eq!(hits, vec![("Sonos", 2), ("Sony", 2)]);
 
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
+        let filter = json!("price >= 100");
+        let hits = index
+            .search_facet_values("brand", "so", 10, Some(&filter))
+            .unwrap();
+        let hits: Vec<_> = hits
+            .iter()
+            .map(|hit| (hit.value.as_str(), hit.count))
+            .collect();
+        assert_eq!(hits, vec![("Sonos", 2), ("Sony", 1)]);
+
+        let error = index.search_facet_values("id", "1", 10, None).unwrap_err();
+        assert!(matches!(
+            error,
+            IndexError::Facet(FacetError::InvalidFacetSearch(_, _))
+        ));
+    }
+
+    #[test]
+    fn geojson_feature_of_hits() {
+        let feature = geojson_feature(json!({
+            "id": 1,
+            "name": "Lille",
+            "_geo": { "lat": 50.6292, "lng": "3.0573" },
+            "_formatted": { "name": "<em>Lille</em>" },
+        }))
         .unwrap();
+        assert_eq!(
+            feature,
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [3.0573, 50.6292] },
+                "properties": {
+                    "id": 1,
+                    "name": "Lille",
+                    "_formatted": { "name": "<em>Lille</em>" },
+                },
+            })
+        );
 
-        assert_eq!(value["title"], "<em>étoile</em>");
-        assert_eq!(value["author"], "J. R. R. Tolkien");
+        assert_eq!(geojson_feature(json!({ "id": 2 })), None);
+        assert_eq!(
+            geojson_feature(json!({ "id": 3, "_geo": { "lat": 50.6292 } })),
+            None
+        );
     }
 
     #[test]
-    fn formatted_with_crop_2() {
+    fn formatted_with_max_highlights() {
         let stop_words = fst::Set::default();
         let mut config = AnalyzerConfig::default();
         config.stop_words(&stop_words);
         let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
-        );
-
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
 
-        let document: serde_json::Value = json!({
-            "title": "Harry Potter and the Half-Blood Prince",
-            "author": "J. K. Rowling",
-        });
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+        let format_options = FormatOptions {
+            highlight: true,
+            crop: None,
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+        let text = "hobbit one hobbit two hobbit three hobbit four hobbit";
+        let highlight = |max_highlights| {
+            let formatter = Formatter::new(
+                &analyzer,
+                (String::from("<em>"), String::from("</em>")),
+                (String::from("…"), String::from("…")),
+            )
+            .with_max_highlights(max_highlights);
+            let mut matches = Vec::new();
+            let (value, _) = formatter.format_string(
+                text.to_string(),
+                &matching_words,
+                format_options,
+                Some(&mut matches),
+            );
+            (value, matches.len())
+        };
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: false,
-                crop: Some(2),
-            },
-        );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        let (value, matches) = highlight(Some(2));
+        assert_eq!(
+            value,
+            "<em>hobbit</em> one <em>hobbit</em> two hobbit three hobbit four hobbit"
         );
+        assert_eq!(value.matches("<em>").count(), 2);
+        // the matches left unhighlighted are still computed.
+        assert_eq!(matches, 5);
+
+        let (value, _) = highlight(None);
+        assert_eq!(value.matches("<em>").count(), 5);
+        let (value, _) = highlight(Some(0));
+        assert_eq!(value, text);
+    }
 
-        let mut matching_words = BTreeMap::new();
-        matching_words.insert("potter", Some(3));
-
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
-        .unwrap();
+    #[test]
+    fn matched_words_are_counted_once() {
+        let analyzer = Analyzer::new(AnalyzerConfig::<Vec<u8>>::default());
+        let mut writer = obkv::KvWriterU16::memory();
+        writer
+            .insert(0, serde_json::to_vec(&json!("the hobbit")).unwrap())
+            .unwrap();
+        writer
+            .insert(
+                1,
+                serde_json::to_vec(&json!(["a hobbit", "a dragon"])).unwrap(),
+            )
+            .unwrap();
+        let bytes = writer.into_inner().unwrap();
+        let obkv = obkv::KvReaderU16::new(&bytes);
 
-        assert_eq!(value["title"], "Harry Potter…");
-        assert_eq!(value["author"], "J. K. Rowling");
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+        matching_words.insert("dragon", Some(6));
+        matching_words.insert("wizard", Some(6));
+
+        let words = typo_words("hobbit dragon wizard", &analyzer);
+        let count = count_matched_words(&obkv, &words, &matching_words, &analyzer).unwrap();
+        assert_eq!(count, 2);
+
+        // a query word repeated in the query still counts once.
+        let words = typo_words("hobbit hobbit", &analyzer);
+        let count = count_matched_words(&obkv, &words, &matching_words, &analyzer).unwrap();
+        assert_eq!(count, 1);
     }
 
     #[test]
-    fn formatted_with_crop_5() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
+    fn ranking_score_follows_the_ranking_rules() {
+        let analyzer = Analyzer::new(AnalyzerConfig::<Vec<u8>>::default());
+        let document = |title: &str| {
+            let mut writer = obkv::KvWriterU16::memory();
+            writer
+                .insert(0, serde_json::to_vec(&json!(title)).unwrap())
+                .unwrap();
+            writer.into_inner().unwrap()
+        };
+        let exact = document("the hobbit dragon");
+        let typo = document("the hobit dragon");
+        let shuffled_typo = document("dragon and the hobit");
+        let one_word = document("the dragon");
+
+        let mut matching_words = BTreeMap::new();
+        matching_words.insert("hobbit", Some(6));
+        matching_words.insert("hobit", Some(5));
+        matching_words.insert("dragon", Some(6));
+
+        let criteria = [
+            Criterion::Words,
+            Criterion::Typo,
+            Criterion::Proximity,
+            Criterion::Attribute,
+            Criterion::Sort,
+            Criterion::Exactness,
+        ];
+        let words = typo_words("hobbit dragon", &analyzer);
+        let score = |bytes: &Vec<u8>, criteria: &[Criterion], words: &[(String, bool)]| {
+            let obkv = obkv::KvReaderU16::new(bytes);
+            ranking_score(&obkv, criteria, words, &matching_words, &analyzer).unwrap()
+        };
+
+        assert_eq!(score(&exact, &criteria, &words), 1.0);
+        assert!(score(&exact, &criteria, &words) > score(&typo, &criteria, &words));
+        assert!(score(&typo, &criteria, &words) > score(&one_word, &criteria, &words));
+        // the proximity rule isn't evaluated, the documents tie on their score.
+        assert_eq!(
+            score(&typo, &criteria, &words),
+            score(&shuffled_typo, &criteria, &words)
         );
+        // a typo costs less than a missing word.
+        assert!(score(&typo, &criteria, &words) > 1.0 - 1.0 / 3.0);
+        assert_eq!(score(&one_word, &criteria, &words), 1.0 - 1.0 / 3.0);
 
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
+        // the evaluation stops at the first sort rule.
+        let sort_first = [Criterion::Sort, Criterion::Words];
+        assert_eq!(score(&one_word, &sort_first, &words), 1.0);
 
-        let document: serde_json::Value = json!({
-            "title": "Harry Potter and the Half-Blood Prince",
-            "author": "J. K. Rowling",
-        });
+        // a placeholder search has nothing to rank.
+        assert_eq!(score(&one_word, &[Criterion::Words], &[]), 1.0);
+    }
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+    #[test]
+    fn query_with_template_keeps_the_set_parameters() {
+        let template = SearchQuery {
+            q: Some("template".to_string()),
+            limit: Some(5),
+            attributes_to_retrieve: Some(IndexSet::from(["title".to_string()])),
+            attributes_to_highlight: Some(vec![AttributeToHighlight::Name("title".to_string())]),
+            filter: Some(json!("genre = horror")),
+            show_typos: Some(true),
+            highlight_numbers: Some(false),
+            ..Default::default()
+        };
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: false,
-                crop: Some(5),
-            },
+        let query = SearchQuery {
+            q: Some("query".to_string()),
+            attributes_to_retrieve: Some(IndexSet::from(["id".to_string(), "name".to_string()])),
+            filter: Some(json!(["year > 2000", ["color = red", "color = blue"]])),
+            ..Default::default()
+        }
+        .with_template(&template);
+
+        assert_eq!(query.q.as_deref(), Some("query"));
+        assert_eq!(
+            query.attributes_to_retrieve,
+            Some(IndexSet::from(["id".to_string(), "name".to_string()]))
         );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        assert_eq!(query.limit, Some(5));
+        assert_eq!(
+            query.attributes_to_highlight,
+            template.attributes_to_highlight
+        );
+        assert_eq!(query.show_typos, Some(true));
+        assert_eq!(
+            query.filter,
+            Some(json!([
+                "year > 2000",
+                ["color = red", "color = blue"],
+                "genre = horror"
+            ]))
         );
 
-        let mut matching_words = BTreeMap::new();
-        matching_words.insert("potter", Some(5));
+        let query = SearchQuery::default().with_template(&template);
+        assert_eq!(query.filter, template.filter);
+        let query = SearchQuery {
+            filter: Some(json!("year > 2000")),
+            ..Default::default()
+        }
+        .with_template(&SearchQuery::default());
+        assert_eq!(query.filter, Some(json!("year > 2000")));
 
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
+        // the parameters explicitly set to their default value are kept.
+        let query: SearchQuery = serde_json::from_value(
+            json!({ "limit": 20, "showTypos": false, "highlightNumbers": true }),
         )
         .unwrap();
-
-        assert_eq!(value["title"], "Harry Potter and the Half…");
-        assert_eq!(value["author"], "J. K. Rowling");
+        let query = query.with_template(&template);
+        assert_eq!(query.limit, Some(20));
+        assert_eq!(query.show_typos, Some(false));
+        assert_eq!(query.highlight_numbers, Some(true));
     }
 
     #[test]
-    fn formatted_with_crop_0() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
-        );
+    fn query_with_template_replaces_the_whole_pagination() {
+        let template = SearchQuery {
+            offset: Some(10),
+            limit: Some(5),
+            cursor: Some(String::new()),
+            ..Default::default()
+        };
 
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
+        let query = SearchQuery::default().with_template(&template);
+        assert_eq!((query.offset, query.limit), (Some(10), Some(5)));
+        assert_eq!(query.cursor, None);
 
-        let document: serde_json::Value = json!({
-            "title": "Harry Potter and the Half-Blood Prince",
-            "author": "J. K. Rowling",
-        });
+        // the page of the query isn't mixed with the offset and limit of the template.
+        let query = SearchQuery {
+            page: Some(2),
+            hits_per_page: Some(3),
+            ..Default::default()
+        }
+        .with_template(&template);
+        assert_eq!((query.offset, query.limit), (None, None));
+        assert_eq!((query.page, query.hits_per_page), (Some(2), Some(3)));
+        assert!(query.offset_and_limit(1000).is_ok());
+
+        let query = SearchQuery {
+            limit: Some(20),
+            ..Default::default()
+        }
+        .with_template(&template);
+        assert_eq!((query.offset, query.limit), (None, Some(20)));
+    }
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+    #[test]
+    fn formatted_fields_selected_by_regex() {
+        let mut fields = FieldsIdsMap::new();
+        let price_us = fields.insert("price_US").unwrap();
+        let price_eu = fields.insert("price_EU").unwrap();
+        let price_jp = fields.insert("price_JP").unwrap();
+        let _old_price = fields.insert("old_price_US").unwrap();
+        let displayed_ids: BTreeSet<_> = fields.iter().map(|(id, _)| id).collect();
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: false,
-                crop: Some(0),
-            },
+        assert_eq!(
+            formatted_fields_ids("re:^price_", &fields, &displayed_ids),
+            vec![price_us, price_eu, price_jp]
         );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        let hidden: BTreeSet<_> = [price_us, price_jp].into_iter().collect();
+        assert_eq!(
+            formatted_fields_ids("re:^price_", &fields, &hidden),
+            vec![price_us, price_jp]
         );
+        assert!(formatted_fields_ids("re:^price_(", &fields, &displayed_ids).is_empty());
 
-        let mut matching_words = BTreeMap::new();
-        matching_words.insert("potter", Some(6));
-
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
-        .unwrap();
-
-        assert_eq!(value["title"], "Harry Potter and the Half-Blood Prince");
-        assert_eq!(value["author"], "J. K. Rowling");
+        let crop = |attr: &str| AttributeToCrop::Name(attr.to_string());
+        assert_eq!(crop("re:^price_").name_and_length(10), ("re:^price_", 10));
+        assert_eq!(crop("re:^price_:5").name_and_length(10), ("re:^price_", 5));
+        assert_eq!(
+            crop("re:(?i:price)").name_and_length(10),
+            ("re:(?i:price)", 10)
+        );
     }
 
     #[test]
-    fn formatted_with_crop_and_no_match() {
-        let stop_words = fst::Set::default();
+    fn check_attribute_regexes_rejects_invalid_and_oversized_regexes() {
+        let query = |value: Value| -> SearchQuery { serde_json::from_value(value).unwrap() };
+
+        let valid = query(json!({
+            "attributesToRetrieve": ["-re:^price_", "title"],
+            "attributesToCrop": ["re:^description_:5"],
+        }));
+        assert!(check_attribute_regexes(&valid).is_ok());
+
+        let oversized = format!("re:{}", "(a|b|c){1000}".repeat(10));
+        for invalid in [
+            json!({ "attributesToRetrieve": ["re:price_("] }),
+            json!({ "attributesToHighlight": [oversized] }),
+        ] {
+            assert!(matches!(
+                check_attribute_regexes(&query(invalid)),
+                Err(IndexError::Search(SearchError::InvalidAttributeRegex(_, _)))
+            ));
+        }
+    }
+
+    #[test]
+    fn normalized_query_removes_stop_words() {
+        assert_eq!(
+            normalized_query("The Étoile", &ANALYZER),
+            vec!["the".to_string(), "etoile".to_string()]
+        );
+
+        let stop_words = fst::Set::from_iter(["the"]).unwrap();
         let mut config = AnalyzerConfig::default();
         config.stop_words(&stop_words);
         let analyzer = Analyzer::new(config);
+        assert_eq!(
+            normalized_query("The Étoile", &analyzer),
+            vec!["etoile".to_string()]
+        );
+        assert!(normalized_query("", &analyzer).is_empty());
+    }
+
+    #[test]
+    fn formatted_cropped_number_stays_a_number() {
         let formatter = Formatter::new(
-            &analyzer,
+            &ANALYZER,
             (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
+            (String::from("…"), String::from("…")),
         );
 
         let mut fields = FieldsIdsMap::new();
+        let id = fields.insert("id").unwrap();
         let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
+        let publication_year = fields.insert("publication_year").unwrap();
+        let price = fields.insert("price").unwrap();
 
-        let document: serde_json::Value = json!({
-            "title": "Harry Potter and the Half-Blood Prince",
-            "author": "J. K. Rowling",
-        });
+        let mut document: Document = serde_json::from_value(json!({
+            "id": 1,
+            "title": "The Hobbit",
+            "publication_year": 1937,
+            "price": 12.5,
+        }))
+        .unwrap();
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
+        let crop = FormatOptions {
+            highlight: false,
+            crop: Some(10),
+            highlight_tags: None,
+            crop_on_sentences: false,
+        };
+        let mut formatted_options: BTreeMap<_, _> = [title, publication_year, price]
             .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+            .map(|id| (id, crop))
             .collect();
-
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: false,
-                crop: Some(1),
-            },
-        );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: Some(20),
-            },
-        );
+        // only retrieved.
+        formatted_options.insert(id, FormatOptions { crop: None, ..crop });
 
         let mut matching_words = BTreeMap::new();
-        matching_words.insert("rowling", Some(3));
+        matching_words.insert("hobbit", Some(6));
 
-        let value = format_fields(
-            &document,
+        let (value, _) = format_fields(
+            &mut document,
             &fields,
             &formatter,
             &matching_words,
             &formatted_options,
+            None,
         )
         .unwrap();
 
-        assert_eq!(value["title"], "Harry…");
-        assert_eq!(value["author"], "J. K. Rowling");
+        assert_eq!(value["title"], "The Hobbit");
+        assert_eq!(value["publication_year"], json!(1937));
+        assert_eq!(value["price"], json!(12.5));
+        assert_eq!(value["id"], json!("1"));
     }
 
     #[test]
-    fn formatted_with_crop_and_highlight() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-        let formatter = Formatter::new(
-            &analyzer,
-            (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
+    fn multi_search_reports_errors_per_query() {
+        let documents: Vec<_> = (0..10)
+            .map(|id| json!({ "id": id, "genre": if id % 2 == 0 { "fantasy" } else { "horror" }, "title": "the hobbit" }))
+            .collect();
+        let (_dir, index) = index_with_documents(
+            json!(documents),
+            Settings {
+                filterable_attributes: Setting::Set(std::iter::once("genre".to_string()).collect()),
+                ..Default::default()
+            },
         );
 
-        let mut fields = FieldsIdsMap::new();
-        let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
+        let query = |value: Value| -> SearchQuery { serde_json::from_value(value).unwrap() };
+        let queries = vec![
+            query(json!({ "q": "hobbit", "limit": 3 })),
+            query(json!({ "q": "hobbit", "page": 0 })),
+            query(json!({ "filter": "genre = horror" })),
+            query(json!({ "facetsDistribution": ["title"] })),
+        ];
 
-        let document: serde_json::Value = json!({
-            "title": "Harry Potter and the Half-Blood Prince",
-            "author": "J. K. Rowling",
-        });
+        let results = index.perform_multi_search(queries.clone()).unwrap();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(
+            results[1],
+            Err(IndexError::Search(SearchError::InvalidPage))
+        ));
+        assert!(matches!(
+            results[3],
+            Err(IndexError::Facet(FacetError::InvalidFacetsDistribution(
+                _,
+                _
+            )))
+        ));
+
+        for (query, result) in queries.into_iter().zip(results) {
+            match (index.perform_search(query), result) {
+                (Ok(expected), Ok(result)) => {
+                    assert_eq!(result.hits, expected.hits);
+                    assert_eq!(result.nb_hits, expected.nb_hits);
+                }
+                (Err(expected), Err(error)) => assert_eq!(error.to_string(), expected.to_string()),
+                (expected, result) => panic!("expected {:?}, got {:?}", expected, result),
+            }
+        }
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+        let results = index.perform_multi_search(vec![]).unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn ranking_rules_override_reorders_hits() {
+        let documents: Vec<_> = (0..10)
+            .map(|id| json!({ "id": id, "rank": (id * 3) % 10, "title": "the hobbit" }))
             .collect();
+        let (_dir, index) = index_with_documents(json!(documents), Settings::default());
+
+        let ranks = |rules: &[&str]| -> Vec<Value> {
+            let query = SearchQuery::builder()
+                .q("hobbit")
+                .ranking_rules(rules.iter().copied())
+                .build();
+            let result = index.perform_search(query).unwrap();
+            result
+                .hits
+                .iter()
+                .map(|hit| hit.document["rank"].clone())
+                .collect()
+        };
 
-        let mut formatted_options = BTreeMap::new();
-        formatted_options.insert(
-            title,
-            FormatOptions {
-                highlight: true,
-                crop: Some(1),
-            },
+        assert_eq!(
+            ranks(&["words", "rank:desc"]),
+            (0..10).rev().map(|rank| json!(rank)).collect::<Vec<_>>()
         );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
-                crop: None,
-            },
+        assert_eq!(
+            ranks(&["rank:asc"]),
+            (0..10).map(|rank| json!(rank)).collect::<Vec<_>>()
         );
 
-        let mut matching_words = BTreeMap::new();
-        matching_words.insert("and", Some(3));
-
-        let value = format_fields(
-            &document,
-            &fields,
-            &formatter,
-            &matching_words,
-            &formatted_options,
-        )
-        .unwrap();
+        // the ranking rules of the index are left untouched.
+        let rtxn = index.read_txn().unwrap();
+        assert!(!index
+            .criteria(&rtxn)
+            .unwrap()
+            .iter()
+            .any(|criterion| matches!(criterion, Criterion::Asc(_) | Criterion::Desc(_))));
+
+        let query = SearchQuery::builder().ranking_rules(["wrods"]).build();
+        assert!(matches!(
+            index.perform_search(query),
+            Err(IndexError::Milli(milli::Error::UserError(
+                milli::UserError::CriterionError(_)
+            )))
+        ));
+    }
 
-        assert_eq!(value["title"], "…<em>and</em>…");
-        assert_eq!(value["author"], "J. K. Rowling");
+    #[test]
+    fn glob_patterns_match_facet_names() {
+        assert!(glob_matches("attributes.*", "attributes.color"));
+        assert!(glob_matches("attributes.*", "attributes.size.eu"));
+        assert!(!glob_matches("attributes.*", "attributes"));
+        assert!(!glob_matches("attributes.*", "price"));
+        assert!(glob_matches("*_id", "author_id"));
+        assert!(glob_matches("price_*_ttc", "price_eu_ttc"));
+        assert!(!glob_matches("price_*_ttc", "price_eu_ht"));
+        assert!(glob_matches("a*a", "aa"));
+        assert!(!glob_matches("a*a", "a"));
+        assert!(glob_matches("genre", "genre"));
+        assert!(!glob_matches("genre", "genres"));
     }
 
     #[test]
-    fn formatted_with_crop_and_highlight_in_word() {
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
+    fn formatted_with_escaped_html() {
         let formatter = Formatter::new(
-            &analyzer,
+            &ANALYZER,
             (String::from("<em>"), String::from("</em>")),
-            String::from("…"),
-        );
+            (String::from("…"), String::from("…")),
+        )
+        .with_escape_html(true);
 
         let mut fields = FieldsIdsMap::new();
         let title = fields.insert("title").unwrap();
-        let author = fields.insert("author").unwrap();
-
-        let document: serde_json::Value = json!({
-            "title": "Harry Potter and the Half-Blood Prince",
-            "author": "J. K. Rowling",
-        });
 
-        // we need to convert the `serde_json::Map` into an `IndexMap`.
-        let document = document
-            .as_object()
-            .unwrap()
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+        let mut document: Document = serde_json::from_value(json!({
+            "title": "The <script>alert('hobbit')</script> & <em>Hobbit</em>",
+        }))
+        .unwrap();
 
         let mut formatted_options = BTreeMap::new();
         formatted_options.insert(
             title,
             FormatOptions {
                 highlight: true,
-                crop: Some(4),
-            },
-        );
-        formatted_options.insert(
-            author,
-            FormatOptions {
-                highlight: false,
                 crop: None,
+                highlight_tags: None,
+                crop_on_sentences: false,
             },
         );
 
         let mut matching_words = BTreeMap::new();
-        matching_words.insert("blood", Some(3));
+        matching_words.insert("hobbit", Some(6));
 
-        let value = format_fields(
-            &document,
+        let (value, _) = format_fields(
+            &mut document,
             &fields,
             &formatter,
             &matching_words,
             &formatted_options,
+            None,
         )
         .unwrap();
 
-        assert_eq!(value["title"], "…the Half-<em>Blo</em>od Prince");
-        assert_eq!(value["author"], "J. K. Rowling");
-    }
-
-    #[test]
-    fn test_compute_value_matches() {
-        let text = "Call me Ishmael. Some years ago—never mind how long precisely—having little or no money in my purse, and nothing particular to interest me on shore, I thought I would sail about a little and see the watery part of the world.";
-        let value = serde_json::json!(text);
-
-        let mut matcher = BTreeMap::new();
-        matcher.insert("ishmael", Some(3));
-        matcher.insert("little", Some(6));
-        matcher.insert("particular", Some(1));
-
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-
-        let mut infos = Vec::new();
-
-        compute_value_matches(&mut infos, &value, &matcher, &analyzer);
-
-        let mut infos = infos.into_iter();
-        let crop = |info: MatchInfo| &text[info.start..info.start + info.length];
-
-        assert_eq!(crop(infos.next().unwrap()), "Ish");
-        assert_eq!(crop(infos.next().unwrap()), "little");
-        assert_eq!(crop(infos.next().unwrap()), "p");
-        assert_eq!(crop(infos.next().unwrap()), "little");
-        assert!(infos.next().is_none());
-    }
-
-    #[test]
-    fn test_compute_match() {
-        let value = serde_json::from_str(r#"{
-            "color": "Green",
-            "name": "Lucas Hess",
-            "gender": "male",
-            "price": 3.5,
-            "address": "412 Losee Terrace, Blairstown, Georgia, 2825",
-            "about": "Mollit ad in exercitation quis Laboris . Anim est ut consequat fugiat duis magna aliquip velit nisi. Commodo eiusmod est consequat proident consectetur aliqua enim fugiat. Aliqua adipisicing laboris elit proident enim veniam laboris mollit. Incididunt fugiat minim ad nostrud deserunt tempor in. Id irure officia labore qui est labore nulla nisi. Magna sit quis tempor esse consectetur amet labore duis aliqua consequat.\r\n"
-  }"#).unwrap();
-        let mut matcher = BTreeMap::new();
-        matcher.insert("green", Some(5));
-        matcher.insert("mollit", Some(6));
-        matcher.insert("laboris", Some(7));
-        matcher.insert("3", Some(1));
-
-        let stop_words = fst::Set::default();
-        let mut config = AnalyzerConfig::default();
-        config.stop_words(&stop_words);
-        let analyzer = Analyzer::new(config);
-
-        let matches = compute_matches(&matcher, &value, &analyzer);
         assert_eq!(
-            format!("{:?}", matches),
-            r##"{"about": [MatchInfo { start: 0, length: 6 }, MatchInfo { start: 31, length: 7 }, MatchInfo { start: 191, length: 7 }, MatchInfo { start: 225, length: 7 }, MatchInfo { start: 233, length: 6 }], "color": [MatchInfo { start: 0, length: 5 }], "price": [MatchInfo { start: 0, length: 1 }]}"##
+            value["title"],
+            "The &lt;script&gt;alert(&#x27;<em>hobbit</em>&#x27;)&lt;/script&gt; &amp; &lt;em&gt;<em>Hobbit</em>&lt;/em&gt;"
+        );
+        assert_eq!(
+            escape_html("no special characters"),
+            Cow::Borrowed("no special characters")
         );
     }
 
     #[test]
-    fn test_insert_geo_distance() {
-        let value: Document = serde_json::from_str(
-            r#"{
-      "_geo": {
-        "lat": 50.629973371633746,
-        "lng": 3.0569447399419567
-      },
-      "city": "Lille",
-      "id": "1"
-    }"#,
-        )
-        .unwrap();
-
-        let sorters = &["_geoPoint(50.629973371633746,3.0569447399419567):desc".to_string()];
-        let mut document = value.clone();
-        insert_geo_distance(sorters, &mut document);
-        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
-
-        let sorters = &["_geoPoint(50.629973371633746, 3.0569447399419567):asc".to_string()];
-        let mut document = value.clone();
-        insert_geo_distance(sorters, &mut document);
-        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
-
-        let sorters =
-            &["_geoPoint(   50.629973371633746   ,  3.0569447399419567   ):desc".to_string()];
-        let mut document = value.clone();
-        insert_geo_distance(sorters, &mut document);
-        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
-
-        let sorters = &[
-            "prix:asc",
-            "villeneuve:desc",
-            "_geoPoint(50.629973371633746, 3.0569447399419567):asc",
-            "ubu:asc",
-        ]
-        .map(|s| s.to_string());
-        let mut document = value.clone();
-        insert_geo_distance(sorters, &mut document);
-        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+    fn candidates_count_before_and_after_the_filter() {
+        let documents: Vec<_> = (0..10)
+            .map(|id| json!({ "id": id, "genre": if id < 3 { "horror" } else { "fantasy" }, "title": "the hobbit" }))
+            .collect();
+        let (_dir, index) = index_with_documents(
+            json!(documents),
+            Settings {
+                filterable_attributes: Setting::Set(std::iter::once("genre".to_string()).collect()),
+                ..Default::default()
+            },
+        );
 
-        // only the first geoPoint is used to compute the distance
-        let sorters = &[
-            "chien:desc",
-            "_geoPoint(50.629973371633746, 3.0569447399419567):asc",
-            "pangolin:desc",
-            "_geoPoint(100.0, -80.0):asc",
-            "chat:asc",
-        ]
-        .map(|s| s.to_string());
-        let mut document = value.clone();
-        insert_geo_distance(sorters, &mut document);
-        assert_eq!(document.get("_geoDistance"), Some(&json!(0)));
+        let query: SearchQuery = serde_json::from_value(json!({
+            "filter": "genre = horror",
+            "excludeDocumentIds": [0],
+            "showCandidatesCount": true,
+        }))
+        .unwrap();
+        let result = index.perform_search(query).unwrap();
+        assert_eq!(
+            result.candidates_count,
+            Some(CandidatesCount {
+                before_filter: 9,
+                after_filter: 2,
+            })
+        );
 
-        // there was no _geoPoint so nothing is inserted in the document
-        let sorters = &["chien:asc".to_string()];
-        let mut document = value;
-        insert_geo_distance(sorters, &mut document);
-        assert_eq!(document.get("_geoDistance"), None);
+        let query = SearchQuery::builder().q("hobbit").build();
+        assert_eq!(index.perform_search(query).unwrap().candidates_count, None);
     }
 }
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+
+use parking_lot::Mutex;
+
+use super::search::{SearchQuery, SearchResult};
+
+/// A LRU cache of the results of the searches made on an index.
+///
+/// The cache must be cleared whenever the index is written. The results of the searches started
+/// before a clear are not inserted, since they may have been computed on the old documents.
+pub struct SearchCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    generation: u64,
+    /// Incremented on each use of a result, to order the results by their last use.
+    tick: u64,
+    /// The results by their normalized query, with the tick of their last use.
+    results: HashMap<String, (SearchResult, u64)>,
+    /// The normalized queries by the tick of the last use of their result, from the least to the
+    /// most recently used.
+    recency: BTreeMap<u64, String>,
+}
+
+impl SearchCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner {
+                generation: 0,
+                tick: 0,
+                results: HashMap::new(),
+                recency: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Returns the key of `query` in the cache, the JSON serialization of the normalized query.
+    ///
+    /// The queries are normalized first, so that queries only differing by the whitespaces of
+    /// their `q` share the same results. The fields are serialized in their declaration order,
+    /// each field being part of the key. `None` is returned if the query can't be serialized, in
+    /// which case it must not be cached.
+    pub fn key(query: &SearchQuery) -> Option<String> {
+        let q = query
+            .q
+            .as_deref()
+            .map(|q| q.split_whitespace().collect::<Vec<_>>().join(" "));
+        let query = SearchQuery { q, ..query.clone() };
+        serde_json::to_string(&query).ok()
+    }
+
+    /// Returns the current generation of the cache, to be given back when inserting a result.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().generation
+    }
+
+    pub fn get(&self, key: &str) -> Option<SearchResult> {
+        let mut inner = self.inner.lock();
+        let Inner {
+            tick,
+            results,
+            recency,
+            ..
+        } = &mut *inner;
+
+        let (result, last_used) = results.get_mut(key)?;
+        let key = recency.remove(last_used)?;
+        *tick += 1;
+        *last_used = *tick;
+        recency.insert(*tick, key);
+        Some(result.clone())
+    }
+
+    /// Inserts the `result` of a search started at `generation`, evicting the least recently used
+    /// result if the cache is full. The result is ignored if the cache has been cleared since.
+    pub fn insert(&self, key: String, generation: u64, result: SearchResult) {
+        let mut inner = self.inner.lock();
+        if inner.generation != generation || self.capacity == 0 {
+            return;
+        }
+        let Inner {
+            tick,
+            results,
+            recency,
+            ..
+        } = &mut *inner;
+
+        match results.remove(&key) {
+            Some((_, last_used)) => {
+                recency.remove(&last_used);
+            }
+            None if results.len() >= self.capacity => {
+                let least_recently_used = recency.keys().next().copied();
+                if let Some(evicted) = least_recently_used.and_then(|tick| recency.remove(&tick)) {
+                    results.remove(&evicted);
+                }
+            }
+            None => (),
+        }
+
+        *tick += 1;
+        results.insert(key.clone(), (result, *tick));
+        recency.insert(*tick, key);
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        inner.results.clear();
+        inner.recency.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn result(nb_hits: u64) -> SearchResult {
+        SearchResult {
+            hits: vec![],
+            nb_hits,
+            exhaustive_nb_hits: false,
+            query: String::new(),
+            limit: 20,
+            offset: 0,
+            processing_time_ms: 0,
+            processing_time_us: 0,
+            facets_distribution: None,
+            exhaustive_facets_count: None,
+            result_hash: None,
+            estimated_total_hits: Some(nb_hits),
+            total_hits: None,
+            total_pages: None,
+            facet_stats: None,
+            processing_times: None,
+            filter_fields: None,
+            suggestion: None,
+            ranking_rules: None,
+            field_coverage: None,
+            warnings: Vec::new(),
+            next_cursor: None,
+            normalized_query: None,
+            candidates_count: None,
+            unfiltered_hits: None,
+        }
+    }
+
+    fn key(id: usize) -> String {
+        SearchCache::key(&SearchQuery::builder().offset(id).build()).unwrap()
+    }
+
+    #[test]
+    fn repeated_query_hits_the_cache() {
+        let cache = SearchCache::new(2);
+        let query = SearchQuery::builder().q("hello  world").build();
+        let key = SearchCache::key(&query).unwrap();
+
+        assert_eq!(cache.get(&key), None);
+        cache.insert(key.clone(), cache.generation(), result(3));
+        assert_eq!(cache.get(&key), Some(result(3)));
+
+        let normalized = SearchQuery::builder().q(" hello world ").build();
+        assert_eq!(SearchCache::key(&normalized), Some(key.clone()));
+        let other = SearchQuery::builder().q("hello world").limit(1).build();
+        assert_ne!(SearchCache::key(&other), Some(key));
+    }
+
+    #[test]
+    fn disabled_distinct_has_its_own_key() {
+        let query = SearchQuery::builder().q("hello").build();
+        let no_distinct = SearchQuery {
+            distinct: Some(None),
+            ..query.clone()
+        };
+        assert_ne!(SearchCache::key(&query), SearchCache::key(&no_distinct));
+    }
+
+    #[test]
+    fn clear_invalidates_the_cache() {
+        let cache = SearchCache::new(2);
+        cache.insert(key(1), cache.generation(), result(3));
+        cache.clear();
+        assert_eq!(cache.get(&key(1)), None);
+
+        // a search started before the clear is not cached.
+        let generation = cache.generation();
+        cache.clear();
+        cache.insert(key(1), generation, result(3));
+        assert_eq!(cache.get(&key(1)), None);
+    }
+
+    #[test]
+    fn least_recently_used_result_is_evicted() {
+        let cache = SearchCache::new(2);
+        cache.insert(key(1), cache.generation(), result(1));
+        cache.insert(key(2), cache.generation(), result(2));
+        assert!(cache.get(&key(1)).is_some());
+
+        cache.insert(key(3), cache.generation(), result(3));
+        assert_eq!(cache.get(&key(2)), None);
+        assert_eq!(cache.get(&key(1)), Some(result(1)));
+        assert_eq!(cache.get(&key(3)), Some(result(3)));
+
+        // reinserting a result makes it the most recently used one.
+        cache.insert(key(1), cache.generation(), result(1));
+        cache.insert(key(4), cache.generation(), result(4));
+        assert_eq!(cache.get(&key(3)), None);
+        assert_eq!(cache.get(&key(1)), Some(result(1)));
+    }
+}
@@ -4,6 +4,8 @@ use std::num::NonZeroUsize;
 
 use log::{debug, info, trace};
 use milli::documents::DocumentBatchReader;
+use milli::heed::types::{SerdeJson, Str};
+use milli::heed::RwTxn;
 use milli::update::{
     DocumentAdditionResult, DocumentDeletionResult, IndexDocumentsConfig, IndexDocumentsMethod,
     Setting,
@@ -11,10 +13,16 @@ use milli::update::{
 use serde::{Deserialize, Serialize, Serializer};
 use uuid::Uuid;
 
-use super::error::Result;
+use super::error::{IndexError, Result};
 use super::index::{Index, IndexMeta};
+use super::DefaultSearchQuery;
 use crate::update_file_store::UpdateFileStore;
 
+/// The key of the default search query in the main database of the index.
+pub(super) const DEFAULT_SEARCH_QUERY_KEY: &str = "default-search-query";
+/// The key of the maximum number of hits of the searches in the main database of the index.
+pub(super) const MAX_TOTAL_HITS_KEY: &str = "max-total-hits";
+
 fn serialize_with_wildcard<S>(
     field: &Setting<Vec<String>>,
     s: S,
@@ -114,6 +122,14 @@ pub struct Settings<T> {
     #[serde(default, skip_serializing_if = "Setting::is_not_set")]
     #[cfg_attr(test, proptest(strategy = "test::setting_strategy()"))]
     pub typo: Setting<TypoSettings>,
+    #[serde(default, skip_serializing_if = "Setting::is_not_set")]
+    #[cfg_attr(test, proptest(value = "Setting::NotSet"))]
+    pub default_search_query: Setting<DefaultSearchQuery>,
+    /// The maximum number of hits the searches can page through and report, the
+    /// `--max-total-hits` of the instance applies when it is reset.
+    #[serde(default, skip_serializing_if = "Setting::is_not_set")]
+    #[cfg_attr(test, proptest(value = "Setting::NotSet"))]
+    pub max_total_hits: Setting<usize>,
 
     #[serde(skip)]
     pub _kind: PhantomData<T>,
@@ -131,6 +147,8 @@ impl Settings<Checked> {
             synonyms: Setting::Reset,
             distinct_attribute: Setting::Reset,
             typo: Setting::Reset,
+            default_search_query: Setting::Reset,
+            max_total_hits: Setting::Reset,
             _kind: PhantomData,
         }
     }
@@ -146,6 +164,8 @@ impl Settings<Checked> {
             synonyms,
             distinct_attribute,
             typo: typo_tolerance,
+            default_search_query,
+            max_total_hits,
             ..
         } = self;
 
@@ -159,12 +179,25 @@ impl Settings<Checked> {
             synonyms,
             distinct_attribute,
             typo: typo_tolerance,
+            default_search_query,
+            max_total_hits,
             _kind: PhantomData,
         }
     }
 }
 
 impl Settings<Unchecked> {
+    /// Rejects the settings whose values can't be applied to an index, before they are enqueued.
+    pub fn validate(&self) -> Result<()> {
+        if let Setting::Set(0) = self.max_total_hits {
+            return Err(IndexError::InvalidSettings(
+                "`maxTotalHits` must be greater than 0.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn check(self) -> Settings<Checked> {
         let displayed_attributes = match self.displayed_attributes {
             Setting::Set(fields) => {
@@ -198,6 +231,8 @@ impl Settings<Unchecked> {
             synonyms: self.synonyms,
             distinct_attribute: self.distinct_attribute,
             typo: self.typo,
+            default_search_query: self.default_search_query,
+            max_total_hits: self.max_total_hits,
             _kind: PhantomData,
         }
     }
@@ -229,6 +264,7 @@ impl Index {
         let mut txn = self.write_txn()?;
         let res = self.update_primary_key_txn(&mut txn, primary_key)?;
         txn.commit()?;
+        self.clear_search_cache();
 
         Ok(res)
     }
@@ -246,6 +282,7 @@ impl Index {
         let deleted = builder.execute()?;
 
         txn.commit()?;
+        self.clear_search_cache();
 
         Ok(deleted)
     }
@@ -254,6 +291,7 @@ impl Index {
         let mut txn = self.write_txn()?;
         milli::update::ClearDocuments::new(&mut txn, self).execute()?;
         txn.commit()?;
+        self.clear_search_cache();
 
         Ok(())
     }
@@ -297,6 +335,7 @@ impl Index {
         let addition = builder.execute()?;
 
         txn.commit()?;
+        self.clear_search_cache();
 
         info!("document addition done: {:?}", addition);
 
@@ -312,13 +351,47 @@ impl Index {
         apply_settings_to_builder(settings, &mut builder);
 
         builder.execute(|indexing_step| debug!("update: {:?}", indexing_step))?;
+        store_search_settings(self, &mut txn, settings)?;
 
         txn.commit()?;
+        self.clear_search_cache();
 
         Ok(())
     }
 }
 
+/// Stores the default search query and the maximum number of hits of `settings` in the main
+/// database of the index, milli not knowing about them.
+pub fn store_search_settings(
+    index: &milli::Index,
+    txn: &mut RwTxn,
+    settings: &Settings<Checked>,
+) -> Result<()> {
+    store_setting(
+        index,
+        txn,
+        DEFAULT_SEARCH_QUERY_KEY,
+        &settings.default_search_query,
+    )?;
+    store_setting(index, txn, MAX_TOTAL_HITS_KEY, &settings.max_total_hits)
+}
+
+fn store_setting<T: Serialize>(
+    index: &milli::Index,
+    txn: &mut RwTxn,
+    key: &str,
+    setting: &Setting<T>,
+) -> Result<()> {
+    match setting {
+        Setting::Set(value) => index.main.put::<_, Str, SerdeJson<T>>(txn, key, value)?,
+        Setting::Reset => {
+            index.main.delete::<_, Str>(txn, key)?;
+        }
+        Setting::NotSet => (),
+    }
+    Ok(())
+}
+
 pub fn apply_settings_to_builder(
     settings: &Settings<Checked>,
     builder: &mut milli::update::Settings,
@@ -456,6 +529,8 @@ pub(crate) mod test {
             synonyms: Setting::NotSet,
             distinct_attribute: Setting::NotSet,
             typo: Setting::NotSet,
+            default_search_query: Setting::NotSet,
+            max_total_hits: Setting::NotSet,
             _kind: PhantomData::<Unchecked>,
         };
 
@@ -478,6 +553,8 @@ pub(crate) mod test {
             synonyms: Setting::NotSet,
             distinct_attribute: Setting::NotSet,
             typo: Setting::NotSet,
+            default_search_query: Setting::NotSet,
+            max_total_hits: Setting::NotSet,
             _kind: PhantomData::<Unchecked>,
         };
 
@@ -23,7 +23,7 @@ use crate::index::{
     Checked, Document, IndexMeta, IndexStats, SearchQuery, SearchResult, Settings, Unchecked,
 };
 use crate::index_controller::dump_actor::{load_dump, DumpActor, DumpActorHandleImpl};
-use crate::options::{IndexerOpts, SchedulerConfig};
+use crate::options::{IndexerOpts, SchedulerConfig, SearchOpts};
 use crate::snapshot::{load_snapshot, SnapshotService};
 use crate::tasks::error::TaskError;
 use crate::tasks::task::{DocumentDeletion, Task, TaskContent, TaskId};
@@ -169,6 +169,7 @@ impl IndexControllerBuilder {
         self,
         db_path: impl AsRef<Path>,
         indexer_options: IndexerOpts,
+        search_options: SearchOpts,
         scheduler_config: SchedulerConfig,
     ) -> anyhow::Result<MeiliSearch> {
         let index_size = self
@@ -219,6 +220,7 @@ impl IndexControllerBuilder {
             &db_path,
             index_size,
             &indexer_options,
+            &search_options,
             meta_env.clone(),
             update_file_store.clone(),
         )?);
@@ -372,11 +374,14 @@ where
                 settings,
                 is_deletion,
                 allow_index_creation,
-            } => TaskContent::SettingsUpdate {
-                settings,
-                is_deletion,
-                allow_index_creation,
-            },
+            } => {
+                settings.validate()?;
+                TaskContent::SettingsUpdate {
+                    settings,
+                    is_deletion,
+                    allow_index_creation,
+                }
+            }
             Update::DocumentAddition {
                 mut payload,
                 primary_key,
@@ -651,9 +656,6 @@ mod test {
 
     use crate::index::error::Result as IndexResult;
     use crate::index::Index;
-    use crate::index::{
-        default_crop_marker, default_highlight_post_tag, default_highlight_pre_tag,
-    };
     use crate::index_resolver::index_store::MockIndexStore;
     use crate::index_resolver::meta_store::MockIndexMetaStore;
     use crate::index_resolver::IndexResolver;
@@ -685,18 +687,79 @@ mod test {
         let query = SearchQuery {
             q: Some(String::from("hello world")),
             offset: Some(10),
-            limit: 0,
+            limit: Some(0),
             attributes_to_retrieve: Some(vec!["string".to_owned()].into_iter().collect()),
             attributes_to_crop: None,
-            crop_length: 18,
+            crop_length: Some(18),
             attributes_to_highlight: None,
-            matches: true,
+            matches: Some(true),
             filter: None,
             sort: None,
             facets_distribution: None,
-            highlight_pre_tag: default_highlight_pre_tag(),
-            highlight_post_tag: default_highlight_post_tag(),
-            crop_marker: default_crop_marker(),
+            highlight_pre_tag: None,
+            highlight_post_tag: None,
+            crop_marker: None,
+            crop_marker_before: None,
+            crop_marker_after: None,
+            prefer_exact_matches: None,
+            show_crop_info: None,
+            min_prefix_length: None,
+            show_result_hash: None,
+            exhaustive_facets_count: None,
+            page: None,
+            hits_per_page: None,
+            attributes_to_search_on: None,
+            matching_strategy: None,
+            matches_on_formatted: None,
+            matches_char_offsets: None,
+            matches_by_pointer: None,
+            show_matched_terms: None,
+            strict_attributes: None,
+            distinct: None,
+            show_processing_times: None,
+            strict_limits: None,
+            min_geo_distance: None,
+            geo_distance_decimals: None,
+            geo_distance_from: None,
+            show_facet_stats: None,
+            max_values_per_facet: None,
+            facet_sort: None,
+            crop_strategy: None,
+            crop_unit: None,
+            crop_on_sentences: None,
+            highlight_numbers: None,
+            exclude_document_ids: None,
+            raw_attributes: None,
+            show_filter_fields: None,
+            disjunctive_facets: None,
+            show_suggestion: None,
+            timeout_ms: None,
+            hit_content: None,
+            show_ranking_rules: None,
+            show_typos: None,
+            show_matched_words_count: None,
+            show_normalized_query: None,
+            formatted_segments: None,
+            crop_snippets: None,
+            show_truncation: None,
+            sort_ties_by_id: None,
+            show_size_bytes: None,
+            typo_tolerance: None,
+            exclude_attributes_by_default: None,
+            show_field_coverage: None,
+            highlight_case_sensitive: None,
+            highlight_diacritic_sensitive: None,
+            min_match_length: None,
+            flatten_documents: None,
+            field_order: None,
+            format: None,
+            max_highlights_per_field: None,
+            cursor: None,
+            ranking_rules: None,
+            escape_html: None,
+            show_candidates_count: None,
+            show_ranking_score: None,
+            ranking_score_threshold: None,
         };
 
         let result = SearchResult {
@@ -707,8 +770,24 @@ mod test {
             limit: 24,
             offset: 0,
             processing_time_ms: 50,
+            processing_time_us: 50_000,
             facets_distribution: None,
             exhaustive_facets_count: Some(true),
+            result_hash: None,
+            estimated_total_hits: None,
+            total_hits: Some(29),
+            total_pages: None,
+            facet_stats: None,
+            processing_times: None,
+            filter_fields: None,
+            suggestion: None,
+            ranking_rules: None,
+            field_coverage: None,
+            warnings: Vec::new(),
+            next_cursor: None,
+            normalized_query: None,
+            candidates_count: None,
+            unfiltered_hits: None,
         };
 
         let mut uuid_store = MockIndexMetaStore::new();
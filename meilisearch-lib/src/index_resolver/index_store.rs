@@ -11,7 +11,7 @@ use uuid::Uuid;
 
 use super::error::{IndexResolverError, Result};
 use crate::index::Index;
-use crate::options::IndexerOpts;
+use crate::options::{IndexerOpts, SearchOpts};
 
 type AsyncMap<K, V> = Arc<RwLock<HashMap<K, V>>>;
 
@@ -28,6 +28,8 @@ pub struct MapIndexStore {
     path: PathBuf,
     index_size: usize,
     indexer_config: Arc<IndexerConfig>,
+    search_cache_size: usize,
+    max_total_hits: Option<usize>,
 }
 
 impl MapIndexStore {
@@ -35,6 +37,7 @@ impl MapIndexStore {
         path: impl AsRef<Path>,
         index_size: usize,
         indexer_opts: &IndexerOpts,
+        search_opts: &SearchOpts,
     ) -> anyhow::Result<Self> {
         let indexer_config = Arc::new(IndexerConfig::try_from(indexer_opts)?);
         let path = path.as_ref().join("indexes/");
@@ -44,6 +47,8 @@ impl MapIndexStore {
             path,
             index_size,
             indexer_config,
+            search_cache_size: search_opts.search_cache_size,
+            max_total_hits: search_opts.max_total_hits,
         })
     }
 }
@@ -65,9 +70,13 @@ impl IndexStore for MapIndexStore {
 
         let index_size = self.index_size;
         let update_handler = self.indexer_config.clone();
+        let search_cache_size = self.search_cache_size;
+        let max_total_hits = self.max_total_hits;
         let index = spawn_blocking(move || -> Result<Index> {
             let index = Index::open(path, index_size, uuid, update_handler)?;
-            Ok(index)
+            Ok(index
+                .with_search_cache(search_cache_size)
+                .with_max_total_hits(max_total_hits))
         })
         .await??;
 
@@ -90,9 +99,16 @@ impl IndexStore for MapIndexStore {
 
                 let index_size = self.index_size;
                 let update_handler = self.indexer_config.clone();
-                let index =
-                    spawn_blocking(move || Index::open(path, index_size, uuid, update_handler))
-                        .await??;
+                let search_cache_size = self.search_cache_size;
+                let max_total_hits = self.max_total_hits;
+                let index = spawn_blocking(move || {
+                    Index::open(path, index_size, uuid, update_handler).map(|index| {
+                        index
+                            .with_search_cache(search_cache_size)
+                            .with_max_total_hits(max_total_hits)
+                    })
+                })
+                .await??;
                 self.index_store.write().await.insert(uuid, index.clone());
                 Ok(Some(index))
             }
@@ -19,7 +19,7 @@ use tokio::task::spawn_blocking;
 use uuid::Uuid;
 
 use crate::index::{error::Result as IndexResult, Index};
-use crate::options::IndexerOpts;
+use crate::options::{IndexerOpts, SearchOpts};
 use crate::tasks::batch::Batch;
 use crate::tasks::task::{DocumentDeletion, Job, Task, TaskContent, TaskEvent, TaskId, TaskResult};
 use crate::tasks::TaskPerformer;
@@ -39,11 +39,12 @@ pub fn create_index_resolver(
     path: impl AsRef<Path>,
     index_size: usize,
     indexer_opts: &IndexerOpts,
+    search_opts: &SearchOpts,
     meta_env: Arc<milli::heed::Env>,
     file_store: UpdateFileStore,
 ) -> anyhow::Result<HardStateIndexResolver> {
     let uuid_store = HeedMetaStore::new(meta_env)?;
-    let index_store = MapIndexStore::new(&path, index_size, indexer_opts)?;
+    let index_store = MapIndexStore::new(&path, index_size, indexer_opts, search_opts)?;
     Ok(IndexResolver::new(uuid_store, index_store, file_store))
 }
 
@@ -39,6 +39,24 @@ pub struct IndexerOpts {
     pub max_indexing_threads: MaxThreads,
 }
 
+#[derive(Debug, Clone, Parser, Default, Serialize)]
+pub struct SearchOpts {
+    /// The number of search results cached by each index, the cache being emptied whenever the
+    /// index is updated.
+    ///
+    /// It defaults to 0, which disables the cache.
+    #[clap(long, env = "MEILI_SEARCH_CACHE_SIZE", default_value = "0")]
+    pub search_cache_size: usize,
+
+    /// The default maximum number of hits a search on an index can page through and report, the
+    /// `maxTotalHits` setting of an index overrides it.
+    ///
+    /// The searches can never return more than 1000 hits, but the number of hits they report is
+    /// only capped when this option or the setting of the index is set.
+    #[clap(long, env = "MEILI_MAX_TOTAL_HITS")]
+    pub max_total_hits: Option<usize>,
+}
+
 #[derive(Debug, Clone, Parser, Default, Serialize)]
 pub struct SchedulerConfig {
     /// enable the autobatching experimental feature